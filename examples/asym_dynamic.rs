@@ -7,7 +7,7 @@
 //! To keep it simple when the time quantum is elapsed it is 
 //! just put back into the master quuee.
 //! 
-//! This also handles affinities. Affinities here are modeled very simply, the affinity just specifies which processor
+//! This also handles affinities. Affinities here are modeled as a mask, the affinity just specifies which processors
 //! the process should be bound to.
 //! 
 //! To make the example even more thorough, we incorporate [NumaPtr] so that
@@ -16,7 +16,7 @@
 
 use std::{sync::Arc, thread, time::Duration};
 
-use osconcepts::{computer::{process::{OpCode, Process}, processor::Cpu}, memory::{ipc::IpcChannel, numa::NumaPtr, pool::{MemoryMutex, RandomAccessMemory}}};
+use osconcepts::{computer::{process::{OpCode, Process}, processor::Cpu}, memory::{ipc::{broadcast::{BroadcastChannel, BroadcastReceiver}, IpcChannel}, numa::NumaPtr, pool::{MemoryMutex, RandomAccessMemory}}};
 
 const TIME_UNIT_DURATION: Duration = Duration::from_millis(50);
 
@@ -30,31 +30,34 @@ pub struct CommonData {
     /// The queue for the master processors.
     master_queue: Arc<IpcChannel<Process>>,
     /// The queue for the slave processors.
-    slave_queue: Arc<IpcChannel<Process>>
+    slave_queue: Arc<IpcChannel<Process>>,
+    /// Tells every slave processor to shut down at once.
+    shutdown: BroadcastChannel<()>
 }
 
 /// The data for the processor.
 pub struct CpuData {
     id: u8,
-    part: NumaPtr<CommonData>
+    part: NumaPtr<CommonData>,
+    shutdown: BroadcastReceiver<()>
 }
 
 
 /// Main kernel processor thread.
 pub fn main_kernel(common: NumaPtr<CommonData>) {
     println!("main launched");
-    let channel = common.lock().get().master_queue.clone();
+    let channel = common.lock().master_queue.clone();
     loop {
-        let msg = channel.recv();
+        let msg = channel.recv().unwrap();
         println!("Master received process: {:?}", msg);
         if msg.code == OpCode::Shutdown && msg.time_units == 0 {
             println!("Master received notice to shutdown, shutting down the slave cores.");
-            for _ in 0..4 {
-                common.lock().get().slave_queue.send(Process::shutdown());
-            }
+            common.lock().shutdown.send(()).unwrap();
+            // Closing the queue wakes up the slaves waiting on it so they see the shutdown.
+            common.lock().slave_queue.close();
             break;
         } else {
-            common.lock().get().slave_queue.send(msg);
+            common.lock().slave_queue.send(msg).unwrap();
         }
         
     
@@ -67,45 +70,45 @@ pub fn main_kernel(common: NumaPtr<CommonData>) {
 pub fn slave_processor(data: CpuData) {
     println!("slave launched");
 
-    let master_work_queue = data.part.lock().get().master_queue.clone();
-    let slave_work_queue = data.part.lock().get().slave_queue.clone();
+    let master_work_queue = data.part.lock().master_queue.clone();
+    let slave_work_queue = data.part.lock().slave_queue.clone();
 
     // Allows us to time the task on the CPU. 
     let mut clock = QUANTA;
     loop {
-        let mut msg = slave_work_queue.recv();
+        if data.shutdown.try_recv().is_some() {
+            println!("Processor ({}) received word to shut down.", data.id);
+            break;
+        }
+        let Ok(mut msg) = slave_work_queue.recv() else {
+            continue;
+        };
         // If the process has a specific affinity,
         // we check this and release it back to the queue if the
         // affinity does not match.
-        if msg.affinity != -1 && msg.affinity != data.id.into() {
-            master_work_queue.send(msg);
+        if !msg.affinity.allows(data.id.into()) {
+            master_work_queue.send(msg).unwrap();
             continue;
         }
 
         
         println!("Processor ({}) received process {}.", data.id, msg.id);
 
-        if msg.code == OpCode::Shutdown && msg.time_units == 0 {
-            println!("Processor ({}) received word to shut down.", data.id);
-            break;
-        } else {
-            // Tick the time quantum.
-            while msg.time_units > 0 && clock > 0 {
-                // run the thread for some time.
-                thread::sleep(TIME_UNIT_DURATION);
-                msg.time_units -= 1;
-                clock -= 1;
-            }
-            println!("Processor ({}) releasing process {}.", data.id, msg.id);
-            clock = QUANTA;
-
-            // Reschedule it back into the primary queue.
-            // There is a special case where it is a shutdown,
-            // shutdowns need to be boradcasted.
-            if msg.time_units > 0 || (msg.code == OpCode::Shutdown) {
-                master_work_queue.send(msg);
-            }
-
+        // Tick the time quantum.
+        while msg.time_units > 0 && clock > 0 {
+            // run the thread for some time.
+            thread::sleep(TIME_UNIT_DURATION);
+            msg.tick();
+            clock -= 1;
+        }
+        println!("Processor ({}) releasing process {}.", data.id, msg.id);
+        clock = QUANTA;
+
+        // Reschedule it back into the primary queue.
+        // There is a special case where it is a shutdown,
+        // shutdowns need to be boradcasted.
+        if msg.time_units > 0 || (msg.code == OpCode::Shutdown) {
+            master_work_queue.send(msg).unwrap();
         }
         
     }
@@ -118,15 +121,17 @@ pub fn user_thread(master: NumaPtr<CommonData>) {
     println!("launched the user thread");
 
     // We will first launch three processes.
-    master.lock().get().slave_queue.send(Process::full(0, 35, OpCode::Inert));
-    master.lock().get().slave_queue.send(Process::full(1, 25, OpCode::Inert));
-    master.lock().get().slave_queue.send(Process::full(2, 5, OpCode::Inert));
+    master.lock().slave_queue.send(Process::full(0, 35, OpCode::Inert)).unwrap();
+    master.lock().slave_queue.send(Process::full(1, 25, OpCode::Inert)).unwrap();
+    master.lock().slave_queue.send(Process::full(2, 5, OpCode::Run(Box::new(|ctx| {
+        println!("Process 2 is doing work, {} units left.", ctx.time_units);
+    })))).unwrap();
 
     // We then launch a shutdown process, this will shutdown the computer upon completion. We do this with an affinity
     // to only one of the processors.
     //
     // The affinity specifically is to processor 2.
-    master.lock().get().slave_queue.send(Process::full(3,125, OpCode::Shutdown).with_affinity(2));
+    master.lock().slave_queue.send(Process::full(3,125, OpCode::Shutdown).with_affinity(2)).unwrap();
 
 
 }
@@ -139,9 +144,11 @@ pub fn main() {
 
 
     // Common Shared Memory
+    let shutdown = BroadcastChannel::new();
     let shared_string = NumaPtr::upgrade(central_ram.store(CommonData {
         master_queue: Arc::new(IpcChannel::new()),
-        slave_queue: Arc::new(IpcChannel::new())
+        slave_queue: Arc::new(IpcChannel::new()),
+        shutdown: shutdown.clone()
     }));
 
 
@@ -150,15 +157,18 @@ pub fn main() {
     let slaves = vec![
         Cpu::new(CpuData {
             id: 1,
-            part: shared_string.clone()
+            part: shared_string.clone(),
+            shutdown: shutdown.subscribe()
         }),
         Cpu::new(CpuData {
             id: 2,
-            part: shared_string.clone()
+            part: shared_string.clone(),
+            shutdown: shutdown.subscribe()
         }),
         Cpu::new(CpuData {
             id: 3,
-            part: shared_string.clone()
+            part: shared_string.clone(),
+            shutdown: shutdown.subscribe()
         }),
         
     ];
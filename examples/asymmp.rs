@@ -33,18 +33,18 @@ pub struct CpuData {
 /// Main kernel processor thread.
 pub fn main_kernel(master: SyncMemoryPtr<MasterData>, common: SyncMemoryPtr<CommonData>) {
     println!("main launched");
-    let channel = master.lock().get().tasks.clone();
+    let channel = master.lock().tasks.clone();
     loop {
-        let msg = channel.recv();
+        let msg = channel.recv().unwrap();
         println!("Master received process: {:?}", msg);
         if msg.code == OpCode::Shutdown {
             println!("Master received notice to shutdown, shutting down the slave cores.");
             for _ in 0..4 {
-                common.lock().get().tasks.send(Process::shutdown());
+                common.lock().tasks.send(Process::shutdown()).unwrap();
             }
             break;
         } else {
-            common.lock().get().tasks.send(msg);
+            common.lock().tasks.send(msg).unwrap();
         }
         
     
@@ -56,10 +56,10 @@ pub fn main_kernel(master: SyncMemoryPtr<MasterData>, common: SyncMemoryPtr<Comm
 /// Slave processor.
 pub fn slave_processor(data: CpuData) {
     println!("slave launched");
-    let channel = data.part.lock().get().tasks.clone();
+    let channel = data.part.lock().tasks.clone();
 
     loop {
-        let msg = channel.recv();
+        let msg = channel.recv().unwrap();
         println!("Process ({}) received work: {:?}", data.id, msg);
 
         if msg.code == OpCode::Shutdown {
@@ -77,14 +77,14 @@ pub fn slave_processor(data: CpuData) {
 
 pub fn user_thread(master: SyncMemoryPtr<MasterData>) {
     println!("launched the user thread");
-    master.lock().get().tasks.send(Process::dummy(0));
-    master.lock().get().tasks.send(Process::dummy(1));
-    master.lock().get().tasks.send(Process::dummy(2));
-    master.lock().get().tasks.send(Process::dummy(3));
-    master.lock().get().tasks.send(Process::dummy(4));
+    master.lock().tasks.send(Process::dummy(0)).unwrap();
+    master.lock().tasks.send(Process::dummy(1)).unwrap();
+    master.lock().tasks.send(Process::dummy(2)).unwrap();
+    master.lock().tasks.send(Process::dummy(3)).unwrap();
+    master.lock().tasks.send(Process::dummy(4)).unwrap();
 
     // Shut down the master.
-    master.lock().get().tasks.send(Process::shutdown());
+    master.lock().tasks.send(Process::shutdown()).unwrap();
 
 }
 
@@ -0,0 +1,24 @@
+//! Buffer Cache
+//!
+//! Reads the same few blocks of a disk over and over through
+//! a buffer cache, only the first read of each block has to
+//! go to the disk and the cache absorbs the rest.
+
+
+use osconcepts::disks::{cache::BufferCache, hard_drive::{DiskAlgorithm, MagneticDisk}, AbstractStorageDevice, RawStoragePtr};
+
+
+fn main() {
+    let cache = BufferCache::new(MagneticDisk::new(4096, DiskAlgorithm::SSTF), 256, 4);
+    for round in 0..5 {
+        for block in 0..4 {
+            cache.read(RawStoragePtr::byte_ptr(block * 256 + round), 16).get();
+        }
+        let stats = cache.stats();
+        println!("round {round} hits {:>2} misses {:>2} disk requests {:>2}", stats.hits, stats.misses, cache.inner().service_record().len());
+    }
+
+    // A fifth block doesn't fit so something has to go.
+    cache.read(RawStoragePtr::byte_ptr(4 * 256), 16).get();
+    println!("after a fifth block {:?}", cache.stats());
+}
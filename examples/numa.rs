@@ -28,7 +28,7 @@ pub fn cpu_function(data: CpuData) {
         } else {
             random_string::generate(6, "DEFdef")
         };
-        *data.part.lock().get_mut() = buffer;
+        *data.part.lock() = buffer;
     }
 }
 
@@ -32,10 +32,10 @@ pub struct CpuData {
 /// Slave processor.
 pub fn processor_core(data: CpuData) {
     println!("slave launched");
-    let channel = data.shared.lock().get().tasks.clone();
+    let channel = data.shared.lock().tasks.clone();
 
     loop {
-        let msg = channel.recv();
+        let msg = channel.recv().unwrap();
         println!("Process ({}) received work: {:?}", data.id, msg);
 
 
@@ -55,15 +55,15 @@ pub fn processor_core(data: CpuData) {
 
 pub fn user_thread(master: SyncMemoryPtr<CommonData>) {
     println!("launched the user thread");
-    master.lock().get().tasks.send(Process::dummy(0));
-    master.lock().get().tasks.send(Process::dummy(1));
-    master.lock().get().tasks.send(Process::dummy(2));
-    master.lock().get().tasks.send(Process::dummy(3));
-    master.lock().get().tasks.send(Process::dummy(4));
+    master.lock().tasks.send(Process::dummy(0)).unwrap();
+    master.lock().tasks.send(Process::dummy(1)).unwrap();
+    master.lock().tasks.send(Process::dummy(2)).unwrap();
+    master.lock().tasks.send(Process::dummy(3)).unwrap();
+    master.lock().tasks.send(Process::dummy(4)).unwrap();
 
     // Shut down the master.
     for _ in 0..3 {
-        master.lock().get().tasks.send(Process::shutdown());
+        master.lock().tasks.send(Process::shutdown()).unwrap();
     }
 
 }
@@ -0,0 +1,23 @@
+//! Thrashing
+//!
+//! Loops over the same eight pages with more and more frames,
+//! once the working set fits in memory the fault rate falls off
+//! a cliff.
+
+
+use osconcepts::memory::paging::pager::Pager;
+
+
+fn main() {
+    for frames in 1..=10 {
+        let pager = Pager::new(frames).with_window(8);
+        let pages: Vec<_> = (0..8).map(|_| pager.alloc()).collect();
+        for _ in 0..50 {
+            for page in &pages {
+                page.read(0..1);
+            }
+        }
+        let bar = "#".repeat((pager.fault_rate() * 50.0) as usize);
+        println!("{frames:>2} frames {:>5.2} {bar} {}", pager.fault_rate(), if pager.is_thrashing() { "(thrashing)" } else { "" });
+    }
+}
@@ -18,8 +18,7 @@ pub fn cpu_function(data: CpuData) {
         } else {
             random_string::generate(6, "DEFdef")
         };
-        let ptr = data.part.lock();
-        *(ptr.get_mut()) = buffer;
+        data.part.with(|f| *f = buffer);
     }
     
 }
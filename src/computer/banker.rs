@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+
+/// Why the [Banker] would not grant a request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Denied {
+    /// The process asked for more than it said it would ever need.
+    ExceedsClaim,
+    /// There is not enough free right now, the process has to wait.
+    Unavailable,
+    /// Granting it would leave the system in an unsafe state.
+    Unsafe
+}
+
+/// A granted request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Allocation {
+    /// Everything the process holds now.
+    pub held: Vec<u32>,
+    /// The order every process can finish in after the grant.
+    pub sequence: Vec<u32>
+}
+
+#[derive(Debug)]
+struct Claim {
+    maximum: Vec<u32>,
+    allocation: Vec<u32>
+}
+
+impl Claim {
+    fn need(&self) -> Vec<u32> {
+        self.maximum.iter().zip(&self.allocation).map(|(m, a)| m - a).collect()
+    }
+}
+
+/// Deadlock avoidance with the banker's algorithm.
+///
+/// Every process declares the most of each resource it will ever need up
+/// front, and a request is only granted if afterwards there is still some
+/// order in which every process could get its maximum and finish.
+///
+/// ```
+/// use osconcepts::computer::banker::{Banker, Denied};
+///
+/// let mut banker = Banker::new(vec![2], [(0, vec![2]), (1, vec![2])]);
+/// assert!(banker.request(0, &[1]).is_ok());
+/// // Now neither could finish if we gave this out.
+/// assert_eq!(banker.request(1, &[1]), Err(Denied::Unsafe));
+/// ```
+#[derive(Debug)]
+pub struct Banker {
+    available: Vec<u32>,
+    claims: BTreeMap<u32, Claim>
+}
+
+impl Banker {
+    /// Creates a banker with the total of each resource and
+    /// the maximum claim of each process.
+    ///
+    /// # Panics
+    /// If a claim does not have one entry per resource or is more than the total.
+    pub fn new(total: Vec<u32>, maximum: impl IntoIterator<Item = (u32, Vec<u32>)>) -> Self {
+        let claims = maximum
+            .into_iter()
+            .map(|(pid, maximum)| {
+                assert_eq!(maximum.len(), total.len(), "Process {pid} has the wrong number of resources.");
+                assert!(fits(&maximum, &total), "Process {pid} claims more than there is.");
+                let allocation = vec![0; total.len()];
+                (pid, Claim { maximum, allocation })
+            })
+            .collect();
+        Self {
+            available: total,
+            claims
+        }
+    }
+    /// Requests resources for a process, this is only granted if
+    /// the system is still safe afterwards.
+    ///
+    /// # Panics
+    /// If the process is not known to the banker.
+    pub fn request(&mut self, pid: u32, amounts: &[u32]) -> Result<Allocation, Denied> {
+        let claim = self.claim(pid);
+        if !fits(amounts, &claim.need()) {
+            return Err(Denied::ExceedsClaim);
+        }
+        if !fits(amounts, &self.available) {
+            return Err(Denied::Unavailable);
+        }
+
+        // Pretend to grant it and back out if that is unsafe.
+        self.shift(pid, amounts, true);
+        match self.safe_sequence() {
+            Some(sequence) => Ok(Allocation {
+                held: self.claims[&pid].allocation.clone(),
+                sequence
+            }),
+            None => {
+                self.shift(pid, amounts, false);
+                Err(Denied::Unsafe)
+            }
+        }
+    }
+    /// Gives resources back from a process.
+    ///
+    /// # Panics
+    /// If the process is unknown or is giving back more than it holds.
+    pub fn release(&mut self, pid: u32, amounts: &[u32]) {
+        assert!(
+            fits(amounts, &self.claim(pid).allocation),
+            "Process {pid} is releasing more than it holds."
+        );
+        self.shift(pid, amounts, false);
+    }
+    /// The order all the processes can finish in, or none if
+    /// the system is unsafe. Earlier processes go first when
+    /// more than one could run.
+    pub fn safe_sequence(&self) -> Option<Vec<u32>> {
+        let mut work = self.available.clone();
+        let mut sequence = vec![];
+        while sequence.len() < self.claims.len() {
+            let (&pid, claim) = self
+                .claims
+                .iter()
+                .find(|(pid, claim)| !sequence.contains(*pid) && fits(&claim.need(), &work))?;
+            // It finishes and gives everything back.
+            for (work, held) in work.iter_mut().zip(&claim.allocation) {
+                *work += held;
+            }
+            sequence.push(pid);
+        }
+        Some(sequence)
+    }
+    /// What is free right now.
+    pub fn available(&self) -> &[u32] {
+        &self.available
+    }
+    /// What a process is holding.
+    pub fn allocation(&self, pid: u32) -> Option<&[u32]> {
+        self.claims.get(&pid).map(|f| f.allocation.as_slice())
+    }
+    /// What a process could still ask for.
+    pub fn need(&self, pid: u32) -> Option<Vec<u32>> {
+        self.claims.get(&pid).map(Claim::need)
+    }
+    fn claim(&self, pid: u32) -> &Claim {
+        self.claims
+            .get(&pid)
+            .unwrap_or_else(|| panic!("Process {pid} is not known to the banker."))
+    }
+    /// Moves resources to the process, or back from it.
+    fn shift(&mut self, pid: u32, amounts: &[u32], grant: bool) {
+        let claim = self.claims.get_mut(&pid).unwrap();
+        for ((available, held), amount) in self.available.iter_mut().zip(&mut claim.allocation).zip(amounts) {
+            if grant {
+                *available -= amount;
+                *held += amount;
+            } else {
+                *available += amount;
+                *held -= amount;
+            }
+        }
+    }
+}
+
+/// If every amount is at most the limit.
+fn fits(amounts: &[u32], limit: &[u32]) -> bool {
+    amounts.len() == limit.len() && amounts.iter().zip(limit).all(|(a, l)| a <= l)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Banker, Denied};
+
+    #[test]
+    pub fn test_banker_textbook() {
+        let mut banker = Banker::new(
+            vec![10, 5, 7],
+            [
+                (0, vec![7, 5, 3]),
+                (1, vec![3, 2, 2]),
+                (2, vec![9, 0, 2]),
+                (3, vec![2, 2, 2]),
+                (4, vec![4, 3, 3]),
+            ],
+        );
+        for (pid, held) in [(0, [0, 1, 0]), (1, [2, 0, 0]), (2, [3, 0, 2]), (3, [2, 1, 1]), (4, [0, 0, 2])] {
+            banker.request(pid, &held).unwrap();
+        }
+        assert_eq!(banker.available(), [3, 3, 2]);
+        assert_eq!(banker.safe_sequence().unwrap(), [1, 3, 0, 2, 4]);
+
+        // P1 asking for (1, 0, 2) is fine.
+        let granted = banker.request(1, &[1, 0, 2]).unwrap();
+        assert_eq!(granted.held, [3, 0, 2]);
+        assert_eq!(granted.sequence, [1, 3, 0, 2, 4]);
+        assert_eq!(banker.available(), [2, 3, 0]);
+
+        // P4 has to wait, there isn't enough.
+        assert_eq!(banker.request(4, &[3, 3, 0]), Err(Denied::Unavailable));
+        // There is enough for P0 but nobody could finish afterwards.
+        assert_eq!(banker.request(0, &[0, 2, 0]), Err(Denied::Unsafe));
+        assert_eq!(banker.available(), [2, 3, 0]);
+        assert_eq!(banker.allocation(0).unwrap(), [0, 1, 0]);
+        assert_eq!(banker.request(3, &[1, 1, 1]), Err(Denied::ExceedsClaim));
+
+        // Once P1 finishes P0 can have it.
+        banker.release(1, &[3, 0, 2]);
+        assert!(banker.request(0, &[0, 2, 0]).is_ok());
+    }
+}
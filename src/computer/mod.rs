@@ -1,4 +1,7 @@
 pub mod process;
 pub mod processor;
 pub mod scheduler;
-pub mod multilevel;
\ No newline at end of file
+pub mod multilevel;
+pub mod resource;
+pub mod banker;
+pub mod smp;
\ No newline at end of file
@@ -1,6 +1,6 @@
 use std::collections::VecDeque;
 
-use super::{process::Process, scheduler::{ProcessRecord, Scheduler, SchedulerAlgorithm}};
+use super::{process::Process, scheduler::{run_length, Gantt, ProcessRecord, Scheduler, SchedulerAlgorithm}};
 
 
 /// A simple multilevel feedback queue.
@@ -10,6 +10,10 @@ use super::{process::Process, scheduler::{ProcessRecord, Scheduler, SchedulerAlg
 /// available queue. This is quite efficient and is a simple
 /// way to implement it.
 /// 
+/// The levels can use any policy. Processes move down a level when the round
+/// robin quantum runs out or when they get preempted on a preemptive level, and
+/// they carry their SRT estimate with them.
+/// 
 /// ```
 /// use osconcepts::computer::multilevel::MultilevelQueue;
 /// use osconcepts::computer::scheduler::SchedulerAlgorithm;
@@ -20,7 +24,16 @@ use super::{process::Process, scheduler::{ProcessRecord, Scheduler, SchedulerAlg
 /// ```
 #[derive(Default)]
 pub struct MultilevelQueue {
-    levels: VecDeque<Scheduler>
+    levels: VecDeque<Scheduler>,
+    /// If set, every process gets moved back to the top
+    /// level after this many ticks.
+    boost: Option<usize>,
+    /// The total number of ticks across all levels.
+    ticks: usize,
+    /// How many of those ticks were idle.
+    idle: usize,
+    /// The execution trace, what ran at each tick and on which level.
+    trace: Vec<(u128, Option<(usize, u32)>)>
 }
 
 impl MultilevelQueue {
@@ -34,25 +47,86 @@ impl MultilevelQueue {
         self.levels.push_back(Scheduler::new(level).with_feedback());
         self
     }
+    /// Periodically boosts every process back to the top level so the
+    /// processes that fell to the bottom cannot starve. This counts the
+    /// ticks done through [MultilevelQueue::tick_current], boosting
+    /// every zero ticks is the same as never boosting.
+    pub fn with_boost(mut self, every_n_ticks: usize) -> Self {
+        self.boost = (every_n_ticks > 0).then_some(every_n_ticks);
+        self
+    }
+    /// Ticks the current task, this counts towards the priority boost.
+    pub fn tick_current(&mut self) {
+        let time = self.ticks as u128;
+        if let Some((level, current)) = self.current_with_key() {
+            current.tick();
+            let entry = (time, Some((level, current.id)));
+            self.trace.push(entry);
+            self.advance();
+        }
+    }
+    /// Lets a tick pass with nothing running, this also
+    /// counts towards the priority boost.
+    pub fn idle_tick(&mut self) {
+        self.trace.push((self.ticks as u128, None));
+        self.idle += 1;
+        self.advance();
+    }
+    /// The fraction of the ticks the CPU was busy for.
+    pub fn utilization(&self) -> f32 {
+        (self.ticks - self.idle) as f32 / self.ticks.max(1) as f32
+    }
+    /// Counts a tick and boosts everything if it is time to.
+    fn advance(&mut self) {
+        self.ticks += 1;
+        if self.boost.is_some_and(|every| self.ticks.is_multiple_of(every)) {
+            self.boost_all();
+        }
+    }
+    /// The execution trace as a Gantt chart, each segment is the level and
+    /// process along with the span of ticks it ran for. This only covers
+    /// ticks done through [MultilevelQueue::tick_current] and
+    /// [MultilevelQueue::idle_tick].
+    pub fn gantt(&self) -> Gantt<Option<(usize, u32)>> {
+        run_length(&self.trace)
+    }
+    /// Moves every process on the lower levels back up to the top.
+    fn boost_all(&mut self) {
+        let mut boosted = vec![];
+        for level in self.levels.iter_mut().skip(1) {
+            boosted.extend(level.drain());
+        }
+        for record in boosted {
+            let bumped = self.levels[0].schedule_record(record);
+            self.demote(0, bumped);
+        }
+    }
     /// Schedules a new task into the topmost queue.
     /// 
     /// # Panics
     /// This will panic if the queue has no levels.
-    pub fn schedule(&mut self, mut process: Process) {
-        let mut point = 0;
-
-        // Keep shifting things down the queue until the queue is good.
-        while let Some(inner) = self.levels[point].schedule(process) {
-            process = inner.proc;
+    pub fn schedule(&mut self, process: Process) {
+        let bumped = self.levels[0].schedule(process);
+        self.demote(0, bumped);
+    }
+    /// Takes a record bumped off a level and moves it down, this keeps
+    /// shifting things down the queue until the queue is good. Anything bumped
+    /// off the last level just goes back into the last level.
+    fn demote(&mut self, mut point: usize, mut bumped: Option<ProcessRecord>) {
+        while let Some(record) = bumped {
             if point != self.levels.len() - 1 {
                 point += 1;
             }
-          
+            bumped = self.levels[point].schedule_record(record);
         }
-
-        // let resul = self.levels[0].schedule(process);
-
-        // println!("Result: {:?}", resul);
+    }
+    /// The schedulers making up each level, from the top down.
+    pub fn levels(&self) -> impl Iterator<Item = &Scheduler> {
+        self.levels.iter()
+    }
+    /// Removes a process from whatever level it is on.
+    pub fn remove(&mut self, pid: u32) -> Option<ProcessRecord> {
+        self.levels.iter_mut().find_map(|level| level.remove(pid))
     }
     /// Gets the current scheduled task.
     /// 
@@ -76,16 +150,9 @@ impl MultilevelQueue {
         // }
 
         for level in 0..self.levels.len() {
-            if level < self.levels.len() - 1 {
-                if let Some(bumped) = self.levels[level].fetch_current().1 {
-                    self.levels[level + 1].schedule(bumped.proc);
-                }
-            } else {
-                // Last level, just put it back in.
-                if let Some(bumped) = self.levels[level].fetch_current().1 {
-                    self.levels[level].schedule(bumped.proc);
-                }
-            }
+            // Anything bumped from the last level just goes back into it.
+            let bumped = self.levels[level].fetch_current().1;
+            self.demote(level, bumped);
         }
 
 
@@ -128,6 +195,9 @@ mod tests {
         assert_eq!(proc.id, 0);
         assert_eq!(level, 1);
 
+        // It should have a fresh quantum on the new level.
+        assert_eq!(queue.current_unchecked().remaining_quantum(), 4);
+
         // Now we tick it.
         queue.current_unchecked().tick_n(4);
         let (level, proc) = queue.current_with_key().unwrap();
@@ -139,6 +209,126 @@ mod tests {
         assert!(queue.current().is_none());
     }
 
+    #[test]
+    pub fn test_multilevel_boost() {
+        // Returns how many ticks a long process got while short ones keep arriving.
+        let run = |mut queue: MultilevelQueue| {
+            queue.schedule(Process::full(0, 100, OpCode::Inert));
+            for _ in 0..4 {
+                queue.tick_current();
+            }
+            assert_eq!(queue.current_with_key().unwrap().0, 2);
+
+            let mut ran = 0;
+            for i in 1..20 {
+                queue.schedule(Process::full(i, 2, OpCode::Inert));
+                for _ in 0..2 {
+                    if queue.current_unchecked().id == 0 {
+                        ran += 1;
+                    }
+                    queue.tick_current();
+                }
+            }
+            ran
+        };
+        let levels = || MultilevelQueue::new()
+            .with_level(SchedulerAlgorithm::RoundRobin(2))
+            .with_level(SchedulerAlgorithm::RoundRobin(2))
+            .with_level(SchedulerAlgorithm::FirstComeFirstServe);
+
+        // Without the boost the bottom level starves.
+        assert_eq!(run(levels()), 0);
+        assert_eq!(run(levels().with_boost(0)), 0);
+        assert!(run(levels().with_boost(10)) > 0);
+    }
+
+    #[test]
+    pub fn test_multilevel_gantt() {
+        let mut queue = MultilevelQueue::new()
+            .with_level(SchedulerAlgorithm::RoundRobin(2))
+            .with_level(SchedulerAlgorithm::FirstComeFirstServe);
+        queue.schedule(Process::full(0, 4, OpCode::Inert));
+        queue.schedule(Process::full(1, 3, OpCode::Inert));
+        for _ in 0..7 {
+            queue.tick_current();
+        }
+        assert_eq!(queue.gantt(), [
+            (Some((0, 0)), 0..2),
+            (Some((0, 1)), 2..4),
+            (Some((1, 0)), 4..6),
+            (Some((1, 1)), 6..7),
+        ]);
+    }
+
+    #[test]
+    pub fn test_multilevel_idle() {
+        let mut queue = MultilevelQueue::new()
+            .with_level(SchedulerAlgorithm::RoundRobin(2))
+            .with_level(SchedulerAlgorithm::FirstComeFirstServe);
+        queue.schedule(Process::full(0, 3, OpCode::Inert));
+        for _ in 0..3 {
+            queue.tick_current();
+        }
+        assert!(queue.current().is_none());
+        for _ in 0..4 {
+            queue.idle_tick();
+        }
+        queue.schedule(Process::full(1, 3, OpCode::Inert));
+        for _ in 0..3 {
+            queue.tick_current();
+        }
+        assert_eq!(queue.utilization(), 0.6);
+        assert_eq!(queue.gantt()[2], (None, 3..7));
+    }
+
+    #[test]
+    pub fn test_multilevel_remove() {
+        let mut queue = MultilevelQueue::new()
+            .with_level(SchedulerAlgorithm::RoundRobin(2))
+            .with_level(SchedulerAlgorithm::FirstComeFirstServe);
+
+        // Push the first process down a level.
+        queue.schedule(Process::full(0, 8, OpCode::Inert));
+        queue.current_unchecked().tick_n(2);
+        queue.schedule(Process::full(1, 8, OpCode::Inert));
+        assert_eq!(queue.current_with_key().unwrap().1.id, 1);
+
+        // The snapshot shows where each process lives.
+        let levels: Vec<_> = queue.levels().map(|f| f.scheduled().unwrap().id).collect();
+        assert_eq!(levels, [1, 0]);
+
+        // Remove the one on the lower level.
+        assert_eq!(queue.remove(0).unwrap().id, 0);
+        assert!(queue.remove(0).is_none());
+        assert_eq!(queue.remove(1).unwrap().id, 1);
+        assert!(queue.current().is_none());
+    }
+
+    #[test]
+    pub fn test_multilevel_priority_level() {
+        let mut queue = MultilevelQueue::new()
+            .with_level(SchedulerAlgorithm::PreemptivePriority)
+            .with_level(SchedulerAlgorithm::RoundRobin(4))
+            .with_level(SchedulerAlgorithm::FirstComeFirstServe);
+
+        queue.schedule(Process::full(0, 8, OpCode::Inert).with_prioirty(5));
+        queue.current_unchecked().tick();
+
+        // The more important process preempts the first, which gets bumped down.
+        queue.schedule(Process::full(1, 2, OpCode::Inert).with_prioirty(1));
+        let levels: Vec<_> = queue.levels().map(|f| f.scheduled().map(|f| f.id)).collect();
+        assert_eq!(levels, [Some(1), Some(0), None]);
+        assert_eq!(queue.current_with_key().unwrap().0, 0);
+        queue.current_unchecked().tick_n(2);
+
+        // The preempted process resumes on level one with a fresh quantum.
+        let (level, proc) = queue.current_with_key().unwrap();
+        assert_eq!((level, proc.id), (1, 0));
+        assert_eq!(proc.remaining_quantum(), 4);
+        queue.current_unchecked().tick_n(4);
+        assert_eq!(queue.current_with_key().unwrap().0, 2);
+    }
+
     #[test]
     pub fn test_multilevel_preemptive() {
         // Form the multi-level feedback quuee.
@@ -1,6 +1,9 @@
-use rand::random;
+use std::{collections::HashMap, fmt::Debug};
 
+use crate::rng::SimRng;
 
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ProcessState {
     New,
     Ready,
@@ -21,14 +24,95 @@ pub struct Process
     pub static_time_units: usize,
 
     pub code: OpCode,
-    /// In actual operating systems this tends to be a mask
-    pub affinity: i32
+    /// The cores the process is allowed to run on.
+    pub affinity: AffinityMask,
+    /// How many lottery tickets the process holds.
+    pub tickets: u32,
+    /// When the process arrives, this is used for replaying traces.
+    pub arrival_time: u128,
+    /// The PID of the process that forked this one.
+    pub parent: Option<u32>,
+    /// The group the process belongs to, for fair share scheduling.
+    pub group: u32,
+    /// If set, this is used instead of the round robin quantum.
+    pub quantum_override: Option<usize>,
+    /// Scratch space for the payload, this is kept between ticks.
+    pub scratch: HashMap<String, i64>
+}
+
+/// The set of cores a process is allowed to run on, each
+/// bit of the mask is a core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AffinityMask(u64);
+
+impl AffinityMask {
+    /// Allows every core.
+    pub fn any() -> Self {
+        Self(u64::MAX)
+    }
+    /// Allows no cores, this is meant to be built on with [AffinityMask::allow_core].
+    pub fn none() -> Self {
+        Self(0)
+    }
+    /// Allows only a single core.
+    pub fn core(core: usize) -> Self {
+        Self::none().allow_core(core)
+    }
+    /// Allows another core.
+    /// 
+    /// # Panics
+    /// If the core is 64 or higher.
+    pub fn allow_core(mut self, core: usize) -> Self {
+        assert!(core < 64, "The mask only covers 64 cores.");
+        self.0 |= 1 << core;
+        self
+    }
+    /// Checks if the process may run on the core.
+    pub fn allows(&self, core: usize) -> bool {
+        core < 64 && self.0 & (1 << core) != 0
+    }
+    /// Checks if every core is allowed.
+    pub fn is_any(&self) -> bool {
+        self.0 == u64::MAX
+    }
+}
+
+impl Default for AffinityMask {
+    fn default() -> Self {
+        Self::any()
+    }
+}
+
+/// What the payload of a process gets to see each tick.
+pub struct ProcessCtx<'a> {
+    /// How many time units are left, including this one.
+    pub time_units: usize,
+    /// Scratch space that is kept between ticks.
+    pub scratch: &'a mut HashMap<String, i64>
 }
 
-#[derive(Debug, PartialEq)]
 pub enum OpCode {
     Shutdown,
-    Inert
+    Inert,
+    /// Runs the payload once per tick.
+    Run(Box<dyn FnMut(&mut ProcessCtx) + Send>)
+}
+
+impl Debug for OpCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Shutdown => write!(f, "Shutdown"),
+            Self::Inert => write!(f, "Inert"),
+            Self::Run(_) => write!(f, "Run(..)")
+        }
+    }
+}
+
+impl PartialEq for OpCode {
+    /// Payloads cannot be compared so they are never equal.
+    fn eq(&self, other: &Self) -> bool {
+        matches!((self, other), (Self::Shutdown, Self::Shutdown) | (Self::Inert, Self::Inert))
+    }
 }
 
 impl Process
@@ -40,17 +124,29 @@ impl Process
             time_units: 0,
             static_time_units: 0,
             code: OpCode::Inert,
-            affinity: -1
+            affinity: AffinityMask::any(),
+            tickets: 1,
+            arrival_time: 0,
+            parent: None,
+            group: 0,
+            quantum_override: None,
+            scratch: HashMap::new()
         }
     }
     pub fn new(time: usize) -> Self {
         Self {
-            id: random(),
+            id: SimRng::global().gen(),
             priority: 0,
             time_units: time,
             static_time_units: time,
             code: OpCode::Inert,
-            affinity: -1
+            affinity: AffinityMask::any(),
+            tickets: 1,
+            arrival_time: 0,
+            parent: None,
+            group: 0,
+            quantum_override: None,
+            scratch: HashMap::new()
         }
     }
     pub fn full(id: u32, time: usize, code: OpCode) -> Self {
@@ -60,26 +156,91 @@ impl Process
             static_time_units: time,
             time_units: time,
             code,
-            affinity: -1
+            affinity: AffinityMask::any(),
+            tickets: 1,
+            arrival_time: 0,
+            parent: None,
+            group: 0,
+            quantum_override: None,
+            scratch: HashMap::new()
         }
     }
     pub fn shutdown() -> Self {
         Self {
-            id: random(),
+            id: SimRng::global().gen(),
             priority: 0,
             time_units: 0,
             static_time_units: 0,
             code: OpCode::Shutdown,
-            affinity: -1
+            affinity: AffinityMask::any(),
+            tickets: 1,
+            arrival_time: 0,
+            parent: None,
+            group: 0,
+            quantum_override: None,
+            scratch: HashMap::new()
+        }
+    }
+    /// Forks the process, the child gets a fresh PID but inherits
+    /// everything else and records this process as its parent.
+    pub fn fork(&self) -> Self {
+        Self {
+            id: SimRng::global().gen(),
+            priority: self.priority,
+            time_units: self.time_units,
+            static_time_units: self.static_time_units,
+            code: OpCode::Inert,
+            affinity: self.affinity,
+            tickets: self.tickets,
+            arrival_time: self.arrival_time,
+            parent: Some(self.id),
+            group: self.group,
+            quantum_override: self.quantum_override,
+            scratch: HashMap::new()
+        }
+    }
+    /// Runs the process for a single time unit, this
+    /// calls the payload if there is one.
+    pub fn tick(&mut self) {
+        if self.time_units == 0 {
+            return;
+        }
+        if let OpCode::Run(payload) = &mut self.code {
+            payload(&mut ProcessCtx {
+                time_units: self.time_units,
+                scratch: &mut self.scratch
+            });
         }
+        self.time_units -= 1;
     }
+    /// Pins the process to a single core.
     pub fn with_affinity(mut self, affinity: u32) -> Self {
-        self.affinity = affinity as i32;
+        self.affinity = AffinityMask::core(affinity as usize);
+        self
+    }
+    pub fn with_affinity_mask(mut self, mask: AffinityMask) -> Self {
+        self.affinity = mask;
         self
     }
     pub fn with_prioirty(mut self, priority: i32) -> Self {
         self.priority = priority;
         self
     }
+    pub fn with_tickets(mut self, tickets: u32) -> Self {
+        self.tickets = tickets;
+        self
+    }
+    pub fn with_quantum(mut self, quantum: usize) -> Self {
+        self.quantum_override = Some(quantum);
+        self
+    }
+    pub fn with_group(mut self, group: u32) -> Self {
+        self.group = group;
+        self
+    }
+    pub fn with_arrival(mut self, time: u128) -> Self {
+        self.arrival_time = time;
+        self
+    }
 }
 
@@ -0,0 +1,89 @@
+use std::collections::VecDeque;
+
+/// A resource that can only be held by one process at a time, such
+/// as a lock. Processes that want it while it is held wait in line.
+#[derive(Debug, Default)]
+pub struct Resource {
+    /// The process currently holding the resource.
+    holder: Option<u32>,
+    /// The processes waiting on the resource in the order they asked.
+    waiters: VecDeque<u32>
+}
+
+impl Resource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Tries to acquire the resource for a process. If it is already held
+    /// by another process this one is put in line and this returns false.
+    pub fn acquire(&mut self, pid: u32) -> bool {
+        match self.holder {
+            None => {
+                self.holder = Some(pid);
+                true
+            }
+            Some(holder) if holder == pid => true,
+            Some(_) => {
+                if !self.waiters.contains(&pid) {
+                    self.waiters.push_back(pid);
+                }
+                false
+            }
+        }
+    }
+    /// Releases the resource, handing it straight to the next process
+    /// in line. This returns the process that now holds it.
+    /// 
+    /// # Panics
+    /// If the process does not hold the resource.
+    pub fn release(&mut self, pid: u32) -> Option<u32> {
+        assert_eq!(self.holder, Some(pid), "Process {pid} does not hold the resource.");
+        self.holder = self.waiters.pop_front();
+        self.holder
+    }
+    /// Takes a process that is going away out of line, if it was holding
+    /// the resource it goes to the next process the same as a release. This
+    /// returns the process that was handed the resource, if any.
+    pub fn abandon(&mut self, pid: u32) -> Option<u32> {
+        self.waiters.retain(|f| *f != pid);
+        match self.holder == Some(pid) {
+            true => self.release(pid),
+            false => None
+        }
+    }
+    /// The process currently holding the resource.
+    pub fn holder(&self) -> Option<u32> {
+        self.holder
+    }
+    /// The processes waiting on the resource.
+    pub fn waiters(&self) -> impl Iterator<Item = &u32> {
+        self.waiters.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Resource;
+
+    #[test]
+    pub fn test_resource() {
+        let mut resource = Resource::new();
+        assert!(resource.acquire(0));
+        assert!(!resource.acquire(1));
+        assert!(!resource.acquire(2));
+        assert_eq!(resource.waiters().count(), 2);
+
+        // Releasing hands it over in order.
+        assert_eq!(resource.release(0), Some(1));
+        assert_eq!(resource.release(1), Some(2));
+        assert_eq!(resource.release(2), None);
+        assert!(resource.acquire(3));
+
+        // A waiter that goes away loses its place and a holder gives it up.
+        assert!(!resource.acquire(4));
+        assert!(!resource.acquire(5));
+        assert_eq!(resource.abandon(4), None);
+        assert_eq!(resource.abandon(3), Some(5));
+        assert_eq!(resource.waiters().count(), 0);
+    }
+}
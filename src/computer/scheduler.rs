@@ -1,14 +1,24 @@
 //! Implementation of a basic CPU scheduler.
 
 use std::{
-    collections::{HashMap, VecDeque}, ops::{Deref, DerefMut}
+    cmp::Ordering, collections::{HashMap, HashSet, VecDeque}, ops::{Deref, DerefMut, Range}
 };
 
-use super::process::Process;
+
+use crate::rng::SimRng;
+
+use super::{process::{Process, ProcessState}, resource::Resource};
 
 const INITIAL_TAU: f32 = 10.0;
 
-#[derive(Debug, PartialEq)]
+/// How many of the last burst lengths are kept for each process.
+const BURST_HISTORY: usize = 8;
+
+/// How much the virtual runtime grows per nice level, each level
+/// is worth roughly ten percent of CPU time like in Linux.
+const NICE_FACTOR: f32 = 1.25;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SchedulerAlgorithm {
     /// First come first serve algorithm.
     FirstComeFirstServe,
@@ -24,6 +34,24 @@ pub enum SchedulerAlgorithm {
     /// This is a preemptive scheduling algorithm
     /// that schedules the shortest job next.
     ShortestRemainingTime(f32),
+    /// Non-preemptive shortest job first, picks the
+    /// process with the shortest total burst time.
+    ShortestJobFirst,
+    /// Lottery scheduling, each process holds a number of
+    /// tickets and the winner of a random draw gets to run.
+    Lottery,
+    /// Highest response ratio next, this is non-preemptive and picks
+    /// the process with the largest (waiting + service) / service.
+    HighestResponseRatio,
+    /// Approximates the completely fair scheduler, the process with
+    /// the smallest virtual runtime is picked and the running process
+    /// gets preempted once it is ahead of another by the granularity.
+    Fair(f32),
+    /// Fair share scheduling, this round robins between the groups
+    /// with the given quantum and then picks the first come first serve
+    /// within the group. Each group gets the same share no matter how
+    /// many processes are in it.
+    FairShare(usize),
 }
 
 #[derive(Debug)]
@@ -35,8 +63,24 @@ pub struct ProcessRecord {
     /// under round robin.
     lifetime: i32,
 
-    /// Estimated remaining time, this is for SRT.
-    estimated_remaining_time: f32,
+    /// The burst estimate, this is for SRT and gets carried
+    /// with the record when it moves between schedulers.
+    tau: f32,
+
+    /// The simulated time the process arrived at.
+    arrival_time: u128,
+
+    /// The simulated time the process first got the CPU.
+    first_run: Option<u128>,
+
+    /// How many ticks the process has spent waiting in the queue.
+    waiting_time: u128,
+
+    /// How many ticks the process has actually run for.
+    elapsed: usize,
+
+    /// The virtual runtime, this is for the fair scheduler.
+    vruntime: f32,
 
     /// The actual process.
     pub proc: Process,
@@ -44,13 +88,22 @@ pub struct ProcessRecord {
 
 impl ProcessRecord {
     pub fn tick(&mut self) {
+        self.elapsed += 1;
+        // Processes with a lower priority accumulate virtual runtime faster.
+        self.vruntime += NICE_FACTOR.powi(self.proc.priority);
         if self.lifetime > 0 {
             self.lifetime -= 1;
         }
-        if self.proc.time_units > 0 {
-            self.proc.time_units -= 1;
-        }
-        self.estimated_remaining_time -= 1.0;
+        self.proc.tick();
+    }
+    /// The estimated time remaining, this is the burst estimate
+    /// minus the work the process has already done.
+    pub fn remaining_estimate(&self) -> f32 {
+        self.tau - self.elapsed as f32
+    }
+    /// How many ticks are left in the round robin quantum.
+    pub fn remaining_quantum(&self) -> i32 {
+        self.lifetime
     }
     pub fn tick_n(&mut self, n: usize) {
         for _ in 0..n {
@@ -72,6 +125,54 @@ impl DerefMut for ProcessRecord {
     }
 }
 
+/// The timing information of a single process that
+/// ran to completion on the [Scheduler].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessStats {
+    /// The process PID.
+    pub id: u32,
+    /// When the process was scheduled.
+    pub arrival: u128,
+    /// When the process first got the CPU.
+    pub first_run: u128,
+    /// When the process finished.
+    pub completion: u128,
+    /// How long the process sat in the ready queue.
+    pub waiting: u128,
+    /// The time from arrival until completion.
+    pub turnaround: u128,
+}
+
+/// Statistics collected by the [Scheduler] over all the
+/// completed processes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchedulerStats {
+    /// Stats for each process in the order they completed.
+    pub processes: Vec<ProcessStats>,
+    /// The average waiting time.
+    pub average_waiting: f32,
+    /// The average turnaround time.
+    pub average_turnaround: f32,
+    /// The fraction of the ticks the CPU was busy for.
+    pub utilization: f32,
+}
+
+/// A Gantt chart, each segment is what ran along with the span of ticks.
+pub type Gantt<T> = Vec<(T, Range<u128>)>;
+
+/// Run-length encodes an execution trace into segments of
+/// consecutive ticks spent on the same thing.
+pub(crate) fn run_length<T: PartialEq + Copy>(trace: &[(u128, T)]) -> Gantt<T> {
+    let mut segments: Vec<(T, Range<u128>)> = vec![];
+    for (time, entry) in trace {
+        match segments.last_mut() {
+            Some((last, span)) if *last == *entry && span.end == *time => span.end += 1,
+            _ => segments.push((*entry, *time..*time + 1)),
+        }
+    }
+    segments
+}
+
 pub struct Normal;
 pub struct Feedback;
 
@@ -99,8 +200,65 @@ pub struct Scheduler {
     /// will be intialized to an initial value and updated
     /// upon getting more information during runs.
     srt_time_table: HashMap<u32, f32>,
+    /// The estimate used for processes that are not in the table.
+    initial_tau: f32,
+    /// The last few actual burst lengths of each process.
+    bursts: HashMap<u32, VecDeque<usize>>,
+
+    /// If set, queued processes have their priority improved
+    /// by one for every `aging` clock ticks they spend waiting.
+    aging: Option<u32>,
+
+    /// The simulated time, this advances every time the
+    /// scheduled process is ticked.
+    time: u128,
 
+    /// How many ticks of the scheduled process we have
+    /// already accounted for.
+    observed: usize,
 
+    /// The stats of every process that has completed.
+    completed: Vec<ProcessStats>,
+
+    /// The random number generator used for lottery draws.
+    rng: SimRng,
+
+    /// Processes blocked on IO, along with how many more
+    /// calls to [Scheduler::advance_io] until they wake.
+    blocked: Vec<(usize, ProcessRecord)>,
+
+    /// How many ticks a context switch costs.
+    switch_cost: usize,
+
+    /// How many context switches have happened.
+    context_switches: usize,
+
+    /// The PID of the last process to get the CPU.
+    last_pid: Option<u32>,
+
+    /// The resources processes can hold, by their ID.
+    resources: HashMap<u32, Resource>,
+
+    /// Processes waiting to acquire a resource.
+    waiting: Vec<ProcessRecord>,
+
+    /// Whether processes holding a resource inherit the
+    /// priority of the processes waiting on it.
+    inheritance: bool,
+
+    /// The execution trace, what ran at each tick.
+    trace: Vec<(u128, Option<u32>)>,
+
+    /// Parents that are waiting on their children to complete.
+    parents_waiting: HashSet<u32>,
+
+    /// How many ticks the CPU spent idle.
+    idle: u128,
+
+    /// The last group that got the CPU, for fair share.
+    last_group: Option<u32>,
+    /// How many ticks each group has run for.
+    group_usage: HashMap<u32, u128>,
 }
 
 
@@ -112,47 +270,338 @@ impl Scheduler {
             scheduled: None,
             queue: VecDeque::default(),
             srt_time_table: HashMap::new(),
+            initial_tau: INITIAL_TAU,
+            bursts: HashMap::new(),
             feedback: false,
             clock: 0,
-
+            aging: None,
+            time: 0,
+            observed: 0,
+            completed: Vec::new(),
+            rng: SimRng::global(),
+            blocked: Vec::new(),
+            switch_cost: 0,
+            context_switches: 0,
+            last_pid: None,
+            resources: HashMap::new(),
+            waiting: Vec::new(),
+            inheritance: false,
+            trace: Vec::new(),
+            parents_waiting: HashSet::new(),
+            idle: 0,
+            last_group: None,
+            group_usage: HashMap::new(),
         }
     }
     pub fn with_feedback(mut self) -> Self {
         self.feedback = true;
         self
     }
+    /// Sets how many ticks of overhead each context switch costs.
+    pub fn with_context_switch_cost(mut self, ticks: usize) -> Self {
+        self.switch_cost = ticks;
+        self
+    }
+    /// The number of context switches so far.
+    pub fn context_switches(&self) -> usize {
+        self.context_switches
+    }
+    /// The total number of ticks spent context switching.
+    pub fn overhead_ticks(&self) -> usize {
+        self.context_switches * self.switch_cost
+    }
+    /// How many ticks each process group has run for.
+    pub fn group_usage(&self) -> &HashMap<u32, u128> {
+        &self.group_usage
+    }
+    /// Seeds the random number generator so that lottery
+    /// draws are reproducible.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = SimRng::from_seed(seed);
+        self
+    }
+    /// Draws from the given generator, this can be shared with the
+    /// rest of the simulation.
+    pub fn with_rng(mut self, rng: SimRng) -> Self {
+        self.rng = rng;
+        self
+    }
+    /// Sets the burst estimate used for processes the scheduler has not seen yet.
+    pub fn with_initial_tau(mut self, tau: f32) -> Self {
+        self.initial_tau = tau;
+        self
+    }
+    /// Primes the burst estimate for a process.
+    pub fn set_tau(&mut self, pid: u32, tau: f32) {
+        self.srt_time_table.insert(pid, tau);
+    }
+    /// The current burst estimate for a process.
+    pub fn tau(&self, pid: u32) -> f32 {
+        self.srt_time_table.get(&pid).copied().unwrap_or(self.initial_tau)
+    }
+    /// The last few actual burst lengths of a process, oldest first.
+    pub fn burst_history(&self, pid: u32) -> impl Iterator<Item = usize> + '_ {
+        self.bursts.get(&pid).into_iter().flatten().copied()
+    }
+    /// Turns on aging for the priority algorithms. For every `rate` ticks
+    /// of the insertion clock that a process spends waiting in the queue its
+    /// effective priority improves by one, so low priority processes cannot
+    /// starve forever.
+    /// 
+    /// # Panics
+    /// If the rate is zero.
+    pub fn with_aging(mut self, rate: u32) -> Self {
+        assert!(rate > 0, "The aging rate must be non-zero.");
+        self.aging = Some(rate);
+        self
+    }
+    /// Turns on priority inheritance, a process holding a resource
+    /// runs at the priority of the most important process waiting on it.
+    pub fn with_priority_inheritance(mut self) -> Self {
+        self.inheritance = true;
+        self
+    }
+    /// Calculates the priority of a queued record after aging
+    /// has been applied.
+    fn aged_priority(&self, record: &ProcessRecord) -> i32 {
+        let priority = self.inherited_priority(record);
+        match self.aging {
+            Some(rate) => {
+                let waited = (self.clock - record.schedule_time) / rate as u128;
+                priority.saturating_sub(waited.try_into().unwrap_or(i32::MAX))
+            }
+            None => priority,
+        }
+    }
+    /// Calculates the priority of a record after inheriting the priorities
+    /// of everything waiting on the resources it holds.
+    fn inherited_priority(&self, record: &ProcessRecord) -> i32 {
+        let mut priority = record.proc.priority;
+        if self.inheritance {
+            let waiters = self
+                .resources
+                .values()
+                .filter(|r| r.holder() == Some(record.id))
+                .flat_map(|r| r.waiters());
+            for waiter in waiters {
+                if let Some(waiter) = self.waiting.iter().find(|f| f.id == *waiter) {
+                    priority = priority.min(waiter.proc.priority);
+                }
+            }
+        }
+        priority
+    }
+    /// The priority a process is currently being scheduled at, this
+    /// includes any priority it has inherited.
+    pub fn effective_priority(&self, pid: u32) -> Option<i32> {
+        let record = self
+            .scheduled
+            .iter()
+            .chain(self.queue.iter())
+            .chain(self.blocked.iter().map(|(_, f)| f))
+            .chain(self.waiting.iter())
+            .find(|f| f.id == pid)?;
+        Some(self.inherited_priority(record))
+    }
+    /// The currently running process tries to acquire a resource. If it is
+    /// held by another process the running process waits for it and the next
+    /// process is scheduled, in which case this returns false.
+    pub fn acquire(&mut self, resource: u32) -> bool {
+        self.account();
+        let Some(current) = self.scheduled.as_ref() else {
+            return false;
+        };
+        if self.resources.entry(resource).or_default().acquire(current.id) {
+            true
+        } else {
+            let current = self.scheduled.take().unwrap();
+            self.waiting.push(current);
+            let next = self.next();
+            self.set_scheduled(next);
+            false
+        }
+    }
+    /// The currently running process releases a resource, handing
+    /// it over to the next process waiting on it.
+    /// 
+    /// # Panics
+    /// If the running process does not hold the resource.
+    pub fn release(&mut self, resource: u32) {
+        self.account();
+        let pid = self.scheduled.as_ref().expect("No process is running.").id;
+        let next = self
+            .resources
+            .get_mut(&resource)
+            .expect("The resource does not exist.")
+            .release(pid);
+        if let Some(next) = next {
+            self.wake_waiter(next);
+        }
+    }
+    /// Hands a resource over to a process that was waiting on it.
+    fn wake_waiter(&mut self, pid: u32) {
+        let index = self.waiting.iter().position(|f| f.id == pid).unwrap();
+        let record = self.waiting.remove(index);
+        self.schedule_inner(record);
+    }
+    /// Takes a process that finished or was killed out of line for every
+    /// resource and releases whatever it was holding.
+    fn abandon_resources(&mut self, pid: u32) {
+        let mut woken: Vec<_> = self.resources.values_mut().filter_map(|f| f.abandon(pid)).collect();
+        // Keep the handovers in the same order every time.
+        woken.sort();
+        for next in woken {
+            self.wake_waiter(next);
+        }
+    }
     /// Schedules a new process onto the scheduler.
     /// 
     /// If this is in feedback mode, whenever something
     /// gets preempted or moved off it will be bumped off
     /// and returned by this function.
     pub fn schedule(&mut self, process: Process) -> Option<ProcessRecord> {
+        self.account();
+        let tau = self.tau(process.id);
         self.schedule_inner(ProcessRecord {
             schedule_time: self.clock,
             lifetime: 0,
-            estimated_remaining_time: INITIAL_TAU,
+            tau,
+            arrival_time: self.time,
+            first_run: None,
+            waiting_time: 0,
+            elapsed: 0,
+            vruntime: self.min_vruntime(),
             proc: process,
         })
     }
+    /// Replays a workload trace, each process is scheduled once the simulated
+    /// time reaches its arrival time and the current process is ticked once
+    /// per step. This returns the PID that ran at each tick, with `None` being
+    /// an idle tick.
+    /// 
+    /// This is not meant to be used in feedback mode as bumped
+    /// processes are discarded.
+    pub fn run_trace(&mut self, mut procs: Vec<Process>, ticks: usize) -> Vec<Option<u32>> {
+        procs.sort_by_key(|p| p.arrival_time);
+        let mut pending = VecDeque::from(procs);
+
+        let mut trace = Vec::with_capacity(ticks);
+        for _ in 0..ticks {
+            // Retire whatever finished last tick before admitting new arrivals.
+            self.fetch_current();
+            while pending.front().is_some_and(|p| p.arrival_time <= self.time) {
+                self.schedule(pending.pop_front().unwrap());
+            }
+
+            match self.current() {
+                Some(current) => {
+                    trace.push(Some(current.id));
+                    current.tick();
+                }
+                None => {
+                    trace.push(None);
+                    self.idle_tick();
+                }
+            }
+        }
+        trace
+    }
+    /// The smallest virtual runtime out of all the processes, new processes
+    /// start here so they do not monopolize the CPU.
+    fn min_vruntime(&self) -> f32 {
+        self.scheduled
+            .iter()
+            .chain(self.queue.iter())
+            .map(|f| f.vruntime)
+            .min_by(f32::total_cmp)
+            .unwrap_or(0.0)
+    }
+    /// Checks if the scheduled process has run far enough ahead of
+    /// a queued one that it should give up the CPU.
+    fn vruntime_exceeded(&self) -> bool {
+        let SchedulerAlgorithm::Fair(granularity) = self.policy else {
+            return false;
+        };
+        let current = self.scheduled.as_ref().unwrap().vruntime;
+        self.queue.iter().any(|f| current > f.vruntime + granularity)
+    }
+    /// The execution trace as a Gantt chart, each segment is a process
+    /// and the span of time it ran for. Idle time shows up as `None`.
+    pub fn gantt(&mut self) -> Gantt<Option<u32>> {
+        self.account();
+        run_length(&self.trace)
+    }
+    /// Advances the simulated time by one tick with nothing running,
+    /// this is for modelling gaps where nothing is ready.
+    pub fn idle_tick(&mut self) {
+        self.account();
+        self.trace.push((self.time, None));
+        self.time += 1;
+        self.idle += 1;
+    }
+    /// Computes the waiting and turnaround times of all the
+    /// processes that have completed so far.
+    pub fn stats(&self) -> SchedulerStats {
+        let count = self.completed.len().max(1) as f32;
+
+        // Count the ticks the scheduled process did since we last accounted.
+        let pending = self.scheduled.as_ref().map_or(0, |f| f.elapsed.saturating_sub(self.observed));
+        let total = self.time + pending as u128;
+        SchedulerStats {
+            utilization: (total - self.idle) as f32 / total.max(1) as f32,
+            processes: self.completed.clone(),
+            average_waiting: self.completed.iter().map(|p| p.waiting as f32).sum::<f32>() / count,
+            average_turnaround: self.completed.iter().map(|p| p.turnaround as f32).sum::<f32>() / count,
+        }
+    }
+    /// Advances the simulated time by however many ticks the scheduled
+    /// process has run since we last looked at it, charging the same amount
+    /// of waiting time to everything in the queue.
+    fn account(&mut self) {
+        if let Some(current) = &self.scheduled {
+            let delta = current.elapsed.saturating_sub(self.observed);
+            self.observed = current.elapsed;
+            for tick in 0..delta as u128 {
+                self.trace.push((self.time + tick, Some(current.id)));
+            }
+            self.time += delta as u128;
+            *self.group_usage.entry(current.proc.group).or_default() += delta as u128;
+            for record in &mut self.queue {
+                record.waiting_time += delta as u128;
+            }
+        }
+    }
+    /// Schedules a record that was handed over from another scheduler, such
+    /// as when the [super::multilevel::MultilevelQueue] moves a process between
+    /// levels. The record keeps its accounting but has its state reset.
+    pub(crate) fn schedule_record(&mut self, mut record: ProcessRecord) -> Option<ProcessRecord> {
+        // Keep the estimate from the last scheduler if we have not seen this one.
+        self.srt_time_table.entry(record.id).or_insert(record.tau);
+        self.normalize(&mut record);
+        self.schedule_inner(record)
+    }
     /// Schedules a process record onto the scheduler.
     /// 
     /// If this is in feedback mode, whenever something
     /// gets preempted or moved off it will be bumped off
     /// and returned by this function.
     fn schedule_inner(&mut self, mut record: ProcessRecord) -> Option<ProcessRecord> {
-        if !self.srt_time_table.contains_key(&record.id) {
-            // If this is not in the table, store the default value.
-            self.srt_time_table.insert(record.id, INITIAL_TAU);
-        }
+        self.account();
+
+        // If this is not in the table, store the estimate the record has.
+        self.srt_time_table.entry(record.id).or_insert(record.tau);
 
         record.schedule_time = self.clock;
 
-        if self.scheduled.is_none() {
+        // A parent waiting on its children can only go in the queue.
+        let runnable = self.runnable(&record);
+        if self.scheduled.is_none() && runnable {
             // No current scheduled task, so just schedule it directly.
             self.set_scheduled_record(record);
         } else if self.policy == SchedulerAlgorithm::PreemptivePriority
+            && runnable
             && self.scheduled.is_some()
-            && record.proc.priority < self.scheduled.as_ref().unwrap().proc.priority
+            && self.inherited_priority(&record) < self.inherited_priority(self.scheduled.as_ref().unwrap())
         {
             let current=  self.scheduled.take();
            
@@ -169,9 +618,10 @@ impl Scheduler {
             }
 
         } else if matches!(self.policy, SchedulerAlgorithm::ShortestRemainingTime(_))
+            && runnable
             && self.scheduled.is_some()
             // Check if the incoming process has a shorter time than the current.
-            && self.scheduled.as_ref().unwrap().estimated_remaining_time > *self.srt_time_table.get(&record.id).unwrap()
+            && self.scheduled.as_ref().unwrap().remaining_estimate() > record.remaining_estimate()
         {
             let current = self.scheduled.take();
             
@@ -191,6 +641,130 @@ impl Scheduler {
         None
 
         
+    }
+    /// The currently scheduled process.
+    pub fn scheduled(&self) -> Option<&ProcessRecord> {
+        self.scheduled.as_ref()
+    }
+    /// The ready queue, in the order the processes would be
+    /// picked by the current policy. Lottery draws are random so
+    /// these are ordered by the number of tickets held.
+    pub fn ready_queue(&self) -> impl Iterator<Item = &ProcessRecord> {
+        let mut ready: Vec<&ProcessRecord> = self.queue.iter().filter(|f| self.runnable(f)).collect();
+        ready.sort_by(|a, b| self.order(a, b));
+        ready.into_iter()
+    }
+    /// The number of processes that are either running or ready.
+    pub fn len(&self) -> usize {
+        self.queue.len() + self.scheduled.iter().count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Takes every running and ready process out of the scheduler,
+    /// the running process comes first followed by the queue.
+    pub(crate) fn drain(&mut self) -> Vec<ProcessRecord> {
+        self.account();
+        self.scheduled.take().into_iter().chain(self.queue.drain(..)).collect()
+    }
+    /// Blocks the currently running process on IO, it will be put back
+    /// into the queue after `wake_after` calls to [Scheduler::advance_io].
+    /// The next process is scheduled in its place.
+    pub fn block_current(&mut self, wake_after: usize) {
+        self.account();
+        if let Some(current) = self.scheduled.take() {
+            self.blocked.push((wake_after, current));
+            let next = self.next();
+            self.set_scheduled(next);
+        }
+    }
+    /// Advances all the outstanding IO by one tick, waking
+    /// up any processes whose IO has completed.
+    pub fn advance_io(&mut self) {
+        let mut woken = vec![];
+        let mut i = 0;
+        while i < self.blocked.len() {
+            let countdown = &mut self.blocked[i].0;
+            *countdown = countdown.saturating_sub(1);
+            if *countdown == 0 {
+                woken.push(self.blocked.remove(i).1);
+            } else {
+                i += 1;
+            }
+        }
+        for record in woken {
+            self.schedule_inner(record);
+        }
+    }
+    /// Makes a parent wait on its children, it will not be picked to run
+    /// until all of its children have completed. If the parent is the one
+    /// running it gives up the CPU, without any children it just carries on.
+    pub fn wait(&mut self, parent: u32) {
+        if !self.has_children(parent) {
+            return;
+        }
+        self.account();
+        self.parents_waiting.insert(parent);
+        if self.scheduled.as_ref().is_some_and(|f| f.id == parent) {
+            let current = self.scheduled.take().unwrap();
+            let next = self.next();
+            self.set_scheduled(next);
+            // Straight into the queue, it can't run or preempt anything yet.
+            self.queue.push_back(current);
+        }
+    }
+    /// Checks if a process has any children that have not completed.
+    fn has_children(&self, parent: u32) -> bool {
+        self.scheduled
+            .iter()
+            .chain(self.queue.iter())
+            .chain(self.blocked.iter().map(|(_, f)| f))
+            .chain(self.waiting.iter())
+            .any(|f| f.proc.parent == Some(parent) && f.proc.time_units > 0)
+    }
+    /// Checks if a queued record can be picked to run, parents
+    /// waiting on their children cannot.
+    fn runnable(&self, record: &ProcessRecord) -> bool {
+        !self.parents_waiting.contains(&record.id) || !self.has_children(record.id)
+    }
+    /// Gets the state of a process in the scheduler.
+    pub fn state(&self, pid: u32) -> Option<ProcessState> {
+        if self.scheduled.as_ref().is_some_and(|f| f.id == pid) {
+            Some(ProcessState::Running)
+        } else if self.queue.iter().any(|f| f.id == pid) {
+            Some(ProcessState::Ready)
+        } else if self.blocked.iter().map(|(_, f)| f).chain(self.waiting.iter()).any(|f| f.id == pid) {
+            // Processes waiting on IO or on a resource are both blocked.
+            Some(ProcessState::Blocked)
+        } else {
+            None
+        }
+    }
+    /// Removes a process from the scheduler, this is how we model
+    /// killing a process. If the process being removed is the one that
+    /// is currently running the next one is scheduled in its place.
+    pub fn remove(&mut self, pid: u32) -> Option<ProcessRecord> {
+        self.account();
+        let removed = self.remove_record(pid);
+        if removed.is_some() {
+            self.abandon_resources(pid);
+        }
+        removed
+    }
+    fn remove_record(&mut self, pid: u32) -> Option<ProcessRecord> {
+        if self.scheduled.as_ref().is_some_and(|f| f.id == pid) {
+            let removed = self.scheduled.take();
+            let next = self.next();
+            self.set_scheduled(next);
+            removed
+        } else if let Some(index) = self.queue.iter().position(|f| f.id == pid) {
+            self.queue.remove(index)
+        } else if let Some(index) = self.blocked.iter().position(|(_, f)| f.id == pid) {
+            Some(self.blocked.remove(index).1)
+        } else {
+            let index = self.waiting.iter().position(|f| f.id == pid)?;
+            Some(self.waiting.remove(index))
+        }
     }
     fn set_scheduled(&mut self, record: Option<ProcessRecord>) {
         match record {
@@ -198,14 +772,38 @@ impl Scheduler {
             None => self.scheduled = None,
         }
     }
-    fn set_scheduled_record(&mut self, mut record: ProcessRecord) {
-        // Set the estimated remaining time. This is for shortest time remaining.
-        record.estimated_remaining_time = *self.srt_time_table.get(&record.id).unwrap();
+    /// Resets the scheduler specific state of a record, this has to be done
+    /// whenever a record is installed or comes in from another scheduler as
+    /// the old quantum and estimates no longer make any sense.
+    fn normalize(&self, record: &mut ProcessRecord) {
+        // Set the burst estimate. This is for shortest time remaining.
+        record.tau = self
+            .srt_time_table
+            .get(&record.id)
+            .copied()
+            .unwrap_or(record.tau);
 
+        record.lifetime = match self.policy {
+            SchedulerAlgorithm::RoundRobin(quantum) | SchedulerAlgorithm::FairShare(quantum) => {
+                // The process can ask for its own quantum.
+                record.proc.quantum_override.unwrap_or(quantum).try_into().unwrap()
+            }
+            _ => 0,
+        };
+    }
+    fn set_scheduled_record(&mut self, mut record: ProcessRecord) {
+        self.normalize(&mut record);
 
-        if let SchedulerAlgorithm::RoundRobin(quantum) = self.policy {
-            record.lifetime = quantum.try_into().unwrap();
+        // Resuming the same process is not a context switch.
+        if self.last_pid.is_some_and(|pid| pid != record.id) {
+            self.context_switches += 1;
         }
+        self.last_pid = Some(record.id);
+        self.last_group = Some(record.proc.group);
+
+        // Start accounting for the new process.
+        record.first_run.get_or_insert(self.time);
+        self.observed = record.elapsed;
         self.scheduled = Some(record);
     }
     pub fn current_unchecked(&mut self) -> &mut ProcessRecord {
@@ -218,10 +816,25 @@ impl Scheduler {
     /// are in round robin. This is for implementing multi-level feedback queues.
     pub fn fetch_current(&mut self) -> (Option<&mut ProcessRecord>, Option<ProcessRecord>) {
         let mut bumped = None;
+        self.account();
+        if self.scheduled.is_none() {
+            // A parent may have been left waiting with nothing else to run.
+            let next = self.next();
+            self.set_scheduled(next);
+        }
         if self.scheduled.is_some() {
             if self.scheduled.as_ref().unwrap().proc.time_units == 0 {
+                self.complete();
                 let next = self.next();
 
+                // Keep the actual burst length around.
+                let finished = self.scheduled.as_ref().unwrap();
+                let history = self.bursts.entry(finished.id).or_default();
+                if history.len() == BURST_HISTORY {
+                    history.pop_front();
+                }
+                history.push_back(finished.static_time_units);
+
                 // Update the shortest time remaining table.
                 if let SchedulerAlgorithm::ShortestRemainingTime(alpha) = self.policy {
                     // Update the prediction.
@@ -232,11 +845,15 @@ impl Scheduler {
                     *tau = (alpha * (self.scheduled.as_ref().unwrap().static_time_units as f32))
                         + ((1.0 - alpha) * (*tau));
                 }
+                let finished = self.scheduled.as_ref().unwrap().id;
                 self.set_scheduled(next);
-            } else if matches!(self.policy, SchedulerAlgorithm::RoundRobin(_))
-                && self.scheduled.as_ref().unwrap().lifetime <= 0
+                self.abandon_resources(finished);
+            } else if (matches!(self.policy, SchedulerAlgorithm::RoundRobin(_) | SchedulerAlgorithm::FairShare(_))
+                && self.scheduled.as_ref().unwrap().lifetime <= 0)
+                || self.vruntime_exceeded()
             {
-                // We are using round robin and the time quantum has expired.
+                // We are using round robin and the time quantum has expired,
+                // or the fair scheduler wants to give another process a turn.
                 let current = self.scheduled.take();
                 let next = self.next();
                 self.set_scheduled(next);
@@ -252,41 +869,125 @@ impl Scheduler {
         }
         (self.scheduled.as_mut(), bumped)
     }
-    fn next(&mut self) -> Option<ProcessRecord> {
+    /// Calculates the response ratio of a queued record, using
+    /// the insertion clock to measure how long it has waited.
+    fn response_ratio(&self, record: &ProcessRecord) -> f32 {
+        let waiting = (self.clock - record.schedule_time) as f32;
+        let service = record.proc.static_time_units.max(1) as f32;
+        (waiting + service) / service
+    }
+    /// Records the stats of the scheduled process as it finishes.
+    fn complete(&mut self) {
+        let record = self.scheduled.as_ref().unwrap();
+        self.completed.push(ProcessStats {
+            id: record.id,
+            arrival: record.arrival_time,
+            first_run: record.first_run.unwrap_or(self.time),
+            completion: self.time,
+            waiting: record.waiting_time,
+            turnaround: self.time - record.arrival_time,
+        });
+    }
+    /// Compares two queued records for the current policy, whichever
+    /// orders first is the one [Scheduler::next] would pick. Lottery draws
+    /// are random so they are just ordered by who holds the most tickets.
+    fn order(&self, a: &ProcessRecord, b: &ProcessRecord) -> Ordering {
         match self.policy {
             SchedulerAlgorithm::FirstComeFirstServe | SchedulerAlgorithm::RoundRobin(_) => {
-                let (index, _) = self
-                    .queue
-                    .iter_mut()
-                    .enumerate()
-                    .min_by_key(|(_, f)| f.schedule_time)?;
-                self.queue.remove(index)
+                a.schedule_time.cmp(&b.schedule_time)
             }
             SchedulerAlgorithm::Priority | SchedulerAlgorithm::PreemptivePriority => {
-                let (index, _) = self
-                    .queue
-                    .iter_mut()
-                    .enumerate()
-                    .min_by_key(|(_, f)| f.proc.priority)?;
-                self.queue.remove(index)
+                self.aged_priority(a).cmp(&self.aged_priority(b))
+            }
+            SchedulerAlgorithm::ShortestJobFirst => {
+                // Ties are broken by the order they were scheduled in.
+                (a.proc.static_time_units, a.schedule_time)
+                    .cmp(&(b.proc.static_time_units, b.schedule_time))
+            }
+            SchedulerAlgorithm::Lottery => b
+                .proc
+                .tickets
+                .cmp(&a.proc.tickets)
+                .then(a.schedule_time.cmp(&b.schedule_time)),
+            SchedulerAlgorithm::HighestResponseRatio => {
+                // Ties are broken by the order they were scheduled in.
+                self.response_ratio(b)
+                    .total_cmp(&self.response_ratio(a))
+                    .then(a.schedule_time.cmp(&b.schedule_time))
+            }
+            SchedulerAlgorithm::Fair(_) => a.vruntime.total_cmp(&b.vruntime),
+            SchedulerAlgorithm::FairShare(_) => {
+                // The groups after the last one to run go first, then we wrap around.
+                let turn = |f: &ProcessRecord| {
+                    let group = f.proc.group;
+                    (self.last_group.is_some_and(|last| group <= last), group, f.schedule_time)
+                };
+                turn(a).cmp(&turn(b))
             }
             SchedulerAlgorithm::ShortestRemainingTime(_) => {
-                // Note: tau is our estimated time remaining.
+                // Note: tau minus the elapsed time is our estimated time remaining.
                 // Therefore, we grab the process with the lowest remaining time.
-                let (index, _) = self.queue.iter_mut().enumerate().min_by(|(_, a), (_, b)| {
-                    a.estimated_remaining_time
-                        .total_cmp(&b.estimated_remaining_time)
-                })?;
-                self.queue.remove(index)
+                a.remaining_estimate()
+                    .total_cmp(&b.remaining_estimate())
             }
         }
     }
+    /// Draws a lottery ticket and finds the index of the winner in the queue.
+    fn draw_lottery(&mut self) -> Option<usize> {
+        let total: u64 = self
+            .queue
+            .iter()
+            .filter(|f| self.runnable(f))
+            .map(|f| f.proc.tickets as u64)
+            .sum();
+        if total == 0 {
+            // Nobody holds any tickets so just fall back to arrival order.
+            let (index, _) = self
+                .queue
+                .iter()
+                .enumerate()
+                .filter(|(_, f)| self.runnable(f))
+                .min_by_key(|(_, f)| f.schedule_time)?;
+            return Some(index);
+        }
+
+        // Draw the winning ticket and find who holds it.
+        let mut winner = self.rng.gen_range(0..total);
+        self.queue
+            .iter()
+            .position(|f| {
+                if !self.runnable(f) {
+                    false
+                } else if winner < f.proc.tickets as u64 {
+                    true
+                } else {
+                    winner -= f.proc.tickets as u64;
+                    false
+                }
+            })
+    }
+    fn next(&mut self) -> Option<ProcessRecord> {
+        let index = if self.policy == SchedulerAlgorithm::Lottery {
+            self.draw_lottery()?
+        } else {
+            self.queue
+                .iter()
+                .enumerate()
+                .filter(|(_, f)| self.runnable(f))
+                .min_by(|(_, a), (_, b)| self.order(a, b))?
+                .0
+        };
+        let record = self.queue.remove(index)?;
+        self.parents_waiting.remove(&record.id);
+        Some(record)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
 
-    use crate::computer::process::{OpCode, Process};
+    use crate::{computer::process::{OpCode, Process, ProcessState}, rng::SimRng};
 
     use super::{Scheduler, SchedulerAlgorithm};
 
@@ -347,6 +1048,32 @@ mod tests {
         scheduler.current_unchecked().proc.time_units = 0;
     }
 
+    /// Keeps feeding high priority processes while a low priority one waits,
+    /// returning after how many completions the low priority process ran.
+    fn run_starvation(mut scheduler: Scheduler) -> Option<u32> {
+        scheduler.schedule(Process::full(0, 1, OpCode::Inert));
+        scheduler.schedule(Process::full(1, 1, OpCode::Inert).with_prioirty(10));
+        for i in 2..30 {
+            // A fresh high priority process arrives every time one finishes.
+            scheduler.schedule(Process::full(i, 1, OpCode::Inert));
+            scheduler.current_unchecked().tick();
+            if scheduler.current_unchecked().id == 1 {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    #[test]
+    pub fn scheduler_priority_aging() {
+        // Without aging the low priority process starves.
+        assert!(run_starvation(Scheduler::new(SchedulerAlgorithm::Priority)).is_none());
+
+        // With aging it eventually makes it ahead of the new arrivals.
+        assert!(run_starvation(Scheduler::new(SchedulerAlgorithm::Priority).with_aging(1)).is_some());
+        assert!(run_starvation(Scheduler::new(SchedulerAlgorithm::PreemptivePriority).with_aging(2)).is_some());
+    }
+
     #[test]
     pub fn scheduler_srt_preemption() {
         let mut scheduler = Scheduler::new(SchedulerAlgorithm::ShortestRemainingTime(0.5));
@@ -364,6 +1091,61 @@ mod tests {
         assert_eq!(scheduler.current_unchecked().id, 0);
     }
 
+    #[test]
+    pub fn scheduler_srt_resume() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::ShortestRemainingTime(0.5));
+        for (id, burst) in [(0, 10.0), (1, 3.0), (2, 5.0), (3, 6.0)] {
+            scheduler.srt_time_table.insert(id, burst);
+        }
+
+        // The long process gets preempted with four ticks left.
+        scheduler.schedule(Process::full(0, 10, OpCode::Inert));
+        scheduler.current_unchecked().tick_n(6);
+        scheduler.schedule(Process::full(1, 3, OpCode::Inert));
+        assert_eq!(scheduler.current_unchecked().id, 1);
+        scheduler.schedule(Process::full(2, 5, OpCode::Inert));
+        scheduler.current_unchecked().tick_n(3);
+
+        // It resumes ahead of the five tick process.
+        assert_eq!(scheduler.current_unchecked().id, 0);
+        assert_eq!(scheduler.current_unchecked().remaining_estimate(), 4.0);
+
+        // Something longer than what is left should not preempt it.
+        scheduler.schedule(Process::full(3, 6, OpCode::Inert));
+        assert_eq!(scheduler.current_unchecked().id, 0);
+        scheduler.current_unchecked().tick();
+
+        // A shorter one should, and being preempted leaves the estimate alone.
+        scheduler.srt_time_table.insert(4, 2.0);
+        scheduler.schedule(Process::full(4, 2, OpCode::Inert));
+        assert_eq!(scheduler.current_unchecked().id, 4);
+        assert_eq!(scheduler.srt_time_table[&0], 10.0);
+
+        let mut order = vec![];
+        while let Some(current) = scheduler.current() {
+            order.push(current.id);
+            current.tick_n(current.time_units);
+        }
+        assert_eq!(order, [4, 0, 2, 3]);
+    }
+
+    #[test]
+    pub fn scheduler_srt_history() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::ShortestRemainingTime(0.5))
+            .with_initial_tau(4.0);
+        assert_eq!(scheduler.tau(0), 4.0);
+        scheduler.set_tau(0, 8.0);
+
+        // Run a burst that is shorter than predicted.
+        scheduler.schedule(Process::full(0, 2, OpCode::Inert));
+        scheduler.current_unchecked().tick_n(2);
+        assert!(scheduler.current().is_none());
+
+        assert_eq!(scheduler.tau(0), 5.0);
+        assert_eq!(scheduler.burst_history(0).collect::<Vec<_>>(), [2]);
+        assert_eq!(scheduler.burst_history(1).count(), 0);
+    }
+
     #[test]
     pub fn scheduler_srt() {
         let mut scheduler = Scheduler::new(SchedulerAlgorithm::ShortestRemainingTime(0.5));
@@ -382,6 +1164,595 @@ mod tests {
         assert!(scheduler.srt_time_table.get(&0).unwrap().eq(&6.5));
     }
 
+    /// Runs each process to completion and returns the order they were serviced in.
+    fn service_order(mut scheduler: Scheduler) -> Vec<u32> {
+        scheduler.schedule(Process::full(0, 6, OpCode::Inert));
+        scheduler.schedule(Process::full(1, 8, OpCode::Inert));
+        scheduler.schedule(Process::full(2, 7, OpCode::Inert));
+        scheduler.schedule(Process::full(3, 3, OpCode::Inert));
+        scheduler.schedule(Process::full(4, 3, OpCode::Inert));
+
+        let mut order = vec![];
+        while let Some(current) = scheduler.current() {
+            order.push(current.id);
+            current.tick_n(current.time_units);
+        }
+        order
+    }
+
+    #[test]
+    pub fn scheduler_sjf() {
+        assert_eq!(service_order(Scheduler::new(SchedulerAlgorithm::FirstComeFirstServe)), [0, 1, 2, 3, 4]);
+
+        // The first process is already running so it is not preempted,
+        // then the shortest go first with ties going in arrival order.
+        assert_eq!(service_order(Scheduler::new(SchedulerAlgorithm::ShortestJobFirst)), [0, 3, 4, 2, 1]);
+    }
+
+    #[test]
+    pub fn scheduler_stats() {
+        // The classic textbook example with bursts of 24, 3 and 3.
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::FirstComeFirstServe);
+        scheduler.schedule(Process::full(1, 24, OpCode::Inert));
+        scheduler.schedule(Process::full(2, 3, OpCode::Inert));
+        scheduler.schedule(Process::full(3, 3, OpCode::Inert));
+        while let Some(current) = scheduler.current() {
+            current.tick_n(current.time_units);
+        }
+
+        let stats = scheduler.stats();
+        let waiting: Vec<u128> = stats.processes.iter().map(|p| p.waiting).collect();
+        let turnaround: Vec<u128> = stats.processes.iter().map(|p| p.turnaround).collect();
+        assert_eq!(waiting, [0, 24, 27]);
+        assert_eq!(turnaround, [24, 27, 30]);
+        assert_eq!(stats.processes[2].first_run, 27);
+        assert_eq!(stats.average_waiting, 17.0);
+        assert_eq!(stats.average_turnaround, 27.0);
+    }
+
+    #[test]
+    pub fn scheduler_lottery() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::Lottery).with_seed(42);
+        scheduler.schedule(Process::full(0, 1, OpCode::Inert));
+        scheduler.schedule(Process::full(1, 1, OpCode::Inert).with_tickets(3));
+        scheduler.schedule(Process::full(2, 1, OpCode::Inert).with_tickets(1));
+        scheduler.schedule(Process::full(3, 1, OpCode::Inert).with_tickets(0));
+
+        // Draw a few thousand times, putting the winner back each time.
+        let mut wins = [0; 4];
+        for _ in 0..4000 {
+            let winner = scheduler.next().unwrap();
+            wins[winner.id as usize] += 1;
+            scheduler.queue.push_back(winner);
+        }
+
+        // The process without tickets never wins and we should see roughly 3:1.
+        assert_eq!(wins[3], 0);
+        let ratio = wins[1] as f32 / wins[2] as f32;
+        assert!(ratio > 2.7 && ratio < 3.3, "ratio was {ratio}");
+    }
+
+    #[test]
+    pub fn scheduler_lottery_seeded() {
+        let draw = || {
+            let mut scheduler = Scheduler::new(SchedulerAlgorithm::Lottery).with_seed(7);
+            for i in 0..5 {
+                scheduler.schedule(Process::full(i, 1, OpCode::Inert).with_tickets(i + 1));
+            }
+            let mut order = vec![];
+            while let Some(current) = scheduler.current() {
+                order.push(current.id);
+                current.tick();
+            }
+            order
+        };
+        assert_eq!(draw(), draw());
+    }
+
+    #[test]
+    pub fn scheduler_rng() {
+        // Everything is drawn from the same seeded generator, PIDs included.
+        let run = || {
+            let rng = SimRng::global();
+            rng.reseed(11);
+            let procs = (1..5).map(|i| Process::new(5).with_tickets(i)).collect();
+            Scheduler::new(SchedulerAlgorithm::Lottery)
+                .with_rng(rng)
+                .run_trace(procs, 20)
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    pub fn scheduler_srt_trace() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::ShortestRemainingTime(0.5));
+
+        // Prime the estimates with the real burst times.
+        for (id, burst) in [(1, 8.0), (2, 4.0), (3, 9.0), (4, 5.0)] {
+            scheduler.srt_time_table.insert(id, burst);
+        }
+
+        let trace = scheduler.run_trace(vec![
+            Process::full(1, 8, OpCode::Inert).with_arrival(0),
+            Process::full(2, 4, OpCode::Inert).with_arrival(1),
+            Process::full(3, 9, OpCode::Inert).with_arrival(2),
+            Process::full(4, 5, OpCode::Inert).with_arrival(3),
+        ], 28);
+
+        // P1 0-1, P2 1-5, P4 5-10, P1 10-17, P3 17-26 then idle.
+        let mut expected = vec![Some(1)];
+        expected.extend([Some(2); 4]);
+        expected.extend([Some(4); 5]);
+        expected.extend([Some(1); 7]);
+        expected.extend([Some(3); 9]);
+        expected.extend([None; 2]);
+        assert_eq!(trace, expected);
+    }
+
+    #[test]
+    pub fn scheduler_utilization() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::FirstComeFirstServe);
+        scheduler.schedule(Process::full(0, 3, OpCode::Inert));
+        scheduler.current_unchecked().tick_n(3);
+
+        // Nothing is ready for a while.
+        assert!(scheduler.current().is_none());
+        for _ in 0..4 {
+            scheduler.idle_tick();
+        }
+        scheduler.schedule(Process::full(1, 3, OpCode::Inert));
+        scheduler.current_unchecked().tick_n(3);
+
+        assert_eq!(scheduler.stats().utilization, 0.6);
+        assert_eq!(scheduler.gantt(), [(Some(0), 0..3), (None, 3..7), (Some(1), 7..10)]);
+    }
+
+    #[test]
+    pub fn scheduler_rr_weighted() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::RoundRobin(2));
+        let trace = scheduler.run_trace(vec![
+            Process::full(0, 10, OpCode::Inert).with_quantum(1),
+            Process::full(1, 10, OpCode::Inert).with_quantum(4),
+        ], 10);
+        assert_eq!(trace, [0, 1, 1, 1, 1, 0, 1, 1, 1, 1].map(Some));
+
+        // The override is kept when the process gets moved off and comes back.
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::RoundRobin(2));
+        scheduler.schedule(Process::full(1, 10, OpCode::Inert).with_quantum(4));
+        scheduler.current_unchecked().tick_n(2);
+        scheduler.block_current(1);
+        scheduler.advance_io();
+        assert_eq!(scheduler.current_unchecked().id, 1);
+        assert_eq!(scheduler.current_unchecked().remaining_quantum(), 4);
+    }
+
+    #[test]
+    pub fn scheduler_payload() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let payload = OpCode::Run(Box::new({
+            let count = Arc::clone(&count);
+            move |_| {
+                count.fetch_add(1, Ordering::SeqCst);
+            }
+        }));
+
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::RoundRobin(2));
+        scheduler.schedule(Process::full(0, 5, payload));
+        scheduler.schedule(Process::full(1, 3, OpCode::Inert));
+        while let Some(current) = scheduler.current() {
+            current.tick();
+        }
+
+        // It ran once for every time unit and no more.
+        assert_eq!(count.load(Ordering::SeqCst), 5);
+
+        // The scratch space is kept between ticks.
+        let mut process = Process::full(2, 3, OpCode::Run(Box::new(|ctx| {
+            *ctx.scratch.entry("sum".to_string()).or_default() += ctx.time_units as i64;
+        })));
+        for _ in 0..5 {
+            process.tick();
+        }
+        assert_eq!(process.scratch["sum"], 6);
+    }
+
+    #[test]
+    pub fn scheduler_rr_trace() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::RoundRobin(2));
+        let trace = scheduler.run_trace(vec![
+            Process::full(0, 3, OpCode::Inert),
+            Process::full(1, 2, OpCode::Inert).with_arrival(4),
+        ], 6);
+        assert_eq!(trace, [Some(0), Some(0), Some(0), None, Some(1), Some(1)]);
+    }
+
+    #[test]
+    pub fn scheduler_remove() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::RoundRobin(2));
+        scheduler.schedule(Process::full(0, 6, OpCode::Inert));
+        scheduler.schedule(Process::full(1, 6, OpCode::Inert));
+        scheduler.schedule(Process::full(2, 6, OpCode::Inert));
+        scheduler.current_unchecked().tick();
+
+        // Killing a queued process does not touch the running one.
+        assert_eq!(scheduler.remove(2).unwrap().id, 2);
+        assert_eq!(scheduler.current_unchecked().id, 0);
+        assert_eq!(scheduler.current_unchecked().lifetime, 1);
+
+        // Killing the running one schedules the next.
+        assert_eq!(scheduler.remove(0).unwrap().id, 0);
+        assert_eq!(scheduler.current_unchecked().id, 1);
+
+        // Nothing left to kill.
+        assert!(scheduler.remove(0).is_none());
+        assert!(scheduler.remove(1).is_some());
+        assert!(scheduler.current().is_none());
+    }
+
+    #[test]
+    pub fn scheduler_block_io() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::RoundRobin(2));
+        scheduler.schedule(Process::full(0, 3, OpCode::Inert));
+        scheduler.schedule(Process::full(1, 3, OpCode::Inert));
+
+        // Run the first process a bit and then block it.
+        scheduler.current_unchecked().tick();
+        scheduler.block_current(3);
+        assert_eq!(scheduler.state(0), Some(ProcessState::Blocked));
+        assert_eq!(scheduler.current_unchecked().id, 1);
+
+        // It should only wake up on the third tick of IO.
+        scheduler.advance_io();
+        scheduler.advance_io();
+        assert_eq!(scheduler.state(0), Some(ProcessState::Blocked));
+        scheduler.advance_io();
+        assert_eq!(scheduler.state(0), Some(ProcessState::Ready));
+
+        // Everything should still run to completion.
+        while let Some(current) = scheduler.current() {
+            current.tick();
+        }
+        assert_eq!(scheduler.stats().processes.len(), 2);
+
+        // Blocking the only process leaves nothing to run.
+        scheduler.schedule(Process::full(2, 3, OpCode::Inert));
+        scheduler.block_current(1);
+        assert!(scheduler.current().is_none());
+        scheduler.advance_io();
+        assert_eq!(scheduler.current_unchecked().id, 2);
+    }
+
+    /// Keeps feeding short jobs while a long one waits, returning
+    /// after how many arrivals the long job got to run.
+    fn run_long_job(mut scheduler: Scheduler) -> Option<u32> {
+        scheduler.schedule(Process::full(0, 1, OpCode::Inert));
+        scheduler.schedule(Process::full(1, 6, OpCode::Inert));
+        for i in 2..30 {
+            scheduler.schedule(Process::full(i, 2, OpCode::Inert));
+            let current = scheduler.current_unchecked();
+            current.tick_n(current.time_units);
+            if scheduler.current_unchecked().id == 1 {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    #[test]
+    pub fn scheduler_hrrn() {
+        // Shortest job first starves the long job.
+        assert!(run_long_job(Scheduler::new(SchedulerAlgorithm::ShortestJobFirst)).is_none());
+
+        // The long job wins once it has waited long enough, by the third arrival
+        // its ratio of (3 + 6) / 6 ties the (1 + 2) / 2 of the short job and it
+        // arrived first.
+        assert_eq!(run_long_job(Scheduler::new(SchedulerAlgorithm::HighestResponseRatio)), Some(3));
+    }
+
+    #[test]
+    pub fn scheduler_ready_queue() {
+        for policy in [
+            SchedulerAlgorithm::FirstComeFirstServe,
+            SchedulerAlgorithm::Priority,
+            SchedulerAlgorithm::ShortestJobFirst,
+            SchedulerAlgorithm::HighestResponseRatio,
+            SchedulerAlgorithm::ShortestRemainingTime(0.5),
+        ] {
+            let mut scheduler = Scheduler::new(policy);
+            for (id, time, priority) in [(0, 5, 0), (1, 8, 3), (2, 2, -1), (3, 4, 3), (4, 1, 1)] {
+                scheduler.schedule(Process::full(id, time, OpCode::Inert).with_prioirty(priority));
+            }
+            assert_eq!(scheduler.scheduled().unwrap().id, 0);
+            assert_eq!(scheduler.len(), 5);
+
+            // The snapshot should match the order we actually dequeue in.
+            let snapshot: Vec<u32> = scheduler.ready_queue().map(|f| f.id).collect();
+            let mut dequeued = vec![];
+            while let Some(record) = scheduler.next() {
+                dequeued.push(record.id);
+            }
+            assert_eq!(snapshot, dequeued);
+        }
+    }
+
+    #[test]
+    pub fn scheduler_fair() {
+        // Two equal processes should split the CPU evenly.
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::Fair(1.0));
+        let trace = scheduler.run_trace(vec![
+            Process::full(0, 100, OpCode::Inert),
+            Process::full(1, 100, OpCode::Inert),
+        ], 20);
+        let first = trace.iter().filter(|f| **f == Some(0)).count();
+        assert!(first.abs_diff(10) <= 2, "process 0 ran {first} ticks");
+
+        // Neither should be stuck waiting for long.
+        assert!(trace.windows(5).all(|w| w.iter().any(|f| *f != w[0])));
+
+        // A process three nice levels lower should get roughly half the share.
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::Fair(1.0));
+        let trace = scheduler.run_trace(vec![
+            Process::full(0, 100, OpCode::Inert),
+            Process::full(1, 100, OpCode::Inert).with_prioirty(3),
+        ], 60);
+        let high = trace.iter().filter(|f| **f == Some(0)).count() as f32;
+        let low = trace.iter().filter(|f| **f == Some(1)).count() as f32;
+        let ratio = high / low;
+        assert!(ratio > 1.6 && ratio < 2.4, "ratio was {ratio}");
+    }
+
+    #[test]
+    pub fn scheduler_context_switches() {
+        let run = |quantum| {
+            let mut scheduler = Scheduler::new(SchedulerAlgorithm::RoundRobin(quantum))
+                .with_context_switch_cost(2);
+            scheduler.run_trace(vec![
+                Process::full(0, 4, OpCode::Inert),
+                Process::full(1, 4, OpCode::Inert),
+                Process::full(2, 4, OpCode::Inert),
+            ], 12);
+            (scheduler.context_switches(), scheduler.overhead_ticks())
+        };
+        assert_eq!(run(4), (2, 4));
+        assert_eq!(run(1), (11, 22));
+
+        // A process that is alone never switches.
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::RoundRobin(1))
+            .with_context_switch_cost(2);
+        scheduler.run_trace(vec![Process::full(0, 4, OpCode::Inert)], 4);
+        assert_eq!(scheduler.context_switches(), 0);
+    }
+
+    #[test]
+    pub fn scheduler_record_handover() {
+        let mut small = Scheduler::new(SchedulerAlgorithm::RoundRobin(2)).with_feedback();
+        let mut large = Scheduler::new(SchedulerAlgorithm::RoundRobin(4)).with_feedback();
+        small.schedule(Process::full(0, 8, OpCode::Inert));
+        small.current_unchecked().tick_n(2);
+
+        // The record is bumped with a spent quantum.
+        let bumped = small.fetch_current().1.unwrap();
+        assert_eq!(bumped.remaining_quantum(), 0);
+
+        // It should get a fresh quantum on the new scheduler.
+        large.schedule_record(bumped);
+        assert_eq!(large.current_unchecked().remaining_quantum(), 4);
+
+        // Estimates come from the table of the scheduler it lands on,
+        // less the two ticks that were already done.
+        let mut srt = Scheduler::new(SchedulerAlgorithm::ShortestRemainingTime(0.5));
+        srt.srt_time_table.insert(0, 3.0);
+        srt.schedule_record(large.remove(0).unwrap());
+        assert_eq!(srt.current_unchecked().remaining_estimate(), 1.0);
+        assert_eq!(srt.current_unchecked().remaining_quantum(), 0);
+    }
+
+    /// Runs the classic priority inversion, returning the order the
+    /// processes completed in.
+    fn run_inversion(mut scheduler: Scheduler) -> Vec<u32> {
+        // The low priority process grabs the resource.
+        scheduler.schedule(Process::full(0, 6, OpCode::Inert).with_prioirty(10));
+        assert!(scheduler.acquire(1));
+        scheduler.current_unchecked().tick();
+
+        // The high priority process preempts it and then blocks on the resource.
+        scheduler.schedule(Process::full(2, 2, OpCode::Inert));
+        assert_eq!(scheduler.current_unchecked().id, 2);
+        assert!(!scheduler.acquire(1));
+        assert_eq!(scheduler.current_unchecked().id, 0);
+
+        // Along comes a medium priority process.
+        scheduler.schedule(Process::full(1, 4, OpCode::Inert).with_prioirty(5));
+
+        // The low priority process releases after running for three ticks.
+        while let Some(current) = scheduler.current() {
+            current.tick();
+            if current.id == 0 && current.elapsed == 3 {
+                scheduler.release(1);
+            }
+        }
+        scheduler.stats().processes.iter().map(|f| f.id).collect()
+    }
+
+    #[test]
+    pub fn scheduler_priority_inheritance() {
+        // Without inheritance the medium priority process delays the high one.
+        assert_eq!(run_inversion(Scheduler::new(SchedulerAlgorithm::PreemptivePriority)), [1, 2, 0]);
+
+        // With inheritance the holder runs at the waiter's priority.
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::PreemptivePriority)
+            .with_priority_inheritance();
+        scheduler.schedule(Process::full(0, 6, OpCode::Inert).with_prioirty(10));
+        scheduler.acquire(1);
+        scheduler.schedule(Process::full(2, 2, OpCode::Inert));
+        scheduler.acquire(1);
+        assert_eq!(scheduler.effective_priority(0), Some(0));
+        assert_eq!(scheduler.state(2), Some(ProcessState::Blocked));
+
+        let scheduler = Scheduler::new(SchedulerAlgorithm::PreemptivePriority)
+            .with_priority_inheritance();
+        assert_eq!(run_inversion(scheduler), [2, 1, 0]);
+    }
+
+    #[test]
+    pub fn scheduler_resource_cleanup() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::FirstComeFirstServe);
+        scheduler.schedule(Process::full(1, 4, OpCode::Inert));
+        scheduler.schedule(Process::full(2, 4, OpCode::Inert));
+        scheduler.schedule(Process::full(3, 4, OpCode::Inert));
+        assert!(scheduler.acquire(7));
+        scheduler.block_current(1);
+        assert!(!scheduler.acquire(7));
+        assert_eq!(scheduler.current_unchecked().id, 3);
+        scheduler.advance_io();
+
+        // Killing the waiter takes it out of line so the release goes nowhere.
+        assert_eq!(scheduler.remove(2).unwrap().id, 2);
+        assert!(!scheduler.acquire(7));
+        assert!(scheduler.remove(3).is_some());
+        assert_eq!(scheduler.current_unchecked().id, 1);
+        scheduler.release(7);
+        assert!(scheduler.acquire(7));
+
+        // A holder that finishes gives the resource up to whoever is waiting.
+        scheduler.schedule(Process::full(4, 1, OpCode::Inert));
+        scheduler.block_current(1);
+        assert!(!scheduler.acquire(7));
+        scheduler.advance_io();
+        scheduler.current_unchecked().tick_n(4);
+        assert_eq!(scheduler.current_unchecked().id, 4);
+        assert!(scheduler.acquire(7));
+    }
+
+    #[test]
+    pub fn scheduler_gantt() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::RoundRobin(2));
+        scheduler.run_trace(vec![
+            Process::full(0, 3, OpCode::Inert),
+            Process::full(1, 3, OpCode::Inert),
+        ], 8);
+        assert_eq!(scheduler.gantt(), [
+            (Some(0), 0..2),
+            (Some(1), 2..4),
+            (Some(0), 4..5),
+            (Some(1), 5..6),
+            (None, 6..8),
+        ]);
+    }
+
+    #[test]
+    pub fn scheduler_fork_wait() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::FirstComeFirstServe);
+        let parent = Process::full(0, 4, OpCode::Inert);
+        let first = parent.fork();
+        let second = parent.fork();
+        let children = [first.id, second.id];
+        assert_eq!(first.parent, Some(0));
+
+        scheduler.schedule(parent);
+        scheduler.current_unchecked().tick();
+        scheduler.schedule(first);
+        scheduler.schedule(second);
+
+        // The parent gives up the CPU until both children finish.
+        scheduler.wait(0);
+        let mut order = vec![];
+        while let Some(current) = scheduler.current() {
+            order.push(current.id);
+            current.tick_n(current.time_units);
+        }
+        assert_eq!(order, [children[0], children[1], 0]);
+    }
+
+    #[test]
+    pub fn scheduler_wait_blocked_child() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::FirstComeFirstServe);
+        let parent = Process::full(0, 2, OpCode::Inert);
+        let child = parent.fork();
+        let child_id = child.id;
+        scheduler.schedule(child);
+        scheduler.schedule(parent);
+        scheduler.block_current(2);
+        assert_eq!(scheduler.current_unchecked().id, 0);
+
+        // The only child is blocked, so nothing runs rather than the parent.
+        scheduler.wait(0);
+        assert_eq!(scheduler.state(0), Some(ProcessState::Ready));
+        assert_eq!(scheduler.state(child_id), Some(ProcessState::Blocked));
+        assert!(scheduler.current().is_none());
+
+        scheduler.advance_io();
+        scheduler.advance_io();
+        let mut order = vec![];
+        while let Some(current) = scheduler.current() {
+            order.push(current.id);
+            current.tick_n(current.time_units);
+        }
+        assert_eq!(order, [child_id, 0]);
+    }
+
+    #[test]
+    pub fn scheduler_wait_preemptive_priority() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::PreemptivePriority);
+        let parent = Process::full(10, 2, OpCode::Inert).with_prioirty(-10);
+        let child = parent.fork().with_prioirty(5);
+        let child_id = child.id;
+        scheduler.schedule(parent);
+        scheduler.schedule(child);
+        assert_eq!(scheduler.current_unchecked().id, 10);
+
+        // The parent outranks its child but still has to wait for it.
+        scheduler.wait(10);
+        assert_eq!(scheduler.state(10), Some(ProcessState::Ready));
+        assert_eq!(scheduler.current_unchecked().id, child_id);
+        scheduler.current_unchecked().tick_n(2);
+        assert_eq!(scheduler.current_unchecked().id, 10);
+    }
+
+    #[test]
+    pub fn scheduler_wait_no_children() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::FirstComeFirstServe);
+        let parent = Process::full(1, 2, OpCode::Inert);
+        let child = parent.fork();
+        let child_id = child.id;
+        scheduler.schedule(Process::full(0, 2, OpCode::Inert));
+        scheduler.schedule(parent);
+
+        // There is nothing to wait on so it returns straight away,
+        // the running process doesn't give up the CPU either.
+        scheduler.wait(1);
+        assert_eq!(scheduler.state(1), Some(ProcessState::Ready));
+        scheduler.wait(0);
+        assert_eq!(scheduler.state(0), Some(ProcessState::Running));
+
+        // So the parent isn't left waiting on a child it forks later.
+        scheduler.schedule(child);
+        let mut order = vec![];
+        while let Some(current) = scheduler.current() {
+            order.push(current.id);
+            current.tick_n(current.time_units);
+        }
+        assert_eq!(order, [0, 1, child_id]);
+    }
+
+    #[test]
+    pub fn scheduler_fair_share() {
+        let mut scheduler = Scheduler::new(SchedulerAlgorithm::FairShare(2));
+        let procs = vec![
+            Process::full(0, 100, OpCode::Inert).with_group(0),
+            Process::full(1, 100, OpCode::Inert).with_group(0),
+            Process::full(2, 100, OpCode::Inert).with_group(0),
+            Process::full(3, 100, OpCode::Inert).with_group(1),
+        ];
+        let trace = scheduler.run_trace(procs, 40);
+        scheduler.fetch_current();
+        assert_eq!(&trace[..8], [0, 0, 3, 3, 1, 1, 3, 3].map(Some));
+
+        // The lone process gets as much as the other three combined.
+        assert_eq!(scheduler.group_usage()[&0], 20);
+        assert_eq!(scheduler.group_usage()[&1], 20);
+    }
+
     #[test]
     pub fn scheduler_rr() {
         let mut scheduler = Scheduler::new(SchedulerAlgorithm::RoundRobin(3));
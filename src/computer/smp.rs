@@ -0,0 +1,436 @@
+//! A symmetric multiprocessing scheduler.
+
+use std::{sync::Arc, thread::{self, JoinHandle}};
+
+use parking_lot::Mutex;
+
+use crate::{memory::ipc::IpcChannel, rng::SimRng};
+
+use super::{process::{OpCode, Process}, processor::Cpu, scheduler::{Scheduler, SchedulerAlgorithm}};
+
+/// The ready queue and what the cores are doing, this is all behind one lock.
+struct Ready {
+    /// Picks what runs next with the policy the [SmpScheduler] was made with.
+    scheduler: Scheduler,
+    /// Which of the cores are waiting for something to run.
+    idle: Vec<bool>,
+    /// Set once we have been told to shut down.
+    shutting_down: bool
+}
+
+/// What the cores share.
+struct Shared {
+    ready: Mutex<Ready>,
+    /// Each core's queue, a process sent here is run by that core.
+    inboxes: Vec<Arc<IpcChannel<Process>>>,
+    policy: SchedulerAlgorithm
+}
+
+impl Shared {
+    /// How many ticks a process gets on a core before it goes back in the
+    /// ready queue. Only the round robin policies take it off early.
+    fn quantum(&self, process: &Process) -> usize {
+        match self.policy {
+            SchedulerAlgorithm::RoundRobin(quantum) | SchedulerAlgorithm::FairShare(quantum) => {
+                process.quantum_override.unwrap_or(quantum).max(1)
+            }
+            _ => usize::MAX
+        }
+    }
+    /// Hands out ready processes to the idle cores allowed to run them. Once we
+    /// are shutting down and every core is idle with nothing left to run, a
+    /// shutdown is sent to every core like the examples do.
+    fn dispatch(&self, ready: &mut Ready) {
+        for core in 0..ready.idle.len() {
+            if !ready.idle[core] {
+                continue;
+            }
+            if let Some(process) = take_ready(&mut ready.scheduler, core) {
+                ready.idle[core] = false;
+                // The inboxes are never closed.
+                self.inboxes[core].send(process).unwrap();
+            }
+        }
+        if ready.shutting_down && ready.scheduler.is_empty() && ready.idle.iter().all(|f| *f) {
+            // The cores are no longer idle once they have been told, so this only goes out once.
+            for (core, inbox) in self.inboxes.iter().enumerate() {
+                ready.idle[core] = false;
+                inbox.send(Process::shutdown()).unwrap();
+            }
+        }
+    }
+}
+
+/// The data each core gets.
+struct CoreData {
+    /// The processor ID.
+    id: usize,
+    /// The processes dispatched to this core.
+    inbox: Arc<IpcChannel<Process>>,
+    shared: Arc<Shared>
+}
+
+/// Schedules processes onto a set of worker cores.
+///
+/// The ready queue is shared between all the cores. Whenever a core is free
+/// the next process it is allowed to run by its affinity mask is sent down
+/// its channel, under round robin it runs for a quantum before going back
+/// in the queue. Shutting down sends [OpCode::Shutdown] to every core once
+/// the ready queue is empty.
+///
+/// ```
+/// use osconcepts::computer::{process::Process, scheduler::SchedulerAlgorithm, smp::SmpScheduler};
+///
+/// let smp = SmpScheduler::new(2, SchedulerAlgorithm::FirstComeFirstServe);
+/// smp.submit(Process::new(3));
+/// smp.shutdown();
+/// assert_eq!(smp.join().iter().sum::<usize>(), 1);
+/// ```
+pub struct SmpScheduler {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<usize>>
+}
+
+impl SmpScheduler {
+    /// Starts up the cores, the shared ready queue uses the given policy.
+    pub fn new(cores: usize, policy: SchedulerAlgorithm) -> Self {
+        let shared = Arc::new(Shared {
+            ready: Mutex::new(Ready {
+                scheduler: Scheduler::new(policy),
+                idle: vec![true; cores],
+                shutting_down: false
+            }),
+            inboxes: (0..cores).map(|_| Arc::new(IpcChannel::new())).collect(),
+            policy
+        });
+        let mut workers = vec![];
+        for id in 0..cores {
+            let cpu = Cpu::new(CoreData {
+                id,
+                inbox: Arc::clone(&shared.inboxes[id]),
+                shared: Arc::clone(&shared)
+            });
+            let rng = SimRng::global();
+            workers.push(thread::spawn(move || {
+                SimRng::set_global(rng);
+                run_core(cpu.data())
+            }));
+        }
+        Self {
+            shared,
+            workers
+        }
+    }
+    /// Submits a process to be run.
+    ///
+    /// # Panics
+    /// If the process is not allowed on any of the cores or
+    /// the scheduler has been shut down.
+    pub fn submit(&self, process: Process) {
+        assert!(
+            (0..self.workers.len()).any(|core| process.affinity.allows(core)),
+            "The process is not allowed on any of the cores."
+        );
+        let mut ready = self.shared.ready.lock();
+        assert!(!ready.shutting_down, "The scheduler has been shut down.");
+        ready.scheduler.schedule(process);
+        self.shared.dispatch(&mut ready);
+    }
+    /// Shuts down every core, they will finish whatever
+    /// is left in the ready queue first.
+    pub fn shutdown(&self) {
+        let mut ready = self.shared.ready.lock();
+        ready.shutting_down = true;
+        self.shared.dispatch(&mut ready);
+    }
+    /// Waits for all the cores to exit, returning how many
+    /// processes each of them ran to completion.
+    pub fn join(self) -> Vec<usize> {
+        self.workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+    }
+}
+
+/// How the [LoadBalancer] moves work between cores.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadBalance {
+    /// The busiest core pushes a process to the least loaded one.
+    Push,
+    /// Every core that is falling behind steals a process
+    /// from the busiest one.
+    Steal
+}
+
+/// Per-core run queues that get balanced every so often.
+///
+/// Each core has its own [Scheduler] and whenever the longest queue is
+/// more than the threshold ahead of another, queued processes get migrated
+/// across. Processes only move to cores their affinity allows.
+pub struct LoadBalancer {
+    cores: Vec<Scheduler>,
+    policy: LoadBalance,
+    threshold: usize,
+    migrations: usize
+}
+
+impl LoadBalancer {
+    pub fn new(cores: usize, algorithm: SchedulerAlgorithm, policy: LoadBalance) -> Self {
+        Self {
+            cores: (0..cores).map(|_| Scheduler::new(algorithm)).collect(),
+            policy,
+            threshold: 1,
+            migrations: 0
+        }
+    }
+    /// Sets how far apart the queues can get before
+    /// we start migrating, this defaults to one.
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+    /// Schedules a process onto the least loaded core it is allowed on.
+    ///
+    /// # Panics
+    /// If the process is not allowed on any of the cores.
+    pub fn schedule(&mut self, process: Process) {
+        let core = (0..self.cores.len())
+            .filter(|&i| process.affinity.allows(i))
+            .min_by_key(|&i| self.cores[i].len())
+            .expect("The process is not allowed on any of the cores.");
+        self.schedule_on(core, process);
+    }
+    /// Schedules a process onto a specific core.
+    ///
+    /// # Panics
+    /// If the core does not exist.
+    pub fn schedule_on(&mut self, core: usize, process: Process) {
+        self.cores[core].schedule(process);
+    }
+    /// The scheduler of each core.
+    pub fn cores(&self) -> &[Scheduler] {
+        &self.cores
+    }
+    /// The scheduler of a specific core.
+    pub fn core_mut(&mut self, core: usize) -> &mut Scheduler {
+        &mut self.cores[core]
+    }
+    /// How many processes are running or ready on each core.
+    pub fn queue_lengths(&self) -> Vec<usize> {
+        self.cores.iter().map(Scheduler::len).collect()
+    }
+    /// The total number of processes that have been migrated.
+    pub fn migrations(&self) -> usize {
+        self.migrations
+    }
+    /// Runs one round of load balancing, returning
+    /// how many processes were migrated.
+    pub fn balance(&mut self) -> usize {
+        let busiest = self.busiest();
+        let mut moved = 0;
+        match self.policy {
+            LoadBalance::Push => {
+                let target = self.least_loaded();
+                if self.imbalanced(busiest, target) && self.migrate(busiest, target) {
+                    moved += 1;
+                }
+            }
+            LoadBalance::Steal => {
+                for target in 0..self.cores.len() {
+                    if self.imbalanced(busiest, target) && self.migrate(busiest, target) {
+                        moved += 1;
+                    }
+                }
+            }
+        }
+        self.migrations += moved;
+        moved
+    }
+    fn busiest(&self) -> usize {
+        (0..self.cores.len()).max_by_key(|&i| self.cores[i].len()).unwrap_or(0)
+    }
+    fn least_loaded(&self) -> usize {
+        (0..self.cores.len()).min_by_key(|&i| self.cores[i].len()).unwrap_or(0)
+    }
+    fn imbalanced(&self, from: usize, to: usize) -> bool {
+        self.cores[from].len() > self.cores[to].len() + self.threshold
+    }
+    /// Moves the queued process that would run last on one core
+    /// over to another, skipping anything not allowed there.
+    fn migrate(&mut self, from: usize, to: usize) -> bool {
+        let Some(pid) = self.cores[from].ready_queue().filter(|f| f.proc.affinity.allows(to)).last().map(|f| f.id) else {
+            return false;
+        };
+        let record = self.cores[from].remove(pid).unwrap();
+        self.cores[to].schedule_record(record);
+        true
+    }
+}
+
+/// Takes the next process the core is allowed to run off the ready queue.
+fn take_ready(ready: &mut Scheduler, core: usize) -> Option<Process> {
+    let current = ready.current().filter(|f| f.proc.affinity.allows(core)).map(|f| f.id);
+    let pid = match current {
+        Some(pid) => pid,
+        None => ready.ready_queue().find(|f| f.proc.affinity.allows(core))?.id
+    };
+    ready.remove(pid).map(|f| f.proc)
+}
+
+/// The worker loop of a core, this returns how many processes it ran.
+fn run_core(data: CoreData) -> usize {
+    let mut executed = 0;
+    loop {
+        let Ok(mut process) = data.inbox.recv() else {
+            break;
+        };
+        // A shutdown process with nothing left to run is the word to stop.
+        if process.code == OpCode::Shutdown && process.time_units == 0 {
+            break;
+        }
+        // Run it for its quantum without the lock so the other cores can carry on.
+        let mut quantum = data.shared.quantum(&process);
+        while process.time_units > 0 && quantum > 0 {
+            process.tick();
+            quantum -= 1;
+        }
+
+        let mut ready = data.shared.ready.lock();
+        match process.time_units {
+            0 => executed += 1,
+            // It goes to the back of the ready queue.
+            _ => {
+                ready.scheduler.schedule(process);
+            }
+        }
+        ready.idle[data.id] = true;
+        data.shared.dispatch(&mut ready);
+    }
+    executed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::{AtomicUsize, Ordering}, Arc, Barrier};
+
+    use parking_lot::Mutex;
+
+    use crate::computer::{process::{AffinityMask, OpCode, Process}, scheduler::SchedulerAlgorithm};
+
+    use super::{LoadBalance, LoadBalancer, SmpScheduler};
+
+    #[test]
+    pub fn test_smp_affinity() {
+        let smp = SmpScheduler::new(3, SchedulerAlgorithm::FirstComeFirstServe);
+        for _ in 0..5 {
+            smp.submit(Process::new(2).with_affinity(1));
+        }
+        smp.shutdown();
+        assert_eq!(smp.join(), [0, 5, 0]);
+    }
+
+    #[test]
+    pub fn test_smp_affinity_mask() {
+        let mask = AffinityMask::none().allow_core(1).allow_core(3);
+        assert!(!mask.allows(2));
+        assert!(mask.allows(3));
+
+        let smp = SmpScheduler::new(4, SchedulerAlgorithm::FirstComeFirstServe);
+        for _ in 0..6 {
+            smp.submit(Process::new(2).with_affinity_mask(mask));
+        }
+        smp.shutdown();
+        let counts = smp.join();
+        assert_eq!(counts[0] + counts[2], 0);
+        assert_eq!(counts[1] + counts[3], 6);
+    }
+
+    #[test]
+    pub fn test_load_balance() {
+        for policy in [LoadBalance::Push, LoadBalance::Steal] {
+            let mut cores = LoadBalancer::new(3, SchedulerAlgorithm::FirstComeFirstServe, policy);
+
+            // Everything starts on the first core, a couple of them are pinned there.
+            for i in 0..7 {
+                cores.schedule_on(0, Process::full(i, 5, OpCode::Inert));
+            }
+            cores.schedule(Process::full(7, 5, OpCode::Inert).with_affinity(0));
+            cores.schedule(Process::full(8, 5, OpCode::Inert).with_affinity(0));
+            assert_eq!(cores.queue_lengths(), [9, 0, 0]);
+
+            let mut rounds = 0;
+            while cores.balance() != 0 {
+                rounds += 1;
+                assert!(rounds <= 6, "{policy:?} did not converge");
+            }
+            assert_eq!(cores.queue_lengths(), [3, 3, 3]);
+            assert_eq!(cores.migrations(), 6);
+
+            // The pinned processes never left.
+            assert!(cores.cores()[0].state(7).is_some());
+            assert!(cores.cores()[0].state(8).is_some());
+        }
+    }
+
+    #[test]
+    pub fn test_smp_shutdown() {
+        let smp = SmpScheduler::new(4, SchedulerAlgorithm::RoundRobin(2));
+        for i in 0..20 {
+            smp.submit(Process::new(i + 1));
+        }
+        smp.submit(Process::new(1).with_affinity(3));
+        smp.shutdown();
+
+        // Every core exits and everything got run somewhere.
+        let counts = smp.join();
+        assert_eq!(counts.len(), 4);
+        assert_eq!(counts.iter().sum::<usize>(), 21);
+        assert!(counts[3] >= 1);
+    }
+
+    #[test]
+    pub fn test_smp_round_robin() {
+        let order = Arc::new(Mutex::new(vec![]));
+        let queued = Arc::new(Barrier::new(2));
+        let smp = SmpScheduler::new(1, SchedulerAlgorithm::RoundRobin(2));
+        for i in 0..2 {
+            let (order, queued) = (Arc::clone(&order), Arc::clone(&queued));
+            smp.submit(Process::full(i, 4, OpCode::Run(Box::new(move |_| {
+                // Hold the first one up until the second is queued behind it.
+                if order.lock().is_empty() {
+                    queued.wait();
+                }
+                order.lock().push(i);
+            }))));
+        }
+        queued.wait();
+        smp.shutdown();
+
+        // Each one gets two ticks and then goes to the back of the queue.
+        assert_eq!(smp.join(), [2]);
+        assert_eq!(*order.lock(), [0, 0, 1, 1, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "shut down")]
+    pub fn test_smp_submit_after_shutdown() {
+        let smp = SmpScheduler::new(1, SchedulerAlgorithm::FirstComeFirstServe);
+        smp.shutdown();
+        smp.submit(Process::new(1));
+    }
+
+    #[test]
+    pub fn test_smp_runs_processes() {
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let smp = SmpScheduler::new(2, SchedulerAlgorithm::FirstComeFirstServe);
+        for i in 0..3 {
+            let ticks = Arc::clone(&ticks);
+            smp.submit(Process::full(i, 4, OpCode::Run(Box::new(move |_| {
+                ticks.fetch_add(1, Ordering::SeqCst);
+            }))));
+        }
+        smp.shutdown();
+
+        // A process only counts once the core has run all of it.
+        assert_eq!(smp.join().iter().sum::<usize>(), 3);
+        assert_eq!(ticks.load(Ordering::SeqCst), 12);
+    }
+}
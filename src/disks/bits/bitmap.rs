@@ -0,0 +1,156 @@
+//! Free space bitmaps, one bit per block that is set if the
+//! block is being used.
+
+use super::Bit;
+
+
+/// A bitmap kept as bytes so it can be written out to a device as it is.
+///
+/// Bits go most significant first like [super::BitVec], so block zero is
+/// the top bit of the first byte.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitmap {
+    bytes: Vec<u8>,
+    /// How many bits are in use, the last byte can have some left over.
+    bits: usize
+}
+
+impl Bitmap {
+    /// A bitmap of this many bits that are all clear.
+    pub fn new(bits: usize) -> Self {
+        Self {
+            bytes: vec![0; bits.div_ceil(8)],
+            bits
+        }
+    }
+    /// A bitmap of every bit in these bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            bytes: bytes.to_vec(),
+            bits: bytes.len() * 8
+        }
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
+    pub fn len(&self) -> usize {
+        self.bits
+    }
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+    /// The byte a bit is in and the mask for it.
+    ///
+    /// # Panics
+    /// If the bit is past the end.
+    fn locate(&self, bit: usize) -> (usize, u8) {
+        assert!(bit < self.bits, "Bit {bit} is past the end of the bitmap of {}.", self.bits);
+        (bit / 8, 1 << (7 - bit % 8))
+    }
+    /// # Panics
+    /// If the bit is past the end.
+    pub fn set(&mut self, bit: usize) {
+        let (byte, mask) = self.locate(bit);
+        self.bytes[byte] |= mask;
+    }
+    /// # Panics
+    /// If the bit is past the end.
+    pub fn clear(&mut self, bit: usize) {
+        let (byte, mask) = self.locate(bit);
+        self.bytes[byte] &= !mask;
+    }
+    /// # Panics
+    /// If the bit is past the end.
+    pub fn is_set(&self, bit: usize) -> Bit {
+        let (byte, mask) = self.locate(bit);
+        self.bytes[byte] & mask != 0
+    }
+    pub fn count_set(&self) -> usize {
+        (0..self.bits).filter(|f| self.is_set(*f)).count()
+    }
+    /// The first bit that is clear.
+    pub fn find_first_clear(&self) -> Option<usize> {
+        // Full bytes can be skipped without looking at their bits.
+        let byte = self.bytes.iter().position(|f| *f != 0xFF)?;
+        (byte * 8..self.bits).find(|f| !self.is_set(*f))
+    }
+    /// Where the first run of `length` clear bits starts, for
+    /// finding blocks that are next to each other.
+    ///
+    /// # Panics
+    /// If the length is zero.
+    pub fn find_run_clear(&self, length: usize) -> Option<usize> {
+        assert!(length > 0, "Can't find a run of nothing.");
+        let mut run = 0;
+        for bit in 0..self.bits {
+            run = match self.is_set(bit) {
+                true => 0,
+                false => run + 1
+            };
+            if run == length {
+                return Some(bit + 1 - length);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disks::{RawStoragePtr, SecondaryStorage, StorageDevice};
+
+    use super::Bitmap;
+
+    #[test]
+    pub fn test_bitmap_alloc_free() {
+        let mut bitmap = Bitmap::new(20);
+        assert_eq!(bitmap.to_bytes().len(), 3);
+
+        // Take blocks one at a time like an allocator would.
+        for expected in 0..10 {
+            let bit = bitmap.find_first_clear().unwrap();
+            assert_eq!(bit, expected);
+            bitmap.set(bit);
+        }
+        assert_eq!(bitmap.count_set(), 10);
+        bitmap.clear(3);
+        bitmap.clear(7);
+        assert!(!bitmap.is_set(3));
+        assert_eq!(bitmap.find_first_clear(), Some(3));
+        assert_eq!(bitmap.count_set(), 8);
+
+        // The leftover bits at the end of the last byte aren't blocks.
+        (0..20).for_each(|f| bitmap.set(f));
+        assert_eq!(bitmap.find_first_clear(), None);
+        assert_eq!(bitmap.to_bytes(), [0xFF, 0xFF, 0xF0]);
+    }
+
+    #[test]
+    pub fn test_bitmap_run() {
+        let mut bitmap = Bitmap::new(32);
+        for bit in [0, 1, 2, 5, 13, 20] {
+            bitmap.set(bit);
+        }
+        assert_eq!(bitmap.find_run_clear(2), Some(3));
+        assert_eq!(bitmap.find_run_clear(3), Some(6));
+        // From bit 6 to 12 goes over into the second byte.
+        assert_eq!(bitmap.find_run_clear(7), Some(6));
+        assert_eq!(bitmap.find_run_clear(11), Some(21));
+        assert_eq!(bitmap.find_run_clear(12), None);
+    }
+
+    #[test]
+    pub fn test_bitmap_storage() {
+        let mut bitmap = Bitmap::new(64);
+        for bit in [0, 9, 17, 63] {
+            bitmap.set(bit);
+        }
+        let mut storage = SecondaryStorage::new(64);
+        storage.write(RawStoragePtr::byte_ptr(8), &bitmap.to_bytes());
+
+        let loaded = Bitmap::from_bytes(&storage.read(RawStoragePtr::byte_ptr(8), 8));
+        assert_eq!(loaded, bitmap);
+        assert!(loaded.is_set(63));
+        assert_eq!(loaded.count_set(), 4);
+    }
+}
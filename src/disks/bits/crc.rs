@@ -0,0 +1,228 @@
+//! Cyclic redundancy checks, these catch far more than a parity bit
+//! does, including runs of flipped bits.
+
+use std::collections::BTreeMap;
+
+use parking_lot::Mutex;
+
+use crate::{disks::{AbstractStorageDevice, Bit, DiskError, RawStoragePtr}, memory::ipc::Yield};
+
+
+/// The remainder of every byte, worked out when this is compiled. CRC-8
+/// goes top bit first with the polynomial `x^8 + x^2 + x + 1`.
+const CRC8_TABLE: [u8; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// The same for CRC-32, this goes bottom bit first so the polynomial
+/// is reversed.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-8 with the polynomial `0x07`, starting from zero.
+pub fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, f| CRC8_TABLE[(crc ^ f) as usize])
+}
+
+/// CRC-32 as used by Ethernet and zip files.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, f| (crc >> 8) ^ CRC32_TABLE[((crc ^ *f as u32) & 0xFF) as usize])
+}
+
+/// How many bytes the checksum after each record takes.
+const CHECKSUM: usize = 4;
+
+/// Keeps a CRC-32 after every record stored on another device and checks
+/// it whenever the record is read, so corruption comes back as
+/// [DiskError::ChecksumMismatch] instead of wrong data.
+///
+/// A record is whatever went in one [AbstractStorageDevice::store], writes
+/// into a record update its checksum. Bytes that aren't in a record aren't
+/// checked and the checksums themselves shouldn't be written over.
+pub struct ChecksummedStorage<D> {
+    inner: D,
+    /// How long every record is by where it starts.
+    records: Mutex<BTreeMap<usize, usize>>
+}
+
+impl<D: AbstractStorageDevice> ChecksummedStorage<D> {
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            records: Mutex::new(BTreeMap::new())
+        }
+    }
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+    /// Where a range ends, as long as that fits in a `usize`.
+    fn end(&self, start: usize, length: usize) -> Result<usize, DiskError> {
+        start.checked_add(length).ok_or(DiskError::OutOfBounds {
+            addr: start,
+            len: length,
+            capacity: self.capacity()
+        })
+    }
+    /// Every record that has a byte in the range, as where it starts and how long it is.
+    fn overlapping(&self, start: usize, end: usize) -> Vec<(usize, usize)> {
+        self.records
+            .lock()
+            .range(..end)
+            .filter(|(record, size)| **record + **size > start)
+            .map(|(record, size)| (*record, *size))
+            .collect()
+    }
+    /// Reads a whole record and checks it against its checksum.
+    fn read_record(&self, start: usize, size: usize) -> Result<Vec<u8>, DiskError> {
+        let mut data = self.inner.try_read(RawStoragePtr::byte_ptr(start), size + CHECKSUM).get()?;
+        let checksum = data.split_off(size);
+        match crc32(&data).to_be_bytes() == checksum[..] {
+            true => Ok(data),
+            false => Err(DiskError::ChecksumMismatch { addr: start })
+        }
+    }
+    fn read_bytes(&self, start: usize, length: usize) -> Result<Vec<u8>, DiskError> {
+        let end = self.end(start, length)?;
+        let mut buffer = self.inner.try_read(RawStoragePtr::byte_ptr(start), length).get()?;
+        // Check every record this touches and take the bytes from the checked copy.
+        for (record, size) in self.overlapping(start, end) {
+            let data = self.read_record(record, size)?;
+            let from = record.max(start);
+            let to = (record + size).min(end);
+            buffer[from - start..to - start].copy_from_slice(&data[from - record..to - record]);
+        }
+        Ok(buffer)
+    }
+    fn write_bytes(&self, start: usize, data: &[u8]) -> Result<(), DiskError> {
+        let end = self.end(start, data.len())?;
+        let records = self.overlapping(start, end);
+        // A record has to be right before it is changed or the new checksum would cover up the damage.
+        let mut contents = records
+            .iter()
+            .map(|(record, size)| self.read_record(*record, *size))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.inner.try_write(RawStoragePtr::byte_ptr(start), data).get()?;
+        for ((record, size), content) in records.iter().zip(&mut contents) {
+            let from = (*record).max(start);
+            let to = (record + size).min(end);
+            content[from - record..to - record].copy_from_slice(&data[from - start..to - start]);
+            self.inner.try_write(RawStoragePtr::byte_ptr(record + size), &crc32(content).to_be_bytes()).get()?;
+        }
+        Ok(())
+    }
+}
+
+impl<D: AbstractStorageDevice> AbstractStorageDevice for ChecksummedStorage<D> {
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    fn try_read(&self, addr: RawStoragePtr, length: usize) -> Yield<Result<Vec<u8>, DiskError>> {
+        Yield::ready(self.read_bytes(addr.byte_offset, length))
+    }
+    fn try_write(&self, addr: RawStoragePtr, data: &[u8]) -> Yield<Result<(), DiskError>> {
+        Yield::ready(self.write_bytes(addr.byte_offset, data))
+    }
+    fn try_read_bit(&self, addr: RawStoragePtr) -> Yield<Result<Bit, DiskError>> {
+        let byte = self.read_bytes(addr.byte_offset, 1);
+        Yield::ready(byte.map(|f| (f[0] >> (7 - addr.bit_offset)) & 1 != 0))
+    }
+    fn try_write_bit(&self, addr: RawStoragePtr, bit: Bit) -> Yield<Result<(), DiskError>> {
+        let result = self.read_bytes(addr.byte_offset, 1).and_then(|f| {
+            let mask = 1 << (7 - addr.bit_offset);
+            let byte = if bit { f[0] | mask } else { f[0] & !mask };
+            self.write_bytes(addr.byte_offset, &[byte])
+        });
+        Yield::ready(result)
+    }
+    /// Stores the data with its checksum straight after it.
+    fn try_store(&self, data: &[u8]) -> Yield<Result<RawStoragePtr, DiskError>> {
+        let record = [data, &crc32(data).to_be_bytes()].concat();
+        let result = self.inner.try_store(&record).get();
+        if let Ok(addr) = result {
+            self.records.lock().insert(addr.byte_offset, data.len());
+        }
+        Yield::ready(result)
+    }
+    /// Freeing the start of a record frees its checksum too.
+    fn try_free(&self, addr: RawStoragePtr, length: usize) -> Yield<Result<(), DiskError>> {
+        let mut records = self.records.lock();
+        let length = match records.get(&addr.byte_offset) {
+            Some(size) if *size == length => length + CHECKSUM,
+            _ => length
+        };
+        let result = self.inner.try_free(addr, length).get();
+        if result.is_ok() {
+            records.remove(&addr.byte_offset);
+        }
+        Yield::ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disks::{hard_drive::{DiskAlgorithm, MagneticDisk}, AbstractStorageDevice, DiskError, RawStoragePtr};
+
+    use super::{crc32, crc8, ChecksummedStorage};
+
+    #[test]
+    pub fn test_crc_vectors() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414F_A339);
+        assert_eq!(crc32(&[]), 0);
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc8(&[]), 0);
+    }
+
+    #[test]
+    pub fn test_checksummed_storage() {
+        let disk = MagneticDisk::new(256, DiskAlgorithm::FCFS);
+        let storage = ChecksummedStorage::new(disk.clone());
+        let a = storage.store(&[1, 2, 3, 4]).get();
+        let b = storage.store(&[5, 6, 7]).get();
+        // The checksum goes after the first record.
+        assert_eq!(b.byte_offset, a.byte_offset + 8);
+        assert_eq!(storage.read(a, 4).get(), [1, 2, 3, 4]);
+        assert_eq!(storage.read(RawStoragePtr::byte_ptr(2), 1).get(), [3]);
+
+        // Writing into a record keeps its checksum right.
+        storage.write(RawStoragePtr::byte_ptr(b.byte_offset + 1), &[60]).get();
+        assert_eq!(storage.read(b, 3).get(), [5, 60, 7]);
+
+        // Changing a byte behind its back is noticed.
+        disk.write(RawStoragePtr::byte_ptr(1), &[99]).get();
+        assert_eq!(storage.try_read(a, 4).get(), Err(DiskError::ChecksumMismatch { addr: a.byte_offset }));
+        assert_eq!(storage.try_read(RawStoragePtr::byte_ptr(3), 1).get(), Err(DiskError::ChecksumMismatch { addr: a.byte_offset }));
+        assert_eq!(storage.try_write(a, &[0]).get(), Err(DiskError::ChecksumMismatch { addr: a.byte_offset }));
+        assert_eq!(storage.read(b, 3).get(), [5, 60, 7]);
+
+        // Ranges that run past the end of the address space are out of bounds.
+        let error = DiskError::OutOfBounds { addr: usize::MAX, len: 2, capacity: 256 };
+        assert_eq!(storage.try_write(RawStoragePtr::byte_ptr(usize::MAX), &[1, 2]).get(), Err(error));
+        assert_eq!(storage.try_read(RawStoragePtr::byte_ptr(usize::MAX), 2).get(), Err(error));
+    }
+}
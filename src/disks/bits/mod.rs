@@ -1,34 +1,127 @@
-use std::{fmt::Debug, ops::Index, slice::SliceIndex};
+use std::{fmt::Debug, ops::{BitAnd, BitOr, BitXor, Index, Not}, slice::SliceIndex};
+
+pub mod bitmap;
+pub mod crc;
 
 pub type Bit = bool;
 
 /// A bit vector that stores the bit values as booleans, this is
 /// for working with bits in an easy way.
-#[derive(Debug, Clone)]
-pub struct BitVec([bool; 8]);
+///
+/// Bits go most significant first, so the first bit of a byte is its
+/// top bit.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BitVec(Vec<bool>);
 
 impl BitVec {
+    /// An empty bit vector with room for this many bits.
+    pub fn with_capacity(bits: usize) -> Self {
+        Self(Vec::with_capacity(bits))
+    }
+    /// Splits bytes up into their bits.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self(bytes.iter().flat_map(|f| (0..8).map(move |i| (f >> (7 - i)) & 1 != 0)).collect())
+    }
+    /// Packs the bits back into bytes, the last one is padded
+    /// with zeros if the length isn't a multiple of eight.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0
+            .chunks(8)
+            .map(|f| f.iter().enumerate().fold(0, |acc, (i, bit)| acc | (*bit as u8) << (7 - i)))
+            .collect()
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn push(&mut self, bit: Bit) {
+        self.0.push(bit);
+    }
+    pub fn get(&self, index: usize) -> Option<Bit> {
+        self.0.get(index).copied()
+    }
+    /// # Panics
+    /// If the index is past the end.
+    pub fn set(&mut self, index: usize, bit: Bit) {
+        self.0[index] = bit;
+    }
+    pub fn count_ones(&self) -> usize {
+        self.0.iter().filter(|f| **f).count()
+    }
     /// Calculates the parity of the bit vector by counting the ones
     /// and informing us if they are odd.
     pub fn parity(&self) -> Bit {
-        let mut ones = 0;
-        for i in 0..self.0.len() {
-            if self.0[i] {
-                ones += 1;
-            }
-        }
-        ones % 2 == 1
+        self.count_ones() % 2 == 1
+    }
+    /// Combines two vectors a bit at a time.
+    ///
+    /// # Panics
+    /// If they aren't the same length.
+    fn zip_with(self, rhs: Self, f: impl Fn(Bit, Bit) -> Bit) -> Self {
+        assert_eq!(self.len(), rhs.len(), "The bit vectors aren't the same length.");
+        Self(self.0.into_iter().zip(rhs.0).map(|(a, b)| f(a, b)).collect())
+    }
+}
+
+/// Why a code word couldn't be decoded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EccError {
+    /// Two bits flipped, this can be noticed but not fixed.
+    Uncorrectable,
+    /// The code word isn't eight bits long.
+    WrongLength(usize)
+}
+
+/// Where the data bits of a nibble go in a code word, top bit first.
+const DATA_BITS: [usize; 4] = [3, 5, 6, 7];
+
+/// Encodes a nibble as a Hamming(7,4) code word with an extra parity bit so
+/// one flipped bit can be fixed and two can be noticed (SECDED). Bits one to
+/// seven are the Hamming code with the check bits at one, two and four, and
+/// bit zero is the parity of the whole word.
+///
+/// Only the bottom four bits of the nibble are used.
+pub fn hamming_encode(nibble: u8) -> BitVec {
+    let mut word = [false; 8];
+    for (i, position) in DATA_BITS.iter().enumerate() {
+        word[*position] = (nibble >> (3 - i)) & 1 != 0;
+    }
+    // Each check bit covers the positions that have its bit set.
+    for check in [1, 2, 4] {
+        word[check] = (1..8).filter(|f| f & check != 0 && *f != check).fold(false, |acc, f| acc ^ word[f]);
     }
+    word[0] = word[1..].iter().fold(false, |acc, f| acc ^ f);
+    BitVec(word.to_vec())
 }
 
+/// Decodes a code word from [hamming_encode], giving back the nibble and the
+/// position of the bit that had to be flipped back if there was one.
+pub fn hamming_decode(word: BitVec) -> Result<(u8, Option<usize>), EccError> {
+    if word.len() != 8 {
+        return Err(EccError::WrongLength(word.len()));
+    }
+    let mut word = word.0;
+    // The positions of the set bits XOR to where a single flipped bit is.
+    let syndrome = (1..8).filter(|f| word[*f]).fold(0, |acc, f| acc ^ f);
+    let fixed = match (syndrome, word.iter().fold(false, |acc, f| acc ^ f)) {
+        (0, false) => None,
+        // One bit flipped, if the syndrome is zero it was the parity bit.
+        (position, true) => {
+            word[position] = !word[position];
+            Some(position)
+        }
+        // Two flips cancel out in the parity but not in the syndrome.
+        (_, false) => return Err(EccError::Uncorrectable)
+    };
+    let nibble = DATA_BITS.iter().fold(0, |acc, f| acc << 1 | word[*f] as u8);
+    Ok((nibble, fixed))
+}
 
 impl From<u8> for BitVec {
     fn from(value: u8) -> Self {
-        let mut vec = [false; 8];
-        for i in 0..8 {
-            vec[i] = ((value >> (7 - i)) & 1) != 0;
-        }
-        Self(vec)
+        Self::from_bytes(&[value])
     }
 }
 
@@ -39,24 +132,45 @@ impl<Idx: SliceIndex<[bool]>> Index<Idx> for BitVec {
     }
 }
 
-
-
+/// The first eight bits, a shorter vector is padded with zeros.
 impl From<BitVec> for u8 {
     fn from(value: BitVec) -> Self {
-        let mut number = 0;
-        for i in 0..8 {
-            if value.0[i] {
-                number |= 1 << (7 - i);
-            }
-        }
-        number
+        value.to_bytes().first().copied().unwrap_or(0)
+    }
+}
+
+impl BitAnd for BitVec {
+    type Output = Self;
+    fn bitand(self, rhs: Self) -> Self {
+        self.zip_with(rhs, |a, b| a & b)
+    }
+}
+
+impl BitOr for BitVec {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        self.zip_with(rhs, |a, b| a | b)
+    }
+}
+
+impl BitXor for BitVec {
+    type Output = Self;
+    fn bitxor(self, rhs: Self) -> Self {
+        self.zip_with(rhs, |a, b| a ^ b)
+    }
+}
+
+impl Not for BitVec {
+    type Output = Self;
+    fn not(self) -> Self {
+        Self(self.0.into_iter().map(|f| !f).collect())
     }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use crate::disks::bits::BitVec;
+    use crate::disks::bits::{hamming_decode, hamming_encode, BitVec, EccError};
 
 
     #[test]
@@ -73,4 +187,75 @@ mod tests {
         assert_eq!(BitVec::from(3 as u8).parity(), false);
         assert_eq!(BitVec::from(1 as u8).parity(), true);
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn test_bitvec_bytes() {
+        let bytes = [0xDE, 0xAD, 0xBE, 0xEF, 0x01];
+        let bv = BitVec::from_bytes(&bytes);
+        assert_eq!(bv.len(), 40);
+        assert_eq!(bv.to_bytes(), bytes);
+        assert_eq!(bv.get(0), Some(true));
+        assert_eq!(bv.get(39), Some(true));
+        assert_eq!(bv.get(40), None);
+
+        // A partial byte is padded on the end.
+        let mut bv = BitVec::with_capacity(3);
+        for bit in [true, false, true] {
+            bv.push(bit);
+        }
+        assert_eq!(bv.to_bytes(), [0b1010_0000]);
+        bv.set(1, true);
+        assert_eq!(u8::from(bv), 0b1110_0000);
+    }
+
+    #[test]
+    pub fn test_bitvec_ops() {
+        let (a, b) = ([0x0F, 0xF0, 0x33], [0xFF, 0x0F, 0x55]);
+        let (x, y) = (BitVec::from_bytes(&a), BitVec::from_bytes(&b));
+        let xor: Vec<_> = a.iter().zip(b).map(|(a, b)| a ^ b).collect();
+        assert_eq!((x.clone() ^ y.clone()).to_bytes(), xor);
+        assert_eq!((x.clone() & y.clone()).to_bytes(), [0x0F, 0x00, 0x11]);
+        assert_eq!((x.clone() | y).to_bytes(), [0xFF, 0xFF, 0x77]);
+        assert_eq!((!x).to_bytes(), [0xF0, 0x0F, 0xCC]);
+    }
+
+    #[test]
+    pub fn test_bitvec_parity() {
+        // The parity of the bytes together is the XOR of their parities.
+        for (a, b) in [(3u8, 1u8), (0xFF, 0x80), (0x12, 0x34)] {
+            let together = BitVec::from_bytes(&[a, b]);
+            assert_eq!(together.parity(), BitVec::from(a).parity() ^ BitVec::from(b).parity());
+            assert_eq!(together.count_ones(), (a.count_ones() + b.count_ones()) as usize);
+        }
+    }
+
+    #[test]
+    pub fn test_hamming_single_bit() {
+        for nibble in 0..16 {
+            let word = hamming_encode(nibble);
+            assert_eq!(word.len(), 8);
+            assert_eq!(hamming_decode(word.clone()), Ok((nibble, None)));
+            for position in 0..8 {
+                let mut flipped = word.clone();
+                flipped.set(position, !word[position]);
+                assert_eq!(hamming_decode(flipped), Ok((nibble, Some(position))));
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_hamming_double_bit() {
+        for nibble in 0..16 {
+            let word = hamming_encode(nibble);
+            for a in 0..8 {
+                for b in a + 1..8 {
+                    let mut flipped = word.clone();
+                    flipped.set(a, !word[a]);
+                    flipped.set(b, !word[b]);
+                    assert_eq!(hamming_decode(flipped), Err(EccError::Uncorrectable));
+                }
+            }
+        }
+        assert_eq!(hamming_decode(BitVec::from_bytes(&[0, 0])), Err(EccError::WrongLength(16)));
+    }
+}
@@ -0,0 +1,291 @@
+//! A buffer cache, this keeps recently used blocks of a disk
+//! in memory so repeated requests don't have to go to it.
+
+use std::collections::HashMap;
+
+use parking_lot::Mutex;
+
+use crate::memory::ipc::Yield;
+
+use super::{AbstractStorageDevice, Bit, DiskError, RawStoragePtr};
+
+
+/// How well the [BufferCache] has been doing.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    /// How many blocks were thrown out to make room.
+    pub evictions: usize,
+    /// How many dirty blocks were written to the device.
+    pub write_backs: usize
+}
+
+struct Block {
+    data: Vec<u8>,
+    /// The block has been changed since it was last written to the device.
+    dirty: bool,
+    last_used: u64
+}
+
+struct CacheState {
+    /// Cached blocks by their index on the device.
+    blocks: HashMap<usize, Block>,
+    clock: u64,
+    write_through: bool,
+    stats: CacheStats
+}
+
+/// Caches fixed size blocks of another device and throws out the least
+/// recently used one when it is full.
+///
+/// By default writes only go to the cache and the device gets them when the
+/// block is thrown out or on [BufferCache::flush], in write through mode they
+/// go to both. The device should be a whole number of blocks long.
+pub struct BufferCache<D> {
+    inner: D,
+    block_size: usize,
+    capacity: usize,
+    state: Mutex<CacheState>
+}
+
+impl<D: AbstractStorageDevice> BufferCache<D> {
+    /// Caches up to `capacity` blocks of `block_size` bytes.
+    ///
+    /// # Panics
+    /// If either is zero.
+    pub fn new(inner: D, block_size: usize, capacity: usize) -> Self {
+        assert!(block_size > 0 && capacity > 0, "The cache has to hold something.");
+        Self {
+            inner,
+            block_size,
+            capacity,
+            state: Mutex::new(CacheState {
+                blocks: HashMap::new(),
+                clock: 0,
+                write_through: false,
+                stats: CacheStats::default()
+            })
+        }
+    }
+    pub fn with_write_through(self, write_through: bool) -> Self {
+        self.state.lock().write_through = write_through;
+        self
+    }
+    /// Switches write through on or off, turning it on
+    /// flushes whatever is dirty.
+    pub fn set_write_through(&self, write_through: bool) -> Result<(), DiskError> {
+        let mut state = self.state.lock();
+        state.write_through = write_through;
+        if write_through {
+            self.flush_blocks(&mut state)?;
+        }
+        Ok(())
+    }
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+    pub fn stats(&self) -> CacheStats {
+        self.state.lock().stats
+    }
+    /// Writes every dirty block to the device.
+    pub fn flush(&self) -> Result<(), DiskError> {
+        self.flush_blocks(&mut self.state.lock())
+    }
+    fn flush_blocks(&self, state: &mut CacheState) -> Result<(), DiskError> {
+        let mut dirty: Vec<_> = state.blocks.iter_mut().filter(|(_, f)| f.dirty).collect();
+        dirty.sort_by_key(|(index, _)| **index);
+        for (index, block) in dirty {
+            self.inner.try_write(RawStoragePtr::byte_ptr(index * self.block_size), &block.data).get()?;
+            block.dirty = false;
+            state.stats.write_backs += 1;
+        }
+        Ok(())
+    }
+    /// Gets a block into the cache, reading it from the device unless
+    /// the whole thing is about to be overwritten.
+    fn load<'a>(&self, state: &'a mut CacheState, index: usize, overwrite: bool) -> Result<&'a mut Block, DiskError> {
+        state.clock += 1;
+        let clock = state.clock;
+        if state.blocks.contains_key(&index) {
+            state.stats.hits += 1;
+        } else {
+            state.stats.misses += 1;
+            let data = match overwrite {
+                true => vec![0; self.block_size],
+                false => self.inner.try_read(RawStoragePtr::byte_ptr(index * self.block_size), self.block_size).get()?
+            };
+            if state.blocks.len() >= self.capacity {
+                self.evict(state)?;
+            }
+            state.blocks.insert(index, Block { data, dirty: false, last_used: clock });
+        }
+        let block = state.blocks.get_mut(&index).unwrap();
+        block.last_used = clock;
+        Ok(block)
+    }
+    /// Throws out the least recently used block, writing it back if it is dirty.
+    fn evict(&self, state: &mut CacheState) -> Result<(), DiskError> {
+        let (&index, victim) = state.blocks.iter().min_by_key(|(_, f)| f.last_used).unwrap();
+        if victim.dirty {
+            self.inner.try_write(RawStoragePtr::byte_ptr(index * self.block_size), &victim.data).get()?;
+            state.stats.write_backs += 1;
+        }
+        state.blocks.remove(&index);
+        state.stats.evictions += 1;
+        Ok(())
+    }
+    /// Splits a run of bytes up by the blocks it covers, giving the block,
+    /// where it starts in the block and where it starts in the run.
+    fn spans(&self, addr: usize, length: usize) -> Vec<(usize, usize, usize, usize)> {
+        let mut spans = vec![];
+        let mut done = 0;
+        while done < length {
+            let at = addr + done;
+            let offset = at % self.block_size;
+            let count = (self.block_size - offset).min(length - done);
+            spans.push((at / self.block_size, offset, done, count));
+            done += count;
+        }
+        spans
+    }
+    fn read_bytes(&self, addr: usize, length: usize) -> Result<Vec<u8>, DiskError> {
+        let mut state = self.state.lock();
+        let mut out = vec![0; length];
+        for (index, offset, done, count) in self.spans(addr, length) {
+            let block = self.load(&mut state, index, false)?;
+            out[done..done + count].copy_from_slice(&block.data[offset..offset + count]);
+        }
+        Ok(out)
+    }
+    fn write_bytes(&self, addr: usize, data: &[u8]) -> Result<(), DiskError> {
+        let mut state = self.state.lock();
+        let write_through = state.write_through;
+        for (index, offset, done, count) in self.spans(addr, data.len()) {
+            let block = self.load(&mut state, index, count == self.block_size)?;
+            block.data[offset..offset + count].copy_from_slice(&data[done..done + count]);
+            block.dirty |= !write_through;
+        }
+        if write_through {
+            self.inner.try_write(RawStoragePtr::byte_ptr(addr), data).get()?;
+        }
+        Ok(())
+    }
+    /// Changes whatever copies the cache has of bytes that were
+    /// changed on the device directly.
+    fn refresh(&self, addr: usize, data: &[u8]) {
+        let mut state = self.state.lock();
+        for (index, offset, done, count) in self.spans(addr, data.len()) {
+            if let Some(block) = state.blocks.get_mut(&index) {
+                block.data[offset..offset + count].copy_from_slice(&data[done..done + count]);
+            }
+        }
+    }
+}
+
+impl<D: AbstractStorageDevice> AbstractStorageDevice for BufferCache<D> {
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    fn try_read(&self, addr: RawStoragePtr, length: usize) -> Yield<Result<Vec<u8>, DiskError>> {
+        Yield::ready(self.read_bytes(addr.byte_offset, length))
+    }
+    fn try_write(&self, addr: RawStoragePtr, data: &[u8]) -> Yield<Result<(), DiskError>> {
+        Yield::ready(self.write_bytes(addr.byte_offset, data))
+    }
+    fn try_read_bit(&self, addr: RawStoragePtr) -> Yield<Result<Bit, DiskError>> {
+        let byte = self.read_bytes(addr.byte_offset, 1);
+        Yield::ready(byte.map(|f| (f[0] >> (7 - addr.bit_offset)) & 1 != 0))
+    }
+    fn try_write_bit(&self, addr: RawStoragePtr, bit: Bit) -> Yield<Result<(), DiskError>> {
+        let result = self.read_bytes(addr.byte_offset, 1).and_then(|f| {
+            let mask = 1 << (7 - addr.bit_offset);
+            let byte = if bit { f[0] | mask } else { f[0] & !mask };
+            self.write_bytes(addr.byte_offset, &[byte])
+        });
+        Yield::ready(result)
+    }
+    /// The device picks where this goes so it is sent straight
+    /// there, any cached copies are brought up to date.
+    fn try_store(&self, data: &[u8]) -> Yield<Result<RawStoragePtr, DiskError>> {
+        let result = self.inner.try_store(data).get();
+        if let Ok(addr) = result {
+            self.refresh(addr.byte_offset, data);
+        }
+        Yield::ready(result)
+    }
+    fn try_free(&self, addr: RawStoragePtr, length: usize) -> Yield<Result<(), DiskError>> {
+        let result = self.inner.try_free(addr, length).get();
+        if result.is_ok() {
+            self.refresh(addr.byte_offset, &vec![0; length]);
+        }
+        Yield::ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disks::{hard_drive::{DiskAlgorithm, MagneticDisk}, AbstractStorageDevice, RawStoragePtr};
+
+    use super::BufferCache;
+
+    fn cache(capacity: usize) -> BufferCache<MagneticDisk> {
+        BufferCache::new(MagneticDisk::new(1024, DiskAlgorithm::FCFS), 64, capacity)
+    }
+
+    #[test]
+    pub fn test_cache_hits() {
+        let cache = cache(4);
+        for _ in 0..10 {
+            assert_eq!(cache.read(RawStoragePtr::byte_ptr(10), 4).get(), [0; 4]);
+        }
+        // Only the first read went to the disk.
+        assert_eq!(cache.inner().service_record(), [0]);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (9, 1));
+
+        // This one spans two blocks, the first is already cached.
+        assert_eq!(cache.read(RawStoragePtr::byte_ptr(60), 8).get(), [0; 8]);
+        assert_eq!(cache.inner().service_record(), [0, 64]);
+    }
+
+    #[test]
+    pub fn test_cache_write_back() {
+        let cache = cache(2);
+        cache.write(RawStoragePtr::byte_ptr(62), &[1, 2, 3, 4]).get();
+        assert_eq!(cache.read(RawStoragePtr::byte_ptr(62), 4).get(), [1, 2, 3, 4]);
+        // The writes are only in the cache so far.
+        assert_eq!(cache.inner().read(RawStoragePtr::byte_ptr(62), 4).get(), [0; 4]);
+
+        // Pulling in another block throws out the first dirty one.
+        cache.read(RawStoragePtr::byte_ptr(200), 1).get();
+        assert_eq!(cache.stats().evictions, 1);
+        assert_eq!(cache.inner().read(RawStoragePtr::byte_ptr(62), 4).get(), [1, 2, 0, 0]);
+
+        cache.flush().unwrap();
+        assert_eq!(cache.inner().read(RawStoragePtr::byte_ptr(62), 4).get(), [1, 2, 3, 4]);
+        assert_eq!(cache.stats().write_backs, 2);
+    }
+
+    #[test]
+    pub fn test_cache_write_through() {
+        let cache = cache(2).with_write_through(true);
+        cache.write(RawStoragePtr::byte_ptr(0), &[7; 64]).get();
+        cache.write_bit(RawStoragePtr::bit_ptr(64 * 8), true).get();
+        assert_eq!(cache.inner().read(RawStoragePtr::byte_ptr(0), 65).get()[63..], [7, 128]);
+        assert!(cache.read_bit(RawStoragePtr::bit_ptr(64 * 8)).get());
+
+        // The whole block was overwritten so it never had to be read.
+        assert_eq!(cache.stats().misses, 2);
+        assert_eq!(cache.inner().service_record()[..3], [0, 64, 64]);
+    }
+
+    #[test]
+    pub fn test_cache_store() {
+        let cache = cache(2);
+        cache.read(RawStoragePtr::byte_ptr(0), 1).get();
+        let addr = cache.store(&[5, 6]).get();
+        assert_eq!(cache.read(addr, 2).get(), [5, 6]);
+        assert_eq!(cache.stats().hits, 1);
+    }
+}
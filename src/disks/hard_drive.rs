@@ -1,16 +1,25 @@
 use std::{
+    io,
+    ops::Range,
+    path::Path,
     sync::{
-        atomic::{AtomicU8, AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
-    thread::yield_now,
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
-use parking_lot::Mutex;
+use parking_lot::{Condvar, Mutex};
 
-use crate::memory::ipc::{IpcChannel, Yield};
+#[cfg(feature = "async")]
+use std::future::Future;
 
-use super::{AbstractStorageDevice, Bit, RawStoragePtr, SecondaryStorage, StorageDevice};
+use crate::{memory::ipc::{IpcChannel, Yield}, rng::SimRng};
+#[cfg(feature = "async")]
+use crate::memory::ipc::AsyncYield;
+
+use super::{expect_disk, AbstractStorageDevice, Bit, DiskError, RawStoragePtr, SecondaryStorage, StorageDevice};
 
 #[derive(PartialEq)]
 pub enum DiskAlgorithm {
@@ -22,126 +31,642 @@ pub enum DiskAlgorithm {
     /// Scans across the disk servicing requests as we go,
     /// once we reach the end we go back and service request.
     SCAN,
+    /// SCAN but turns around at the last request instead of
+    /// going all the way to the end.
+    LOOK,
     /// Scans across the disk but unlike SCAN, when we reach
     /// the end we just jump back to the beginning.
     CSCAN,
     /// CSCAN but jumps to the beginning.
-    CLOOK
+    CLOOK,
+    /// Real time requests by earliest deadline, then best effort ones
+    /// by priority and shortest seek and idle ones when nothing else
+    /// is waiting. See [IoClass].
+    Deadline,
+    /// SCAN over batches of this many requests, anything that comes
+    /// in while a batch is being serviced waits for the next one so
+    /// requests near the head can't starve the rest.
+    NStepScan(usize),
+    /// SCAN with two queues, the sweep works through everything that
+    /// was waiting when it started and anything that comes in meanwhile
+    /// waits in the other queue for the next sweep.
+    FSCAN
+}
+
+/// How many bytes are in a sector.
+pub const SECTOR_SIZE: usize = 512;
+
+/// The physical layout of a disk, this is used to work out
+/// how long requests would take on a real one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiskGeometry {
+    pub tracks: usize,
+    pub sectors_per_track: usize,
+    pub rpm: u32,
+    /// How long it takes the head to move one track over.
+    pub seek_ms_per_track: f64
+}
+
+impl DiskGeometry {
+    /// How many bytes the disk holds.
+    pub fn size(&self) -> usize {
+        self.tracks * self.sectors_per_track * SECTOR_SIZE
+    }
+    /// The (track, sector) a byte is in.
+    pub fn locate(&self, byte: usize) -> (usize, usize) {
+        let sector = byte / SECTOR_SIZE;
+        (sector / self.sectors_per_track, sector % self.sectors_per_track)
+    }
+    /// How long a full turn of the platter takes.
+    pub fn rotation_ms(&self) -> f64 {
+        60_000.0 / self.rpm as f64
+    }
+    /// How long it takes to seek between two bytes, this is
+    /// just the head moving and not the rotation.
+    pub fn seek_ms(&self, from: usize, to: usize) -> f64 {
+        self.locate(from).0.abs_diff(self.locate(to).0) as f64 * self.seek_ms_per_track
+    }
+    /// The time to seek to a request, wait half a turn on average for
+    /// the sector to come round and then read or write it.
+    pub fn service_ms(&self, from: usize, to: usize, length: usize) -> f64 {
+        self.seek_ms(from, to) + self.rotation_ms() / 2.0 + self.transfer_ms(length)
+    }
+    /// How long the bytes take to pass under the head.
+    pub fn transfer_ms(&self, length: usize) -> f64 {
+        self.rotation_ms() * length as f64 / (self.sectors_per_track * SECTOR_SIZE) as f64
+    }
+}
+
+/// Keeps track of how far the head has moved and how
+/// long that would have taken.
+struct HeadMotion {
+    /// Keeps track of all the requests serviced, mostly
+    /// used for testing.
+    record: Mutex<Vec<usize>>,
+    /// How far the head has moved in total.
+    travel: AtomicUsize,
+    geometry: Option<DiskGeometry>,
+    /// The virtual time each request took, in the order they were serviced.
+    times: Mutex<Vec<f64>>,
+    /// Seeking that was not on the way to a request, like SCAN running to
+    /// the end, this is added onto the next request.
+    detour: Mutex<f64>,
+    /// Where the wait for the sector to come round is drawn from,
+    /// without one it is always half a turn.
+    rng: Mutex<Option<SimRng>>
+}
+
+impl HeadMotion {
+    fn new(geometry: Option<DiskGeometry>) -> Self {
+        Self {
+            record: Mutex::default(),
+            travel: AtomicUsize::new(0),
+            geometry,
+            times: Mutex::default(),
+            detour: Mutex::default(),
+            rng: Mutex::default()
+        }
+    }
+    /// Moves the head without servicing anything.
+    fn seek(&self, from: usize, to: usize) {
+        self.travel.fetch_add(from.abs_diff(to), Ordering::SeqCst);
+        if let Some(geometry) = &self.geometry {
+            *self.detour.lock() += geometry.seek_ms(from, to);
+        }
+    }
+    /// Moves the head to a request and services it.
+    fn service(&self, from: usize, to: usize, length: usize) {
+        self.record.lock().push(to);
+        self.travel.fetch_add(from.abs_diff(to), Ordering::SeqCst);
+        if let Some(geometry) = &self.geometry {
+            let detour = std::mem::take(&mut *self.detour.lock());
+            let time = match &*self.rng.lock() {
+                Some(rng) => geometry.seek_ms(from, to) + rng.gen_range(0.0..geometry.rotation_ms()) + geometry.transfer_ms(length),
+                None => geometry.service_ms(from, to, length)
+            };
+            self.times.lock().push(detour + time);
+        }
+    }
+}
+
+/// How urgent a request is, this only matters
+/// for [DiskAlgorithm::Deadline].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IoClass {
+    /// Has to be done within this many milliseconds.
+    RealTime(u64),
+    /// Done when it can be, lower priorities go first.
+    BestEffort(u8),
+    /// Only done when the disk has nothing else to do.
+    Idle
+}
+
+impl Default for IoClass {
+    fn default() -> Self {
+        Self::BestEffort(4)
+    }
 }
 
+/// A request along with how urgent it is and when it was sent.
+type Submission = (ServiceRequest, IoClass, Instant);
+
+/// Where the disk sends back the result of a request.
+pub type Reply<T> = Arc<IpcChannel<Result<T, DiskError>>>;
+
 pub enum ServiceRequest {
     Read {
         addr: RawStoragePtr,
-        outbound: Arc<IpcChannel<Vec<u8>>>,
+        outbound: Reply<Vec<u8>>,
         length: usize,
     },
     Write {
         bytes: Vec<u8>,
-        inbound: Arc<IpcChannel<RawStoragePtr>>,
+        inbound: Reply<RawStoragePtr>,
     },
     ReadBit {
         addr: RawStoragePtr,
-        outbound: Arc<IpcChannel<bool>>,
+        outbound: Reply<bool>,
     },
     WriteBit {
         addr: RawStoragePtr,
         value: Bit,
-        confirm: Arc<IpcChannel<()>>,
+        confirm: Reply<()>,
     },
     Edit {
         addr: RawStoragePtr,
         data: Vec<u8>,
-        confirm: Arc<IpcChannel<()>>,
+        confirm: Reply<()>,
+    },
+    Free {
+        addr: RawStoragePtr,
+        length: usize,
+        confirm: Reply<()>,
     }
 }
 
-pub struct MagneticDisk {
-    /// All the scheduled service rquests.
-    requests: Arc<IpcChannel<ServiceRequest>>,
-
-    /// Keeps track of all the requests serviced, mostly
-    /// used for testing.
-    service_record: Arc<Mutex<Vec<usize>>>,
-
+/// How an injected failure makes the disk misbehave.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FailureMode {
+    /// Every request fails.
+    FailAll,
+    /// Requests that touch these bytes fail.
+    FailRegion(Range<usize>),
+    /// Reads of this byte come back with a bit flipped and no error.
+    SilentCorruption(usize)
+}
 
-    offset: Arc<AtomicUsize>,
+impl FailureMode {
+    /// Whether a request for these bytes fails.
+    fn fails(&self, addr: usize, length: usize) -> bool {
+        match self {
+            Self::FailAll => true,
+            Self::FailRegion(region) => addr < region.end && region.start < addr + length.max(1),
+            Self::SilentCorruption(_) => false
+        }
+    }
+}
 
+/// What the disk thread has to respect, this holds the
+/// thread while the disk is paused.
+struct DiskState {
     /// The states are as follows,
     /// 0 = Paused
     /// 1 = Running
     /// 2 = Shutdown
-    state: Arc<AtomicU8>,
+    /// 3 = Stepping, paused again once the next request is let through
+    state: Mutex<u8>,
+    signal: Condvar,
+    failure: Mutex<Option<FailureMode>>,
+    /// Whether edits next to each other get merged into one.
+    coalesce: AtomicBool,
+    /// Requests that have been sent and are waiting to be serviced.
+    waiting: AtomicUsize,
+    /// Whether a request is being serviced right now.
+    busy: AtomicBool
+}
+
+impl DiskState {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(1),
+            signal: Condvar::new(),
+            failure: Mutex::new(None),
+            coalesce: AtomicBool::new(false),
+            waiting: AtomicUsize::new(0),
+            busy: AtomicBool::new(false)
+        }
+    }
+    fn set(&self, state: u8) {
+        *self.state.lock() = state;
+        self.signal.notify_all();
+    }
+    /// Waits until the disk is not paused.
+    fn wait(&self) {
+        let mut state = self.state.lock();
+        while *state == 0 {
+            self.signal.wait(&mut state);
+        }
+        if *state == 3 {
+            *state = 0;
+        }
+    }
+    fn in_flight(&self) -> usize {
+        self.waiting.load(Ordering::SeqCst) + self.busy.load(Ordering::SeqCst) as usize
+    }
+    /// Takes requests off the waiting count as they go under the head.
+    fn start(&self, requests: usize) {
+        // Busy goes up first so the disk never looks idle in between.
+        self.busy.store(true, Ordering::SeqCst);
+        self.waiting.fetch_sub(requests, Ordering::SeqCst);
+    }
+    fn finish(&self) {
+        self.busy.store(false, Ordering::SeqCst);
+        // Notify while holding the lock so a waiter can't miss it.
+        let _state = self.state.lock();
+        self.signal.notify_all();
+    }
+}
+
+impl ServiceRequest {
+    /// How many bytes the request moves.
+    fn length(&self) -> usize {
+        match self {
+            Self::Read { length, .. } | Self::Free { length, .. } => *length,
+            Self::Write { bytes, .. } => bytes.len(),
+            Self::Edit { data, .. } => data.len(),
+            Self::ReadBit { .. } | Self::WriteBit { .. } => 1,
+        }
+    }
+    /// Sends back an error instead of servicing the request.
+    fn fail(self, error: DiskError) {
+        let _ = match self {
+            Self::Read { outbound, .. } => outbound.send(Err(error)),
+            Self::Write { inbound, .. } => inbound.send(Err(error)),
+            Self::ReadBit { outbound, .. } => outbound.send(Err(error)),
+            Self::WriteBit { confirm, .. } | Self::Edit { confirm, .. } | Self::Free { confirm, .. } => confirm.send(Err(error)),
+        };
+    }
+}
+
+/// A disk with a thread that services the requests sent to it.
+///
+/// Cloning a disk gives another handle to the same one, so something
+/// like a RAID array can own a disk while we keep a handle to poke at it.
+/// The disk is shut down once every handle has been dropped.
+#[derive(Clone)]
+pub struct MagneticDisk {
+    /// All the scheduled service rquests.
+    requests: Arc<IpcChannel<Submission>>,
+
+    offset: Arc<AtomicUsize>,
+
+    motion: Arc<HeadMotion>,
+
+    state: Arc<DiskState>,
+
+    /// The thread servicing the requests, this is taken
+    /// when it gets joined.
+    worker: Arc<Mutex<Option<JoinHandle<()>>>>,
+
+    handles: Arc<Handles>,
+
+    capacity: usize,
+}
+
+/// Shuts the disk down when the last handle to it is dropped.
+struct Handles {
+    requests: Arc<IpcChannel<Submission>>,
+    state: Arc<DiskState>
+}
+
+impl Handles {
+    fn shutdown(&self) {
+        self.requests.close();
+        self.state.set(2);
+    }
+}
+
+impl Drop for Handles {
+    fn drop(&mut self) {
+        // Let the thread finish off what it has and stop.
+        self.shutdown();
+    }
 }
 
 impl MagneticDisk {
     pub fn new(size: usize, algorithm: DiskAlgorithm) -> Self {
+        Self::spawn(SecondaryStorage::new(size), algorithm, None)
+    }
+    /// Creates a disk that is kept in a file, so whatever was on it
+    /// is still there when it is opened again.
+    pub fn new_persistent(path: impl AsRef<Path>, size: usize, algorithm: DiskAlgorithm) -> io::Result<Self> {
+        Ok(Self::spawn(SecondaryStorage::open(path, size)?, algorithm, None))
+    }
+    /// Creates a disk with the given layout, this is the same as a
+    /// flat one but it keeps track of how long requests would take.
+    pub fn new_with_geometry(geometry: DiskGeometry, algorithm: DiskAlgorithm) -> Self {
+        Self::spawn(SecondaryStorage::new(geometry.size()), algorithm, Some(geometry))
+    }
+    fn spawn(storage: SecondaryStorage, algorithm: DiskAlgorithm, geometry: Option<DiskGeometry>) -> Self {
+        let requests = Arc::new(IpcChannel::new());
+        let state = Arc::new(DiskState::new());
         let object = Self {
-            requests: Arc::new(IpcChannel::new()),
-            state: Arc::new(AtomicU8::new(1)),
-            offset: Arc::new(AtomicUsize::new(0)),
-            service_record: Arc::default(),
+            requests: Arc::clone(&requests),
+            state: Arc::clone(&state),
+            offset: Arc::new(AtomicUsize::new(storage.get_offset())),
+            motion: Arc::new(HeadMotion::new(geometry)),
+            worker: Arc::default(),
+            handles: Arc::new(Handles { requests, state }),
+            capacity: storage.capacity(),
         };
-        std::thread::spawn({
+        let worker = std::thread::spawn({
             let requests = Arc::clone(&object.requests);
             let state = Arc::clone(&object.state);
-            let record = Arc::clone(&object.service_record);
             let offset = Arc::clone(&object.offset);
+            let motion = Arc::clone(&object.motion);
+            let rng = SimRng::global();
             move || {
-                run_disk(requests, SecondaryStorage::new(size), state, record, algorithm, offset);
+                SimRng::set_global(rng);
+                run_disk(requests, storage, state, algorithm, offset, motion);
             }
         });
+        *object.worker.lock() = Some(worker);
         object
     }
+    /// Merges writes that are next to or on top of each other while they
+    /// wait in the queue, so lots of small writes only need one seek.
+    /// Where they overlap the one sent last wins.
+    pub fn with_write_coalescing(self) -> Self {
+        self.state.coalesce.store(true, Ordering::SeqCst);
+        self
+    }
+    /// Draws how long each request waits for its sector to come round from
+    /// the generator, rather than taking half a turn every time. This only
+    /// matters for a disk with a [DiskGeometry].
+    pub fn with_rng(self, rng: SimRng) -> Self {
+        *self.motion.rng.lock() = Some(rng);
+        self
+    }
     pub fn pause(&self) {
-        self.state.store(0, Ordering::SeqCst);
+        self.state.set(0);
     }
     pub fn run(&self) {
-        self.state.store(1, Ordering::SeqCst);
+        self.state.set(1);
+    }
+    /// Lets a paused disk service one more request and then pauses it again.
+    pub fn step(&self) {
+        self.state.set(3);
+    }
+    /// Makes the disk misbehave until it is healed, this
+    /// replaces any failure that was already injected.
+    pub fn inject_failure(&self, mode: FailureMode) {
+        *self.state.failure.lock() = Some(mode);
+    }
+    /// Clears an injected failure.
+    pub fn heal(&self) {
+        *self.state.failure.lock() = None;
     }
+    /// Stops taking requests, everything that was already sent
+    /// still gets serviced before the disk stops.
     pub fn shutdown(&self) {
-        self.state.store(2, Ordering::SeqCst);
+        self.handles.shutdown();
+    }
+    /// Shuts the disk down and waits for the outstanding
+    /// requests to be serviced.
+    pub fn shutdown_and_join(&self) {
+        self.shutdown();
+        if let Some(worker) = self.worker.lock().take() {
+            worker.join().expect("The disk thread panicked.");
+        }
+    }
+    /// How many requests have been sent and not started yet, this
+    /// counts the ones the disk has taken in and is still scheduling.
+    pub fn queue_depth(&self) -> usize {
+        self.state.waiting.load(Ordering::SeqCst)
+    }
+    /// How many requests have been sent and not answered yet, this
+    /// is the queue depth plus the one being serviced.
+    pub fn in_flight(&self) -> usize {
+        self.state.in_flight()
+    }
+    /// Waits until every request that has been sent is answered, this
+    /// never returns if the disk is paused with requests waiting.
+    pub fn wait_idle(&self) {
+        let mut state = self.state.state.lock();
+        while self.state.in_flight() > 0 {
+            self.state.signal.wait(&mut state);
+        }
     }
     /// This is the sequential offset pointer, this is the pointer that is updated
     /// when we perform store operations.
     pub fn get_offset(&self) -> usize {
         self.offset.load(Ordering::SeqCst)
     }
+    /// Where every request that has been serviced went, in the
+    /// order they were serviced.
+    pub fn service_record(&self) -> Vec<usize> {
+        self.motion.record.lock().clone()
+    }
+    /// How many bytes the head has moved across to service
+    /// the requests so far.
+    pub fn head_travel(&self) -> usize {
+        self.motion.travel.load(Ordering::SeqCst)
+    }
+    pub fn geometry(&self) -> Option<DiskGeometry> {
+        self.motion.geometry
+    }
+    /// How long each request would have taken in milliseconds, in the
+    /// order they were serviced. This is empty if the disk has no geometry.
+    pub fn service_times(&self) -> Vec<f64> {
+        self.motion.times.lock().clone()
+    }
+    /// How long the disk would have spent servicing everything
+    /// so far in milliseconds.
+    pub fn virtual_time(&self) -> f64 {
+        self.motion.times.lock().iter().sum()
+    }
+    /// Reads from the disk with a given urgency.
+    pub fn read_with(&self, addr: RawStoragePtr, length: usize, class: IoClass) -> Yield<Vec<u8>> {
+        let chan = Arc::new(IpcChannel::new());
+        let request = ServiceRequest::Read {
+            addr,
+            outbound: chan.clone(),
+            length,
+        };
+        self.submit_with(request, chan, class).map(expect_disk)
+    }
+    /// Writes to the disk with a given urgency.
+    pub fn write_with(&self, addr: RawStoragePtr, data: &[u8], class: IoClass) -> Yield<()> {
+        let chan = Arc::new(IpcChannel::new());
+        let request = ServiceRequest::Edit {
+            addr,
+            data: data.to_vec(),
+            confirm: chan.clone(),
+        };
+        self.submit_with(request, chan, class).map(expect_disk)
+    }
+    /// Sends a request to the disk, if the disk has been shut
+    /// down it fails straight away.
+    fn submit<T: Send + 'static>(&self, request: ServiceRequest, reply: Reply<T>) -> Yield<Result<T, DiskError>> {
+        self.submit_with(request, reply, IoClass::default())
+    }
+    fn submit_with<T: Send + 'static>(&self, request: ServiceRequest, reply: Reply<T>, class: IoClass) -> Yield<Result<T, DiskError>> {
+        self.send(request, &reply, class);
+        Yield::new(reply)
+    }
+    fn send<T>(&self, request: ServiceRequest, reply: &Reply<T>, class: IoClass) {
+        // Counted before it is sent so the disk can't take it off first.
+        self.state.waiting.fetch_add(1, Ordering::SeqCst);
+        if self.requests.send((request, class, Instant::now())).is_err() {
+            self.state.waiting.fetch_sub(1, Ordering::SeqCst);
+            let _ = reply.send(Err(DiskError::DeviceShutDown));
+            reply.close();
+        }
+    }
+}
+
+/// The same requests as [AbstractStorageDevice] but they can be awaited,
+/// the request is sent straight away and the future waits on the reply.
+#[cfg(feature = "async")]
+impl MagneticDisk {
+    pub fn read_async(&self, addr: RawStoragePtr, length: usize) -> impl Future<Output = Result<Vec<u8>, DiskError>> {
+        let chan = Arc::new(IpcChannel::new());
+        let request = ServiceRequest::Read {
+            addr,
+            outbound: chan.clone(),
+            length,
+        };
+        self.submit_async(request, chan)
+    }
+    pub fn write_async(&self, addr: RawStoragePtr, data: &[u8]) -> impl Future<Output = Result<(), DiskError>> {
+        let chan = Arc::new(IpcChannel::new());
+        let request = ServiceRequest::Edit {
+            addr,
+            data: data.to_vec(),
+            confirm: chan.clone(),
+        };
+        self.submit_async(request, chan)
+    }
+    pub fn store_async(&self, data: &[u8]) -> impl Future<Output = Result<RawStoragePtr, DiskError>> {
+        let chan = Arc::new(IpcChannel::new());
+        let request = ServiceRequest::Write {
+            bytes: data.to_vec(),
+            inbound: chan.clone(),
+        };
+        self.submit_async(request, chan)
+    }
+    pub fn read_bit_async(&self, addr: RawStoragePtr) -> impl Future<Output = Result<Bit, DiskError>> {
+        let chan = Arc::new(IpcChannel::new());
+        let request = ServiceRequest::ReadBit {
+            addr,
+            outbound: chan.clone(),
+        };
+        self.submit_async(request, chan)
+    }
+    pub fn write_bit_async(&self, addr: RawStoragePtr, value: Bit) -> impl Future<Output = Result<(), DiskError>> {
+        let chan = Arc::new(IpcChannel::new());
+        let request = ServiceRequest::WriteBit {
+            addr,
+            value,
+            confirm: chan.clone(),
+        };
+        self.submit_async(request, chan)
+    }
+    pub fn free_async(&self, addr: RawStoragePtr, length: usize) -> impl Future<Output = Result<(), DiskError>> {
+        let chan = Arc::new(IpcChannel::new());
+        let request = ServiceRequest::Free {
+            addr,
+            length,
+            confirm: chan.clone(),
+        };
+        self.submit_async(request, chan)
+    }
+    fn submit_async<T>(&self, request: ServiceRequest, reply: Reply<T>) -> impl Future<Output = Result<T, DiskError>> {
+        self.send(request, &reply, IoClass::default());
+        let reply = AsyncYield::new(reply);
+        // If the disk went away without answering it was shut down.
+        async move { reply.await.unwrap_or(Err(DiskError::DeviceShutDown)) }
+    }
+}
+
+/// A request waiting to be serviced.
+struct Pending {
+    /// Where on the disk it goes.
+    offset: RawStoragePtr,
+    /// When it arrived, in the order requests were received.
+    clock: usize,
+    class: IoClass,
+    /// When a real time request has to be done by.
+    deadline: Option<Instant>,
+    item: ServiceRequest,
+    /// The confirms of edits that were merged into this one.
+    merged: Vec<Reply<()>>
+}
+
+impl Pending {
+    /// The bytes the request touches, one that runs off the end of the
+    /// address space is cut short there.
+    fn range(&self) -> Range<usize> {
+        self.offset.byte_offset..self.offset.byte_offset.saturating_add(self.item.length())
+    }
 }
 
 fn run_disk(
-    request_queue: Arc<IpcChannel<ServiceRequest>>,
+    request_queue: Arc<IpcChannel<Submission>>,
     mut storage: SecondaryStorage,
-    state: Arc<AtomicU8>,
-    record: Arc<Mutex<Vec<usize>>>,
+    state: Arc<DiskState>,
     algorithm: DiskAlgorithm,
-    disk_offset: Arc<AtomicUsize>
+    disk_offset: Arc<AtomicUsize>,
+    motion: Arc<HeadMotion>
 ) {
     // let algorithm = DiskAlgorithm::SSTF;
 
     let mut head = 0;
     let mut scan_forward = true;
+    let mut served_here = false;
 
     let mut clock = 0;
-    let mut service_queue = vec![];
+    let mut service_queue: Vec<Pending> = vec![];
+    // The requests N step SCAN is working through.
+    let mut batch = vec![];
+
+    let mut admit = |(item, class, sent): Submission, queue: &mut Vec<Pending>, storage: &SecondaryStorage| {
+        let item = match state.coalesce.load(Ordering::SeqCst) {
+            true => match coalesce(item, class, queue, storage) {
+                Some(item) => item,
+                None => return
+            },
+            false => item
+        };
+        queue.push(Pending {
+            offset: locate(&item, storage),
+            clock,
+            deadline: match class {
+                IoClass::RealTime(ms) => Some(sent + Duration::from_millis(ms)),
+                _ => None
+            },
+            class,
+            item,
+            merged: vec![]
+        });
+        clock += 1;
+    };
 
     loop {
-        if state.load(Ordering::SeqCst) != 1 {
-            yield_now();
-            continue;
+        // Sleep until there is something to do, once the disk is shut
+        // down and everything has been serviced the channel runs dry.
+        if service_queue.is_empty() && batch.is_empty() {
+            match request_queue.recv() {
+                Ok(submission) => admit(submission, &mut service_queue, &storage),
+                Err(_) => {
+                    storage.flush().expect("Couldn't flush the disk.");
+                    return;
+                }
+            }
         }
-        while let Some(item) = request_queue.try_recv() {
-            let offset = match &item {
-                ServiceRequest::Edit { addr, .. }
-                | ServiceRequest::Read { addr, .. }
-                | ServiceRequest::ReadBit { addr, .. }
-                | ServiceRequest::WriteBit { addr, .. } => *addr,
-                ServiceRequest::Write { .. } => RawStoragePtr {
-                    byte_offset: storage.get_offset(),
-                    bit_offset: 0,
-                },
-            };
-            service_queue.push((offset, clock, item));
-            clock += 1;
+        state.wait();
+        while let Ok(submission) = request_queue.try_recv() {
+            admit(submission, &mut service_queue, &storage);
         }
 
         if algorithm == DiskAlgorithm::FCFS && !service_queue.is_empty() {
@@ -150,10 +675,9 @@ fn run_disk(
             if let Some((index, _)) = service_queue
                 .iter_mut()
                 .enumerate()
-                .min_by_key(|(_, (_, time, _))| *time)
+                .min_by_key(|(_, f)| f.clock)
             {
-                let (offset, _, item) = service_queue.remove(index);
-                service_request(item, offset, &mut storage, &record, &mut head, &disk_offset);
+                service_request(service_queue.remove(index), &mut storage, &mut head, &disk_offset, &motion, &state);
             }
         } else if algorithm == DiskAlgorithm::SSTF && !service_queue.is_empty() {
             // We are using shortest seek time first and thus we will choose
@@ -161,54 +685,203 @@ fn run_disk(
             if let Some((index, _)) = service_queue
                 .iter_mut()
                 .enumerate()
-                .min_by_key(|(_, (offset, _, _))| offset.byte_offset.abs_diff(head))
+                .min_by_key(|(_, f)| f.offset.byte_offset.abs_diff(head))
             {
-                let (offset, _, item) = service_queue.remove(index);
-                service_request(item, offset, &mut storage, &record, &mut head, &disk_offset);
+                service_request(service_queue.remove(index), &mut storage, &mut head, &disk_offset, &motion, &state);
             }
-        } else if (algorithm == DiskAlgorithm::SCAN || algorithm == DiskAlgorithm::CSCAN || algorithm == DiskAlgorithm::CLOOK) && !service_queue.is_empty() {
-            // We are using SCAN or CSCAN and thus we just service if we are on that spot.
+        } else if algorithm == DiskAlgorithm::Deadline && !service_queue.is_empty() {
+            // Real time requests go by whose deadline is soonest, then best
+            // effort by priority and seek time, idle ones only go when there
+            // is nothing else.
             if let Some((index, _)) = service_queue
-                .iter_mut()
+                .iter()
                 .enumerate()
-                .find(|(_, (offset, _, _))| offset.byte_offset == head)
+                .min_by_key(|(_, f)| {
+                    let seek = f.offset.byte_offset.abs_diff(head);
+                    match f.class {
+                        IoClass::RealTime(_) => (0, f.deadline, 0, 0),
+                        IoClass::BestEffort(priority) => (1, None, priority, seek),
+                        IoClass::Idle => (2, None, 0, seek)
+                    }
+                })
             {
-                let (offset, _, item) = service_queue.remove(index);
-                service_request(item, offset, &mut storage, &record, &mut head, &disk_offset);
+                service_request(service_queue.remove(index), &mut storage, &mut head, &disk_offset, &motion, &state);
+            }
+        } else if matches!(algorithm, DiskAlgorithm::NStepScan(_) | DiskAlgorithm::FSCAN) {
+            // Freeze the oldest requests into a batch and sweep through
+            // all of them before looking at anything newer, FSCAN takes
+            // everything that is waiting.
+            if batch.is_empty() {
+                let n = match algorithm {
+                    DiskAlgorithm::NStepScan(n) => n.max(1).min(service_queue.len()),
+                    _ => service_queue.len()
+                };
+                batch.extend(service_queue.drain(..n));
             }
+            let index = next_elevator(&batch, &algorithm, &mut head, &mut scan_forward, &mut served_here, storage.capacity().saturating_sub(1), &motion);
+            service_request(batch.remove(index), &mut storage, &mut head, &disk_offset, &motion, &state);
+            served_here = true;
+        } else if !service_queue.is_empty() {
+            // We are using one of the elevators so we go straight to the
+            // next request in the direction we are heading.
+            let index = next_elevator(&service_queue, &algorithm, &mut head, &mut scan_forward, &mut served_here, storage.capacity().saturating_sub(1), &motion);
+            service_request(service_queue.remove(index), &mut storage, &mut head, &disk_offset, &motion, &state);
+            served_here = true;
         }
+    }
+}
 
-        // This moves the head along if we are using scan or cscan.
-        if algorithm == DiskAlgorithm::SCAN || algorithm == DiskAlgorithm::CSCAN || algorithm == DiskAlgorithm::CLOOK {
-            head = if scan_forward { head + 1 } else { head - 1 };
-
-            // If we are using CLOOK and there are no more requests in this direction jump o the beginning.
-            if algorithm == DiskAlgorithm::CLOOK
-                && service_queue.iter().find(|(o, _, _)| o.byte_offset >= head).is_none()
-             {
-                head = 0;
-            } else if head >= storage.buffer.len() {
-                if algorithm == DiskAlgorithm::SCAN {
-                    scan_forward = false;
-                } else {
-                    head = 0;
-                }
-            } else if head <= 0 && !scan_forward {
-                scan_forward = true;
+/// Finds the next request for the SCAN and LOOK family, if there is nothing
+/// left ahead of the head it goes to the end of the disk and turns around
+/// or jumps back depending on the algorithm.
+fn next_elevator(
+    service_queue: &[Pending],
+    algorithm: &DiskAlgorithm,
+    head: &mut usize,
+    forward: &mut bool,
+    served_here: &mut bool,
+    end: usize,
+    motion: &HeadMotion
+) -> usize {
+    loop {
+        // Whatever is right under the head has already been serviced on this pass.
+        let ahead = service_queue
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| match *forward {
+                true => f.offset.byte_offset > *head || (!*served_here && f.offset.byte_offset == *head),
+                false => f.offset.byte_offset < *head || (!*served_here && f.offset.byte_offset == *head)
+            });
+        let next = match *forward {
+            true => ahead.min_by_key(|(_, f)| f.offset.byte_offset),
+            false => ahead.max_by_key(|(_, f)| f.offset.byte_offset)
+        };
+        if let Some((index, _)) = next {
+            return index;
+        }
+
+        let target = match (algorithm, *forward) {
+            (DiskAlgorithm::SCAN | DiskAlgorithm::NStepScan(_) | DiskAlgorithm::FSCAN, true) => end,
+            (DiskAlgorithm::SCAN | DiskAlgorithm::NStepScan(_) | DiskAlgorithm::FSCAN, false) => 0,
+            // Turn around right where we are.
+            (DiskAlgorithm::LOOK, _) => *head,
+            (DiskAlgorithm::CSCAN, _) => {
+                // Run to the end and then all the way back.
+                motion.seek(*head, end);
+                *head = end;
+                0
             }
+            // Jump straight back to the lowest request.
+            _ => service_queue.iter().map(|f| f.offset.byte_offset).min().unwrap()
+        };
+        motion.seek(*head, target);
+        *head = target;
+        if matches!(algorithm, DiskAlgorithm::SCAN | DiskAlgorithm::LOOK | DiskAlgorithm::NStepScan(_) | DiskAlgorithm::FSCAN) {
+            *forward = !*forward;
         }
+        *served_here = false;
+    }
+}
+
+/// Merges an edit into one that is waiting next to it, giving it back if
+/// it couldn't be. It is only merged if nothing sent after the waiting one
+/// touches the same bytes, so nothing can tell they were merged.
+fn coalesce(item: ServiceRequest, class: IoClass, queue: &mut [Pending], storage: &SecondaryStorage) -> Option<ServiceRequest> {
+    let ServiceRequest::Edit { addr, data, confirm } = item else {
+        return Some(item);
+    };
+    let range = addr.byte_offset..addr.byte_offset.saturating_add(data.len());
+    let target = storage.check(addr, data.len()).ok().and_then(|_| {
+        queue.iter().position(|f| {
+            let other = f.range();
+            let joined = other.start.min(range.start)..other.end.max(range.end);
+            // One that doesn't fit is left alone so it still fails on its own.
+            matches!(f.item, ServiceRequest::Edit { .. })
+                && storage.check(f.offset, f.item.length()).is_ok()
+                && f.class == class
+                && other.start <= range.end
+                && range.start <= other.end
+                && queue.iter().all(|g| g.clock <= f.clock || g.range().end <= joined.start || joined.end <= g.range().start)
+        })
+    });
+    let Some(index) = target else {
+        return Some(ServiceRequest::Edit { addr, data, confirm });
+    };
+
+    let pending = &mut queue[index];
+    let start = pending.offset.byte_offset.min(range.start);
+    let end = pending.range().end.max(range.end);
+    if let ServiceRequest::Edit { addr: old_addr, data: old, .. } = &mut pending.item {
+        let mut joined = vec![0; end - start];
+        joined[old_addr.byte_offset - start..][..old.len()].copy_from_slice(old);
+        // The newer data goes on top.
+        joined[range.start - start..][..data.len()].copy_from_slice(&data);
+        *old = joined;
+        *old_addr = RawStoragePtr::byte_ptr(start);
+    }
+    pending.offset = RawStoragePtr::byte_ptr(start);
+    pending.merged.push(confirm);
+    None
+}
+
+/// Where on the disk a request has to go.
+fn locate(item: &ServiceRequest, storage: &SecondaryStorage) -> RawStoragePtr {
+    match item {
+        ServiceRequest::Edit { addr, .. }
+        | ServiceRequest::Read { addr, .. }
+        | ServiceRequest::ReadBit { addr, .. }
+        | ServiceRequest::WriteBit { addr, .. }
+        | ServiceRequest::Free { addr, .. } => *addr,
+        ServiceRequest::Write { bytes, .. } => RawStoragePtr {
+            byte_offset: storage.store_location(bytes.len()),
+            bit_offset: 0,
+        },
     }
 }
 
 fn service_request(
-    item: ServiceRequest,
-    offset: RawStoragePtr,
+    pending: Pending,
+    storage: &mut SecondaryStorage,
+    head: &mut usize,
+    offset_disk: &AtomicUsize,
+    motion: &HeadMotion,
+    state: &DiskState
+) {
+    // The merged edits were sent as requests of their own.
+    state.start(1 + pending.merged.len());
+    perform_request(pending, storage, head, offset_disk, motion, state);
+    state.finish();
+}
+
+fn perform_request(
+    pending: Pending,
     storage: &mut SecondaryStorage,
-    record: &Mutex<Vec<usize>>,
     head: &mut usize,
-    offset_disk: &AtomicUsize
+    offset_disk: &AtomicUsize,
+    motion: &HeadMotion,
+    state: &DiskState
 ) {
-    record.lock().push(offset.byte_offset);
+    let Pending { mut offset, item, merged, .. } = pending;
+    // Stores queued together were all located before any of them went in.
+    if let ServiceRequest::Write { .. } = item {
+        offset = locate(&item, storage);
+    }
+    // Check the request fits before the head goes anywhere.
+    let length = item.length();
+    let failure = state.failure.lock().clone();
+    let error = match storage.check(offset, length) {
+        Err(error) => Some(error),
+        Ok(()) if failure.as_ref().is_some_and(|f| f.fails(offset.byte_offset, length)) => Some(DiskError::DeviceFailed),
+        Ok(()) => None
+    };
+    if let Some(error) = error {
+        item.fail(error);
+        for confirm in merged {
+            let _ = confirm.send(Err(error));
+        }
+        return;
+    }
+    motion.service(*head, offset.byte_offset, length);
     *head = offset.byte_offset;
     match item {
         ServiceRequest::Read {
@@ -216,92 +889,118 @@ fn service_request(
             outbound,
             length,
         } => {
-            outbound.send(storage.read(addr, length).to_vec());
+            let mut data = storage.read(addr, length);
+            if let Some(FailureMode::SilentCorruption(at)) = failure {
+                if let Some(byte) = at.checked_sub(addr.byte_offset).and_then(|f| data.get_mut(f)) {
+                    *byte ^= 1;
+                }
+            }
+            let _ = outbound.send(Ok(data));
         }
         ServiceRequest::Edit {
             addr,
             data,
             confirm,
         } => {
-            storage.write(addr, &data);
-            confirm.send(());
+            let result = storage.try_write(addr, &data);
+            for confirm in merged.iter().chain([&confirm]) {
+                let _ = confirm.send(result);
+            }
         }
         ServiceRequest::Write { bytes, inbound } => {
-            inbound.send(storage.store(&bytes));
+            let result = storage.try_store(&bytes);
+            // The offset has to be up to date by the time the caller hears back.
+            offset_disk.store(storage.get_offset(), Ordering::SeqCst);
+            let _ = inbound.send(result);
         }
         ServiceRequest::ReadBit { addr, outbound } => {
-            outbound.send(storage.read_bit(addr));
+            // The corruption flips the lowest bit of the byte.
+            let flipped = failure == Some(FailureMode::SilentCorruption(addr.byte_offset)) && addr.bit_offset == 7;
+            let _ = outbound.send(Ok(storage.read_bit(addr) != flipped));
         }
         ServiceRequest::WriteBit {
             addr,
             value,
             confirm,
         } => {
-            storage.write_bit(addr, value);
-            confirm.send(());
+            let _ = confirm.send(storage.try_write_bit(addr, value));
+        }
+        ServiceRequest::Free {
+            addr,
+            length,
+            confirm,
+        } => {
+            let _ = confirm.send(storage.try_free(addr, length));
         }
     }
     offset_disk.store(storage.get_offset(), Ordering::SeqCst);
 }
 
 impl AbstractStorageDevice for MagneticDisk {
-    fn write(&self, addr: super::RawStoragePtr, data: &[u8]) -> Yield<()> {
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+    fn try_write(&self, addr: RawStoragePtr, data: &[u8]) -> Yield<Result<(), DiskError>> {
         let chan = Arc::new(IpcChannel::new());
         let request = ServiceRequest::Edit {
             addr,
             data: data.to_vec(),
             confirm: chan.clone(),
         };
-        self.requests.send(request);
-        Yield::new(chan)
+        self.submit(request, chan)
     }
-    fn read(&self, addr: super::RawStoragePtr, length: usize) -> Yield<Vec<u8>> {
+    fn try_read(&self, addr: RawStoragePtr, length: usize) -> Yield<Result<Vec<u8>, DiskError>> {
         let chan = Arc::new(IpcChannel::new());
         let request = ServiceRequest::Read {
             addr,
             outbound: chan.clone(),
             length,
         };
-        self.requests.send(request);
-        Yield::new(chan)
+        self.submit(request, chan)
     }
-    fn store(&self, data: &[u8]) -> Yield<super::RawStoragePtr> {
+    fn try_store(&self, data: &[u8]) -> Yield<Result<RawStoragePtr, DiskError>> {
         let chan = Arc::new(IpcChannel::new());
         let request = ServiceRequest::Write {
             bytes: data.to_vec(),
             inbound: chan.clone(),
         };
-        self.requests.send(request);
-        Yield::new(chan)
+        self.submit(request, chan)
     }
-    fn read_bit(&self, addr: RawStoragePtr) -> Yield<Bit> {
+    fn try_read_bit(&self, addr: RawStoragePtr) -> Yield<Result<Bit, DiskError>> {
         let chan = Arc::new(IpcChannel::new());
         let request = ServiceRequest::ReadBit {
             addr,
             outbound: chan.clone(),
         };
-        self.requests.send(request);
-        Yield::new(chan)
+        self.submit(request, chan)
     }
-    fn write_bit(&self, addr: RawStoragePtr, value: Bit) -> Yield<()> {
+    fn try_write_bit(&self, addr: RawStoragePtr, value: Bit) -> Yield<Result<(), DiskError>> {
         let chan = Arc::new(IpcChannel::new());
         let request = ServiceRequest::WriteBit {
             addr,
             value,
             confirm: chan.clone(),
         };
-        self.requests.send(request);
-        Yield::new(chan)
+        self.submit(request, chan)
+    }
+    fn try_free(&self, addr: RawStoragePtr, length: usize) -> Yield<Result<(), DiskError>> {
+        let chan = Arc::new(IpcChannel::new());
+        let request = ServiceRequest::Free {
+            addr,
+            length,
+            confirm: chan.clone(),
+        };
+        self.submit(request, chan)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{sync::Arc, thread::sleep, time::Duration};
+    use std::{sync::{atomic::{AtomicBool, Ordering}, mpsc::channel, Arc}, thread::{self, sleep}, time::Duration};
 
-    use crate::disks::{hard_drive::DiskAlgorithm, AbstractStorageDevice, RawStoragePtr};
+    use crate::{disks::{hard_drive::DiskAlgorithm, AbstractStorageDevice, DiskError, RawStoragePtr}, memory::ipc::Yield, rng::SimRng};
 
-    use super::MagneticDisk;
+    use super::{DiskGeometry, FailureMode, IoClass, MagneticDisk};
 
     #[test]
     pub fn test_magnetic_disk_simple() {
@@ -315,6 +1014,35 @@ mod tests {
         magn.write(RawStoragePtr::byte_ptr(25), &[4,5,6]).get();
     }
 
+    #[test]
+    pub fn test_magnetic_disk_shutdown() {
+        let magn = Arc::new(MagneticDisk::new(4096, DiskAlgorithm::SSTF));
+        magn.pause();
+        let stored = magn.store(&[1, 2, 3]);
+        let written = magn.write(RawStoragePtr::byte_ptr(100), &[4, 5]);
+        let read = magn.read(RawStoragePtr::byte_ptr(100), 2);
+        sleep(Duration::from_millis(50));
+
+        // The outstanding requests still get serviced.
+        let (done, finished) = channel();
+        thread::spawn({
+            let magn = Arc::clone(&magn);
+            move || {
+                magn.shutdown_and_join();
+                done.send(()).unwrap();
+            }
+        });
+        finished.recv_timeout(Duration::from_secs(5)).expect("The disk thread never stopped.");
+        assert_eq!(stored.get().byte_offset, 0);
+        written.get();
+        assert_eq!(read.get(), [4, 5]);
+        assert_eq!(magn.service_record().len(), 3);
+
+        // Anything sent after fails straight away.
+        assert_eq!(magn.try_read(RawStoragePtr::byte_ptr(0), 3).get(), Err(DiskError::DeviceShutDown));
+        assert_eq!(magn.try_store(&[1]).get().map(|_| ()), Err(DiskError::DeviceShutDown));
+    }
+
     #[test]
     pub fn test_magnetic_disk_servicing_fcfs() {
         let magn = Arc::new(MagneticDisk::new(4096, DiskAlgorithm::FCFS));
@@ -333,7 +1061,7 @@ mod tests {
         r3.get();
         r4.get();
 
-        assert_eq!(*magn.service_record.lock(), [0, 0, 96, 50]);
+        assert_eq!(magn.service_record(), [0, 3, 96, 50]);
     }
 
 
@@ -359,7 +1087,7 @@ mod tests {
         r5.get();
         r6.get();
 
-        assert_eq!(*magn.service_record.lock(), [0, 0, 45, 50, 51, 96]);
+        assert_eq!(magn.service_record(), [0, 3, 45, 50, 51, 96]);
     }
 
     #[test]
@@ -380,7 +1108,32 @@ mod tests {
         r3.get();
         r4.get();
 
-        assert_eq!(*magn.service_record.lock(), [0, 50, 96, 0]);
+        assert_eq!(magn.service_record(), [0, 50, 96, 3]);
+        // We went all the way to the end before turning around.
+        assert_eq!(magn.head_travel(), 4095 + (4095 - 3));
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_servicing_look() {
+        let magn = Arc::new(MagneticDisk::new(4096, DiskAlgorithm::LOOK));
+        magn.pause();
+
+        let r1 = magn.store(&[1, 2, 3]);
+        let r2 = magn.store(&[4, 5, 6]);
+        let r3 = magn.write(RawStoragePtr::byte_ptr(96), &[7, 8]);
+        let r4 = magn.write(RawStoragePtr::byte_ptr(50), &[7, 8]);
+
+        sleep(Duration::from_millis(50));
+        magn.run();
+
+        r1.get();
+        r2.get();
+        r3.get();
+        r4.get();
+
+        assert_eq!(magn.service_record(), [0, 50, 96, 3]);
+        // We turned around at 96 rather than the end.
+        assert_eq!(magn.head_travel(), 96 + (96 - 3));
     }
 
     #[test]
@@ -401,7 +1154,7 @@ mod tests {
         r3.get();
         r4.get();
 
-        assert_eq!(*magn.service_record.lock(), [0, 50, 96, 0]);
+        assert_eq!(magn.service_record(), [0, 50, 96, 3]);
     }
 
     #[test]
@@ -422,7 +1175,421 @@ mod tests {
         r3.get();
         r4.get();
 
-        assert_eq!(*magn.service_record.lock(), [0, 50, 96, 0]);
+        assert_eq!(magn.service_record(), [0, 50, 96, 3]);
+    }
+
+    /// Parks the head half way across a big disk and then asks for
+    /// one request behind it and two past it.
+    fn travel(algorithm: DiskAlgorithm) -> (Vec<usize>, usize) {
+        let magn = MagneticDisk::new(1 << 20, algorithm);
+        magn.write(RawStoragePtr::byte_ptr(500_000), &[1]).get();
+        magn.pause();
+
+        let requests: Vec<_> = [10_000, 700_000, 1_000_000]
+            .into_iter()
+            .map(|addr| magn.write(RawStoragePtr::byte_ptr(addr), &[1]))
+            .collect();
+        sleep(Duration::from_millis(50));
+        magn.run();
+        Yield::join_get(requests);
+
+        let record = magn.service_record();
+        (record, magn.head_travel())
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_head_travel() {
+        let end = (1 << 20) - 1;
+        assert_eq!(travel(DiskAlgorithm::SCAN), (vec![500_000, 700_000, 1_000_000, 10_000], end + end - 10_000));
+        assert_eq!(travel(DiskAlgorithm::CSCAN), (vec![500_000, 700_000, 1_000_000, 10_000], end + end + 10_000));
+        assert_eq!(travel(DiskAlgorithm::CLOOK), (vec![500_000, 700_000, 1_000_000, 10_000], 1_000_000 + 990_000));
+        assert_eq!(travel(DiskAlgorithm::SSTF), (vec![500_000, 700_000, 1_000_000, 10_000], 1_000_000 + 990_000));
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_scan_look_reversal() {
+        let sweep = |algorithm| {
+            let magn = MagneticDisk::new(4096, algorithm);
+            magn.pause();
+            let requests: Vec<_> = [10, 50, 90]
+                .into_iter()
+                .map(|addr| magn.read(RawStoragePtr::byte_ptr(addr), 1))
+                .collect();
+            sleep(Duration::from_millis(50));
+            magn.run();
+            Yield::join_all(requests);
+            assert_eq!(magn.head_travel(), 90);
+
+            // Going back for 0 shows where the head turned around.
+            magn.read(RawStoragePtr::byte_ptr(0), 1).get();
+            assert_eq!(magn.service_record(), [10, 50, 90, 0]);
+            magn.head_travel()
+        };
+        assert_eq!(sweep(DiskAlgorithm::SCAN), 4095 * 2);
+        assert_eq!(sweep(DiskAlgorithm::LOOK), 90 * 2);
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_geometry() {
+        // A turn takes 10ms and each track is 4096 bytes.
+        let geometry = DiskGeometry {
+            tracks: 100,
+            sectors_per_track: 8,
+            rpm: 6000,
+            seek_ms_per_track: 1.0
+        };
+        assert_eq!(geometry.locate(4096 * 3 + 1030), (3, 2));
+
+        let near = MagneticDisk::new_with_geometry(geometry, DiskAlgorithm::FCFS);
+        near.read(RawStoragePtr::byte_ptr(0), 512).get();
+        near.read(RawStoragePtr::byte_ptr(2048), 512).get();
+        // Half a turn of waiting and an eighth of a turn reading each.
+        assert_eq!(near.service_times(), [6.25, 6.25]);
+
+        let far = MagneticDisk::new_with_geometry(geometry, DiskAlgorithm::FCFS);
+        far.read(RawStoragePtr::byte_ptr(0), 512).get();
+        far.read(RawStoragePtr::byte_ptr(4096 * 90), 512).get();
+        assert_eq!(far.service_times(), [6.25, 96.25]);
+        assert!(near.virtual_time() < far.virtual_time());
+
+        // With a generator the wait for the sector is random but the same for the same seed.
+        let times = |seed| {
+            let disk = MagneticDisk::new_with_geometry(geometry, DiskAlgorithm::FCFS).with_rng(SimRng::from_seed(seed));
+            for addr in [0, 4096 * 90, 2048] {
+                disk.read(RawStoragePtr::byte_ptr(addr), 512).get();
+            }
+            disk.service_times()
+        };
+        assert_eq!(times(4), times(4));
+        assert_ne!(times(4), times(5));
+        assert!(times(4).iter().zip([1.25, 91.25, 91.25]).all(|(time, least)| (least..least + 10.0).contains(time)));
+
+        // Disks without a geometry don't keep time.
+        let flat = MagneticDisk::new(4096, DiskAlgorithm::FCFS);
+        flat.read(RawStoragePtr::byte_ptr(0), 1).get();
+        assert!(flat.service_times().is_empty());
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_free() {
+        let magn = MagneticDisk::new(4096, DiskAlgorithm::FCFS);
+        let first = magn.store(&[1; 100]).get();
+        magn.store(&[2; 100]).get();
+        magn.free(first, 100).get();
+        assert_eq!(magn.read(first, 100).get(), [0; 100]);
+
+        // The smaller blob reuses the freed space.
+        let reused = magn.store(&[3; 40]).get();
+        assert_eq!(reused.byte_offset, first.byte_offset);
+        assert_eq!(magn.read(first, 41).get()[39..], [3, 0]);
+        assert_eq!(magn.get_offset(), 200);
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_out_of_bounds() {
+        let magn = MagneticDisk::new(256, DiskAlgorithm::SCAN);
+        let error = DiskError::OutOfBounds { addr: 250, len: 10, capacity: 256 };
+        assert_eq!(magn.try_read(RawStoragePtr::byte_ptr(250), 10).get(), Err(error));
+        assert_eq!(magn.try_write(RawStoragePtr::byte_ptr(250), &[1; 10]).get(), Err(error));
+        assert!(magn.try_read_bit(RawStoragePtr::bit_ptr(256 * 8)).get().is_err());
+        assert!(magn.try_store(&[1; 257]).get().is_err());
+
+        // Running off the end of the address space is out of bounds too, not a crashed worker.
+        let error = DiskError::OutOfBounds { addr: usize::MAX, len: 2, capacity: 256 };
+        assert_eq!(magn.try_read(RawStoragePtr::byte_ptr(usize::MAX), 2).get(), Err(error));
+        assert_eq!(magn.try_write(RawStoragePtr::byte_ptr(usize::MAX), &[1; 2]).get(), Err(error));
+
+        // Right up to the end is fine and the disk keeps going.
+        magn.try_write(RawStoragePtr::byte_ptr(246), &[1; 10]).get().unwrap();
+        assert_eq!(magn.try_read(RawStoragePtr::byte_ptr(255), 1).get(), Ok(vec![1]));
+        assert!(magn.try_read_bit(RawStoragePtr::bit_ptr(256 * 8 - 1)).get().unwrap());
+        assert_eq!(magn.service_record(), [246, 255, 255]);
     }
 
+    #[test]
+    pub fn test_magnetic_disk_empty() {
+        // Nothing fits on it but the elevators still have to be able to sweep it.
+        for algorithm in [DiskAlgorithm::SCAN, DiskAlgorithm::FSCAN] {
+            let magn = MagneticDisk::new(0, algorithm);
+            assert_eq!(magn.try_read(RawStoragePtr::byte_ptr(0), 0).get(), Ok(vec![]));
+            assert!(magn.try_read(RawStoragePtr::byte_ptr(0), 1).get().is_err());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "OutOfBounds")]
+    pub fn test_magnetic_disk_out_of_bounds_panics() {
+        let magn = MagneticDisk::new(256, DiskAlgorithm::FCFS);
+        magn.read(RawStoragePtr::byte_ptr(256), 1).get();
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_persistent() {
+        let path = std::env::temp_dir().join(format!("osconcepts-magnetic-{}.disk", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let magn = MagneticDisk::new_persistent(&path, 4096, DiskAlgorithm::SSTF).unwrap();
+        let stored = magn.store(&[1, 2, 3]).get();
+        magn.write(RawStoragePtr::byte_ptr(1000), &[4, 5]).get();
+        magn.shutdown_and_join();
+        drop(magn);
+
+        let magn = MagneticDisk::new_persistent(&path, 4096, DiskAlgorithm::SSTF).unwrap();
+        assert_eq!(magn.get_offset(), 3);
+        assert_eq!(magn.read(stored, 3).get(), [1, 2, 3]);
+        assert_eq!(magn.read(RawStoragePtr::byte_ptr(1000), 2).get(), [4, 5]);
+        magn.shutdown_and_join();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_nstep_scan() {
+        let magn = Arc::new(MagneticDisk::new(4096, DiskAlgorithm::NStepScan(4)));
+        magn.pause();
+        for addr in 0..3 {
+            magn.write(RawStoragePtr::byte_ptr(addr), &[1]);
+        }
+        let far = magn.read(RawStoragePtr::byte_ptr(4000), 1);
+
+        // Keep the head busy right where it is.
+        let stop = Arc::new(AtomicBool::new(false));
+        let feeder = thread::spawn({
+            let magn = Arc::clone(&magn);
+            let stop = Arc::clone(&stop);
+            move || {
+                let mut addr = 0;
+                while !stop.load(Ordering::SeqCst) {
+                    magn.write(RawStoragePtr::byte_ptr(addr % 8), &[2]);
+                    addr += 1;
+                    thread::yield_now();
+                }
+            }
+        });
+        sleep(Duration::from_millis(20));
+        magn.run();
+        far.get();
+        stop.store(true, Ordering::SeqCst);
+        feeder.join().unwrap();
+
+        // The far read was in the first batch so it went fourth.
+        let record = magn.service_record();
+        assert_eq!(record.iter().position(|f| *f == 4000), Some(3));
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_failure() {
+        let magn = MagneticDisk::new(256, DiskAlgorithm::FCFS);
+        magn.write(RawStoragePtr::byte_ptr(10), &[1, 2, 3]).get();
+
+        magn.inject_failure(FailureMode::FailAll);
+        assert_eq!(magn.try_read(RawStoragePtr::byte_ptr(10), 3).get(), Err(DiskError::DeviceFailed));
+        assert_eq!(magn.try_store(&[1]).get().map(|_| ()), Err(DiskError::DeviceFailed));
+
+        // Only the region fails.
+        magn.inject_failure(FailureMode::FailRegion(12..20));
+        assert_eq!(magn.try_read(RawStoragePtr::byte_ptr(10), 3).get(), Err(DiskError::DeviceFailed));
+        assert_eq!(magn.try_read(RawStoragePtr::byte_ptr(10), 2).get(), Ok(vec![1, 2]));
+        assert!(magn.try_write_bit(RawStoragePtr::bit_ptr(20 * 8), true).get().is_ok());
+
+        // Corruption doesn't error, the data is just wrong.
+        magn.inject_failure(FailureMode::SilentCorruption(11));
+        assert_eq!(magn.read(RawStoragePtr::byte_ptr(10), 3).get(), [1, 3, 3]);
+        assert!(magn.read_bit(RawStoragePtr::bit_ptr(11 * 8 + 7)).get());
+
+        magn.heal();
+        assert_eq!(magn.read(RawStoragePtr::byte_ptr(10), 3).get(), [1, 2, 3]);
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_handles() {
+        let magn = MagneticDisk::new(256, DiskAlgorithm::FCFS);
+        let handle = magn.clone();
+        handle.write(RawStoragePtr::byte_ptr(0), &[1]).get();
+        assert_eq!(magn.read(RawStoragePtr::byte_ptr(0), 1).get(), [1]);
+
+        // Dropping one handle leaves the disk running.
+        drop(magn);
+        assert_eq!(handle.read(RawStoragePtr::byte_ptr(0), 1).get(), [1]);
+        handle.shutdown_and_join();
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_deadline() {
+        let magn = MagneticDisk::new(4096, DiskAlgorithm::Deadline);
+        magn.pause();
+        let requests = vec![
+            magn.read_with(RawStoragePtr::byte_ptr(10), 1, IoClass::Idle),
+            magn.read_with(RawStoragePtr::byte_ptr(20), 1, IoClass::BestEffort(4)),
+            magn.read_with(RawStoragePtr::byte_ptr(4000), 1, IoClass::RealTime(500)),
+            magn.read_with(RawStoragePtr::byte_ptr(30), 1, IoClass::BestEffort(0)),
+            magn.read_with(RawStoragePtr::byte_ptr(3000), 1, IoClass::RealTime(100)),
+            magn.read(RawStoragePtr::byte_ptr(25), 1),
+        ];
+        sleep(Duration::from_millis(50));
+        magn.run();
+        Yield::join_all(requests);
+
+        // The real time ones go first no matter how far away they are.
+        assert_eq!(magn.service_record(), [3000, 4000, 30, 25, 20, 10]);
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_capacity() {
+        let magn = MagneticDisk::new(256, DiskAlgorithm::FCFS);
+        assert_eq!(magn.capacity(), 256);
+        magn.try_store(&[1; 200]).get().unwrap();
+        assert_eq!(
+            magn.try_store(&[1; 100]).get().map(|_| ()),
+            Err(DiskError::OutOfBounds { addr: 200, len: 100, capacity: 256 })
+        );
+        magn.try_store(&[1; 56]).get().unwrap();
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_queued_overflow() {
+        let magn = MagneticDisk::new(4096, DiskAlgorithm::FCFS);
+        magn.pause();
+        let stores: Vec<_> = (0..3).map(|_| magn.try_store(&[1; 1500])).collect();
+        sleep(Duration::from_millis(50));
+        magn.run();
+
+        // Only the first two fit, even though they all looked like they would when sent.
+        let results: Vec<_> = stores.into_iter().map(|f| f.get().map(|f| f.byte_offset)).collect();
+        assert_eq!(results, [Ok(0), Ok(1500), Err(DiskError::OutOfBounds { addr: 3000, len: 1500, capacity: 4096 })]);
+        assert_eq!(magn.read(RawStoragePtr::byte_ptr(2999), 1).get(), [1]);
+    }
+
+    /// Stops a sweep over 100, 200 and 300 after the first read and sends a
+    /// read for 250, returning the disk once everything is done.
+    fn late_request(algorithm: DiskAlgorithm) -> MagneticDisk {
+        let magn = MagneticDisk::new(4096, algorithm);
+        magn.pause();
+        let sweep: Vec<_> = [100, 200, 300].into_iter().map(|f| magn.read(RawStoragePtr::byte_ptr(f), 1)).collect();
+        sleep(Duration::from_millis(50));
+
+        // Service just the first one so the disk stops partway through the sweep.
+        magn.step();
+        let mut sweep = sweep.into_iter();
+        sweep.next().unwrap().get();
+        assert_eq!(magn.service_record(), [100]);
+
+        // Then send something it hasn't got to yet.
+        let late = magn.read(RawStoragePtr::byte_ptr(250), 1);
+        sleep(Duration::from_millis(50));
+        assert_eq!(magn.queue_depth(), 3);
+        magn.run();
+        Yield::join_get(sweep.collect());
+        late.get();
+        magn
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_fscan() {
+        // Plain SCAN picks it up on the way past.
+        assert_eq!(late_request(DiskAlgorithm::SCAN).service_record(), [100, 200, 250, 300]);
+
+        // FSCAN froze the first sweep so it waits for the way back.
+        let magn = late_request(DiskAlgorithm::FSCAN);
+        assert_eq!(magn.service_record(), [100, 200, 300, 250]);
+        assert_eq!(magn.head_travel(), 4095 + (4095 - 250));
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_queue_depth() {
+        let magn = MagneticDisk::new(4096, DiskAlgorithm::SSTF);
+        assert_eq!((magn.queue_depth(), magn.in_flight()), (0, 0));
+        magn.pause();
+        let requests: Vec<_> = (0..10).map(|f| magn.read(RawStoragePtr::byte_ptr(f * 100), 1)).collect();
+        // Some of these are sitting in the disk's own queue by now.
+        sleep(Duration::from_millis(50));
+        assert_eq!((magn.queue_depth(), magn.in_flight()), (10, 10));
+
+        magn.run();
+        magn.wait_idle();
+        assert_eq!((magn.queue_depth(), magn.in_flight()), (0, 0));
+        assert!(requests.iter().all(Yield::is_ready));
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_coalescing() {
+        let magn = MagneticDisk::new(4096, DiskAlgorithm::FCFS).with_write_coalescing();
+        magn.pause();
+        let writes: Vec<_> = (0..64).map(|i| magn.write(RawStoragePtr::byte_ptr(100 + i), &[i as u8])).collect();
+        sleep(Duration::from_millis(50));
+        magn.run();
+        Yield::join_get(writes);
+
+        // Every write got its confirm but the head only went there once.
+        assert_eq!(magn.service_record(), [100]);
+        let expected: Vec<u8> = (0..64).collect();
+        assert_eq!(magn.read(RawStoragePtr::byte_ptr(100), 64).get(), expected);
+    }
+
+    #[test]
+    pub fn test_magnetic_disk_coalescing_overlap() {
+        let magn = MagneticDisk::new(4096, DiskAlgorithm::FCFS).with_write_coalescing();
+        magn.pause();
+        let first = magn.write(RawStoragePtr::byte_ptr(10), &[1; 4]);
+        let second = magn.write(RawStoragePtr::byte_ptr(12), &[2; 4]);
+        // This read has to see the first two, so the last write can't join them.
+        let read = magn.read(RawStoragePtr::byte_ptr(10), 8);
+        let third = magn.write(RawStoragePtr::byte_ptr(8), &[3; 4]);
+        sleep(Duration::from_millis(50));
+        magn.run();
+        Yield::join_get(vec![first, second, third]);
+
+        assert_eq!(read.get(), [1, 1, 2, 2, 2, 2, 0, 0]);
+        assert_eq!(magn.service_record(), [10, 10, 8]);
+        assert_eq!(magn.read(RawStoragePtr::byte_ptr(8), 8).get(), [3, 3, 3, 3, 2, 2, 2, 2]);
+    }
+
+    /// Polls a future on this thread, parking until it is woken up.
+    #[cfg(feature = "async")]
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        use std::{pin::pin, task::{Context, Poll, Wake, Waker}, thread::Thread};
+
+        struct Unpark(Thread);
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(value) => return value,
+                Poll::Pending => thread::park()
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    pub fn test_magnetic_disk_async() {
+        let disk = MagneticDisk::new(4096, DiskAlgorithm::FCFS);
+        let mut writes = vec![];
+        let mut reads = vec![];
+        // Everything is sent up front and then awaited from this one thread,
+        // each read goes in after its write so it sees the data.
+        for i in 0..50 {
+            writes.push(disk.write_async(RawStoragePtr::byte_ptr(i * 8), &[i as u8; 8]));
+            reads.push(disk.read_async(RawStoragePtr::byte_ptr(i * 8), 8));
+        }
+        block_on(async {
+            for write in writes {
+                write.await.unwrap();
+            }
+            for (i, read) in reads.into_iter().enumerate() {
+                assert_eq!(read.await.unwrap(), [i as u8; 8]);
+            }
+        });
+
+        let ptr = block_on(disk.store_async(&[1, 2, 3])).unwrap();
+        assert_eq!(block_on(disk.read_async(ptr, 3)), Ok(vec![1, 2, 3]));
+        disk.shutdown_and_join();
+        assert_eq!(block_on(disk.read_async(ptr, 3)), Err(DiskError::DeviceShutDown));
+    }
 }
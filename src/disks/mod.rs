@@ -1,12 +1,43 @@
+use std::{collections::BTreeMap, fs::{File, OpenOptions}, io::{self, Read, Seek, SeekFrom, Write}, path::Path};
+
 use crate::memory::ipc::Yield;
 
 
 pub mod hard_drive;
+pub mod cache;
 pub mod raid;
 pub mod bits;
 
 pub type Bit = bool;
 
+/// Why a request to a disk failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiskError {
+    /// The request goes past the end of the disk.
+    OutOfBounds {
+        addr: usize,
+        len: usize,
+        capacity: usize
+    },
+    /// The disk has been shut down so nothing is servicing requests.
+    DeviceShutDown,
+    /// The disk has failed.
+    DeviceFailed,
+    /// More bits flipped than the error correcting code can fix, it can
+    /// tell something is wrong but not what.
+    Uncorrectable,
+    /// The array the disks are in isn't set up right.
+    Misconfigured(raid::RaidConfigError),
+    /// The record that starts here doesn't match its checksum.
+    ChecksumMismatch {
+        addr: usize
+    },
+    /// The device can't do what was asked of it.
+    Unsupported,
+    /// The file behind the device couldn't be written to.
+    Io(io::ErrorKind)
+}
+
 #[derive(Clone, Debug, Copy)]
 pub struct RawStoragePtr {
     byte_offset: usize,
@@ -34,33 +65,176 @@ pub trait StorageDevice {
     fn store(&mut self, data: &[u8]) -> RawStoragePtr;
     fn write(&mut self, addr: RawStoragePtr, data: &[u8]);
     fn read(&self, addr: RawStoragePtr, length: usize) -> Vec<u8>;
+    /// Gives back space so [StorageDevice::store] can use it again.
+    fn free(&mut self, addr: RawStoragePtr, length: usize);
+    /// How many bytes the device holds.
+    fn capacity(&self) -> usize;
+    /// How many bytes have been stored and not freed.
+    fn used(&self) -> usize;
+    /// Stores data, failing if there is nowhere to put it.
+    fn try_store(&mut self, data: &[u8]) -> Result<RawStoragePtr, DiskError>;
+    /// Checks that a run of bytes is on the device.
+    fn check(&self, addr: RawStoragePtr, length: usize) -> Result<(), DiskError> {
+        match addr.byte_offset.checked_add(length).is_some_and(|end| end <= self.capacity()) {
+            true => Ok(()),
+            false => Err(DiskError::OutOfBounds {
+                addr: addr.byte_offset,
+                len: length,
+                capacity: self.capacity()
+            })
+        }
+    }
+    fn try_write(&mut self, addr: RawStoragePtr, data: &[u8]) -> Result<(), DiskError> {
+        self.check(addr, data.len())?;
+        self.write(addr, data);
+        Ok(())
+    }
+    fn try_read(&self, addr: RawStoragePtr, length: usize) -> Result<Vec<u8>, DiskError> {
+        self.check(addr, length)?;
+        Ok(self.read(addr, length))
+    }
+    fn try_write_bit(&mut self, addr: RawStoragePtr, bit: Bit) -> Result<(), DiskError> {
+        self.check(addr, 1)?;
+        self.write_bit(addr, bit);
+        Ok(())
+    }
+    fn try_free(&mut self, addr: RawStoragePtr, length: usize) -> Result<(), DiskError> {
+        self.check(addr, length)?;
+        self.free(addr, length);
+        Ok(())
+    }
 }
 
+/// A storage device that services requests in the background.
+///
+/// The `try_*` methods report a [DiskError] if the request fails, the
+/// rest wait on them and panic instead.
 pub trait AbstractStorageDevice {
-    fn write_bit(&self, addr: RawStoragePtr, bit: Bit) -> Yield<()>;
-    fn read_bit(&self, addr: RawStoragePtr) -> Yield<Bit>;
-    fn store(&self, data: &[u8]) -> Yield<RawStoragePtr>;
-    fn write(&self, addr: RawStoragePtr, data: &[u8]) -> Yield<()>;
-    fn read(&self, addr: RawStoragePtr, length: usize) -> Yield<Vec<u8>>;
+    fn try_write_bit(&self, addr: RawStoragePtr, bit: Bit) -> Yield<Result<(), DiskError>>;
+    fn try_read_bit(&self, addr: RawStoragePtr) -> Yield<Result<Bit, DiskError>>;
+    fn try_store(&self, data: &[u8]) -> Yield<Result<RawStoragePtr, DiskError>>;
+    fn try_write(&self, addr: RawStoragePtr, data: &[u8]) -> Yield<Result<(), DiskError>>;
+    fn try_read(&self, addr: RawStoragePtr, length: usize) -> Yield<Result<Vec<u8>, DiskError>>;
+    /// How many bytes the device holds.
+    fn capacity(&self) -> usize;
+    /// Gives back space so [AbstractStorageDevice::store] can use it again.
+    fn try_free(&self, addr: RawStoragePtr, length: usize) -> Yield<Result<(), DiskError>>;
+    fn write_bit(&self, addr: RawStoragePtr, bit: Bit) -> Yield<()> {
+        self.try_write_bit(addr, bit).map(expect_disk)
+    }
+    fn read_bit(&self, addr: RawStoragePtr) -> Yield<Bit> {
+        self.try_read_bit(addr).map(expect_disk)
+    }
+    fn store(&self, data: &[u8]) -> Yield<RawStoragePtr> {
+        self.try_store(data).map(expect_disk)
+    }
+    fn write(&self, addr: RawStoragePtr, data: &[u8]) -> Yield<()> {
+        self.try_write(addr, data).map(expect_disk)
+    }
+    fn read(&self, addr: RawStoragePtr, length: usize) -> Yield<Vec<u8>> {
+        self.try_read(addr, length).map(expect_disk)
+    }
+    fn free(&self, addr: RawStoragePtr, length: usize) -> Yield<()> {
+        self.try_free(addr, length).map(expect_disk)
+    }
 }
 
+fn expect_disk<T>(result: Result<T, DiskError>) -> T {
+    result.unwrap_or_else(|e| panic!("The disk request failed: {e:?}"))
+}
+
+/// How many bytes at the start of a disk file hold the offset.
+const HEADER: u64 = 8;
+
 #[derive(Default)]
 pub struct SecondaryStorage {
     buffer: Vec<u8>,
+    /// The furthest anything has been stored.
     offset: usize,
+    /// Freed space below the offset, the length by where it starts.
+    free: BTreeMap<usize, usize>,
+    /// Every change is written through to this if the
+    /// storage was opened from a file.
+    file: Option<File>,
 }
 
 impl SecondaryStorage {
     pub fn new(size: usize) -> Self {
         Self {
             buffer: vec![0u8; size],
-            offset: 0
+            offset: 0,
+            free: BTreeMap::new(),
+            file: None
+        }
+    }
+    /// Opens storage that is kept in a file so it is still there next
+    /// time, the file is made if it does not exist. The offset is kept
+    /// in a header at the start of the file but freed space is not, so
+    /// that is lost when the file is opened again.
+    ///
+    /// A file that is already there has to be the right size for the
+    /// storage, it is never cut down or grown.
+    pub fn open(path: impl AsRef<Path>, size: usize) -> io::Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        match file.metadata()?.len() {
+            0 => file.set_len(HEADER + size as u64)?,
+            length if length != HEADER + size as u64 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("The disk file holds {} bytes, not {size}.", length.saturating_sub(HEADER))
+                ));
+            }
+            _ => ()
+        }
+
+        let mut header = [0; HEADER as usize];
+        file.read_exact(&mut header)?;
+        let mut buffer = vec![0; size];
+        file.read_exact(&mut buffer)?;
+        Ok(Self {
+            buffer,
+            offset: (u64::from_le_bytes(header) as usize).min(size),
+            free: BTreeMap::new(),
+            file: Some(file)
+        })
+    }
+    /// Makes sure everything has made it to the file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.sync_all(),
+            None => Ok(())
         }
     }
+    /// Writes some of the buffer and the offset through to the file.
+    fn persist(&mut self, start: usize, length: usize) -> Result<(), DiskError> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        let header = (self.offset as u64).to_le_bytes();
+        file.seek(SeekFrom::Start(0))
+            .and_then(|_| file.write_all(&header))
+            .and_then(|_| file.seek(SeekFrom::Start(HEADER + start as u64)))
+            .and_then(|_| file.write_all(&self.buffer[start..start + length]))
+            .map_err(|e| DiskError::Io(e.kind()))
+    }
+    /// The furthest anything has been stored, freeing
+    /// space does not move this back.
     pub fn get_offset(&self) -> usize {
         self.offset
     }
-    
+    /// Where [StorageDevice::store] would put this many bytes, this is the
+    /// first freed space they fit in or the end of what has been stored.
+    pub fn store_location(&self, length: usize) -> usize {
+        if length == 0 {
+            return self.offset;
+        }
+        self.free
+            .iter()
+            .find(|(_, size)| **size >= length)
+            .map(|(start, _)| *start)
+            .unwrap_or(self.offset)
+    }
+
 }
 
 impl StorageDevice for SecondaryStorage {
@@ -68,11 +242,17 @@ impl StorageDevice for SecondaryStorage {
         ((self.buffer[addr.byte_offset] >> (7 - addr.bit_offset)) & 1) != 0
     }
     fn write_bit(&mut self, addr: RawStoragePtr, bit: Bit) {
+        expect_disk(self.try_write_bit(addr, bit));
+    }
+    /// The `try_*` changes also fail if they can't be written through to the file.
+    fn try_write_bit(&mut self, addr: RawStoragePtr, bit: Bit) -> Result<(), DiskError> {
+        self.check(addr, 1)?;
         if bit {
             self.buffer[addr.byte_offset] |= 1 << (7 - addr.bit_offset);
         } else {
             self.buffer[addr.byte_offset] &= !(1 << (7 - addr.bit_offset));
         }
+        self.persist(addr.byte_offset, 1)
 
 
         // let related_bytes = self.buffer[addr.byte_offset];
@@ -96,7 +276,11 @@ impl StorageDevice for SecondaryStorage {
         
     }
     fn store(&mut self, data: &[u8]) -> RawStoragePtr {
-        let addr = self.offset;
+        expect_disk(self.try_store(data))
+    }
+    fn try_store(&mut self, data: &[u8]) -> Result<RawStoragePtr, DiskError> {
+        let addr = self.store_location(data.len());
+        self.check(RawStoragePtr::byte_ptr(addr), data.len())?;
 
         self.buffer[addr..addr + data.len()].copy_from_slice(data);
 
@@ -104,27 +288,75 @@ impl StorageDevice for SecondaryStorage {
         //     self.buffer[addr + i] = data[i];
         // }
 
-        self.offset += data.len();
-        RawStoragePtr {
+        if let Some(size) = self.free.remove(&addr) {
+            if size > data.len() {
+                self.free.insert(addr + data.len(), size - data.len());
+            }
+        } else {
+            self.offset += data.len();
+        }
+        self.persist(addr, data.len())?;
+        Ok(RawStoragePtr {
             byte_offset: addr,
             bit_offset: 0
-        }
+        })
     }
     fn write(&mut self, addr: RawStoragePtr, data: &[u8]) {
+        expect_disk(self.try_write(addr, data));
+    }
+    fn try_write(&mut self, addr: RawStoragePtr, data: &[u8]) -> Result<(), DiskError> {
+        self.check(addr, data.len())?;
         self.buffer[addr.byte_offset..addr.byte_offset + data.len()].copy_from_slice(data);
-     
+        self.persist(addr.byte_offset, data.len())
     }
     fn read(&self, addr: RawStoragePtr, length: usize) -> Vec<u8> {
         
         self.buffer[addr.byte_offset..addr.byte_offset + length].to_vec()
     }
+    fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+    fn used(&self) -> usize {
+        self.offset - self.free.values().sum::<usize>()
+    }
+    fn free(&mut self, addr: RawStoragePtr, length: usize) {
+        expect_disk(self.try_free(addr, length));
+    }
+    fn try_free(&mut self, addr: RawStoragePtr, length: usize) -> Result<(), DiskError> {
+        self.check(addr, length)?;
+        if length == 0 {
+            return Ok(());
+        }
+        self.buffer[addr.byte_offset..addr.byte_offset + length].fill(0);
+        self.persist(addr.byte_offset, length)?;
+
+        // Merge it with the free space on either side.
+        let mut start = addr.byte_offset;
+        let mut end = start + length;
+        if let Some((&before, &size)) = self.free.range(..start).next_back() {
+            if before + size >= start {
+                self.free.remove(&before);
+                start = before;
+                end = end.max(before + size);
+            }
+        }
+        while let Some((&after, &size)) = self.free.range(start..=end).next() {
+            self.free.remove(&after);
+            end = end.max(after + size);
+        }
+        // Nothing past the offset has been stored, so there is no need to remember it.
+        if start < self.offset {
+            self.free.insert(start, end.min(self.offset) - start);
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::disks::StorageDevice;
 
-    use super::{RawStoragePtr, SecondaryStorage};
+    use super::{DiskError, RawStoragePtr, SecondaryStorage};
 
 
     #[test]
@@ -150,4 +382,78 @@ mod tests {
         // assert!(disk.read_bit(RawStoragePtr::bit_ptr(0)));
         
     }
+
+    #[test]
+    pub fn test_storage_free() {
+        let mut disk = SecondaryStorage::new(64);
+        let a = disk.store(&[1; 10]);
+        let b = disk.store(&[2; 10]);
+        disk.free(a, 10);
+        assert_eq!(disk.read(a, 10), [0; 10]);
+
+        // A smaller blob goes in the hole and what is left gets used next.
+        let c = disk.store(&[3; 4]);
+        assert_eq!(c.byte_offset, a.byte_offset);
+        assert_eq!(disk.store(&[4; 6]).byte_offset, 4);
+        assert_eq!(disk.get_offset(), 20);
+
+        // Too big for any hole so it goes on the end.
+        disk.free(b, 10);
+        assert_eq!(disk.store(&[5; 12]).byte_offset, 20);
+        assert_eq!(disk.get_offset(), 32);
+    }
+
+    #[test]
+    pub fn test_storage_file() {
+        let path = std::env::temp_dir().join(format!("osconcepts-storage-{}.disk", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut disk = SecondaryStorage::open(&path, 64).unwrap();
+        let addr = disk.store(&[1, 2, 3]);
+        disk.write_bit(RawStoragePtr::bit_ptr(80), true);
+        drop(disk);
+
+        // The offset comes back from the header.
+        let mut disk = SecondaryStorage::open(&path, 64).unwrap();
+        assert_eq!(disk.get_offset(), 3);
+        assert_eq!(disk.read(addr, 3), [1, 2, 3]);
+        assert!(disk.read_bit(RawStoragePtr::bit_ptr(80)));
+        assert_eq!(disk.store(&[4]).byte_offset, 3);
+        drop(disk);
+
+        // Opening it as a different size doesn't touch it.
+        let error = SecondaryStorage::open(&path, 32).err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 8 + 64);
+
+        // A file that can't be written comes back as an error instead of a panic.
+        let mut disk = SecondaryStorage::open(&path, 64).unwrap();
+        disk.file = Some(std::fs::File::open(&path).unwrap());
+        assert!(matches!(disk.try_write(addr, &[9]), Err(DiskError::Io(_))));
+        drop(disk);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    pub fn test_storage_bounds() {
+        let mut disk = SecondaryStorage::new(16);
+        assert_eq!(disk.capacity(), 16);
+        disk.try_write(RawStoragePtr::byte_ptr(12), &[1; 4]).unwrap();
+        assert_eq!(disk.try_read(RawStoragePtr::byte_ptr(12), 4), Ok(vec![1; 4]));
+
+        let error = DiskError::OutOfBounds { addr: 13, len: 4, capacity: 16 };
+        assert_eq!(disk.try_write(RawStoragePtr::byte_ptr(13), &[1; 4]), Err(error));
+        assert_eq!(disk.try_read(RawStoragePtr::byte_ptr(13), 4), Err(error));
+
+        // Stores fill it up exactly and then fail.
+        let addr = disk.try_store(&[2; 10]).unwrap();
+        assert_eq!(disk.used(), 10);
+        assert!(disk.try_store(&[3; 7]).is_err());
+        disk.try_store(&[3; 6]).unwrap();
+        assert_eq!(disk.used(), 16);
+
+        disk.free(addr, 10);
+        assert_eq!(disk.used(), 6);
+        assert_eq!(disk.get_offset(), 16);
+    }
 }
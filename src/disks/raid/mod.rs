@@ -1,4 +1,529 @@
+use std::{fmt, ops::Range, sync::{atomic::{AtomicUsize, Ordering}, Arc}, thread::{self, JoinHandle}};
+
+use parking_lot::{Mutex, RwLock};
+
+use crate::memory::ipc::Yield;
+
+use super::{hard_drive::{FailureMode, MagneticDisk}, AbstractStorageDevice, Bit, DiskError, RawStoragePtr};
+
 pub mod raid0;
 pub mod raid1;
+pub mod raid2;
 pub mod raid3;
 pub mod raid4;
+pub mod raid5;
+pub mod raid10;
+
+/// An array that spreads bytes over its disks, given a way to read and
+/// write the bytes anywhere this makes it an [AbstractStorageDevice] so
+/// it can go wherever a disk can.
+trait Striped {
+    /// The furthest anything has been written.
+    fn offset(&self) -> &AtomicUsize;
+    /// How many bytes the array holds.
+    fn size(&self) -> usize;
+    fn read_range(&self, start: usize, length: usize) -> Result<Vec<u8>, DiskError>;
+    /// Writes over whatever is there, keeping any parity right.
+    fn write_range(&self, start: usize, data: &[u8]) -> Result<(), DiskError>;
+    /// Checks the array is set up right, arrays that come from a
+    /// builder were checked when they were built.
+    fn validate(&self) -> Result<(), RaidConfigError> {
+        Ok(())
+    }
+}
+
+impl<S: Striped> AbstractStorageDevice for S {
+    /// An array that isn't set up right doesn't hold anything.
+    fn capacity(&self) -> usize {
+        match self.validate() {
+            Ok(_) => self.size(),
+            Err(_) => 0
+        }
+    }
+    fn try_read(&self, addr: RawStoragePtr, length: usize) -> Yield<Result<Vec<u8>, DiskError>> {
+        Yield::ready(check(self, addr, length).and_then(|_| self.read_range(addr.byte_offset, length)))
+    }
+    fn try_write(&self, addr: RawStoragePtr, data: &[u8]) -> Yield<Result<(), DiskError>> {
+        let result = check(self, addr, data.len()).and_then(|_| self.write_range(addr.byte_offset, data));
+        if result.is_ok() {
+            self.offset().fetch_max(addr.byte_offset + data.len(), Ordering::SeqCst);
+        }
+        Yield::ready(result)
+    }
+    /// Takes the space off the end before writing to it so two
+    /// stores at once never get the same pointer.
+    fn try_store(&self, data: &[u8]) -> Yield<Result<RawStoragePtr, DiskError>> {
+        let reserved = self
+            .validate()
+            .map_err(DiskError::Misconfigured)
+            .and_then(|_| reserve(self.offset(), data.len(), self.size()));
+        let start = match reserved {
+            Ok(start) => start,
+            Err(error) => return Yield::ready(Err(error))
+        };
+        let ptr = RawStoragePtr::byte_ptr(start);
+        let result = self.try_write(ptr, data).get();
+        if result.is_err() {
+            let _ = self.offset().compare_exchange(start + data.len(), start, Ordering::SeqCst, Ordering::SeqCst);
+        }
+        Yield::ready(result.map(|_| ptr))
+    }
+    fn try_read_bit(&self, addr: RawStoragePtr) -> Yield<Result<Bit, DiskError>> {
+        self.try_read(addr, 1).map(move |f| f.map(|byte| (byte[0] >> (7 - addr.bit_offset)) & 1 != 0))
+    }
+    /// Reads the byte, changes the bit and writes it back. This isn't
+    /// atomic, another write to the same byte in between is lost.
+    fn try_write_bit(&self, addr: RawStoragePtr, bit: Bit) -> Yield<Result<(), DiskError>> {
+        let byte = match self.try_read(addr, 1).get() {
+            Ok(byte) => byte[0],
+            Err(e) => return Yield::ready(Err(e))
+        };
+        let mask = 1 << (7 - addr.bit_offset);
+        let byte = if bit { byte | mask } else { byte & !mask };
+        self.try_write(RawStoragePtr::byte_ptr(addr.byte_offset), &[byte])
+    }
+    /// Zeroes the bytes, keeping any parity right. The arrays only store at the
+    /// end so the space is only used again if it was the last thing stored.
+    fn try_free(&self, addr: RawStoragePtr, length: usize) -> Yield<Result<(), DiskError>> {
+        let result = check(self, addr, length).and_then(|_| self.write_range(addr.byte_offset, &vec![0; length]));
+        if result.is_ok() {
+            let _ = self.offset().compare_exchange(addr.byte_offset + length, addr.byte_offset, Ordering::SeqCst, Ordering::SeqCst);
+        }
+        Yield::ready(result)
+    }
+}
+
+/// Moves the end of what has been stored along to make room for
+/// `length` more bytes, giving back where the room starts.
+fn reserve(offset: &AtomicUsize, length: usize, capacity: usize) -> Result<usize, DiskError> {
+    offset
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |f| f.checked_add(length).filter(|end| *end <= capacity))
+        .map_err(|addr| DiskError::OutOfBounds { addr, len: length, capacity })
+}
+
+/// Where a byte goes when it is dealt out in chunks of `unit` bytes over
+/// `width` disks in turn, this is which of the disks and where on it.
+fn locate(byte: usize, unit: usize, width: usize) -> (usize, usize) {
+    let chunk = byte / unit;
+    (chunk % width, (chunk / width) * unit + byte % unit)
+}
+
+/// How far into each disk the first `length` bytes go.
+fn depth(length: usize, unit: usize, width: usize) -> usize {
+    length.div_ceil(unit * width) * unit
+}
+
+/// Checks the array is set up right and a run of bytes is inside it.
+fn check(array: &impl Striped, addr: RawStoragePtr, length: usize) -> Result<(), DiskError> {
+    array.validate().map_err(DiskError::Misconfigured)?;
+    match addr.byte_offset.checked_add(length).is_some_and(|end| end <= array.size()) {
+        true => Ok(()),
+        false => Err(DiskError::OutOfBounds { addr: addr.byte_offset, len: length, capacity: array.size() })
+    }
+}
+
+/// Splits `length` bytes from `start` into the runs of them that sit one after
+/// another on a disk, given which disk each byte goes on and where. A run never
+/// goes past the end of a chunk of `unit` bytes so it is always in one stripe.
+/// Each run is the disk, where on it the run starts and which of the bytes it is.
+fn runs(start: usize, length: usize, unit: usize, locate: impl Fn(usize) -> (usize, usize)) -> Vec<(usize, usize, Range<usize>)> {
+    let mut runs = vec![];
+    let mut byte = start;
+    while byte < start + length {
+        let (disk, at) = locate(byte);
+        let run = (unit - byte % unit).min(start + length - byte);
+        runs.push((disk, at, byte - start..byte - start + run));
+        byte += run;
+    }
+    runs
+}
+
+/// New bytes for one disk in a stripe, as the disk, where on it they go and the bytes.
+type Change<'a> = (usize, usize, &'a [u8]);
+
+/// Writes new bytes into one stripe of a parity array, keeping its parity right.
+/// The disks include the parity one and the changes are the new bytes for each
+/// data disk that changes and where on the disk they go.
+///
+/// If the whole stripe changes the parity is just worked out from it, otherwise
+/// only the old bytes being replaced and the old parity are read and the change
+/// between the old and new bytes is XORed into the parity. That is four disk
+/// requests to change one byte. A failed disk is never touched, its old bytes are
+/// worked out from the rest if they're needed.
+fn update_stripe(disks: &[MagneticDisk], parity: usize, changes: &[Change], failed: Option<usize>) -> Result<(), DiskError> {
+    // The part of the stripe that changes, the parity has to change over all of it.
+    let Some(low) = changes.iter().map(|(_, at, _)| *at).min() else {
+        return Ok(());
+    };
+    let high = changes.iter().map(|(_, at, bytes)| at + bytes.len()).max().unwrap_or(low);
+    let span = high - low;
+    let full = changes.len() == disks.len() - 1 && changes.iter().all(|(_, at, bytes)| *at == low && bytes.len() == span);
+    // The disks we need the old bytes of.
+    let needed: Vec<usize> = match (full, failed) {
+        (true, _) => vec![],
+        (false, None) => changes.iter().map(|(disk, _, _)| *disk).chain([parity]).collect(),
+        (false, Some(failed)) => (0..disks.len()).filter(|f| *f != failed).collect()
+    };
+    let reads = needed.iter().map(|f| disks[*f].try_read(RawStoragePtr::byte_ptr(low), span)).collect();
+    let mut old = vec![vec![0; span]; disks.len()];
+    for (disk, bytes) in needed.iter().zip(Yield::join_all(reads)) {
+        old[*disk] = bytes?;
+    }
+    if let Some(failed) = failed.filter(|_| !full) {
+        // The stripe XORs to zero so the missing bytes are the XOR of the rest.
+        old[failed] = (0..span).map(|i| old.iter().fold(0, |acc, f| acc ^ f[i])).collect();
+    }
+
+    let mut new_parity = old[parity].clone();
+    for (disk, at, bytes) in changes {
+        for (i, byte) in bytes.iter().enumerate() {
+            new_parity[at - low + i] ^= old[*disk][at - low + i] ^ byte;
+        }
+    }
+    let writes = changes
+        .iter()
+        .copied()
+        .chain([(parity, low, &new_parity[..])])
+        .filter(|(disk, _, _)| Some(*disk) != failed)
+        .map(|(disk, at, bytes)| disks[disk].try_write(RawStoragePtr::byte_ptr(at), bytes))
+        .collect();
+    Yield::join_all(writes).into_iter().collect()
+}
+
+/// How many requests a set of disks have serviced.
+fn operations<'a>(disks: impl IntoIterator<Item = &'a MagneticDisk>) -> usize {
+    disks.into_iter().map(|f| f.service_record().len()).sum()
+}
+
+/// Why a RAID array can't be set up the way it was asked for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RaidConfigError {
+    /// The level needs a parity disk and there isn't one.
+    MissingParityDisk,
+    /// There aren't enough disks for the level.
+    TooFewDisks {
+        required: usize,
+        got: usize
+    },
+    /// There are more disks than the level can use.
+    TooManyDisks {
+        allowed: usize,
+        got: usize
+    },
+    /// A disk is left over without a mirror.
+    UnpairedDisk,
+    /// The stripe unit is zero bytes.
+    ZeroStripeUnit,
+    /// The disks aren't all the same size, `expected` is the size of the first one.
+    MismatchedDiskSizes {
+        expected: usize,
+        got: usize
+    },
+    /// A disk has failed and the array has to be rebuilt before it can change.
+    FailedDisk {
+        index: usize
+    }
+}
+
+impl fmt::Display for RaidConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingParityDisk => write!(f, "the array needs a parity disk"),
+            Self::TooFewDisks { required, got } => write!(f, "the array needs at least {required} disks but has {got}"),
+            Self::TooManyDisks { allowed, got } => write!(f, "the array can use at most {allowed} disks but has {got}"),
+            Self::UnpairedDisk => write!(f, "the array needs an even number of disks to pair them into mirrors"),
+            Self::ZeroStripeUnit => write!(f, "the stripe unit can't be zero bytes"),
+            Self::MismatchedDiskSizes { expected, got } => {
+                write!(f, "the disks must all be the same size, found {got} bytes where {expected} were expected")
+            }
+            Self::FailedDisk { index } => write!(f, "disk {index} has failed and has to be rebuilt first")
+        }
+    }
+}
+
+impl std::error::Error for RaidConfigError {}
+
+/// Checks there are enough disks.
+fn at_least(required: usize, got: usize) -> Result<(), RaidConfigError> {
+    match got >= required {
+        true => Ok(()),
+        false => Err(RaidConfigError::TooFewDisks { required, got })
+    }
+}
+
+/// Checks every disk holds as much as the first one.
+fn same_size<'a, D: AbstractStorageDevice + 'a>(disks: impl IntoIterator<Item = &'a D>) -> Result<(), RaidConfigError> {
+    let mut sizes = disks.into_iter().map(|f| f.capacity());
+    let Some(expected) = sizes.next() else {
+        return Ok(());
+    };
+    match sizes.find(|f| *f != expected) {
+        Some(got) => Err(RaidConfigError::MismatchedDiskSizes { expected, got }),
+        None => Ok(())
+    }
+}
+
+/// The disks of a parity array, these are behind a lock so
+/// a rebuilt spare can be swapped in.
+type Members = Arc<RwLock<Box<[MagneticDisk]>>>;
+
+/// Keeps track of a failed disk in a parity array and rebuilding it onto a hot spare.
+#[derive(Default)]
+struct Rebuild {
+    /// The disk that failed, reads and writes go around it until it is rebuilt.
+    failed: Mutex<Option<usize>>,
+    spare: Mutex<Option<MagneticDisk>>,
+    /// How far down the disks the rebuild has got, out of how far it has to go.
+    progress: Mutex<Option<(usize, usize)>>,
+    /// Writes hold this, and so does a rebuild the whole way through
+    /// so nothing changes under it.
+    writes: Mutex<()>,
+    worker: Mutex<Option<JoinHandle<()>>>
+}
+
+impl Rebuild {
+    fn failed(&self) -> Option<usize> {
+        *self.failed.lock()
+    }
+    /// Fails a member and rebuilds it onto the spare if there is one, every
+    /// byte of the lost disk is the XOR of the bytes at the same place on the
+    /// rest of them, including the parity disk if it is separate.
+    ///
+    /// # Panics
+    /// If a disk has already failed, the array can only lose one at a time.
+    fn fail(self: &Arc<Self>, index: usize, members: &Members, parity: Option<&MagneticDisk>) {
+        {
+            // Writes have to be going around the disk before it stops answering.
+            let _writes = self.writes.lock();
+            let mut failed = self.failed.lock();
+            if let Some(already) = *failed {
+                panic!("Disk {already} has already failed.");
+            }
+            *failed = Some(index);
+            members.read()[index].inject_failure(FailureMode::FailAll);
+        }
+        let Some(spare) = self.spare.lock().take() else {
+            return;
+        };
+
+        let survivors: Vec<_> = members
+            .read()
+            .iter()
+            .enumerate()
+            .filter(|(disk, _)| *disk != index)
+            .map(|(_, f)| f.clone())
+            .chain(parity.cloned())
+            .collect();
+        // Like a real rebuild this does the whole disk, not just what has been written.
+        let depth = survivors.iter().map(|f| f.capacity()).chain([spare.capacity()]).min().unwrap();
+        *self.progress.lock() = Some((0, depth));
+
+        let rebuild = Arc::clone(self);
+        let members = Arc::clone(members);
+        *self.worker.lock() = Some(thread::spawn(move || {
+            let _writes = rebuild.writes.lock();
+            for at in 0..depth {
+                let reads = survivors.iter().map(|f| f.read(RawStoragePtr::byte_ptr(at), 1).map(|f| f[0])).collect();
+                let byte = Yield::join_all(reads).into_iter().fold(0, |acc, f| acc ^ f);
+                spare.write(RawStoragePtr::byte_ptr(at), &[byte]).get();
+                *rebuild.progress.lock() = Some((at + 1, depth));
+            }
+            // The spare has to be in before anything stops going around the slot.
+            members.write()[index] = spare;
+            *rebuild.failed.lock() = None;
+            *rebuild.progress.lock() = None;
+        }));
+    }
+    /// How far through a rebuild is from zero to one, this
+    /// is none if there isn't one going.
+    fn status(&self) -> Option<f32> {
+        self.progress.lock().map(|(done, total)| match total {
+            0 => 1.0,
+            _ => done as f32 / total as f32
+        })
+    }
+    fn wait(&self) {
+        if let Some(worker) = self.worker.lock().take() {
+            worker.join().expect("The rebuild panicked.");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disks::{hard_drive::{DiskAlgorithm, FailureMode, MagneticDisk}, AbstractStorageDevice, DiskError, RawStoragePtr};
+
+    use super::{raid0::Raid0, raid1::Raid1, raid10::Raid10Builder, raid2::Raid2Builder, raid3::Raid3Builder, raid4::Raid4Builder, raid5::Raid5Builder, RaidConfigError};
+
+    fn disk() -> MagneticDisk {
+        MagneticDisk::new(64, DiskAlgorithm::FCFS)
+    }
+
+    /// Anything that takes a device doesn't care what is behind it.
+    fn exercise(device: &dyn AbstractStorageDevice) {
+        let ptr = device.store(&[1, 2, 3, 4, 5]).get();
+        device.write(RawStoragePtr::byte_ptr(ptr.byte_offset + 1), &[9, 9]).get();
+        assert_eq!(device.read(ptr, 5).get(), [1, 9, 9, 4, 5]);
+
+        // The lowest bit of the first byte.
+        let bit = RawStoragePtr::bit_ptr(ptr.byte_offset * 8 + 7);
+        assert!(device.read_bit(bit).get());
+        device.write_bit(bit, false).get();
+        assert_eq!(device.read(ptr, 1).get(), [0]);
+
+        // Freeing the last thing stored zeroes it and lets the space be used again.
+        device.free(ptr, 5).get();
+        assert_eq!(device.read(ptr, 5).get(), [0; 5]);
+        assert_eq!(device.store(&[6, 7]).get().byte_offset, ptr.byte_offset);
+        assert_eq!(device.read(ptr, 2).get(), [6, 7]);
+
+        let end = RawStoragePtr::byte_ptr(device.capacity());
+        assert_eq!(
+            device.try_write(end, &[1]).get(),
+            Err(DiskError::OutOfBounds { addr: device.capacity(), len: 1, capacity: device.capacity() })
+        );
+        assert_eq!(
+            device.try_read(RawStoragePtr::byte_ptr(usize::MAX), 2).get(),
+            Err(DiskError::OutOfBounds { addr: usize::MAX, len: 2, capacity: device.capacity() })
+        );
+    }
+
+    #[test]
+    pub fn test_raid_storage_devices() {
+        exercise(&disk());
+        exercise(&Raid0::new().with_disk(disk()).with_disk(disk()));
+        exercise(&Raid1::new().with_disk(disk()).with_disk(disk()));
+        let raid2 = (0..4).fold(Raid2Builder::default(), |f, _| f.with_disk(disk()).with_check_disk(disk())).build().unwrap();
+        exercise(&raid2);
+        assert_eq!(raid2.capacity(), 256);
+        exercise(&Raid3Builder::default().with_disk(disk()).with_disk(disk()).with_parity_disk(disk()).build().unwrap());
+        exercise(&Raid4Builder::default().with_disk(disk()).with_disk(disk()).with_parity_disk(disk()).build().unwrap());
+        let raid5 = Raid5Builder::default().with_disk(disk()).with_disk(disk()).with_disk(disk()).build().unwrap();
+        exercise(&raid5);
+        assert_eq!(raid5.capacity(), 128);
+        assert!(raid5.check_array_integrity());
+        assert_eq!(
+            raid5.try_free(RawStoragePtr::byte_ptr(127), 2).get(),
+            Err(DiskError::OutOfBounds { addr: 127, len: 2, capacity: 128 })
+        );
+    }
+
+    #[test]
+    pub fn test_raid_stripe_unit_operations() {
+        let data: Vec<u8> = (0..96).collect();
+        // Writes and reads the same bytes, giving back how many requests each array took.
+        let operations = |unit: usize| {
+            let raid0 = Raid0::new().with_disk(disk()).with_disk(disk()).with_stripe_unit(unit);
+            let raid4 = Raid4Builder::default().with_disk(disk()).with_disk(disk()).with_parity_disk(disk()).with_stripe_unit(unit).build().unwrap();
+            let raid5 = Raid5Builder::default().with_disk(disk()).with_disk(disk()).with_disk(disk()).with_stripe_unit(unit).build().unwrap();
+            let ptr = raid0.write(&data);
+            assert_eq!(raid0.read(ptr, 96), data);
+            let ptr = raid4.write(&data);
+            assert_eq!(raid4.read(ptr, 96), data);
+            let ptr = raid5.write(&data);
+            assert_eq!(raid5.read(ptr, 96), data);
+            [raid0.disk_operations(), raid4.disk_operations(), raid5.disk_operations()]
+        };
+
+        // Each chunk is one request, so bigger chunks mean fewer of them.
+        let counts: Vec<_> = [1, 4, 16].into_iter().map(operations).collect();
+        for array in 0..3 {
+            assert!(counts[0][array] > counts[1][array] && counts[1][array] > counts[2][array]);
+        }
+        assert_eq!(counts[0][0], 192);
+        assert_eq!(counts[2][0], 12);
+    }
+
+    #[test]
+    pub fn test_raid_concurrent_stores() {
+        let raid0 = Raid0::new().with_disk(disk()).with_disk(disk());
+        let raid1 = Raid1::new().with_disk(disk()).with_disk(disk());
+        for device in [&raid0 as &(dyn AbstractStorageDevice + Sync), &raid1] {
+            // Every store gets its own space, none of them write over each other.
+            let stored: Vec<_> = std::thread::scope(|s| {
+                let handles: Vec<_> = (0..4u8).map(|f| s.spawn(move || (f, device.store(&[f; 8]).get()))).collect();
+                handles.into_iter().map(|f| f.join().unwrap()).collect()
+            });
+            let mut starts: Vec<_> = stored.iter().map(|(_, ptr)| ptr.byte_offset).collect();
+            starts.sort();
+            assert_eq!(starts, [0, 8, 16, 24]);
+            for (value, ptr) in stored {
+                assert_eq!(device.read(ptr, 8).get(), [value; 8]);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_raid_member_failure() {
+        // Each array is given a disk we keep a handle to so it can be failed under it.
+        let arrays: Vec<(MagneticDisk, Box<dyn AbstractStorageDevice>)> = vec![
+            {
+                let failing = disk();
+                let raid2 = (0..3).fold(Raid2Builder::default().with_disk(failing.clone()), |f, _| f.with_disk(disk()));
+                (failing, Box::new((0..4).fold(raid2, |f, _| f.with_check_disk(disk())).build().unwrap()))
+            },
+            {
+                let failing = disk();
+                (failing.clone(), Box::new(Raid3Builder::default().with_disk(failing).with_disk(disk()).with_parity_disk(disk()).build().unwrap()))
+            },
+            {
+                let failing = disk();
+                (failing.clone(), Box::new(Raid4Builder::default().with_disk(failing).with_disk(disk()).with_parity_disk(disk()).build().unwrap()))
+            },
+            {
+                let failing = disk();
+                (failing.clone(), Box::new(Raid5Builder::default().with_disk(failing).with_disk(disk()).with_disk(disk()).build().unwrap()))
+            }
+        ];
+        for (failing, device) in arrays {
+            let ptr = device.store(&[1, 2, 3]).get();
+            failing.inject_failure(FailureMode::FailAll);
+            assert_eq!(device.try_read(ptr, 3).get(), Err(DiskError::DeviceFailed));
+            assert_eq!(device.try_write(ptr, &[4, 5, 6]).get(), Err(DiskError::DeviceFailed));
+
+            // Some of the write may have got through, but it reads again once the disk is back.
+            failing.heal();
+            assert!(device.try_read(ptr, 3).get().is_ok());
+        }
+    }
+
+    #[test]
+    pub fn test_raid_config_errors() {
+        let few = Raid4Builder::default().with_disk(disk()).with_parity_disk(disk()).build();
+        assert_eq!(few.err(), Some(RaidConfigError::TooFewDisks { required: 2, got: 1 }));
+        let parity = Raid3Builder::default().with_disk(disk()).with_disk(disk()).build();
+        assert_eq!(parity.err(), Some(RaidConfigError::MissingParityDisk));
+        let unit = Raid5Builder::default().with_disk(disk()).with_disk(disk()).with_disk(disk()).with_stripe_unit(0).build();
+        assert_eq!(unit.err(), Some(RaidConfigError::ZeroStripeUnit));
+        let sizes = Raid5Builder::default()
+            .with_disk(disk())
+            .with_disk(disk())
+            .with_disk(MagneticDisk::new(32, DiskAlgorithm::FCFS))
+            .build();
+        assert_eq!(sizes.err(), Some(RaidConfigError::MismatchedDiskSizes { expected: 64, got: 32 }));
+        let many = (0..5).fold(Raid2Builder::default(), |f, _| f.with_disk(disk()).with_check_disk(disk())).build();
+        assert_eq!(many.err(), Some(RaidConfigError::TooManyDisks { allowed: 4, got: 5 }));
+        assert_eq!(Raid10Builder::default().build().err(), Some(RaidConfigError::TooFewDisks { required: 2, got: 0 }));
+
+        // The messages say what is wrong.
+        assert_eq!(RaidConfigError::TooFewDisks { required: 3, got: 2 }.to_string(), "the array needs at least 3 disks but has 2");
+        assert_eq!(RaidConfigError::MissingParityDisk.to_string(), "the array needs a parity disk");
+        assert!(RaidConfigError::MismatchedDiskSizes { expected: 64, got: 32 }.to_string().contains("same size"));
+    }
+
+    #[test]
+    pub fn test_raid_config_at_first_write() {
+        let empty = Raid0::<MagneticDisk>::new();
+        assert_eq!(empty.capacity(), 0);
+        let error = DiskError::Misconfigured(RaidConfigError::TooFewDisks { required: 1, got: 0 });
+        assert_eq!(empty.try_store(&[1]).get().err(), Some(error));
+
+        let unit = Raid0::new().with_disk(disk()).with_stripe_unit(0);
+        assert_eq!(unit.try_write(RawStoragePtr::byte_ptr(0), &[1]).get(), Err(DiskError::Misconfigured(RaidConfigError::ZeroStripeUnit)));
+
+        let single = Raid1::new().with_disk(disk());
+        let error = DiskError::Misconfigured(RaidConfigError::TooFewDisks { required: 2, got: 1 });
+        assert_eq!(single.try_store(&[1]).get().err(), Some(error));
+        assert_eq!(single.try_read(RawStoragePtr::byte_ptr(0), 1).get(), Err(error));
+    }
+}
@@ -1,49 +1,113 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::disks::{hard_drive::MagneticDisk, AbstractStorageDevice, RawStoragePtr};
+use crate::{disks::{hard_drive::MagneticDisk, AbstractStorageDevice, DiskError, RawStoragePtr}, memory::ipc::Yield};
 
-/// A RAID0 array. Stripping is done at the byte level for simplicity.
-pub struct Raid0 {
-    array: Vec<MagneticDisk>,
-    offset: AtomicUsize
+use super::{at_least, locate, operations, runs, same_size, RaidConfigError, Striped};
+
+/// A RAID0 array. Stripping is done at the byte level unless
+/// a bigger stripe unit is set.
+///
+/// The members can be anything that stores data, so striping over
+/// [super::raid1::Raid1] mirrors gives RAID10.
+pub struct Raid0<D = MagneticDisk> {
+    array: Vec<D>,
+    offset: AtomicUsize,
+    /// How many bytes go to a disk before moving on to the next.
+    unit: usize
 }
 
-impl Raid0 {
+impl<D: AbstractStorageDevice> Raid0<D> {
     pub fn new() -> Self {
         Self {
             array: vec![],
-            offset: AtomicUsize::new(0)
+            offset: AtomicUsize::new(0),
+            unit: 1
         }
     }
-    pub fn with_disk(mut self, disk: MagneticDisk) -> Self {
+    pub fn with_disk(mut self, disk: D) -> Self {
         self.array.push(disk);
         self
     }
-    /// Writes to the RAID0 array, performing striping
-    /// at the byte level.
+    /// Sends chunks of this many bytes to each disk in turn.
+    pub fn with_stripe_unit(mut self, unit: usize) -> Self {
+        self.unit = unit;
+        self
+    }
+    /// Grows the array onto another disk, everything written so far is read
+    /// out and striped again over one more disk. Pointers into the array
+    /// still point at the same bytes after.
+    ///
+    /// The new disk has to be the same size as the others. If a disk fails
+    /// while the array is being restriped the error is given back and the
+    /// array is left part way through.
+    pub fn add_disk(&mut self, disk: D) -> Result<(), DiskError> {
+        self.validate().map_err(DiskError::Misconfigured)?;
+        same_size(self.array.iter().chain([&disk])).map_err(DiskError::Misconfigured)?;
+        let data = self.read_range(0, self.offset.load(Ordering::SeqCst))?;
+        self.array.push(disk);
+        self.write_range(0, &data)
+    }
+    /// Writes to the RAID0 array, striping it over the
+    /// disks a stripe unit at a time.
     pub fn write(&self, data: &[u8]) -> RawStoragePtr {
-        let ptr = RawStoragePtr::byte_ptr(self.offset.load(Ordering::SeqCst));
-        let current_offset = self.offset.load(Ordering::SeqCst);
-        for i in 0..data.len() {
-            let disk = (i + current_offset) % self.array.len();
-            self.array[disk].write(RawStoragePtr::byte_ptr((i + current_offset) / self.array.len()), &[data[i]]).get();
-        }
-        self.offset.fetch_add(data.len(), Ordering::SeqCst);
-        ptr
+        self.store(data).get()
     }
     /// Reads from the RAID0 array.
+    ///
+    /// # Panics
+    /// If the array isn't set up right or a disk fails.
     pub fn read(&self, ptr: RawStoragePtr, length: usize) -> Vec<u8> {
-        let mut buffer = vec![];
-        for i in (ptr.byte_offset)..(ptr.byte_offset + length) {
-            buffer.push(self.array[i % self.array.len()].read(RawStoragePtr::byte_ptr(i / self.array.len()), 1).get()[0]);
+        AbstractStorageDevice::read(self, ptr, length).get()
+    }
+}
+
+impl Raid0 {
+    /// How many requests the disks in the array have serviced.
+    pub fn disk_operations(&self) -> usize {
+        operations(&self.array)
+    }
+}
+
+impl<D: AbstractStorageDevice> Striped for Raid0<D> {
+    fn offset(&self) -> &AtomicUsize {
+        &self.offset
+    }
+    fn size(&self) -> usize {
+        // A chunk can't hang off the end of a disk.
+        let depth = self.array.iter().map(|f| f.capacity()).min().unwrap_or(0);
+        depth / self.unit * self.unit * self.array.len()
+    }
+    /// Each disk gets one request for each chunk it has in the range.
+    fn read_range(&self, start: usize, length: usize) -> Result<Vec<u8>, DiskError> {
+        let reads: Vec<_> = runs(start, length, self.unit, |f| locate(f, self.unit, self.array.len()))
+            .into_iter()
+            .map(|(disk, at, run)| self.array[disk].try_read(RawStoragePtr::byte_ptr(at), run.len()))
+            .collect();
+        let mut buffer = Vec::with_capacity(length);
+        for read in reads {
+            buffer.extend(read.get()?);
+        }
+        Ok(buffer)
+    }
+    fn write_range(&self, start: usize, data: &[u8]) -> Result<(), DiskError> {
+        let writes: Vec<_> = runs(start, data.len(), self.unit, |f| locate(f, self.unit, self.array.len()))
+            .into_iter()
+            .map(|(disk, at, run)| self.array[disk].try_write(RawStoragePtr::byte_ptr(at), &data[run]))
+            .collect();
+        writes.into_iter().try_for_each(Yield::get)
+    }
+    fn validate(&self) -> Result<(), RaidConfigError> {
+        at_least(1, self.array.len())?;
+        match self.unit {
+            0 => Err(RaidConfigError::ZeroStripeUnit),
+            _ => Ok(())
         }
-        buffer
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::disks::hard_drive::{DiskAlgorithm, MagneticDisk};
+    use crate::disks::{hard_drive::{DiskAlgorithm, FailureMode, MagneticDisk}, raid::RaidConfigError, AbstractStorageDevice, DiskError, RawStoragePtr};
 
     use super::Raid0;
 
@@ -65,4 +129,64 @@ mod tests {
         assert_eq!(raid.read(ptr2, 4), [6,7,8,9]);
   
     }
-}
\ No newline at end of file
+
+    #[test]
+    #[should_panic(expected = "TooFewDisks")]
+    pub fn test_raid0_read_no_disks() {
+        Raid0::<MagneticDisk>::new().read(RawStoragePtr::byte_ptr(0), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "ZeroStripeUnit")]
+    pub fn test_raid0_read_zero_stripe_unit() {
+        Raid0::new().with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS)).with_stripe_unit(0).read(RawStoragePtr::byte_ptr(0), 1);
+    }
+
+    #[test]
+    pub fn test_raid0_stripe_unit() {
+        let disks: Vec<_> = (0..3).map(|_| MagneticDisk::new(256, DiskAlgorithm::FCFS)).collect();
+        let raid = disks.iter().fold(Raid0::new().with_stripe_unit(16), |f, disk| f.with_disk(disk.clone()));
+        let data: Vec<u8> = (0..100).collect();
+        let ptr = raid.write(&data);
+
+        for (start, length) in [(0, 100), (3, 20), (15, 2), (47, 50), (99, 1)] {
+            assert_eq!(raid.read(RawStoragePtr::byte_ptr(ptr.byte_offset + start), length), data[start..start + length]);
+        }
+
+        // Each disk gets every third chunk of sixteen.
+        let chunks = |f: &[usize]| f.iter().flat_map(|f| data[*f..(*f + 16).min(100)].to_vec()).collect::<Vec<_>>();
+        assert_eq!(disks[0].read(RawStoragePtr::byte_ptr(0), 36).get(), chunks(&[0, 48, 96]));
+        assert_eq!(disks[1].read(RawStoragePtr::byte_ptr(0), 32).get(), chunks(&[16, 64]));
+        assert_eq!(disks[2].read(RawStoragePtr::byte_ptr(0), 32).get(), chunks(&[32, 80]));
+    }
+
+    #[test]
+    pub fn test_raid0_add_disk() {
+        let disks: Vec<_> = (0..3).map(|_| MagneticDisk::new(256, DiskAlgorithm::FCFS)).collect();
+        let mut raid = Raid0::new().with_disk(disks[0].clone()).with_disk(disks[1].clone());
+        let ptr = raid.write(&[1, 2, 3, 4, 5, 6]);
+        assert_eq!(raid.capacity(), 512);
+
+        raid.add_disk(disks[2].clone()).unwrap();
+        assert_eq!(raid.capacity(), 768);
+        assert_eq!(raid.read(ptr, 6), [1, 2, 3, 4, 5, 6]);
+
+        // New writes carry on after the old ones over all three disks.
+        let ptr2 = raid.write(&[7, 8, 9]);
+        assert_eq!(ptr2.byte_offset, 6);
+        assert_eq!(disks[0].read(RawStoragePtr::byte_ptr(0), 3).get(), [1, 4, 7]);
+        assert_eq!(disks[1].read(RawStoragePtr::byte_ptr(0), 3).get(), [2, 5, 8]);
+        assert_eq!(disks[2].read(RawStoragePtr::byte_ptr(0), 3).get(), [3, 6, 9]);
+
+        // A smaller disk would leave the array too small for what is on it.
+        let error = RaidConfigError::MismatchedDiskSizes { expected: 256, got: 4 };
+        assert_eq!(raid.add_disk(MagneticDisk::new(4, DiskAlgorithm::FCFS)), Err(DiskError::Misconfigured(error)));
+        assert_eq!(raid.capacity(), 768);
+        assert_eq!(raid.read(ptr, 9), [1, 2, 3, 4, 5, 6, 7, 8, 9]);
+
+        // A disk that fails while the array is restriped gives back the error.
+        let failing = MagneticDisk::new(256, DiskAlgorithm::FCFS);
+        failing.inject_failure(FailureMode::FailAll);
+        assert_eq!(raid.add_disk(failing), Err(DiskError::DeviceFailed));
+    }
+}
@@ -1,45 +1,173 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::disks::{hard_drive::MagneticDisk, AbstractStorageDevice, RawStoragePtr};
+use crate::{disks::{hard_drive::MagneticDisk, AbstractStorageDevice, Bit, DiskError, RawStoragePtr}, memory::ipc::Yield};
+
+use super::{at_least, reserve};
+
+/// Which mirror a [Raid1] read goes to first, if that one fails
+/// the read moves on to the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ReadPolicy {
+    /// Each read starts at the next mirror along so they share the load.
+    #[default]
+    RoundRobin,
+    /// Always start at this mirror.
+    Preferred(usize)
+}
 
 /// A RAID1 array. Mirroring.
-pub struct Raid1 {
-    array: Vec<MagneticDisk>,
-    offset: AtomicUsize
+pub struct Raid1<D = MagneticDisk> {
+    array: Vec<D>,
+    offset: AtomicUsize,
+    policy: ReadPolicy,
+    /// How many reads have been done, for the round robin.
+    reads: AtomicUsize
 }
 
-impl Raid1 {
+impl<D: AbstractStorageDevice> Raid1<D> {
     pub fn new() -> Self {
         Self {
             array: vec![],
-            offset: AtomicUsize::new(0)
+            offset: AtomicUsize::new(0),
+            policy: ReadPolicy::default(),
+            reads: AtomicUsize::new(0)
         }
     }
-    pub fn with_disk(mut self, disk: MagneticDisk) -> Self {
+    pub fn with_disk(mut self, disk: D) -> Self {
         self.array.push(disk);
         self
     }
-    /// Writes to the RAID0 array, performing striping
-    /// at the byte level.
+    pub fn with_read_policy(mut self, policy: ReadPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+    /// Writes to every mirror in the RAID1 array, mirrors that
+    /// fail are skipped.
+    ///
+    /// # Panics
+    /// If there aren't enough disks to mirror onto or every mirror fails.
     pub fn write(&self, data: &[u8]) -> RawStoragePtr {
-        let ptr = RawStoragePtr::byte_ptr(self.offset.load(Ordering::SeqCst));
-        for disk in &self.array {
-            disk.write(ptr, data).get();
-        }
-        self.offset.fetch_add(data.len(), Ordering::SeqCst);
-        ptr
+        self.store(data).get()
     }
-    /// Reads from the RAID0 array.
+    /// Reads from the RAID1 array, mirrors that fail are skipped.
+    ///
+    /// # Panics
+    /// If every mirror fails.
     pub fn read(&self, ptr: RawStoragePtr, length: usize) -> Vec<u8> {
-        self.array[0].read(ptr, length).get()
+        self.read_from(self.mirrors(), ptr, length)
+            .expect("Every mirror in the array failed.")
+    }
+    /// The mirrors in the order a read should try them.
+    fn mirrors(&self) -> Vec<usize> {
+        let first = match self.policy {
+            ReadPolicy::RoundRobin => self.reads.fetch_add(1, Ordering::SeqCst),
+            ReadPolicy::Preferred(disk) => disk
+        };
+        (0..self.array.len()).map(|f| (first + f) % self.array.len()).collect()
+    }
+    /// Reads from the first of these mirrors that works.
+    fn read_from(&self, disks: impl IntoIterator<Item = usize>, ptr: RawStoragePtr, length: usize) -> Result<Vec<u8>, DiskError> {
+        self.validate()?;
+        first_ok(disks.into_iter().map(|f| self.array[f].try_read(ptr, length).get()))
+    }
+    /// Sends a request to every mirror, it works as long as one of them does.
+    fn mirror<T>(&self, request: impl Fn(&D) -> Yield<Result<T, DiskError>>) -> Result<T, DiskError> {
+        self.validate()?;
+        let requests: Vec<_> = self.array.iter().map(request).collect();
+        first_ok(requests.into_iter().map(Yield::get))
+    }
+    /// Checks there is something to mirror onto, this is done
+    /// by each request rather than up front.
+    fn validate(&self) -> Result<(), DiskError> {
+        at_least(2, self.array.len()).map_err(DiskError::Misconfigured)
+    }
+    /// Swaps a disk out for another one, giving back the old one. The new
+    /// disk has nothing on it until it is [Raid1::resilver]ed.
+    pub fn replace(&mut self, index: usize, disk: D) -> D {
+        std::mem::replace(&mut self.array[index], disk)
+    }
+    /// Copies everything that has been written from a mirror that
+    /// works onto the disk at this index.
+    ///
+    /// # Panics
+    /// If none of the other mirrors work.
+    pub fn resilver(&self, index: usize) {
+        let length = self.offset.load(Ordering::SeqCst);
+        let data = self
+            .read_from((0..self.array.len()).filter(|f| *f != index), RawStoragePtr::byte_ptr(0), length)
+            .expect("There is no working mirror to resilver from.");
+        self.array[index].write(RawStoragePtr::byte_ptr(0), &data).get();
+    }
+}
+
+/// The first result that worked, or the last error.
+fn first_ok<T>(results: impl Iterator<Item = Result<T, DiskError>>) -> Result<T, DiskError> {
+    let mut error = DiskError::DeviceFailed;
+    for result in results {
+        match result {
+            Ok(value) => return Ok(value),
+            Err(e) => error = e
+        }
+    }
+    Err(error)
+}
+
+/// This is what lets a [super::raid0::Raid0] stripe over mirrors.
+impl<D: AbstractStorageDevice> AbstractStorageDevice for Raid1<D> {
+    fn capacity(&self) -> usize {
+        self.array.iter().map(|f| f.capacity()).min().unwrap_or(0)
+    }
+    fn try_write(&self, addr: RawStoragePtr, data: &[u8]) -> Yield<Result<(), DiskError>> {
+        let result = self.mirror(|f| f.try_write(addr, data));
+        if result.is_ok() {
+            self.offset.fetch_max(addr.byte_offset + data.len(), Ordering::SeqCst);
+        }
+        Yield::ready(result)
+    }
+    fn try_read(&self, addr: RawStoragePtr, length: usize) -> Yield<Result<Vec<u8>, DiskError>> {
+        Yield::ready(self.read_from(self.mirrors(), addr, length))
+    }
+    /// Takes the space off the end before writing to it so two
+    /// stores at once never get the same pointer.
+    fn try_store(&self, data: &[u8]) -> Yield<Result<RawStoragePtr, DiskError>> {
+        let start = match self.validate().and_then(|_| reserve(&self.offset, data.len(), self.capacity())) {
+            Ok(start) => start,
+            Err(error) => return Yield::ready(Err(error))
+        };
+        let ptr = RawStoragePtr::byte_ptr(start);
+        let result = self.try_write(ptr, data).get();
+        if result.is_err() {
+            let _ = self.offset.compare_exchange(start + data.len(), start, Ordering::SeqCst, Ordering::SeqCst);
+        }
+        Yield::ready(result.map(|_| ptr))
+    }
+    fn try_read_bit(&self, addr: RawStoragePtr) -> Yield<Result<Bit, DiskError>> {
+        let result = self.validate().and_then(|_| first_ok(self.mirrors().into_iter().map(|f| self.array[f].try_read_bit(addr).get())));
+        Yield::ready(result)
+    }
+    fn try_write_bit(&self, addr: RawStoragePtr, bit: Bit) -> Yield<Result<(), DiskError>> {
+        let result = self.mirror(|f| f.try_write_bit(addr, bit));
+        if result.is_ok() {
+            self.offset.fetch_max(addr.byte_offset + 1, Ordering::SeqCst);
+        }
+        Yield::ready(result)
+    }
+    /// Zeroes the bytes on every mirror, the space is only used
+    /// again if it was the last thing stored.
+    fn try_free(&self, addr: RawStoragePtr, length: usize) -> Yield<Result<(), DiskError>> {
+        let result = self.mirror(|f| f.try_write(addr, &vec![0; length]));
+        if result.is_ok() {
+            let _ = self.offset.compare_exchange(addr.byte_offset + length, addr.byte_offset, Ordering::SeqCst, Ordering::SeqCst);
+        }
+        Yield::ready(result)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::disks::hard_drive::{DiskAlgorithm, MagneticDisk};
+    use crate::disks::{hard_drive::{DiskAlgorithm, FailureMode, MagneticDisk}, AbstractStorageDevice};
 
-    use super::Raid1;
+    use super::{Raid1, ReadPolicy};
 
     #[test]
     pub fn test_raid1_array() {
@@ -59,4 +187,59 @@ mod tests {
         assert_eq!(raid.read(ptr2, 4), [6,7,8,9]);
   
     }
-}
\ No newline at end of file
+
+    #[test]
+    #[should_panic(expected = "TooFewDisks")]
+    pub fn test_raid1_write_one_disk() {
+        Raid1::new().with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS)).write(&[1]);
+    }
+
+    #[test]
+    pub fn test_raid1_read_policy() {
+        let disks = [MagneticDisk::new(256, DiskAlgorithm::FCFS), MagneticDisk::new(256, DiskAlgorithm::FCFS)];
+        let raid = Raid1::new().with_disk(disks[0].clone()).with_disk(disks[1].clone());
+        let ptr = raid.write(&[1, 2, 3]);
+        for _ in 0..4 {
+            raid.read(ptr, 3);
+        }
+        // One write and two reads each.
+        assert_eq!(disks.iter().map(|f| f.service_record().len()).collect::<Vec<_>>(), [3, 3]);
+
+        let raid = raid.with_read_policy(ReadPolicy::Preferred(1));
+        for _ in 0..4 {
+            raid.read(ptr, 3);
+        }
+        assert_eq!(disks.iter().map(|f| f.service_record().len()).collect::<Vec<_>>(), [3, 7]);
+    }
+
+    #[test]
+    pub fn test_raid1_write_failed_mirror() {
+        let failing = MagneticDisk::new(256, DiskAlgorithm::FCFS);
+        let raid = Raid1::new().with_disk(failing.clone()).with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS));
+        failing.inject_failure(FailureMode::FailAll);
+
+        // The write still lands on the mirror that works.
+        let ptr = raid.write(&[1, 2, 3]);
+        assert_eq!(raid.read(ptr, 3), [1, 2, 3]);
+    }
+
+    #[test]
+    pub fn test_raid1_resilver() {
+        let failing = MagneticDisk::new(256, DiskAlgorithm::FCFS);
+        let mut raid = Raid1::new()
+            .with_disk(failing.clone())
+            .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .with_read_policy(ReadPolicy::Preferred(0));
+        let ptr = raid.write(&[1, 2, 3, 4, 5]);
+
+        // The reads carry on from the other mirror.
+        failing.inject_failure(FailureMode::FailAll);
+        assert_eq!(raid.read(ptr, 5), [1, 2, 3, 4, 5]);
+
+        let fresh = MagneticDisk::new(256, DiskAlgorithm::FCFS);
+        raid.replace(0, fresh.clone());
+        raid.resilver(0);
+        assert_eq!(fresh.read(ptr, 5).get(), [1, 2, 3, 4, 5]);
+        assert_eq!(raid.read(ptr, 5), [1, 2, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{disks::{hard_drive::MagneticDisk, AbstractStorageDevice, DiskError, RawStoragePtr}, memory::ipc::Yield};
+
+use super::{at_least, raid0::Raid0, raid1::Raid1, same_size, RaidConfigError};
+
+
+#[derive(Default)]
+pub struct Raid10Builder {
+    array: Vec<MagneticDisk>
+}
+
+impl Raid10Builder {
+    /// Adds a pair of disks that mirror each other.
+    pub fn with_mirror(self, disk_a: MagneticDisk, disk_b: MagneticDisk) -> Self {
+        self.with_disk(disk_a).with_disk(disk_b)
+    }
+    /// Adds a disk, every two of these make a mirror.
+    pub fn with_disk(mut self, disk: MagneticDisk) -> Self {
+        self.array.push(disk);
+        self
+    }
+    /// Builds the array, this needs at least one mirror and
+    /// no disks left over.
+    pub fn build(self) -> Result<Raid10, RaidConfigError> {
+        at_least(2, self.array.len())?;
+        if !self.array.len().is_multiple_of(2) {
+            return Err(RaidConfigError::UnpairedDisk);
+        }
+        same_size(&self.array)?;
+        let mut disks = self.array.into_iter();
+        let mut stripe = Raid0::new();
+        while let (Some(a), Some(b)) = (disks.next(), disks.next()) {
+            stripe = stripe.with_disk(Raid1::new().with_disk(a).with_disk(b));
+        }
+        Ok(Raid10 { stripe })
+    }
+}
+
+/// A RAID10 array, a [Raid0] stripe over [Raid1] mirror pairs.
+///
+/// Any one disk in each pair can go without losing anything.
+pub struct Raid10 {
+    stripe: Raid0<Raid1>
+}
+
+impl Raid10 {
+    /// Writes to the RAID10 array, every byte goes to both disks of a pair.
+    pub fn write(&self, data: &[u8]) -> RawStoragePtr {
+        self.stripe.write(data)
+    }
+    /// Reads from the RAID10 array.
+    pub fn read(&self, ptr: RawStoragePtr, length: usize) -> Vec<u8> {
+        self.stripe.read(ptr, length)
+    }
+    /// Reads from the RAID10 array, this fails if both disks
+    /// of a pair it needs have failed.
+    pub fn try_read(&self, ptr: RawStoragePtr, length: usize) -> Yield<Result<Vec<u8>, DiskError>> {
+        self.stripe.try_read(ptr, length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disks::{hard_drive::{DiskAlgorithm, FailureMode, MagneticDisk}, DiskError};
+
+    use crate::disks::raid::RaidConfigError;
+
+    use super::Raid10Builder;
+
+    #[test]
+    pub fn test_raid10_array() {
+        let disks: Vec<_> = (0..4).map(|_| MagneticDisk::new(256, DiskAlgorithm::FCFS)).collect();
+        let raid = Raid10Builder::default()
+            .with_mirror(disks[0].clone(), disks[1].clone())
+            .with_mirror(disks[2].clone(), disks[3].clone())
+            .build()
+            .unwrap();
+        let ptr = raid.write(&[1, 2, 3, 4, 5]);
+        assert_eq!(raid.read(ptr, 5), [1, 2, 3, 4, 5]);
+
+        // Losing one of a pair is fine.
+        disks[2].inject_failure(FailureMode::FailAll);
+        for _ in 0..2 {
+            assert_eq!(raid.read(ptr, 5), [1, 2, 3, 4, 5]);
+        }
+
+        // Losing both of them is not.
+        disks[3].inject_failure(FailureMode::FailAll);
+        assert_eq!(raid.try_read(ptr, 5).get(), Err(DiskError::DeviceFailed));
+    }
+
+    #[test]
+    pub fn test_raid10_odd_disks() {
+        let raid = Raid10Builder::default()
+            .with_mirror(MagneticDisk::new(256, DiskAlgorithm::FCFS), MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .build();
+        assert_eq!(raid.err(), Some(RaidConfigError::UnpairedDisk));
+    }
+}
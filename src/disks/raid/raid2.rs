@@ -0,0 +1,196 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{disks::{bits::{hamming_decode, hamming_encode, BitVec, EccError}, hard_drive::MagneticDisk, AbstractStorageDevice, DiskError, RawStoragePtr}, memory::ipc::Yield};
+
+use super::{at_least, same_size, RaidConfigError, Striped};
+
+
+/// Where each disk's bit goes in a [hamming_encode] code word, the
+/// data disks come first and then the check disks.
+const POSITIONS: [usize; 8] = [3, 5, 6, 7, 1, 2, 4, 0];
+
+/// Decodes a code word, the disks only care that it can't be fixed.
+fn decode(word: BitVec) -> Result<(u8, Option<usize>), DiskError> {
+    hamming_decode(word).map_err(|e| match e {
+        EccError::Uncorrectable => DiskError::Uncorrectable,
+        EccError::WrongLength(length) => unreachable!("The code words are always eight bits, not {length}.")
+    })
+}
+
+#[derive(Default)]
+pub struct Raid2Builder {
+    array: Vec<MagneticDisk>,
+    check: Vec<MagneticDisk>
+}
+
+impl Raid2Builder {
+    pub fn with_disk(mut self, disk: MagneticDisk) -> Self {
+        self.array.push(disk);
+        self
+    }
+    pub fn with_check_disk(mut self, disk: MagneticDisk) -> Self {
+        self.check.push(disk);
+        self
+    }
+    /// Builds the array, this needs four data disks and four check disks.
+    pub fn build(self) -> Result<Raid2, RaidConfigError> {
+        for disks in [&self.array, &self.check] {
+            at_least(4, disks.len())?;
+            if disks.len() > 4 {
+                return Err(RaidConfigError::TooManyDisks { allowed: 4, got: disks.len() });
+            }
+        }
+        same_size(self.array.iter().chain(&self.check))?;
+        Ok(Raid2 {
+            array: self.array.into_iter().chain(self.check).collect(),
+            offset: AtomicUsize::new(0),
+            corrected: AtomicUsize::new(0)
+        })
+    }
+}
+
+/// A RAID2 array.
+///
+/// Each nibble is striped a bit to a disk over four data disks and the
+/// other four disks hold a Hamming code over it, so any one flipped bit in
+/// a nibble gets fixed and two get noticed.
+pub struct Raid2 {
+    /// The data disks and then the check disks.
+    array: Box<[MagneticDisk]>,
+    offset: AtomicUsize,
+    /// How many flipped bits reads have fixed.
+    corrected: AtomicUsize
+}
+
+impl Raid2 {
+    /// Writes to the RAID2 array.
+    pub fn write(&self, data: &[u8]) -> RawStoragePtr {
+        self.store(data).get()
+    }
+    /// Reads the code word at a bit height, one bit from each disk.
+    fn word(&self, bit_height: usize) -> Result<BitVec, DiskError> {
+        let reads = self.array.iter().map(|f| f.try_read_bit(RawStoragePtr::bit_ptr(bit_height))).collect();
+        let mut word = BitVec::from(0);
+        for (position, bit) in POSITIONS.iter().zip(Yield::join_all(reads)) {
+            word.set(*position, bit?);
+        }
+        Ok(word)
+    }
+    fn nibble(&self, bit_height: usize) -> Result<u8, DiskError> {
+        let (nibble, fixed) = decode(self.word(bit_height)?)?;
+        if fixed.is_some() {
+            self.corrected.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok(nibble)
+    }
+    /// Reads from the RAID2 array, a flipped bit is fixed on the way out.
+    ///
+    /// This is an error if two bits flipped in the same nibble since
+    /// that can be found but not fixed.
+    pub fn read(&self, ptr: RawStoragePtr, length: usize) -> Result<Vec<u8>, DiskError> {
+        // The high nibble of every byte is one bit height and the low one is the next.
+        (ptr.byte_offset..ptr.byte_offset + length)
+            .map(|i| Ok(self.nibble(2 * i)? << 4 | self.nibble(2 * i + 1)?))
+            .collect()
+    }
+    /// How many flipped bits reads have fixed.
+    pub fn corrected_bits(&self) -> usize {
+        self.corrected.load(Ordering::SeqCst)
+    }
+    /// Checks the integrity of the RAID2 array, this is false if any
+    /// code word has a flipped bit even if it could be fixed, or
+    /// can't be read.
+    pub fn check_array_integrity(&self) -> bool {
+        (0..2 * self.offset.load(Ordering::SeqCst)).all(|bit_height| matches!(self.word(bit_height).and_then(decode), Ok((_, None))))
+    }
+}
+
+impl Striped for Raid2 {
+    fn offset(&self) -> &AtomicUsize {
+        &self.offset
+    }
+    fn size(&self) -> usize {
+        // Every byte on a disk holds eight nibbles.
+        self.array.iter().map(|f| f.capacity()).min().unwrap_or(0) * 4
+    }
+    fn read_range(&self, start: usize, length: usize) -> Result<Vec<u8>, DiskError> {
+        self.read(RawStoragePtr::byte_ptr(start), length)
+    }
+    /// Bytes are whole code words so this never has to read anything back.
+    fn write_range(&self, start: usize, data: &[u8]) -> Result<(), DiskError> {
+        for (i, byte) in data.iter().enumerate() {
+            for (half, nibble) in [byte >> 4, byte & 0xF].into_iter().enumerate() {
+                let bit_height = 2 * (start + i) + half;
+                let word = hamming_encode(nibble);
+                let writes = self.array
+                    .iter()
+                    .zip(POSITIONS)
+                    .map(|(disk, position)| disk.try_write_bit(RawStoragePtr::bit_ptr(bit_height), word[position]))
+                    .collect();
+                Yield::join_all(writes).into_iter().collect::<Result<(), _>>()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disks::{hard_drive::{DiskAlgorithm, MagneticDisk}, AbstractStorageDevice, DiskError, RawStoragePtr};
+
+    use super::{Raid2, Raid2Builder};
+
+    fn array() -> Raid2 {
+        (0..4)
+            .fold(Raid2Builder::default(), |f, _| {
+                f.with_disk(MagneticDisk::new(64, DiskAlgorithm::FCFS))
+                    .with_check_disk(MagneticDisk::new(64, DiskAlgorithm::FCFS))
+            })
+            .build()
+            .unwrap()
+    }
+
+    /// Flips a bit on one of the disks.
+    fn flip(raid: &Raid2, disk: usize, bit_height: usize) {
+        let ptr = RawStoragePtr::bit_ptr(bit_height);
+        let bit = raid.array[disk].read_bit(ptr).get();
+        raid.array[disk].write_bit(ptr, !bit).get();
+    }
+
+    #[test]
+    pub fn test_raid2_array() {
+        let raid = array();
+        let ptr = raid.write(&[2, 4, 5, 200]);
+        assert_eq!(raid.read(ptr, 4), Ok(vec![2, 4, 5, 200]));
+        raid.try_write(RawStoragePtr::byte_ptr(1), &[17]).get().unwrap();
+        assert_eq!(raid.read(ptr, 4), Ok(vec![2, 17, 5, 200]));
+        assert!(raid.check_array_integrity());
+        assert_eq!(raid.corrected_bits(), 0);
+    }
+
+    #[test]
+    pub fn test_raid2_single_bit_error() {
+        let raid = array();
+        let ptr = raid.write(&[0xA5, 0x3C]);
+
+        // A data bit and a check bit in different nibbles.
+        flip(&raid, 1, 0);
+        flip(&raid, 6, 3);
+        assert!(!raid.check_array_integrity());
+        assert_eq!(raid.read(ptr, 2), Ok(vec![0xA5, 0x3C]));
+        assert_eq!(raid.corrected_bits(), 2);
+    }
+
+    #[test]
+    pub fn test_raid2_double_bit_error() {
+        let raid = array();
+        let ptr = raid.write(&[0xA5, 0x3C]);
+
+        // Two bits in the low nibble of the second byte.
+        flip(&raid, 0, 3);
+        flip(&raid, 2, 3);
+        assert_eq!(raid.read(ptr, 2), Err(DiskError::Uncorrectable));
+        assert_eq!(raid.read(ptr, 1), Ok(vec![0xA5]));
+        assert_eq!(raid.corrected_bits(), 0);
+    }
+}
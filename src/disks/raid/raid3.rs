@@ -1,6 +1,8 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::disks::{bits::BitVec, hard_drive::MagneticDisk, AbstractStorageDevice, RawStoragePtr};
+use crate::{disks::{bits::BitVec, hard_drive::MagneticDisk, AbstractStorageDevice, DiskError, RawStoragePtr}, memory::ipc::Yield};
+
+use super::{at_least, same_size, RaidConfigError, Striped};
 
 
 #[derive(Default)]
@@ -18,16 +20,23 @@ impl Raid3Builder {
         self.parity = Some(disk);
         self
     }
-    pub fn build(self) -> Raid3 {
-        Raid3 {
+    /// Builds the array, this needs at least two data disks and a parity disk.
+    pub fn build(self) -> Result<Raid3, RaidConfigError> {
+        at_least(2, self.array.len())?;
+        let parity = self.parity.ok_or(RaidConfigError::MissingParityDisk)?;
+        same_size(self.array.iter().chain([&parity]))?;
+        Ok(Raid3 {
             array: self.array.into_boxed_slice(),
-            parity: self.parity.unwrap(),
+            parity,
             offset: AtomicUsize::new(0)
-        }
+        })
     }
 }
 
 /// A RAID3 array. Mirroring.
+///
+/// The bits are striped over the disks and each bit on the parity disk is the
+/// XOR of the bits at the same place on the data disks.
 pub struct Raid3 {
     array: Box<[MagneticDisk]>,
     parity: MagneticDisk,
@@ -40,59 +49,108 @@ impl Raid3 {
     /// 
     /// This will only perform byte level writes.
     pub fn write(&self, data: &[u8]) -> RawStoragePtr {
-        let current_offset = self.offset.load(Ordering::SeqCst);
-        let ptr = RawStoragePtr::byte_ptr(current_offset);
-
-        for i in 0..data.len() {
-            let byte = BitVec::from(data[i]);
-            for j in 0..8 {
-                let bit_index = (8 * (i + current_offset)) + j;
-
-                let disk = bit_index % self.array.len();
-                let bit_height = bit_index / self.array.len();
-
-                self.array[disk].write_bit(RawStoragePtr::bit_ptr(bit_height), byte[j]).get();
-            }
-
-            // Write the parity byte to the disk.
-            self.parity.write_bit(RawStoragePtr::bit_ptr(i + current_offset), byte.parity()).get();
-        }
-        self.offset.fetch_add(data.len(), Ordering::SeqCst);
-        ptr
+        self.store(data).get()
+    }
+    /// Reads the bit at this height from every data disk.
+    fn row(&self, bit_height: usize) -> Result<Vec<bool>, DiskError> {
+        let reads = self.array.iter().map(|f| f.try_read_bit(RawStoragePtr::bit_ptr(bit_height))).collect();
+        Yield::join_all(reads).into_iter().collect()
     }
     /// Reads from the RAID3 array. Only supports byte level reads.
+    ///
+    /// # Panics
+    /// If the read goes past the end of the array or a disk fails.
     pub fn read(&self, ptr: RawStoragePtr, length: usize) -> Vec<u8> {
+        AbstractStorageDevice::read(self, ptr, length).get()
+    }
+    /// Reads from the array without touching a data disk that has failed,
+    /// its bits are put back together from the other disks and the parity.
+    ///
+    /// # Panics
+    /// If there is no data disk at that index or one of the others fails.
+    pub fn read_degraded(&self, failed_disk: usize, ptr: RawStoragePtr, length: usize) -> Vec<u8> {
+        assert!(failed_disk < self.array.len(), "There is no data disk {failed_disk} in the array.");
+        self.read_around(Some(failed_disk), ptr.byte_offset, length).expect("A disk the read needed failed.")
+    }
+    /// Reads the bytes a bit at a time, working out the bits on the
+    /// failed disk if there is one from the rest.
+    fn read_around(&self, failed_disk: Option<usize>, start: usize, length: usize) -> Result<Vec<u8>, DiskError> {
         let mut buffer = vec![0u8; length];
-        for bit_index in ptr.byte_offset * 8..((ptr.byte_offset + length) * 8) {
-         
-
+        for bit_index in start * 8..((start + length) * 8) {
             // Get the index into the buffer.
-            let buffer_index = (bit_index - (8 * ptr.byte_offset)) / 8;
-            let buffer_stride = (bit_index - (8 * ptr.byte_offset)) % 8;
-            
+            let buffer_index = (bit_index - (8 * start)) / 8;
+            let buffer_stride = (bit_index - (8 * start)) % 8;
+
             // This calcuates what disk we have to look up from and the position of the bi.
             let disk = bit_index % self.array.len();
             let bit_height = bit_index / self.array.len();
 
+            let bit = match Some(disk) == failed_disk {
+                // The XOR of the parity and everything else in the row.
+                true => self
+                    .row_around(disk, bit_height)?
+                    .into_iter()
+                    .fold(self.parity.try_read_bit(RawStoragePtr::bit_ptr(bit_height)).get()?, |acc, f| acc ^ f),
+                false => self.array[disk].try_read_bit(RawStoragePtr::bit_ptr(bit_height)).get()?
+            };
             // Updates the value in the buffer.
-            if self.array[disk].read_bit(RawStoragePtr::bit_ptr(bit_height)).get() {
+            if bit {
                 buffer[buffer_index] |= 1 << (7 - buffer_stride);
             }
         }
-        buffer
+        Ok(buffer)
+    }
+    /// Reads the bit at this height from every data disk but the failed one.
+    fn row_around(&self, failed_disk: usize, bit_height: usize) -> Result<Vec<bool>, DiskError> {
+        let reads = self.array
+            .iter()
+            .enumerate()
+            .filter(|(disk, _)| *disk != failed_disk)
+            .map(|(_, f)| f.try_read_bit(RawStoragePtr::bit_ptr(bit_height)))
+            .collect();
+        Yield::join_all(reads).into_iter().collect()
     }
-    /// Checks the integrity of the RAID3 array.
+    /// Checks the integrity of the RAID3 array, a row that
+    /// can't be read isn't intact.
     pub fn check_array_integrity(&self) -> bool {
-        let current_offset = self.offset.load(Ordering::SeqCst);
-        for byte in 0..current_offset {
-            let parity = self.parity.read_bit(RawStoragePtr::bit_ptr(byte)).get();
-            let current: BitVec = self.read(RawStoragePtr::byte_ptr(byte), 1)[0].into();
-      
-            if parity != current.parity() {
-                return false;
+        let rows = (8 * self.offset.load(Ordering::SeqCst)).div_ceil(self.array.len());
+        (0..rows).all(|bit_height| {
+            let parity = self.parity.try_read_bit(RawStoragePtr::bit_ptr(bit_height)).get();
+            matches!((parity, self.row(bit_height)), (Ok(parity), Ok(row)) if !row.iter().fold(parity, |acc, f| acc ^ f))
+        })
+    }
+}
+
+impl Striped for Raid3 {
+    fn offset(&self) -> &AtomicUsize {
+        &self.offset
+    }
+    fn size(&self) -> usize {
+        self.array.iter().chain([&self.parity]).map(|f| f.capacity()).min().unwrap_or(0) * self.array.len()
+    }
+    fn read_range(&self, start: usize, length: usize) -> Result<Vec<u8>, DiskError> {
+        self.read_around(None, start, length)
+    }
+    /// Writes over whatever is already there and then works the parity
+    /// out again for every row of bits it touched.
+    fn write_range(&self, start: usize, data: &[u8]) -> Result<(), DiskError> {
+        for (i, byte) in data.iter().enumerate() {
+            let byte = BitVec::from(*byte);
+            for j in 0..8 {
+                let bit_index = (8 * (i + start)) + j;
+
+                let disk = bit_index % self.array.len();
+                let bit_height = bit_index / self.array.len();
+
+                self.array[disk].try_write_bit(RawStoragePtr::bit_ptr(bit_height), byte[j]).get()?;
             }
         }
-        true
+        let rows = (8 * start / self.array.len())..(8 * (start + data.len())).div_ceil(self.array.len());
+        for bit_height in rows {
+            let parity = self.row(bit_height)?.into_iter().fold(false, |acc, f| acc ^ f);
+            self.parity.try_write_bit(RawStoragePtr::bit_ptr(bit_height), parity).get()?;
+        }
+        Ok(())
     }
 }
 
@@ -109,7 +167,8 @@ mod tests {
             .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
             .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
             .with_parity_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
-            .build();
+            .build()
+            .unwrap();
 
         
         // Do a write and read it
@@ -137,7 +196,8 @@ mod tests {
             .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
             .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
             .with_parity_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
-            .build();
+            .build()
+            .unwrap();
 
         
         
@@ -156,4 +216,22 @@ mod tests {
         assert!(!raid.check_array_integrity());
   
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn test_raid3_degraded_read() {
+        let raid = Raid3Builder::default()
+            .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .with_parity_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .build()
+            .unwrap();
+        let ptr = raid.write(&[2, 4, 5, 200]);
+        raid.try_write(RawStoragePtr::byte_ptr(1), &[17]).get().unwrap();
+        assert!(raid.check_array_integrity());
+
+        raid.array[2].write(RawStoragePtr::byte_ptr(0), &[0; 256]).get();
+        assert_ne!(raid.read(ptr, 4), [2, 17, 5, 200]);
+        assert_eq!(raid.read_degraded(2, ptr, 4), [2, 17, 5, 200]);
+    }
+}
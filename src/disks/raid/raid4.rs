@@ -1,12 +1,18 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{collections::BTreeMap, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
 
-use crate::disks::{bits::BitVec, hard_drive::MagneticDisk, AbstractStorageDevice, RawStoragePtr};
+use parking_lot::RwLock;
+
+use crate::{disks::{hard_drive::MagneticDisk, AbstractStorageDevice, DiskError, RawStoragePtr}, memory::ipc::Yield};
+
+use super::{at_least, depth, locate, operations, runs, same_size, update_stripe, Change, Members, RaidConfigError, Rebuild, Striped};
 
 
 #[derive(Default)]
 pub struct Raid4Builder {
     array: Vec<MagneticDisk>,
-    parity: Option<MagneticDisk>
+    parity: Option<MagneticDisk>,
+    unit: Option<usize>,
+    spare: Option<MagneticDisk>
 }
 
 impl Raid4Builder {
@@ -18,67 +24,211 @@ impl Raid4Builder {
         self.parity = Some(disk);
         self
     }
-    pub fn build(self) -> Raid4 {
-        Raid4 {
-            array: self.array.into_boxed_slice(),
-            parity: self.parity.unwrap(),
-            offset: AtomicUsize::new(0)
+    /// Sends chunks of this many bytes to each disk in turn, this is
+    /// one byte if it isn't set.
+    pub fn with_stripe_unit(mut self, unit: usize) -> Self {
+        self.unit = Some(unit);
+        self
+    }
+    /// A disk that sits idle until a data disk fails and
+    /// then gets rebuilt to take its place.
+    pub fn with_hot_spare(mut self, disk: MagneticDisk) -> Self {
+        self.spare = Some(disk);
+        self
+    }
+    /// Builds the array, this needs at least two data disks and a parity disk.
+    pub fn build(self) -> Result<Raid4, RaidConfigError> {
+        at_least(2, self.array.len())?;
+        let parity = self.parity.ok_or(RaidConfigError::MissingParityDisk)?;
+        same_size(self.array.iter().chain([&parity]).chain(&self.spare))?;
+        let unit = self.unit.unwrap_or(1);
+        if unit == 0 {
+            return Err(RaidConfigError::ZeroStripeUnit);
         }
+
+        let rebuild = Rebuild::default();
+        *rebuild.spare.lock() = self.spare;
+        Ok(Raid4 {
+            array: Arc::new(RwLock::new(self.array.into_boxed_slice())),
+            parity,
+            offset: AtomicUsize::new(0),
+            unit,
+            rebuild: Arc::new(rebuild)
+        })
     }
 }
 
 /// A RAID4 array. 
 /// 
-/// Byte-level (it should be block but this is a huge pain so we are doing it at
-/// the byte level) striping w/ a parity disk.
+/// Striping w/ a parity disk, this is byte-level unless a bigger stripe unit is
+/// set. Each byte on the parity disk is the XOR of the bytes at the same place on
+/// the data disks, so any one of them can be worked out from the rest.
 pub struct Raid4 {
-    array: Box<[MagneticDisk]>,
+    array: Members,
     parity: MagneticDisk,
-    offset: AtomicUsize
+    offset: AtomicUsize,
+    /// How many bytes go to a disk before moving on to the next.
+    unit: usize,
+    rebuild: Arc<Rebuild>
 }
 
 impl Raid4 {
-    /// Writes to the RAID4 array, performing striping at the
-    /// byte level.
-    /// 
-    /// This will only perform byte level writes.
+    /// Writes to the RAID4 array, striping it over the data
+    /// disks a stripe unit at a time.
     pub fn write(&self, data: &[u8]) -> RawStoragePtr {
-        let ptr = RawStoragePtr::byte_ptr(self.offset.load(Ordering::SeqCst));
-        let current_offset = self.offset.load(Ordering::SeqCst);
-        for i in 0..data.len() {
-            let disk = (i + current_offset) % self.array.len();
-            self.array[disk].write(RawStoragePtr::byte_ptr((i + current_offset) / self.array.len()), &[data[i]]).get();
-            self.parity.write_bit(RawStoragePtr::bit_ptr(i + current_offset), BitVec::from(data[i]).parity()).get();
+        self.store(data).get()
+    }
+    /// The data disks as they are right now.
+    fn array(&self) -> Box<[MagneticDisk]> {
+        self.array.read().clone()
+    }
+    fn width(&self) -> usize {
+        self.array.read().len()
+    }
+    /// Which data disk a byte is on and where.
+    fn locate(&self, byte: usize) -> (usize, RawStoragePtr) {
+        let (disk, at) = locate(byte, self.unit, self.width());
+        (disk, RawStoragePtr::byte_ptr(at))
+    }
+    /// Reads the byte at a stripe from every data disk, a failed
+    /// one is worked out from the rest.
+    fn read_stripe(&self, stripe: usize) -> Result<Vec<u8>, DiskError> {
+        let failed = self.rebuild.failed();
+        let reads = self.array()
+            .iter()
+            .enumerate()
+            .map(|(disk, f)| match Some(disk) == failed {
+                true => Yield::ready(Ok(0)),
+                false => f.try_read(RawStoragePtr::byte_ptr(stripe), 1).map(|f| f.map(|f| f[0]))
+            })
+            .collect();
+        let mut bytes = Yield::join_all(reads).into_iter().collect::<Result<Vec<_>, _>>()?;
+        if let Some(failed) = failed {
+            let parity = self.parity.try_read(RawStoragePtr::byte_ptr(stripe), 1).get()?[0];
+            bytes[failed] = bytes.iter().fold(parity, |acc, f| acc ^ f);
         }
-        self.offset.fetch_add(data.len(), Ordering::SeqCst);
-        ptr
+        Ok(bytes)
     }
     /// Reads from the RAID4 array. Only supports byte level reads.
+    ///
+    /// If a disk has failed this reads around it.
+    ///
+    /// # Panics
+    /// If the read goes past the end of the array or a disk it needs fails.
     pub fn read(&self, ptr: RawStoragePtr, length: usize) -> Vec<u8> {
-        let mut buffer = vec![];
-        for i in (ptr.byte_offset)..(ptr.byte_offset + length) {
-            buffer.push(self.array[i % self.array.len()].read(RawStoragePtr::byte_ptr(i / self.array.len()), 1).get()[0]);
-        }
-        buffer
+        AbstractStorageDevice::read(self, ptr, length).get()
+    }
+    /// Reads from the array without touching a data disk that has failed,
+    /// its bytes are put back together from the other disks and the parity.
+    ///
+    /// # Panics
+    /// If there is no data disk at that index or one of the others fails.
+    pub fn read_degraded(&self, failed_disk: usize, ptr: RawStoragePtr, length: usize) -> Vec<u8> {
+        assert!(failed_disk < self.width(), "There is no data disk {failed_disk} in the array.");
+        self.read_around(failed_disk, ptr.byte_offset, length).expect("A disk the read needed failed.")
+    }
+    /// Reads the bytes, working out the ones on the failed disk from the rest.
+    fn read_around(&self, failed_disk: usize, start: usize, length: usize) -> Result<Vec<u8>, DiskError> {
+        let array = self.array();
+        (start..start + length)
+            .map(|i| {
+                let (disk, stripe) = self.locate(i);
+                if disk != failed_disk {
+                    return Ok(array[disk].try_read(stripe, 1).get()?[0]);
+                }
+                array
+                    .iter()
+                    .enumerate()
+                    .filter(|(disk, _)| *disk != failed_disk)
+                    .try_fold(self.parity.try_read(stripe, 1).get()?[0], |acc, (_, f)| Ok(acc ^ f.try_read(stripe, 1).get()?[0]))
+            })
+            .collect()
     }
-    /// Checks the integrity of the RAID4 array.
+    /// Checks the integrity of the RAID4 array, a stripe that
+    /// can't be read isn't intact.
     pub fn check_array_integrity(&self) -> bool {
-        let current_offset = self.offset.load(Ordering::SeqCst);
-        for byte in 0..current_offset {
-            let parity = self.parity.read_bit(RawStoragePtr::bit_ptr(byte)).get();
-            let current: BitVec = self.read(RawStoragePtr::byte_ptr(byte), 1)[0].into();
-      
-            if parity != current.parity() {
-                return false;
-            }
+        let stripes = depth(self.offset.load(Ordering::SeqCst), self.unit, self.width());
+        (0..stripes).all(|stripe| {
+            let parity = self.parity.try_read(RawStoragePtr::byte_ptr(stripe), 1).get();
+            let bytes = self.read_stripe(stripe);
+            matches!((parity, bytes), (Ok(parity), Ok(bytes)) if bytes.iter().fold(parity[0], |acc, f| acc ^ f) == 0)
+        })
+    }
+    /// Writes over the bytes at a pointer. Each stripe that only partly
+    /// changes costs reading the old bytes and the old parity before
+    /// writing the new ones, this is the small write penalty.
+    pub fn write_at(&self, ptr: RawStoragePtr, data: &[u8]) {
+        AbstractStorageDevice::write(self, ptr, data).get();
+    }
+    /// How many requests the disks in the array have serviced,
+    /// including the parity disk.
+    pub fn disk_operations(&self) -> usize {
+        operations(self.array().iter().chain([&self.parity]))
+    }
+    /// Fails a data disk, the array keeps going without it and if there
+    /// is a hot spare it gets rebuilt onto that in the background.
+    ///
+    /// # Panics
+    /// If there is no data disk at that index, or if a disk has already failed.
+    pub fn fail_disk(&self, index: usize) {
+        assert!(index < self.width(), "There is no data disk {index} in the array.");
+        self.rebuild.fail(index, &self.array, Some(&self.parity));
+    }
+    /// How far through rebuilding onto the hot spare the array
+    /// is from zero to one, or none if it isn't rebuilding.
+    pub fn rebuild_status(&self) -> Option<f32> {
+        self.rebuild.status()
+    }
+    /// Waits for a rebuild to finish.
+    pub fn wait_rebuild(&self) {
+        self.rebuild.wait();
+    }
+}
+
+impl Striped for Raid4 {
+    fn offset(&self) -> &AtomicUsize {
+        &self.offset
+    }
+    fn size(&self) -> usize {
+        let depth = self.array().iter().chain([&self.parity]).map(|f| f.capacity()).min().unwrap_or(0);
+        depth / self.unit * self.unit * self.width()
+    }
+    fn read_range(&self, start: usize, length: usize) -> Result<Vec<u8>, DiskError> {
+        if let Some(failed) = self.rebuild.failed() {
+            return self.read_around(failed, start, length);
         }
-        true
+        let array = self.array();
+        // One request for each chunk, sent out at once so the disks work in parallel.
+        let reads: Vec<_> = runs(start, length, self.unit, |f| locate(f, self.unit, array.len()))
+            .into_iter()
+            .map(|(disk, at, run)| array[disk].try_read(RawStoragePtr::byte_ptr(at), run.len()))
+            .collect();
+        let mut buffer = Vec::with_capacity(length);
+        for read in reads {
+            buffer.extend(read.get()?);
+        }
+        Ok(buffer)
+    }
+    fn write_range(&self, start: usize, data: &[u8]) -> Result<(), DiskError> {
+        let _writes = self.rebuild.writes.lock();
+        let failed = self.rebuild.failed();
+        // The new bytes for each stripe, by the disk they go on and where.
+        let mut stripes: BTreeMap<usize, Vec<Change>> = BTreeMap::new();
+        for (disk, at, run) in runs(start, data.len(), self.unit, |f| locate(f, self.unit, self.width())) {
+            stripes.entry(at / self.unit).or_default().push((disk, at, &data[run]));
+        }
+        // The parity disk goes on the end.
+        let disks: Vec<_> = self.array().iter().chain([&self.parity]).cloned().collect();
+        for changes in stripes.values() {
+            update_stripe(&disks, disks.len() - 1, changes, failed)?;
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::disks::{hard_drive::{DiskAlgorithm, MagneticDisk}, raid::raid4::Raid4Builder, AbstractStorageDevice, RawStoragePtr};
+    use crate::disks::{hard_drive::{DiskAlgorithm, FailureMode, MagneticDisk}, raid::raid4::Raid4Builder, AbstractStorageDevice, RawStoragePtr};
 
 
 
@@ -89,7 +239,8 @@ mod tests {
             .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
             .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
             .with_parity_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
-            .build();
+            .build()
+            .unwrap();
 
         
         // Do a write and read it
@@ -117,7 +268,8 @@ mod tests {
             .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
             .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
             .with_parity_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
-            .build();
+            .build()
+            .unwrap();
 
         
         
@@ -127,7 +279,7 @@ mod tests {
 
 
         // Corrupt the array
-        raid.array[0].write_bit(RawStoragePtr::bit_ptr(0), true);
+        raid.array()[0].write_bit(RawStoragePtr::bit_ptr(0), true);
 
     
 
@@ -136,4 +288,130 @@ mod tests {
         assert!(!raid.check_array_integrity());
   
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn test_raid4_silent_corruption() {
+        let raid = Raid4Builder::default()
+            .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .with_parity_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .build()
+            .unwrap();
+        raid.write(&[2, 4, 5]);
+
+        // The disk doesn't notice but the parity does.
+        raid.array()[1].inject_failure(FailureMode::SilentCorruption(0));
+        assert_eq!(raid.read(RawStoragePtr::byte_ptr(0), 3), [2, 5, 5]);
+        assert!(!raid.check_array_integrity());
+
+        raid.array()[1].heal();
+        assert!(raid.check_array_integrity());
+    }
+
+    #[test]
+    pub fn test_raid4_disk_handles() {
+        let watched = MagneticDisk::new(256, DiskAlgorithm::FCFS);
+        let raid = Raid4Builder::default()
+            .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .with_disk(watched.clone())
+            .with_parity_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .build()
+            .unwrap();
+        raid.write(&[2, 4, 5]);
+        assert!(raid.check_array_integrity());
+
+        // Corrupt it through the handle we kept.
+        watched.write(RawStoragePtr::byte_ptr(0), &[6]).get();
+        assert!(!raid.check_array_integrity());
+    }
+
+    #[test]
+    pub fn test_raid4_degraded_read() {
+        let raid = Raid4Builder::default()
+            .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .with_parity_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .build()
+            .unwrap();
+        let ptr = raid.write(&[1, 2, 3, 4, 5, 6, 7]);
+        // Overwriting the middle of a stripe keeps the parity right.
+        raid.try_write(RawStoragePtr::byte_ptr(4), &[9, 9]).get().unwrap();
+        assert!(raid.check_array_integrity());
+
+        raid.array()[1].write(RawStoragePtr::byte_ptr(0), &[0; 256]).get();
+        assert_ne!(raid.read(ptr, 7), [1, 2, 3, 4, 9, 9, 7]);
+        assert_eq!(raid.read_degraded(1, ptr, 7), [1, 2, 3, 4, 9, 9, 7]);
+    }
+
+    #[test]
+    pub fn test_raid4_stripe_unit() {
+        let parity = MagneticDisk::new(256, DiskAlgorithm::FCFS);
+        let raid = Raid4Builder::default()
+            .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .with_parity_disk(parity.clone())
+            .with_stripe_unit(4)
+            .build()
+            .unwrap();
+        let data: Vec<u8> = (1..=30).collect();
+        let ptr = raid.write(&data);
+        assert_eq!(raid.read(RawStoragePtr::byte_ptr(5), 13), data[5..18]);
+        assert!(raid.check_array_integrity());
+
+        // The first parity chunk covers the first three chunks of data.
+        let expected: Vec<u8> = (0..4).map(|f| data[f] ^ data[f + 4] ^ data[f + 8]).collect();
+        assert_eq!(parity.read(RawStoragePtr::byte_ptr(0), 4).get(), expected);
+
+        raid.array()[2].write(RawStoragePtr::byte_ptr(0), &[0; 256]).get();
+        assert_eq!(raid.read_degraded(2, ptr, 30), data);
+    }
+
+    #[test]
+    pub fn test_raid4_hot_spare() {
+        let spare = MagneticDisk::new(256, DiskAlgorithm::FCFS);
+        let raid = (0..3)
+            .fold(Raid4Builder::default().with_hot_spare(spare.clone()), |f, _| f.with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS)))
+            .with_parity_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .build()
+            .unwrap();
+        let data: Vec<u8> = (1..=40).collect();
+        let ptr = raid.write(&data[..20]);
+
+        raid.fail_disk(2);
+        assert_eq!(raid.read(ptr, 20), data[..20]);
+        raid.write(&data[20..]);
+        assert_eq!(raid.read(ptr, 40), data);
+
+        raid.wait_rebuild();
+        assert_eq!(raid.rebuild_status(), None);
+        assert_eq!(raid.read(ptr, 40), data);
+        assert!(raid.check_array_integrity());
+        // Breaking the spare breaks the array, so it really is in there.
+        spare.write(RawStoragePtr::byte_ptr(0), &[0; 10]).get();
+        assert!(!raid.check_array_integrity());
+    }
+
+    #[test]
+    pub fn test_raid4_overwrite() {
+        let raid = (0..3)
+            .fold(Raid4Builder::default(), |f, _| f.with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS)))
+            .with_parity_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS))
+            .build()
+            .unwrap();
+        let mut data: Vec<u8> = (1..=15).collect();
+        let ptr = raid.write(&data);
+
+        let before = raid.disk_operations();
+        raid.write_at(RawStoragePtr::byte_ptr(7), &[42]);
+        assert_eq!(raid.disk_operations() - before, 4);
+        data[7] = 42;
+
+        // Over the end of one stripe and into the next two.
+        raid.write_at(RawStoragePtr::byte_ptr(2), &[9; 5]);
+        data[2..7].copy_from_slice(&[9; 5]);
+        assert_eq!(raid.read(ptr, 15), data);
+        assert!(raid.check_array_integrity());
+    }
+}
@@ -0,0 +1,451 @@
+use std::{collections::BTreeMap, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
+
+use parking_lot::RwLock;
+
+use crate::{disks::{hard_drive::MagneticDisk, AbstractStorageDevice, DiskError, RawStoragePtr}, memory::ipc::Yield};
+
+use super::{at_least, depth, locate, operations, runs, same_size, update_stripe, Change, Members, RaidConfigError, Rebuild, Striped};
+
+
+#[derive(Default)]
+pub struct Raid5Builder {
+    array: Vec<MagneticDisk>,
+    unit: Option<usize>,
+    spare: Option<MagneticDisk>
+}
+
+impl Raid5Builder {
+    pub fn with_disk(mut self, disk: MagneticDisk) -> Self {
+        self.array.push(disk);
+        self
+    }
+    /// Sends chunks of this many bytes to each disk in turn, this is
+    /// one byte if it isn't set.
+    pub fn with_stripe_unit(mut self, unit: usize) -> Self {
+        self.unit = Some(unit);
+        self
+    }
+    /// A disk that sits idle until one in the array fails and
+    /// then gets rebuilt to take its place.
+    pub fn with_hot_spare(mut self, disk: MagneticDisk) -> Self {
+        self.spare = Some(disk);
+        self
+    }
+    /// Builds the array, this needs at least three disks.
+    pub fn build(self) -> Result<Raid5, RaidConfigError> {
+        at_least(3, self.array.len())?;
+        same_size(self.array.iter().chain(&self.spare))?;
+        let unit = self.unit.unwrap_or(1);
+        if unit == 0 {
+            return Err(RaidConfigError::ZeroStripeUnit);
+        }
+
+        let rebuild = Rebuild::default();
+        *rebuild.spare.lock() = self.spare;
+        Ok(Raid5 {
+            array: Arc::new(RwLock::new(self.array.into_boxed_slice())),
+            offset: AtomicUsize::new(0),
+            unit,
+            rebuild: Arc::new(rebuild)
+        })
+    }
+}
+
+/// A RAID5 array.
+///
+/// Byte-level striping like [super::raid4::Raid4] but there is no parity disk,
+/// each stripe keeps its parity byte on a different disk so the parity writes
+/// are spread over all of them.
+pub struct Raid5 {
+    array: Members,
+    offset: AtomicUsize,
+    /// How many bytes go to a disk before moving on to the next.
+    unit: usize,
+    rebuild: Arc<Rebuild>
+}
+
+impl Raid5 {
+    /// The disks as they are right now.
+    fn array(&self) -> Box<[MagneticDisk]> {
+        self.array.read().clone()
+    }
+    fn disks(&self) -> usize {
+        self.array.read().len()
+    }
+    /// How many data chunks are in each stripe.
+    fn width(&self) -> usize {
+        self.disks().saturating_sub(1)
+    }
+    /// Which disk holds the parity for a stripe, this moves along
+    /// one disk every stripe unit.
+    fn parity_disk(&self, stripe: usize) -> usize {
+        (stripe / self.unit) % self.disks()
+    }
+    /// Which disk a byte is on, the byte is at its stripe on that disk.
+    fn locate(&self, byte: usize) -> (usize, usize) {
+        let (position, stripe) = locate(byte, self.unit, self.width());
+        // The data goes on the disks either side of the parity one.
+        let disk = match position < self.parity_disk(stripe) {
+            true => position,
+            false => position + 1
+        };
+        (disk, stripe)
+    }
+    /// How many stripes have something in them.
+    fn stripes(&self) -> usize {
+        depth(self.offset.load(Ordering::SeqCst), self.unit, self.width())
+    }
+    /// Reads the byte at a stripe from every disk, a failed one
+    /// is worked out from the rest.
+    fn read_stripe(&self, stripe: usize) -> Result<Vec<u8>, DiskError> {
+        let failed = self.rebuild.failed();
+        let reads = self.array()
+            .iter()
+            .enumerate()
+            .map(|(disk, f)| match Some(disk) == failed {
+                true => Yield::ready(Ok(0)),
+                false => f.try_read(RawStoragePtr::byte_ptr(stripe), 1).map(|f| f.map(|f| f[0]))
+            })
+            .collect();
+        let mut bytes = Yield::join_all(reads).into_iter().collect::<Result<Vec<_>, _>>()?;
+        if let Some(failed) = failed {
+            bytes[failed] = bytes.iter().fold(0, |acc, f| acc ^ f);
+        }
+        Ok(bytes)
+    }
+    /// Writes to the RAID5 array, striping it over the disks a stripe
+    /// unit at a time and updating the parity of every stripe it touches.
+    pub fn write(&self, data: &[u8]) -> RawStoragePtr {
+        self.store(data).get()
+    }
+    /// Reads from the RAID5 array.
+    ///
+    /// If a disk has failed this reads around it.
+    ///
+    /// # Panics
+    /// If the read goes past the end of the array or a disk it needs fails.
+    pub fn read(&self, ptr: RawStoragePtr, length: usize) -> Vec<u8> {
+        AbstractStorageDevice::read(self, ptr, length).get()
+    }
+    /// Rebuilds everything on a disk from the others, this is what
+    /// happens when a failed disk is swapped out for a blank one.
+    ///
+    /// # Panics
+    /// If there is no disk at that index.
+    pub fn reconstruct(&self, disk_index: usize) {
+        assert!(disk_index < self.disks(), "There is no disk {disk_index} in the array.");
+        for stripe in 0..self.stripes() {
+            // Every stripe XORs to zero, so the missing byte is the XOR of the rest.
+            let bytes = self.read_stripe(stripe).expect("Couldn't read the stripe to rebuild it.");
+            let lost = bytes
+                .iter()
+                .enumerate()
+                .filter(|(disk, _)| *disk != disk_index)
+                .fold(0, |acc, (_, f)| acc ^ f);
+            self.array()[disk_index].write(RawStoragePtr::byte_ptr(stripe), &[lost]).get();
+        }
+    }
+    /// Checks the integrity of the RAID5 array, a stripe that
+    /// can't be read isn't intact.
+    pub fn check_array_integrity(&self) -> bool {
+        (0..self.stripes()).all(|stripe| self.read_stripe(stripe).is_ok_and(|bytes| bytes.iter().fold(0, |acc, f| acc ^ f) == 0))
+    }
+    /// Grows the array onto another disk, everything written so far is read
+    /// out and striped again over one more disk with the parity worked out
+    /// again. Pointers into the array still point at the same bytes after.
+    ///
+    /// A disk that has failed has to be rebuilt first. If one fails while the
+    /// array is being restriped the error is given back and the array is left
+    /// part way through.
+    pub fn add_disk(&self, disk: MagneticDisk) -> Result<(), DiskError> {
+        let _writes = self.rebuild.writes.lock();
+        if let Some(index) = self.rebuild.failed() {
+            return Err(DiskError::Misconfigured(RaidConfigError::FailedDisk { index }));
+        }
+        same_size(self.array().iter().chain([&disk])).map_err(DiskError::Misconfigured)?;
+
+        let mut data = self.read_range(0, self.offset.load(Ordering::SeqCst))?;
+        let mut array = self.array().into_vec();
+        array.push(disk);
+        *self.array.write() = array.into_boxed_slice();
+        // The parity on the disks doesn't match the new layout, so fill out the
+        // last stripe to write them all whole without reading any of it back.
+        data.resize(self.stripes() * self.width(), 0);
+        self.write_stripes(0, &data)
+    }
+    /// Writes over the bytes at a pointer. Each stripe that only partly
+    /// changes costs reading the old bytes and the old parity before
+    /// writing the new ones, this is the small write penalty.
+    pub fn write_at(&self, ptr: RawStoragePtr, data: &[u8]) {
+        AbstractStorageDevice::write(self, ptr, data).get();
+    }
+    /// How many requests the disks in the array have serviced.
+    pub fn disk_operations(&self) -> usize {
+        operations(self.array().iter())
+    }
+    /// Fails a disk, the array keeps going without it and if there
+    /// is a hot spare it gets rebuilt onto that in the background.
+    ///
+    /// # Panics
+    /// If there is no disk at that index, or if a disk has already failed.
+    pub fn fail_disk(&self, index: usize) {
+        assert!(index < self.disks(), "There is no disk {index} in the array.");
+        self.rebuild.fail(index, &self.array, None);
+    }
+    /// How far through rebuilding onto the hot spare the array
+    /// is from zero to one, or none if it isn't rebuilding.
+    pub fn rebuild_status(&self) -> Option<f32> {
+        self.rebuild.status()
+    }
+    /// Waits for a rebuild to finish.
+    pub fn wait_rebuild(&self) {
+        self.rebuild.wait();
+    }
+}
+
+impl Striped for Raid5 {
+    fn offset(&self) -> &AtomicUsize {
+        &self.offset
+    }
+    fn size(&self) -> usize {
+        let depth = self.array().iter().map(|f| f.capacity()).min().unwrap_or(0);
+        depth / self.unit * self.unit * self.width()
+    }
+    fn read_range(&self, start: usize, length: usize) -> Result<Vec<u8>, DiskError> {
+        if self.rebuild.failed().is_some() {
+            return (start..start + length)
+                .map(|i| {
+                    let (disk, stripe) = self.locate(i);
+                    Ok(self.read_stripe(stripe)?[disk])
+                })
+                .collect();
+        }
+        let array = self.array();
+        // One request for each chunk, sent out at once so the disks work in parallel.
+        let reads: Vec<_> = runs(start, length, self.unit, |f| self.locate(f))
+            .into_iter()
+            .map(|(disk, stripe, run)| array[disk].try_read(RawStoragePtr::byte_ptr(stripe), run.len()))
+            .collect();
+        let mut buffer = Vec::with_capacity(length);
+        for read in reads {
+            buffer.extend(read.get()?);
+        }
+        Ok(buffer)
+    }
+    fn write_range(&self, start: usize, data: &[u8]) -> Result<(), DiskError> {
+        let _writes = self.rebuild.writes.lock();
+        self.write_stripes(start, data)
+    }
+}
+
+impl Raid5 {
+    /// Writes the bytes a stripe at a time, the caller holds the write lock.
+    fn write_stripes(&self, start: usize, data: &[u8]) -> Result<(), DiskError> {
+        let failed = self.rebuild.failed();
+        // The new bytes for each stripe, by the disk they go on and where.
+        let mut stripes: BTreeMap<usize, Vec<Change>> = BTreeMap::new();
+        for (disk, stripe, run) in runs(start, data.len(), self.unit, |f| self.locate(f)) {
+            stripes.entry(stripe / self.unit).or_default().push((disk, stripe, &data[run]));
+        }
+        let array = self.array();
+        for (row, changes) in stripes {
+            update_stripe(&array, self.parity_disk(row * self.unit), &changes, failed)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disks::{hard_drive::{DiskAlgorithm, MagneticDisk}, AbstractStorageDevice, DiskError, RawStoragePtr};
+
+    use crate::disks::raid::{raid0::Raid0, RaidConfigError};
+
+    use super::{Raid5, Raid5Builder};
+
+    fn array(disks: usize) -> Raid5 {
+        (0..disks)
+            .fold(Raid5Builder::default(), |f, _| f.with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS)))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    pub fn test_raid5_array() {
+        let raid = array(4);
+        let ptr = raid.write(&[1, 2, 3, 4, 5]);
+        assert_eq!(raid.read(ptr, 5), [1, 2, 3, 4, 5]);
+
+        // This one starts partway through a stripe.
+        let ptr2 = raid.write(&[6, 7, 8, 9]);
+        assert_eq!(ptr2.byte_offset, 5);
+        assert_eq!(raid.read(ptr2, 4), [6, 7, 8, 9]);
+        assert_eq!(raid.read(RawStoragePtr::byte_ptr(3), 4), [4, 5, 6, 7]);
+        assert!(raid.check_array_integrity());
+    }
+
+    #[test]
+    pub fn test_raid5_parity_rotates() {
+        let raid = array(3);
+        raid.write(&[1, 2, 3, 4, 5, 6]);
+
+        // Each stripe has two data bytes and the parity moves along a disk.
+        let disks: Vec<_> = raid.array().iter().map(|f| f.read(RawStoragePtr::byte_ptr(0), 3).get()).collect();
+        assert_eq!(disks[0], [1 ^ 2, 3, 5]);
+        assert_eq!(disks[1], [1, 3 ^ 4, 6]);
+        assert_eq!(disks[2], [2, 4, 5 ^ 6]);
+    }
+
+    #[test]
+    pub fn test_raid5_reconstruct() {
+        let raid = array(4);
+        let data: Vec<u8> = (1..=20).collect();
+        let ptr = raid.write(&data);
+
+        // Lose a disk, the reads and the parity are both wrong now.
+        raid.array()[2].write(RawStoragePtr::byte_ptr(0), &[0; 256]).get();
+        assert_ne!(raid.read(ptr, 20), data);
+        assert!(!raid.check_array_integrity());
+
+        raid.reconstruct(2);
+        assert_eq!(raid.read(ptr, 20), data);
+        assert!(raid.check_array_integrity());
+    }
+
+    #[test]
+    pub fn test_raid5_stripe_unit() {
+        let raid = (0..3)
+            .fold(Raid5Builder::default().with_stripe_unit(4), |f, _| f.with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS)))
+            .build()
+            .unwrap();
+        let data: Vec<u8> = (1..=30).collect();
+        let ptr = raid.write(&data);
+        assert_eq!(raid.read(RawStoragePtr::byte_ptr(3), 10), data[3..13]);
+
+        // The parity chunk moves along a disk every four bytes down.
+        let disks: Vec<_> = raid.array().iter().map(|f| f.read(RawStoragePtr::byte_ptr(0), 8).get()).collect();
+        let parity = |a: usize, b: usize| (0..4).map(|f| data[a + f] ^ data[b + f]).collect::<Vec<_>>();
+        assert_eq!(disks[0], [parity(0, 4), data[8..12].to_vec()].concat());
+        assert_eq!(disks[1], [data[0..4].to_vec(), parity(8, 12)].concat());
+        assert_eq!(disks[2], [data[4..8].to_vec(), data[12..16].to_vec()].concat());
+
+        raid.array()[1].write(RawStoragePtr::byte_ptr(0), &[0; 256]).get();
+        raid.reconstruct(1);
+        assert_eq!(raid.read(ptr, 30), data);
+        assert!(raid.check_array_integrity());
+    }
+
+    #[test]
+    pub fn test_raid5_hot_spare() {
+        let spare = MagneticDisk::new(256, DiskAlgorithm::FCFS);
+        let raid = (0..4)
+            .fold(Raid5Builder::default().with_hot_spare(spare.clone()), |f, _| f.with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS)))
+            .build()
+            .unwrap();
+        let data: Vec<u8> = (1..=60).collect();
+        let ptr = raid.write(&data[..30]);
+        assert_eq!(raid.rebuild_status(), None);
+
+        // Lose a disk partway through, everything still reads back while it rebuilds.
+        raid.fail_disk(1);
+        assert!(raid.rebuild_status().is_some());
+        assert_eq!(raid.read(ptr, 30), data[..30]);
+        raid.write(&data[30..]);
+        assert_eq!(raid.read(ptr, 60), data);
+
+        raid.wait_rebuild();
+        assert_eq!(raid.rebuild_status(), None);
+        assert_eq!(raid.read(ptr, 60), data);
+        assert!(raid.check_array_integrity());
+        // Breaking the spare breaks the array, so it really is in there.
+        spare.write(RawStoragePtr::byte_ptr(0), &[0; 10]).get();
+        assert!(!raid.check_array_integrity());
+    }
+
+    #[test]
+    #[should_panic(expected = "already failed")]
+    pub fn test_raid5_fail_twice() {
+        let raid = array(3);
+        raid.fail_disk(0);
+        raid.fail_disk(1);
+    }
+
+    #[test]
+    pub fn test_raid5_add_disk() {
+        let raid = array(3);
+        let data: Vec<u8> = (1..=12).collect();
+        let ptr = raid.write(&data);
+
+        let disk = MagneticDisk::new(256, DiskAlgorithm::FCFS);
+        raid.add_disk(disk.clone()).unwrap();
+        assert_eq!(raid.read(ptr, 12), data);
+        assert!(raid.check_array_integrity());
+
+        // Twelve bytes is four stripes over three data disks now, so the
+        // next stripe has its parity on the first disk and data on the rest.
+        let ptr2 = raid.write(&[100, 101, 102]);
+        assert_eq!(ptr2.byte_offset, 12);
+        let stripe: Vec<_> = raid.array().iter().map(|f| f.read(RawStoragePtr::byte_ptr(4), 1).get()[0]).collect();
+        assert_eq!(stripe, [100 ^ 101 ^ 102, 100, 101, 102]);
+        assert_eq!(disk.read(RawStoragePtr::byte_ptr(0), 1).get(), [3]);
+        assert!(raid.check_array_integrity());
+
+        let small = MagneticDisk::new(128, DiskAlgorithm::FCFS);
+        let error = RaidConfigError::MismatchedDiskSizes { expected: 256, got: 128 };
+        assert_eq!(raid.add_disk(small), Err(DiskError::Misconfigured(error)));
+
+        // A failed disk has to be rebuilt before the array can grow.
+        raid.fail_disk(2);
+        let error = RaidConfigError::FailedDisk { index: 2 };
+        assert_eq!(raid.add_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS)), Err(DiskError::Misconfigured(error)));
+        assert_eq!(raid.read(ptr, 12), data);
+    }
+
+    #[test]
+    pub fn test_raid5_small_write_penalty() {
+        let raid = array(4);
+        let data: Vec<u8> = (1..=12).collect();
+        let ptr = raid.write(&data);
+
+        // One byte reads the old byte and parity then writes both.
+        let before = raid.disk_operations();
+        raid.write_at(RawStoragePtr::byte_ptr(4), &[50]);
+        assert_eq!(raid.disk_operations() - before, 4);
+
+        // A whole stripe doesn't need to read anything.
+        let before = raid.disk_operations();
+        raid.write_at(RawStoragePtr::byte_ptr(6), &[60, 61, 62]);
+        assert_eq!(raid.disk_operations() - before, 4);
+
+        let raid0 = Raid0::new().with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS)).with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS));
+        raid0.write(&data);
+        let before = raid0.disk_operations();
+        AbstractStorageDevice::write(&raid0, RawStoragePtr::byte_ptr(4), &[50]).get();
+        assert_eq!(raid0.disk_operations() - before, 1);
+
+        let mut expected = data.clone();
+        expected[4] = 50;
+        expected[6..9].copy_from_slice(&[60, 61, 62]);
+        assert_eq!(raid.read(ptr, 12), expected);
+        assert!(raid.check_array_integrity());
+    }
+
+    #[test]
+    pub fn test_raid5_overwrite() {
+        let raid = (0..3)
+            .fold(Raid5Builder::default().with_stripe_unit(2), |f, _| f.with_disk(MagneticDisk::new(256, DiskAlgorithm::FCFS)))
+            .build()
+            .unwrap();
+        let mut data: Vec<u8> = (1..=20).collect();
+        let ptr = raid.write(&data);
+
+        // These start and end partway through stripes and chunks.
+        for (start, new) in [(3, vec![90, 91, 92, 93, 94]), (0, vec![80]), (11, vec![70; 9])] {
+            raid.write_at(RawStoragePtr::byte_ptr(start), &new);
+            data[start..start + new.len()].copy_from_slice(&new);
+            assert_eq!(raid.read(ptr, 20), data);
+            assert!(raid.check_array_integrity());
+        }
+        assert_eq!(raid.write(&[1]).byte_offset, 20);
+    }
+}
@@ -0,0 +1,148 @@
+//! Splits a storage device up into numbered blocks that files are
+//! made of, with a bitmap at the start saying which are used.
+
+use crate::disks::{bits::bitmap::Bitmap, RawStoragePtr, StorageDevice};
+
+
+/// How many bytes a block number takes when it is written in a block.
+pub(super) const POINTER: usize = 4;
+
+/// Reads a block number back out of the bytes it was written as.
+pub(super) fn read_pointer(bytes: &[u8]) -> usize {
+    u32::from_be_bytes(bytes[..POINTER].try_into().unwrap()) as usize
+}
+
+/// The bytes to write a block number as.
+pub(super) fn write_pointer(block: usize) -> [u8; POINTER] {
+    (block as u32).to_be_bytes()
+}
+
+/// A device split into blocks of the same size.
+///
+/// The free space bitmap is kept in the first blocks of the device so they
+/// are always marked as used, every change to it is written straight back.
+///
+/// ```
+/// use osconcepts::{disks::SecondaryStorage, filesystem::block::BlockDevice};
+///
+/// let mut device = BlockDevice::new(SecondaryStorage::new(256), 16);
+/// // The bitmap for sixteen blocks fits in the first one.
+/// assert_eq!(device.free_blocks(), 15);
+/// let block = device.alloc().unwrap();
+/// device.write_block(block, &[1, 2, 3]);
+/// assert_eq!(device.read_block(block)[..3], [1, 2, 3]);
+/// ```
+pub struct BlockDevice<S> {
+    device: S,
+    block_size: usize,
+    bitmap: Bitmap
+}
+
+impl<S: StorageDevice> BlockDevice<S> {
+    /// Formats a device into blocks, anything already on it is forgotten.
+    ///
+    /// # Panics
+    /// If the block size is zero or the device can't hold its own bitmap.
+    pub fn new(device: S, block_size: usize) -> Self {
+        assert!(block_size > 0, "The blocks can't be zero bytes.");
+        let mut bitmap = Bitmap::new(device.capacity() / block_size);
+        let reserved = bitmap.to_bytes().len().div_ceil(block_size);
+        assert!(reserved < bitmap.len(), "The device is too small to hold its bitmap and a block.");
+        for block in 0..reserved {
+            bitmap.set(block);
+        }
+        let mut device = Self {
+            device,
+            block_size,
+            bitmap
+        };
+        device.persist();
+        device
+    }
+    /// Writes the bitmap to the start of the device.
+    fn persist(&mut self) {
+        self.device.write(RawStoragePtr::byte_ptr(0), &self.bitmap.to_bytes());
+    }
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+    /// How many blocks there are, including the ones holding the bitmap.
+    pub fn blocks(&self) -> usize {
+        self.bitmap.len()
+    }
+    pub fn free_blocks(&self) -> usize {
+        self.bitmap.len() - self.bitmap.count_set()
+    }
+    pub fn bitmap(&self) -> &Bitmap {
+        &self.bitmap
+    }
+    pub fn device(&self) -> &S {
+        &self.device
+    }
+    /// Takes the first free block, this is none if they are all used.
+    pub fn alloc(&mut self) -> Option<usize> {
+        let block = self.bitmap.find_first_clear()?;
+        self.bitmap.set(block);
+        self.persist();
+        Some(block)
+    }
+    /// Takes a block that the caller picked.
+    ///
+    /// # Panics
+    /// If the block is already being used.
+    pub fn claim(&mut self, block: usize) {
+        assert!(!self.bitmap.is_set(block), "Block {block} is already being used.");
+        self.bitmap.set(block);
+        self.persist();
+    }
+    /// Gives a block back.
+    ///
+    /// # Panics
+    /// If the block isn't being used.
+    pub fn free(&mut self, block: usize) {
+        assert!(self.bitmap.is_set(block), "Double free of block {block}.");
+        self.bitmap.clear(block);
+        self.persist();
+    }
+    /// Where a block starts on the device.
+    pub fn address(&self, block: usize) -> RawStoragePtr {
+        RawStoragePtr::byte_ptr(block * self.block_size)
+    }
+    pub fn read_block(&self, block: usize) -> Vec<u8> {
+        self.device.read(self.address(block), self.block_size)
+    }
+    /// Writes to the start of a block, the rest of it is left alone.
+    ///
+    /// # Panics
+    /// If the data doesn't fit in a block.
+    pub fn write_block(&mut self, block: usize, data: &[u8]) {
+        assert!(data.len() <= self.block_size, "{} bytes don't fit in a block of {}.", data.len(), self.block_size);
+        self.device.write(self.address(block), data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disks::{bits::bitmap::Bitmap, RawStoragePtr, SecondaryStorage, StorageDevice};
+
+    use super::BlockDevice;
+
+    #[test]
+    pub fn test_block_device() {
+        // 100 blocks need 13 bytes of bitmap, which is two blocks of eight.
+        let mut device = BlockDevice::new(SecondaryStorage::new(800), 8);
+        assert_eq!((device.blocks(), device.free_blocks()), (100, 98));
+        let blocks: Vec<_> = (0..3).map(|_| device.alloc().unwrap()).collect();
+        assert_eq!(blocks, [2, 3, 4]);
+
+        device.write_block(3, &[7; 8]);
+        assert_eq!(device.device().read(RawStoragePtr::byte_ptr(24), 8), [7; 8]);
+        device.free(3);
+        assert_eq!(device.alloc(), Some(3));
+
+        // The bitmap on the device is the same as the one in memory.
+        let on_disk = Bitmap::from_bytes(&device.device().read(RawStoragePtr::byte_ptr(0), 13));
+        assert!((0..100).all(|f| on_disk.is_set(f) == device.bitmap().is_set(f)));
+        assert_eq!(on_disk.to_bytes()[0], 0b1111_1000);
+    }
+}
@@ -0,0 +1,274 @@
+//! Contiguous allocation, every file is one run of blocks that are
+//! next to each other so it can be read straight through.
+
+use std::{collections::{BTreeMap, HashMap}, fmt};
+
+use crate::disks::{SecondaryStorage, StorageDevice};
+
+use super::block::BlockDevice;
+
+
+/// Where a file's blocks start and how long the file is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct File {
+    start: usize,
+    length: usize
+}
+
+/// Why a file couldn't be stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AllocError {
+    /// There aren't enough free blocks at all.
+    OutOfSpace,
+    /// There are enough free blocks but they are split up into holes
+    /// that are each too small, compacting would make room.
+    NoContiguousSpace
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfSpace => write!(f, "there aren't enough free blocks"),
+            Self::NoContiguousSpace => write!(f, "there are enough free blocks but not next to each other")
+        }
+    }
+}
+
+impl std::error::Error for AllocError {}
+
+#[derive(Default)]
+pub struct Directory {
+    files: HashMap<String, File>
+}
+
+impl Directory {
+    pub fn new() -> Self {
+        Self {
+            files: HashMap::new()
+        }
+    }
+    pub fn open_file<S: StorageDevice>(&mut self, name: String, alloc: &mut ContiguousAllocator<S>, data: &[u8]) -> Result<(), AllocError> {
+        self.files.insert(name, alloc.store_file(data)?);
+        Ok(())
+    }
+    pub fn read_file<S: StorageDevice>(&self, name: &str, alloc: &ContiguousAllocator<S>) -> Vec<u8> {
+        alloc.read_file(*self.files.get(name).unwrap())
+    }
+    pub fn delete_file<S: StorageDevice>(&mut self, name: &str, alloc: &mut ContiguousAllocator<S>) {
+        let file = self.files.remove(name).unwrap();
+        alloc.delete_file(file);
+    }
+    /// Where a file starts and how many bytes it is.
+    pub fn entry(&self, name: &str) -> Option<(usize, usize)> {
+        self.files.get(name).map(|f| (f.start, f.length))
+    }
+    /// Compacts the allocator and points every file at where it went.
+    pub fn compact<S: StorageDevice>(&mut self, alloc: &mut ContiguousAllocator<S>) {
+        let relocations = alloc.compact();
+        for file in self.files.values_mut() {
+            if let Some(start) = relocations.get(&file.start) {
+                file.start = *start;
+            }
+        }
+    }
+}
+
+
+/// Contiguous allocation over a [BlockDevice], the free blocks are kept
+/// as a list of extents and files go in the first one big enough.
+///
+/// ```
+/// use osconcepts::{disks::SecondaryStorage, filesystem::{block::BlockDevice, contiguous::{ContiguousAllocator, Directory}}};
+///
+/// let mut alloc = ContiguousAllocator::new(BlockDevice::new(SecondaryStorage::new(128), 8));
+/// let mut directory = Directory::new();
+/// directory.open_file("a".to_string(), &mut alloc, &[1; 20]).unwrap();
+/// // Block zero is the bitmap and the file takes the three after it.
+/// assert_eq!(directory.entry("a"), Some((1, 20)));
+/// assert_eq!(alloc.extents(), [(4, 12)]);
+/// ```
+pub struct ContiguousAllocator<S = SecondaryStorage> {
+    device: BlockDevice<S>,
+    /// How many blocks every free extent is by where it starts.
+    extents: BTreeMap<usize, usize>,
+    /// How many blocks every file takes by where it starts.
+    allocated: BTreeMap<usize, usize>
+}
+
+impl<S: StorageDevice> ContiguousAllocator<S> {
+    pub fn new(device: BlockDevice<S>) -> Self {
+        let mut extents = BTreeMap::new();
+        // The device is fresh so everything after the bitmap is free.
+        let reserved = device.blocks() - device.free_blocks();
+        extents.insert(reserved, device.free_blocks());
+        Self {
+            device,
+            extents,
+            allocated: BTreeMap::new()
+        }
+    }
+    pub fn device(&self) -> &BlockDevice<S> {
+        &self.device
+    }
+    /// Every free extent as (start block, blocks) in order.
+    pub fn extents(&self) -> Vec<(usize, usize)> {
+        self.extents.iter().map(|(start, size)| (*start, *size)).collect()
+    }
+    /// How many blocks a file of this many bytes takes, even an
+    /// empty file takes a block.
+    fn blocks_for(&self, length: usize) -> usize {
+        length.div_ceil(self.device.block_size()).max(1)
+    }
+    /// Stores a file in the first extent that fits it.
+    fn store_file(&mut self, data: &[u8]) -> Result<File, AllocError> {
+        let needed = self.blocks_for(data.len());
+        if self.device.free_blocks() < needed {
+            return Err(AllocError::OutOfSpace);
+        }
+        let (&start, &size) = self
+            .extents
+            .iter()
+            .find(|(_, size)| **size >= needed)
+            .ok_or(AllocError::NoContiguousSpace)?;
+
+        self.extents.remove(&start);
+        if size > needed {
+            self.extents.insert(start + needed, size - needed);
+        }
+        self.allocated.insert(start, needed);
+        for (i, chunk) in data.chunks(self.device.block_size()).enumerate() {
+            self.device.write_block(start + i, chunk);
+        }
+        (start..start + needed).for_each(|f| self.device.claim(f));
+        Ok(File {
+            start,
+            length: data.len()
+        })
+    }
+    /// Frees the blocks of a file, merging them with the extents on
+    /// either side.
+    fn delete_file(&mut self, file: File) {
+        let size = self.allocated.remove(&file.start).unwrap();
+        (file.start..file.start + size).for_each(|f| self.device.free(f));
+
+        let mut start = file.start;
+        let mut end = file.start + size;
+        if let Some((&before, &before_size)) = self.extents.range(..start).next_back() {
+            if before + before_size == start {
+                self.extents.remove(&before);
+                start = before;
+            }
+        }
+        if let Some(after_size) = self.extents.remove(&end) {
+            end += after_size;
+        }
+        self.extents.insert(start, end - start);
+    }
+    /// Reads a file straight through from its first block.
+    fn read_file(&self, file: File) -> Vec<u8> {
+        let size = self.blocks_for(file.length);
+        let mut buffer: Vec<u8> = (file.start..file.start + size).flat_map(|f| self.device.read_block(f)).collect();
+        buffer.truncate(file.length);
+        buffer
+    }
+    /// Slides every file down to the start of the device so the free
+    /// blocks are one extent at the end, returning where each file that
+    /// moved went.
+    fn compact(&mut self) -> HashMap<usize, usize> {
+        let mut relocations = HashMap::new();
+        // Everything before the first extent is already where it should be.
+        let Some(&first) = self.extents.keys().next() else {
+            return relocations;
+        };
+        let mut next = self.allocated.keys().next().map_or(first, |f| first.min(*f));
+        let allocated = std::mem::take(&mut self.allocated);
+        for (start, size) in allocated {
+            if start != next {
+                // Going up through the blocks never writes over one that hasn't moved yet.
+                for i in 0..size {
+                    let block = self.device.read_block(start + i);
+                    self.device.write_block(next + i, &block);
+                }
+                (start..start + size).for_each(|f| self.device.free(f));
+                (next..next + size).for_each(|f| self.device.claim(f));
+                relocations.insert(start, next);
+            }
+            self.allocated.insert(next, size);
+            next += size;
+        }
+        self.extents.clear();
+        if next < self.device.blocks() {
+            self.extents.insert(next, self.device.blocks() - next);
+        }
+        relocations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{disks::SecondaryStorage, filesystem::{block::BlockDevice, contiguous::Directory}};
+
+    use super::{AllocError, ContiguousAllocator};
+
+    /// Sixteen blocks of eight bytes, the first one is the bitmap.
+    fn allocator() -> ContiguousAllocator {
+        ContiguousAllocator::new(BlockDevice::new(SecondaryStorage::new(128), 8))
+    }
+
+    #[test]
+    pub fn test_contiguous_allocation() {
+        let mut alloc = allocator();
+        let mut directory = Directory::new();
+        directory.open_file("josh".to_string(), &mut alloc, &[1,2,3]).unwrap();
+        directory.open_file("josh2".to_string(), &mut alloc, &(0..20).collect::<Vec<_>>()).unwrap();
+        directory.open_file("empty".to_string(), &mut alloc, &[]).unwrap();
+
+        assert_eq!(directory.read_file("josh", &alloc), [1,2,3]);
+        assert_eq!(directory.read_file("josh2", &alloc), (0..20).collect::<Vec<_>>());
+        assert!(directory.read_file("empty", &alloc).is_empty());
+        assert_eq!(directory.entry("josh2"), Some((2, 20)));
+        assert_eq!(alloc.extents(), [(6, 10)]);
+
+        directory.delete_file("josh2", &mut alloc);
+        assert_eq!(alloc.extents(), [(2, 3), (6, 10)]);
+        // Freeing the empty file joins up the extents on either side of it.
+        directory.delete_file("empty", &mut alloc);
+        assert_eq!(alloc.extents(), [(2, 14)]);
+        assert_eq!(alloc.device().free_blocks(), 14);
+
+        assert_eq!(directory.open_file("big".to_string(), &mut alloc, &[0; 200]), Err(AllocError::OutOfSpace));
+    }
+
+    #[test]
+    pub fn test_contiguous_fragmentation() {
+        let mut alloc = allocator();
+        let mut directory = Directory::new();
+        // Five files of three blocks each fill the device.
+        for (i, name) in ["a", "b", "c", "d", "e"].into_iter().enumerate() {
+            directory.open_file(name.to_string(), &mut alloc, &[i as u8; 24]).unwrap();
+        }
+        directory.delete_file("b", &mut alloc);
+        directory.delete_file("d", &mut alloc);
+        assert_eq!(alloc.extents(), [(4, 3), (10, 3)]);
+
+        // Six blocks are free but no four of them are next to each other.
+        assert_eq!(alloc.device().free_blocks(), 6);
+        assert_eq!(directory.open_file("f".to_string(), &mut alloc, &[9; 32]), Err(AllocError::NoContiguousSpace));
+        assert!(directory.entry("f").is_none());
+
+        directory.compact(&mut alloc);
+        assert_eq!(alloc.extents(), [(10, 6)]);
+        assert_eq!(directory.entry("a"), Some((1, 24)));
+        assert_eq!(directory.entry("c"), Some((4, 24)));
+        assert_eq!(directory.entry("e"), Some((7, 24)));
+        assert_eq!(directory.read_file("c", &alloc), [2; 24]);
+        assert_eq!(directory.read_file("e", &alloc), [4; 24]);
+        assert!((1..10).all(|f| alloc.device().bitmap().is_set(f)));
+
+        // The same file fits now.
+        directory.open_file("f".to_string(), &mut alloc, &[9; 32]).unwrap();
+        assert_eq!(directory.entry("f"), Some((10, 32)));
+        assert_eq!(directory.read_file("f", &alloc), [9; 32]);
+        assert_eq!(alloc.extents(), [(14, 2)]);
+    }
+}
@@ -1,11 +1,20 @@
-use std::{collections::HashMap, ptr};
+use std::collections::HashMap;
 
+use crate::disks::{SecondaryStorage, StorageDevice};
 
-const BLOCK_SIZE: usize = 2;
+use super::block::{read_pointer, write_pointer, BlockDevice, POINTER};
+
+
+/// Where a file's index block is and how long the file is.
+#[derive(Clone, Copy, Debug)]
+struct File {
+    index: usize,
+    length: usize
+}
 
 #[derive(Default)]
 pub struct Directory {
-    files: HashMap<String, IndexBlock>
+    files: HashMap<String, File>
 }
 
 impl Directory {
@@ -14,183 +23,130 @@ impl Directory {
             ..Default::default()
         }
     }
-    pub fn open_file(&mut self, name: String, alloc: &mut IndexedAllocator, data: &[u8]) {
+    pub fn open_file<S: StorageDevice>(&mut self, name: String, alloc: &mut IndexedAllocator<S>, data: &[u8]) {
         self.files.insert(name, alloc.store_file(data));
     }
-    pub fn read_file(&mut self, name: &str) -> Vec<u8> {
-        IndexedAllocator::read_file(self.files.get(name).unwrap())
+    pub fn read_file<S: StorageDevice>(&self, name: &str, alloc: &IndexedAllocator<S>) -> Vec<u8> {
+        alloc.read_file(*self.files.get(name).unwrap())
     }
-    pub fn delete_file(&mut self, name: &str, alloc: &mut IndexedAllocator) {
-        let index = self.files.remove(name).unwrap();
-        alloc.delete_file(&index);
+    pub fn delete_file<S: StorageDevice>(&mut self, name: &str, alloc: &mut IndexedAllocator<S>) {
+        let file = self.files.remove(name).unwrap();
+        alloc.delete_file(file);
     }
 }
 
-impl Block {
-    pub fn collect(mut pointer: *const Block) -> Vec<*const Block> {
-        let mut list = vec![];
-        while !pointer.is_null() {
-            list.push(pointer);
-            pointer = unsafe { (*pointer).next };
-        }
-        list
-    }
-}
 
-#[derive(Clone, Debug)]
-struct Block {
-    /// Points to the next block in the master list.
-    next: *const Block,
-    /// Bytes actually written
-    length: usize,
-    /// The data contained within the block.
-    data: [u8; BLOCK_SIZE]
+/// Indexed allocation over a [BlockDevice], every file has an index
+/// block that lists the numbers of the blocks its data is in.
+pub struct IndexedAllocator<S = SecondaryStorage> {
+    device: BlockDevice<S>
 }
 
-impl Block {
-    pub fn init() -> Self {
-        Self {
-            next: ptr::null(),
-            length: 0,
-            data: [0u8; BLOCK_SIZE]
-        }
+impl<S: StorageDevice> IndexedAllocator<S> {
+    /// # Panics
+    /// If the blocks are too small to hold a pointer.
+    pub fn new(device: BlockDevice<S>) -> Self {
+        assert!(device.block_size() >= POINTER, "The blocks are too small to index anything.");
+        Self { device }
     }
-    pub fn alloc() -> *const Self {
-        Box::leak(Box::new(Self::init())) as *const Self
+    pub fn device(&self) -> &BlockDevice<S> {
+        &self.device
     }
-}
-
-
-/// This is the index block that points to all
-/// the file blocks.
-struct IndexBlock {
-    /// Link to the indexes in order.
-    indexes: Vec<*const Block>
-}
-
-
-
-/// An incredibly unsafe [LinkedAllocator]
-/// for use with files.
-pub struct IndexedAllocator {
-    /// The master list of blocks.
-    blocks: *const Block,
-    /// The list of free blocks.
-    free_list: Vec<*const Block>,
-}
-
-impl IndexedAllocator {
-    pub fn new(blocks: usize) -> Self {
-        let head = Block::alloc();
-
-        let mut free_list = vec![];
-        
-
-        // create the linked list of blocks.
-        let mut current = head;
-        for _ in 0..blocks - 1 {
-            let next = Block::alloc();
-            unsafe { (*(current as *mut Block)).next = next };
-            free_list.push(current);
-            current = next;
-        }
-
-        Self {
-            blocks: head,
-            free_list
-        }
+    /// The data blocks of a file, from its index block.
+    fn indexes(&self, file: File) -> Vec<usize> {
+        let count = file.length.div_ceil(self.device.block_size());
+        self.device.read_block(file.index).chunks(POINTER).take(count).map(read_pointer).collect()
     }
-    
-    /// Stores a file into the linked allocator.
-    fn store_file(&mut self, data: &[u8]) -> IndexBlock {
-        if self.free_list.is_empty() || self.free_list.len() < data.len() / BLOCK_SIZE {
+    /// Stores a file into the indexed allocator.
+    ///
+    /// # Panics
+    /// If there aren't enough free blocks or the file is too big for one index block.
+    fn store_file(&mut self, data: &[u8]) -> File {
+        let needed = data.len().div_ceil(self.device.block_size());
+        assert!(
+            needed * POINTER <= self.device.block_size(),
+            "The file is too big for one index block."
+        );
+        if self.device.free_blocks() < needed + 1 {
             panic!("No more room in the allocator!");
         }
-        let mut offset = 0;
-
-        
-        let mut indexes = vec![];
-
-        while offset < data.len() {
-            // pop the next block.
-            let block_ptr = self.free_list.pop().unwrap();
-            let block = unsafe { &mut *block_ptr.cast_mut() };
-            
-            // push the index into the list.
-            indexes.push(block_ptr);
-
-      
-
-            // Fill the block with data.
-            let length = (offset + BLOCK_SIZE).min(data.len());
-            block.data[0..length - offset].copy_from_slice(&data[offset..length]);
-            block.length = length - offset;
-            
-            // Increase the offset
-            offset += BLOCK_SIZE;
+
+        let index = self.device.alloc().unwrap();
+        let mut pointers = vec![];
+        for chunk in data.chunks(self.device.block_size()) {
+            let block = self.device.alloc().unwrap();
+            self.device.write_block(block, chunk);
+            pointers.extend(write_pointer(block));
         }
-        IndexBlock {
-            indexes
+        self.device.write_block(index, &pointers);
+        File {
+            index,
+            length: data.len()
         }
     }
-    /// Delete file
-    fn delete_file(&mut self, start: &IndexBlock) {
-        
-        for c in &start.indexes {
-            let c = *c;
-            let b = unsafe { &mut *c.cast_mut() };
-            b.length = 0;
-            self.free_list.push(c);
+    /// Frees the data blocks of a file and its index block.
+    fn delete_file(&mut self, file: File) {
+        for block in self.indexes(file) {
+            self.device.free(block);
         }
+        self.device.free(file.index);
     }
-    /// Reads a file by traversing the linked list.
-    fn read_file(start: &IndexBlock) -> Vec<u8> {
-        let mut buffer = vec![];
-        for file in &start.indexes {
-            let block = unsafe { &*(*file) };
-            buffer.extend_from_slice(&block.data[..block.length]);
-        }
-
-   
+    /// Reads a file by going through its index block.
+    fn read_file(&self, file: File) -> Vec<u8> {
+        let mut buffer: Vec<u8> = self.indexes(file).into_iter().flat_map(|f| self.device.read_block(f)).collect();
+        buffer.truncate(file.length);
         buffer
     }
 }
 
-
-impl Drop for IndexedAllocator {
-    fn drop(&mut self) {
-        for tobox in Block::collect(self.blocks) {
-            let boxed = unsafe { Box::from_raw(tobox as *mut Block) };
-            drop(boxed);
-        }
-
-    }
-}
-
 #[cfg(test)]
 mod tests {
 
 
-    use crate::filesystem::indexed::Directory;
+    use crate::{disks::{RawStoragePtr, SecondaryStorage, StorageDevice}, filesystem::{block::BlockDevice, indexed::Directory}};
 
     use super::IndexedAllocator;
 
+    fn allocator(blocks: usize) -> IndexedAllocator {
+        IndexedAllocator::new(BlockDevice::new(SecondaryStorage::new(blocks * 8), 8))
+    }
 
+    /// A block straight off the device.
+    fn raw(alloc: &IndexedAllocator, block: usize) -> Vec<u8> {
+        alloc.device().device().read(RawStoragePtr::byte_ptr(block * 8), 8)
+    }
 
     #[test]
     pub fn test_indexed_allocation() {
-        let mut alloc = IndexedAllocator::new(25);
+        let mut alloc = allocator(25);
 
         let mut directory = Directory::new();
         directory.open_file("josh".to_string(), &mut alloc, &[1,2,3]);
         directory.open_file("josh2".to_string(), &mut alloc, &[0,3,6,9]);
 
 
-        assert_eq!(directory.read_file("josh"), [1,2,3]);
-        assert_eq!(directory.read_file("josh2"), [0,3,6,9]);
+        assert_eq!(directory.read_file("josh", &alloc), [1,2,3]);
+        assert_eq!(directory.read_file("josh2", &alloc), [0,3,6,9]);
 
         directory.delete_file("josh", &mut alloc);
-        
+
     }
 
-}
\ No newline at end of file
+    #[test]
+    pub fn test_indexed_layout() {
+        let mut alloc = allocator(25);
+        let mut directory = Directory::new();
+        directory.open_file("notes".to_string(), &mut alloc, &(1..=10).collect::<Vec<_>>());
+        assert_eq!(directory.read_file("notes", &alloc), (1..=10).collect::<Vec<_>>());
+
+        // Block zero is the bitmap, then the index block and the two data blocks.
+        assert_eq!(raw(&alloc, 1), [0, 0, 0, 2, 0, 0, 0, 3]);
+        assert_eq!(raw(&alloc, 2), [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(raw(&alloc, 3)[..2], [9, 10]);
+        assert_eq!(raw(&alloc, 0)[0], 0b1111_0000);
+
+        directory.delete_file("notes", &mut alloc);
+        assert_eq!(raw(&alloc, 0)[0], 0b1000_0000);
+        assert_eq!(alloc.device().free_blocks(), 24);
+    }
+}
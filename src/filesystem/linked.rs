@@ -1,11 +1,23 @@
-use std::{collections::HashMap, ptr};
+use std::collections::HashMap;
 
+use crate::disks::{SecondaryStorage, StorageDevice};
 
-const BLOCK_SIZE: usize = 2;
+use super::block::{read_pointer, write_pointer, BlockDevice, POINTER};
+
+
+/// The next pointer of the last block in a file.
+const END: usize = u32::MAX as usize;
+
+/// Where a file starts and how long it is.
+#[derive(Clone, Copy, Debug)]
+struct File {
+    start: usize,
+    length: usize
+}
 
 #[derive(Default)]
 pub struct Directory {
-    files: HashMap<String, *const Block>
+    files: HashMap<String, File>
 }
 
 impl Directory {
@@ -14,173 +26,134 @@ impl Directory {
             files: HashMap::new()
         }
     }
-    pub fn open_file(&mut self, name: String, alloc: &mut LinkedAllocator, data: &[u8]) {
+    pub fn open_file<S: StorageDevice>(&mut self, name: String, alloc: &mut LinkedAllocator<S>, data: &[u8]) {
         self.files.insert(name, alloc.store_file(data));
     }
-    pub fn read_file(&mut self, name: &str) -> Vec<u8> {
-        LinkedAllocator::read_file(*self.files.get(name).unwrap())
+    pub fn read_file<S: StorageDevice>(&self, name: &str, alloc: &LinkedAllocator<S>) -> Vec<u8> {
+        alloc.read_file(*self.files.get(name).unwrap())
     }
-    pub fn delete_file(&mut self, name: &str, alloc: &mut LinkedAllocator) {
-        let index = self.files.remove(name).unwrap();
-        alloc.delete_file(index);
-    }
-}
-
-impl Block {
-    pub fn collect(mut pointer: *const Block) -> Vec<*const Block> {
-        let mut list = vec![];
-        while !pointer.is_null() {
-            list.push(pointer);
-            pointer = unsafe { (*pointer).next };
-        }
-        list
+    pub fn delete_file<S: StorageDevice>(&mut self, name: &str, alloc: &mut LinkedAllocator<S>) {
+        let file = self.files.remove(name).unwrap();
+        alloc.delete_file(file);
     }
 }
 
-#[derive(Clone, Debug)]
-struct Block {
-    /// Points to the next block in the master list.
-    next: *const Block,
-    /// Points to the next block in a file.
-    file_pointer: *const Block,
-    /// Bytes actually written
-    length: usize,
-    /// The data contained within the block.
-    data: [u8; BLOCK_SIZE]
-}
-
 
-/// An incredibly unsafe [LinkedAllocator]
-/// for use with files.
-pub struct LinkedAllocator {
-    /// The master list of blocks.
-    blocks: *const Block,
-    /// The list of free blocks.
-    free_list: Vec<*const Block>,
+/// Linked allocation over a [BlockDevice], each block of a file starts
+/// with the number of the next one.
+pub struct LinkedAllocator<S = SecondaryStorage> {
+    device: BlockDevice<S>
 }
 
-impl LinkedAllocator {
-    pub fn new(blocks: usize) -> Self {
-        let head = Box::leak(Box::new(Block {
-            next: ptr::null(),
-            file_pointer: ptr::null(),
-            length: 0,
-            data: [0u8; BLOCK_SIZE]
-        })) as *const Block;
-
-        let mut free_list = vec![];
-        
-
-        // create the linked list of blocks.
-        let mut current = head;
-        for _ in 0..blocks - 1 {
-            let next = Box::leak(Box::new(Block {
-                next: ptr::null(),
-                file_pointer: ptr::null(),
-                length: 0,
-                data: [0u8; BLOCK_SIZE]
-            }));
-            unsafe { (*(current as *mut Block)).next = next };
-            free_list.push(current);
-            current = next;
-        }
-
-        Self {
-            blocks: head,
-            free_list
+impl<S: StorageDevice> LinkedAllocator<S> {
+    /// # Panics
+    /// If the blocks are too small to hold a pointer and some data.
+    pub fn new(device: BlockDevice<S>) -> Self {
+        assert!(device.block_size() > POINTER, "The blocks are too small to link together.");
+        Self { device }
+    }
+    pub fn device(&self) -> &BlockDevice<S> {
+        &self.device
+    }
+    /// How much of a file fits in each block.
+    fn payload(&self) -> usize {
+        self.device.block_size() - POINTER
+    }
+    /// Every block of a file in order.
+    fn chain(&self, start: usize) -> Vec<usize> {
+        let mut blocks = vec![];
+        let mut block = start;
+        while block != END {
+            blocks.push(block);
+            block = read_pointer(&self.device.read_block(block));
         }
+        blocks
     }
-    
-    /// Stores a file into the linked allocator.
-    fn store_file(&mut self, data: &[u8]) -> *const Block {
-        if self.free_list.is_empty() || self.free_list.len() < data.len() / BLOCK_SIZE {
+    /// Stores a file into the linked allocator, even an empty
+    /// file takes a block.
+    ///
+    /// # Panics
+    /// If there aren't enough free blocks.
+    fn store_file(&mut self, data: &[u8]) -> File {
+        let needed = data.len().div_ceil(self.payload()).max(1);
+        if self.device.free_blocks() < needed {
             panic!("No more room in the allocator!");
         }
-        let mut offset = 0;
-        let mut ptr: *const Block = ptr::null();
-        let mut prev: *const Block = ptr::null();
-        
-        while offset < data.len() {
-            // pop the next block.
-            let block_ptr = self.free_list.pop().unwrap();
-            let block = unsafe { &mut *block_ptr.cast_mut() };
-            if ptr.is_null() {
-                ptr = block_ptr;
-                prev = block_ptr;
-            } else {
-                // Configure the file pointers.
-                unsafe { (*prev.cast_mut()).file_pointer = block_ptr };
-                prev = block_ptr;
-            }
-
-      
-
-            // Fill the block with data.
-            let length = (offset + BLOCK_SIZE).min(data.len());
-            block.data[0..length - offset].copy_from_slice(&data[offset..length]);
-            block.length = length - offset;
-            
-            // Increase the offset
-            offset += BLOCK_SIZE;
+        let blocks: Vec<_> = (0..needed).map(|_| self.device.alloc().unwrap()).collect();
+        for (i, block) in blocks.iter().enumerate() {
+            let next = blocks.get(i + 1).copied().unwrap_or(END);
+            let chunk = data.chunks(self.payload()).nth(i).unwrap_or(&[]);
+            self.device.write_block(*block, &[&write_pointer(next), chunk].concat());
+        }
+        File {
+            start: blocks[0],
+            length: data.len()
         }
-        ptr
     }
-    /// Delete file
-    fn delete_file(&mut self, start: *const Block) {
-        let collected = Block::collect(start);
-        for c in collected {
-            let b = unsafe { &mut *c.cast_mut() };
-            b.length = 0;
-            b.file_pointer = ptr::null();
-            self.free_list.push(c);
+    /// Frees every block of a file.
+    fn delete_file(&mut self, file: File) {
+        for block in self.chain(file.start) {
+            self.device.free(block);
         }
     }
     /// Reads a file by traversing the linked list.
-    fn read_file(start: *const Block) -> Vec<u8> {
-        let mut current = start;
-        let mut buffer = vec![];
-        while !current.is_null() {
-            let cur = unsafe { &*current };
-            buffer.extend_from_slice(&cur.data[..cur.length]);
-            current = cur.file_pointer;
-        }
-
+    fn read_file(&self, file: File) -> Vec<u8> {
+        let mut buffer: Vec<u8> = self
+            .chain(file.start)
+            .into_iter()
+            .flat_map(|f| self.device.read_block(f).split_off(POINTER))
+            .collect();
+        buffer.truncate(file.length);
         buffer
     }
 }
 
-
-impl Drop for LinkedAllocator {
-    fn drop(&mut self) {
-        for tobox in Block::collect(self.blocks) {
-            let boxed = unsafe { Box::from_raw(tobox as *mut Block) };
-            drop(boxed);
-        }
-
-    }
-}
-
 #[cfg(test)]
 mod tests {
-    use crate::filesystem::linked::Directory;
+    use crate::{disks::{RawStoragePtr, SecondaryStorage, StorageDevice}, filesystem::{block::BlockDevice, linked::Directory}};
 
     use super::LinkedAllocator;
 
+    fn allocator(blocks: usize) -> LinkedAllocator {
+        LinkedAllocator::new(BlockDevice::new(SecondaryStorage::new(blocks * 8), 8))
+    }
 
+    /// A block straight off the device.
+    fn raw(alloc: &LinkedAllocator, block: usize) -> Vec<u8> {
+        alloc.device().device().read(RawStoragePtr::byte_ptr(block * 8), 8)
+    }
 
     #[test]
     pub fn test_linked_allocation() {
-        let mut alloc = LinkedAllocator::new(25);
+        let mut alloc = allocator(25);
 
         let mut directory = Directory::new();
         directory.open_file("josh".to_string(), &mut alloc, &[1,2,3]);
         directory.open_file("josh2".to_string(), &mut alloc, &[0,3,6,9]);
 
 
-        assert_eq!(directory.read_file("josh"), [1,2,3]);
-        assert_eq!(directory.read_file("josh2"), [0,3,6,9]);
-        
+        assert_eq!(directory.read_file("josh", &alloc), [1,2,3]);
+        assert_eq!(directory.read_file("josh2", &alloc), [0,3,6,9]);
+
     }
 
-    
-}
\ No newline at end of file
+    #[test]
+    pub fn test_linked_layout() {
+        let mut alloc = allocator(25);
+        let mut directory = Directory::new();
+        directory.open_file("notes".to_string(), &mut alloc, &(1..=10).collect::<Vec<_>>());
+        directory.open_file("empty".to_string(), &mut alloc, &[]);
+        assert_eq!(directory.read_file("notes", &alloc), (1..=10).collect::<Vec<_>>());
+        assert!(directory.read_file("empty", &alloc).is_empty());
+
+        // Block zero is the bitmap, then each block points at the next.
+        assert_eq!(raw(&alloc, 1), [0, 0, 0, 2, 1, 2, 3, 4]);
+        assert_eq!(raw(&alloc, 2), [0, 0, 0, 3, 5, 6, 7, 8]);
+        assert_eq!(raw(&alloc, 3), [0xFF, 0xFF, 0xFF, 0xFF, 9, 10, 0, 0]);
+        assert_eq!(raw(&alloc, 0)[0], 0b1111_1000);
+
+        directory.delete_file("notes", &mut alloc);
+        assert_eq!(raw(&alloc, 0)[0], 0b1000_1000);
+        assert_eq!(alloc.device().free_blocks(), 23);
+    }
+}
@@ -1,2 +1,4 @@
+pub mod block;
+pub mod contiguous;
 pub mod indexed;
 pub mod linked;
\ No newline at end of file
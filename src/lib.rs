@@ -1,16 +1,17 @@
 use std::{thread::sleep, time::Duration};
 
-use rand::{thread_rng, Rng};
+use rng::SimRng;
 
 pub mod memory;
 pub mod computer;
 pub mod disks;
 pub mod filesystem;
+pub mod rng;
 
 pub struct Delay;
 
 impl Delay {
     pub fn delay_random(millis: u64) {
-        sleep(Duration::from_millis(thread_rng().gen_range(0..millis)));
+        sleep(Duration::from_millis(SimRng::global().gen_range(0..millis)));
     }
 }
\ No newline at end of file
@@ -0,0 +1,208 @@
+//! Contiguous memory allocation, every allocation is one run of
+//! bytes and the free space between them is kept as a list of holes.
+
+use std::collections::{BTreeMap, HashMap};
+
+
+/// Which hole an allocation goes in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    /// The first hole that is big enough.
+    FirstFit,
+    /// The smallest hole that is big enough.
+    BestFit,
+    /// The biggest hole.
+    WorstFit
+}
+
+/// Variable partition memory over a fixed arena of bytes.
+///
+/// ```
+/// use osconcepts::memory::contiguous::{HoleListAllocator, Strategy};
+///
+/// let mut memory = HoleListAllocator::new(100);
+/// let a = memory.alloc(40, Strategy::FirstFit).unwrap();
+/// let b = memory.alloc(60, Strategy::FirstFit).unwrap();
+/// assert_eq!((a, b), (0, 40));
+/// assert!(memory.alloc(1, Strategy::FirstFit).is_none());
+/// ```
+pub struct HoleListAllocator {
+    arena: Vec<u8>,
+    /// The size of every hole by where it starts.
+    holes: BTreeMap<usize, usize>,
+    /// The size of every allocation by where it starts.
+    allocated: BTreeMap<usize, usize>
+}
+
+impl HoleListAllocator {
+    pub fn new(size: usize) -> Self {
+        let mut holes = BTreeMap::new();
+        if size > 0 {
+            holes.insert(0, size);
+        }
+        Self {
+            arena: vec![0; size],
+            holes,
+            allocated: BTreeMap::new()
+        }
+    }
+    /// Allocates a run of bytes and returns where it starts, or none if
+    /// no single hole is big enough.
+    ///
+    /// # Panics
+    /// If the size is zero.
+    pub fn alloc(&mut self, size: usize, strategy: Strategy) -> Option<usize> {
+        assert!(size > 0, "Can't allocate nothing.");
+        let mut fits = self.holes.iter().filter(|(_, hole)| **hole >= size);
+        let (&start, &hole) = match strategy {
+            Strategy::FirstFit => fits.next(),
+            // Ties go to the earlier hole.
+            Strategy::BestFit => fits.min_by_key(|(_, hole)| **hole),
+            Strategy::WorstFit => fits.rev().max_by_key(|(_, hole)| **hole)
+        }?;
+
+        self.holes.remove(&start);
+        if hole > size {
+            self.holes.insert(start + size, hole - size);
+        }
+        self.allocated.insert(start, size);
+        Some(start)
+    }
+    /// Frees an allocation, merging the hole it leaves with the holes
+    /// on either side of it.
+    ///
+    /// # Panics
+    /// If there is no allocation of that size there.
+    pub fn free(&mut self, ptr: usize, size: usize) {
+        match self.allocated.get(&ptr) {
+            Some(allocated) => assert_eq!(*allocated, size, "The allocation at {ptr} is {allocated} bytes not {size}."),
+            None => panic!("Double free of {ptr}, or it was never allocated.")
+        }
+        self.allocated.remove(&ptr);
+
+        let mut start = ptr;
+        let mut end = ptr + size;
+        if let Some((&before, &before_size)) = self.holes.range(..ptr).next_back() {
+            if before + before_size == ptr {
+                self.holes.remove(&before);
+                start = before;
+            }
+        }
+        if let Some(after_size) = self.holes.remove(&end) {
+            end += after_size;
+        }
+        self.holes.insert(start, end - start);
+    }
+    /// Every hole as (start, size) in address order.
+    pub fn holes(&self) -> Vec<(usize, usize)> {
+        self.holes.iter().map(|(start, size)| (*start, *size)).collect()
+    }
+    pub fn free_bytes(&self) -> usize {
+        self.holes.values().sum()
+    }
+    /// How much of the free space can't be used for one big allocation,
+    /// this is `1 - largest hole / free space` so zero is one big hole.
+    pub fn external_fragmentation(&self) -> f64 {
+        let free = self.free_bytes();
+        if free == 0 {
+            return 0.0;
+        }
+        let largest = self.holes.values().max().copied().unwrap_or(0);
+        1.0 - largest as f64 / free as f64
+    }
+    /// Reads bytes out of the arena.
+    pub fn read(&self, ptr: usize, length: usize) -> &[u8] {
+        &self.arena[ptr..ptr + length]
+    }
+    /// Writes bytes into the arena.
+    pub fn write(&mut self, ptr: usize, data: &[u8]) {
+        self.arena[ptr..ptr + data.len()].copy_from_slice(data);
+    }
+    /// Slides every allocation down to the start of the arena so the
+    /// free space is one hole at the end, returning where each allocation
+    /// that moved went.
+    pub fn compact(&mut self) -> HashMap<usize, usize> {
+        let mut relocations = HashMap::new();
+        let mut next = 0;
+        let allocated = std::mem::take(&mut self.allocated);
+        for (start, size) in allocated {
+            if start != next {
+                self.arena.copy_within(start..start + size, next);
+                relocations.insert(start, next);
+            }
+            self.allocated.insert(next, size);
+            next += size;
+        }
+        self.holes.clear();
+        if next < self.arena.len() {
+            self.holes.insert(next, self.arena.len() - next);
+        }
+        relocations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{HoleListAllocator, Strategy};
+
+    /// Leaves holes of 25, 20 and 35 bytes and then asks for 18 and 22.
+    fn trace(strategy: Strategy) -> (Vec<usize>, HoleListAllocator) {
+        let mut memory = HoleListAllocator::new(100);
+        let blocks: Vec<_> = [25, 10, 20, 10, 35]
+            .into_iter()
+            .map(|size| (memory.alloc(size, Strategy::FirstFit).unwrap(), size))
+            .collect();
+        for (ptr, size) in [blocks[0], blocks[2], blocks[4]] {
+            memory.free(ptr, size);
+        }
+        assert_eq!(memory.holes(), [(0, 25), (35, 20), (65, 35)]);
+        let placed = [18, 22].into_iter().map(|size| memory.alloc(size, strategy).unwrap()).collect();
+        (placed, memory)
+    }
+
+    #[test]
+    pub fn test_contiguous_strategies() {
+        let (first, first_memory) = trace(Strategy::FirstFit);
+        let (best, best_memory) = trace(Strategy::BestFit);
+        let (worst, worst_memory) = trace(Strategy::WorstFit);
+        assert_eq!(first, [0, 65]);
+        assert_eq!(best, [35, 0]);
+        assert_eq!(worst, [65, 0]);
+
+        // They all have 40 bytes free but split up differently.
+        assert_eq!(first_memory.holes(), [(18, 7), (35, 20), (87, 13)]);
+        assert_eq!(best_memory.holes(), [(22, 3), (53, 2), (65, 35)]);
+        assert_eq!(worst_memory.holes(), [(22, 3), (35, 20), (83, 17)]);
+        assert_eq!(first_memory.external_fragmentation(), 0.5);
+        assert_eq!(best_memory.external_fragmentation(), 0.125);
+        assert_eq!(worst_memory.external_fragmentation(), 0.5);
+    }
+
+    #[test]
+    pub fn test_contiguous_coalesce() {
+        let mut memory = HoleListAllocator::new(30);
+        let blocks: Vec<_> = (0..3).map(|_| memory.alloc(10, Strategy::FirstFit).unwrap()).collect();
+        memory.free(blocks[0], 10);
+        memory.free(blocks[2], 10);
+        assert_eq!(memory.holes(), [(0, 10), (20, 10)]);
+        // Freeing the middle joins all three.
+        memory.free(blocks[1], 10);
+        assert_eq!(memory.holes(), [(0, 30)]);
+        assert_eq!(memory.external_fragmentation(), 0.0);
+    }
+
+    #[test]
+    pub fn test_contiguous_compact() {
+        let (placed, mut memory) = trace(Strategy::FirstFit);
+        memory.write(placed[1], &[7; 22]);
+        assert!(memory.alloc(30, Strategy::FirstFit).is_none());
+
+        let relocations = memory.compact();
+        assert_eq!(relocations, HashMap::from([(25, 18), (55, 28), (65, 38)]));
+        assert_eq!(memory.read(38, 22), [7; 22]);
+        assert_eq!(memory.holes(), [(60, 40)]);
+        assert_eq!(memory.alloc(30, Strategy::FirstFit), Some(60));
+    }
+}
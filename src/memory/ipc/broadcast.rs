@@ -0,0 +1,190 @@
+use std::{collections::{HashMap, VecDeque}, sync::Arc};
+
+use parking_lot::{Condvar, Mutex};
+
+use super::Disconnected;
+
+
+/// The shared ring of messages, each subscriber has a cursor into it.
+struct Ring<T> {
+    messages: VecDeque<T>,
+    /// The sequence number of the first message in the ring.
+    offset: usize,
+    /// The sequence number of the next message each subscriber will read.
+    cursors: HashMap<usize, usize>,
+    next_subscriber: usize,
+    closed: bool
+}
+
+impl<T> Ring<T> {
+    /// Drops the messages every subscriber has already read.
+    fn trim(&mut self) {
+        let end = self.offset + self.messages.len();
+        let oldest = self.cursors.values().copied().min().unwrap_or(end);
+        while self.offset < oldest {
+            self.messages.pop_front();
+            self.offset += 1;
+        }
+    }
+}
+
+struct Shared<T> {
+    ring: Mutex<Ring<T>>,
+    signal: Condvar
+}
+
+/// A channel where every message is delivered to every subscriber.
+///
+/// Subscribers only see the messages sent after they subscribed.
+pub struct BroadcastChannel<T>(Arc<Shared<T>>);
+
+impl<T> Clone for BroadcastChannel<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<T: Clone> BroadcastChannel<T> {
+    pub fn new() -> Self {
+        Self(Arc::new(Shared {
+            ring: Mutex::new(Ring {
+                messages: VecDeque::new(),
+                offset: 0,
+                cursors: HashMap::new(),
+                next_subscriber: 0,
+                closed: false
+            }),
+            signal: Condvar::new()
+        }))
+    }
+    /// Subscribes to the channel.
+    pub fn subscribe(&self) -> BroadcastReceiver<T> {
+        let mut ring = self.0.ring.lock();
+        let id = ring.next_subscriber;
+        ring.next_subscriber += 1;
+        let end = ring.offset + ring.messages.len();
+        ring.cursors.insert(id, end);
+        BroadcastReceiver {
+            id,
+            shared: Arc::clone(&self.0)
+        }
+    }
+    /// Sends a message to every subscriber, this
+    /// fails if the channel has been closed.
+    pub fn send(&self, data: T) -> Result<(), Disconnected> {
+        let mut ring = self.0.ring.lock();
+        if ring.closed {
+            return Err(Disconnected);
+        }
+        if !ring.cursors.is_empty() {
+            // Nobody would ever read it otherwise.
+            ring.messages.push_back(data);
+        }
+        self.0.signal.notify_all();
+        Ok(())
+    }
+    /// Closes the channel, subscribers can still read
+    /// what was sent before it was closed.
+    pub fn close(&self) {
+        self.0.ring.lock().closed = true;
+        self.0.signal.notify_all();
+    }
+}
+
+impl<T: Clone> Default for BroadcastChannel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A subscription to a [BroadcastChannel].
+pub struct BroadcastReceiver<T> {
+    id: usize,
+    shared: Arc<Shared<T>>
+}
+
+impl<T: Clone> BroadcastReceiver<T> {
+    /// Tries to receive the next message.
+    pub fn try_recv(&self) -> Option<T> {
+        let mut ring = self.shared.ring.lock();
+        self.take(&mut ring)
+    }
+    /// Waits for the next message, this fails once the channel
+    /// is closed and we have read everything.
+    pub fn recv(&self) -> Result<T, Disconnected> {
+        let mut ring = self.shared.ring.lock();
+        loop {
+            if let Some(message) = self.take(&mut ring) {
+                return Ok(message);
+            }
+            if ring.closed {
+                return Err(Disconnected);
+            }
+            self.shared.signal.wait(&mut ring);
+        }
+    }
+    fn take(&self, ring: &mut Ring<T>) -> Option<T> {
+        let cursor = ring.cursors[&self.id];
+        let message = ring.messages.get(cursor - ring.offset)?.clone();
+        ring.cursors.insert(self.id, cursor + 1);
+        ring.trim();
+        Some(message)
+    }
+}
+
+impl<T> Drop for BroadcastReceiver<T> {
+    fn drop(&mut self) {
+        let mut ring = self.shared.ring.lock();
+        ring.cursors.remove(&self.id);
+        ring.trim();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::BroadcastChannel;
+
+    #[test]
+    pub fn test_broadcast_late_subscriber() {
+        let channel = BroadcastChannel::new();
+        let early = channel.subscribe();
+        channel.send(1).unwrap();
+        let late = channel.subscribe();
+        channel.send(2).unwrap();
+
+        assert_eq!(early.try_recv(), Some(1));
+        assert_eq!(early.try_recv(), Some(2));
+        assert_eq!(late.try_recv(), Some(2));
+        assert_eq!(late.try_recv(), None);
+    }
+
+    #[test]
+    pub fn test_broadcast_subscribers() {
+        let channel = BroadcastChannel::new();
+        let subscribers: Vec<_> = (0..4)
+            .map(|_| {
+                let receiver = channel.subscribe();
+                thread::spawn(move || {
+                    let mut seen = vec![];
+                    while let Ok(message) = receiver.recv() {
+                        seen.push(message);
+                    }
+                    seen
+                })
+            })
+            .collect();
+
+        for i in 0..100 {
+            channel.send(i).unwrap();
+        }
+        channel.close();
+
+        // Everyone gets the whole sequence.
+        for subscriber in subscribers {
+            assert_eq!(subscriber.join().unwrap(), (0..100).collect::<Vec<_>>());
+        }
+    }
+}
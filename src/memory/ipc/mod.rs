@@ -1,12 +1,30 @@
-use std::{collections::VecDeque, sync::Arc};
+use std::{collections::VecDeque, sync::{atomic::{AtomicBool, Ordering}, Arc}, task::{Context, Poll, Waker}};
 
 use parking_lot::{Condvar, Mutex};
 
+pub mod broadcast;
+pub mod priority;
 
 
+/// The channel has been closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Disconnected;
+
+/// Why [IpcChannel::try_recv] didn't give back a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// There is nothing in the channel right now.
+    Empty,
+    /// The channel is closed and there is nothing left in it.
+    Disconnected
+}
+
 pub struct IpcChannel<T> {
     signal: Condvar,
-    queue: Mutex<VecDeque<T>>
+    queue: Mutex<VecDeque<T>>,
+    closed: AtomicBool,
+    /// Tasks polling the channel that want to know when something arrives.
+    wakers: Mutex<Vec<Waker>>
 }
 
 impl<T> IpcChannel<T> {
@@ -14,49 +32,257 @@ impl<T> IpcChannel<T> {
         Self {
             queue: Mutex::new(VecDeque::new()),
             signal: Condvar::new(),
+            closed: AtomicBool::new(false),
+            wakers: Mutex::new(vec![])
         }
     }
-    /// Sends a value into the IPC channel.
-    pub fn send(&self, data: T) {
+    /// Sends a value into the IPC channel, this wakes up
+    /// one of the receivers waiting on it. This fails if
+    /// the channel has been closed.
+    pub fn send(&self, data: T) -> Result<(), Disconnected> {
         let mut queue = self.queue.lock();
+        if self.is_closed() {
+            return Err(Disconnected);
+        }
         queue.push_back(data);
+        // Notify while holding the lock so a waiter can't miss it.
         self.signal.notify_one();
+        self.wake();
+        Ok(())
+    }
+    /// Closes the channel, nothing more can be sent and everyone
+    /// waiting on it is woken up. Whatever is already in the
+    /// channel can still be received.
+    pub fn close(&self) {
+        let _queue = self.queue.lock();
+        self.closed.store(true, Ordering::SeqCst);
+        self.signal.notify_all();
+        self.wake();
+    }
+    fn wake(&self) {
+        for waker in self.wakers.lock().drain(..) {
+            waker.wake();
+        }
     }
-    /// Tries to receive a value.
-    pub fn try_recv(&self) -> Option<T> {
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+    /// The number of values waiting to be received.
+    pub fn len(&self) -> usize {
+        self.queue.lock().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Tries to receive a value without waiting, whatever was
+    /// sent before the channel closed still comes through.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
         let mut queue = self.queue.lock();
-        if !queue.is_empty() {
-            queue.pop_front()
-        } else {
-            None
+        match queue.pop_front() {
+            Some(value) => Ok(value),
+            None if self.is_closed() => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty)
         }
     }
-    /// Receives unconditionally.
-    pub fn recv(&self) -> T {
+    /// Tries to receive a value from a task, if there isn't one
+    /// the task is woken up when there is.
+    pub fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Result<T, Disconnected>> {
         let mut queue = self.queue.lock();
-        if !queue.is_empty() {
-            queue.pop_front().unwrap()
+        if let Some(value) = queue.pop_front() {
+            Poll::Ready(Ok(value))
+        } else if self.is_closed() {
+            Poll::Ready(Err(Disconnected))
         } else {
+            // This is done under the queue lock so a send can't slip past.
+            self.wakers.lock().push(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+    /// Waits for a value, this fails once the channel
+    /// is closed and there is nothing left in it.
+    pub fn recv(&self) -> Result<T, Disconnected> {
+        let mut queue = self.queue.lock();
+        // Another receiver may have beaten us to it or
+        // the wakeup may be spurious, so keep waiting.
+        while queue.is_empty() {
+            if self.is_closed() {
+                return Err(Disconnected);
+            }
             self.signal.wait(&mut queue);
-            queue.pop_front().unwrap()
         }
+        Ok(queue.pop_front().unwrap())
     }
 }
 
 
 /// A synchronous future.
-pub struct Yield<T>(Arc<IpcChannel<T>>);
+pub struct Yield<T> {
+    /// Checks if the value has arrived.
+    ready: Box<dyn Fn() -> bool + Send>,
+    /// Waits for the value.
+    wait: Box<dyn FnOnce() -> Result<T, Disconnected> + Send>
+}
 
-impl<T> Yield<T> {
+impl<T: Send + 'static> Yield<T> {
     pub fn new(channel: Arc<IpcChannel<T>>) -> Self {
-        Self(channel)
+        Self {
+            ready: Box::new({
+                let channel = Arc::clone(&channel);
+                move || !channel.is_empty()
+            }),
+            wait: Box::new(move || channel.recv())
+        }
+    }
+    /// A yield that already has its value.
+    pub fn ready(value: T) -> Self {
+        let channel = Arc::new(IpcChannel::new());
+        let _ = channel.send(value);
+        Self::new(channel)
+    }
+    /// Transforms the value once it arrives, the function
+    /// is only run when the value is waited on.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U + Send + 'static) -> Yield<U> {
+        let wait = self.wait;
+        Yield {
+            ready: self.ready,
+            wait: Box::new(move || wait().map(f))
+        }
     }
+}
+
+impl<T> Yield<T> {
+    /// Checks if the value has arrived without waiting on it.
+    pub fn is_ready(&self) -> bool {
+        (self.ready)()
+    }
+    /// Waits for the value.
+    /// 
+    /// # Panics
+    /// If the channel was closed without a value, use
+    /// [Yield::try_get] to handle this.
     pub fn get(self) -> T {
-        self.0.recv()
+        self.try_get().expect("The channel was closed before a value was sent.")
+    }
+    /// Waits for the value, this fails if the channel
+    /// was closed without one.
+    pub fn try_get(self) -> Result<T, Disconnected> {
+        (self.wait)()
+    }
+    /// Waits on all the values, these are returned in the
+    /// same order as the yields no matter when they arrive.
+    pub fn join_all(yields: Vec<Yield<T>>) -> Vec<T> {
+        yields.into_iter().map(Yield::get).collect()
+    }
+    pub fn join_get(yields: Vec<Yield<T>>) {
+        Self::join_all(yields);
     }
-    pub fn join_get(mut yields: Vec<Yield<T>>) {
-        for _ in 0..yields.len() {
-            yields.pop().unwrap().get();
+}
+
+/// An asynchronous version of [Yield], awaiting it gives
+/// back the value or [Disconnected].
+#[cfg(feature = "async")]
+pub struct AsyncYield<T> {
+    channel: Arc<IpcChannel<T>>
+}
+
+#[cfg(feature = "async")]
+impl<T> AsyncYield<T> {
+    pub fn new(channel: Arc<IpcChannel<T>>) -> Self {
+        Self { channel }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> std::future::Future for AsyncYield<T> {
+    type Output = Result<T, Disconnected>;
+    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.channel.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread, time::Duration};
+
+    use crate::disks::{hard_drive::{DiskAlgorithm, MagneticDisk}, AbstractStorageDevice, RawStoragePtr};
+
+    use super::{Disconnected, IpcChannel, TryRecvError, Yield};
+
+    #[test]
+    pub fn test_yield_join_all() {
+        let disk = MagneticDisk::new(256, DiskAlgorithm::SSTF);
+        disk.write(RawStoragePtr::byte_ptr(200), &[1]).get();
+        disk.write(RawStoragePtr::byte_ptr(100), &[2]).get();
+        disk.write(RawStoragePtr::byte_ptr(0), &[3]).get();
+
+        // Queue the reads up so the disk services them nearest first.
+        disk.pause();
+        let reads: Vec<_> = [200, 100, 0]
+            .into_iter()
+            .map(|addr| disk.read(RawStoragePtr::byte_ptr(addr), 1).map(|f| f[0]))
+            .collect();
+        thread::sleep(Duration::from_millis(50));
+        assert!(!reads[0].is_ready());
+        disk.run();
+
+        // The results still come back in the order they were asked for.
+        assert_eq!(Yield::join_all(reads), [1, 2, 3]);
+    }
+
+    #[test]
+    pub fn test_ipc_close() {
+        let channel = Arc::new(IpcChannel::<u32>::new());
+        assert_eq!(channel.try_recv(), Err(TryRecvError::Empty));
+        let waiters: Vec<_> = (0..3)
+            .map(|_| {
+                let channel = Arc::clone(&channel);
+                thread::spawn(move || channel.recv())
+            })
+            .collect();
+
+        // Everyone waiting gets woken up, whether they were blocked yet or not.
+        channel.close();
+        for waiter in waiters {
+            assert_eq!(waiter.join().unwrap(), Err(Disconnected));
+        }
+        assert_eq!(channel.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    pub fn test_ipc_close_drain() {
+        let channel = IpcChannel::new();
+        channel.send(1).unwrap();
+        channel.send(2).unwrap();
+        channel.close();
+        assert_eq!(channel.send(3), Err(Disconnected));
+
+        // What was sent before closing still comes through.
+        assert_eq!(channel.try_recv(), Ok(1));
+        assert_eq!(channel.recv(), Ok(2));
+        assert_eq!(channel.try_recv(), Err(TryRecvError::Disconnected));
+        assert_eq!(channel.recv(), Err(Disconnected));
+    }
+
+    #[test]
+    pub fn test_ipc_many_consumers() {
+        let channel = Arc::new(IpcChannel::new());
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                let channel = Arc::clone(&channel);
+                thread::spawn(move || (0..2500).map(|_| channel.recv().unwrap()).collect::<Vec<usize>>())
+            })
+            .collect();
+
+        for i in 0..10000 {
+            channel.send(i).unwrap();
         }
+
+        // Every value should arrive exactly once.
+        let mut received: Vec<usize> = consumers
+            .into_iter()
+            .flat_map(|f| f.join().unwrap())
+            .collect();
+        received.sort();
+        assert_eq!(received, (0..10000).collect::<Vec<_>>());
     }
-}
\ No newline at end of file
+}
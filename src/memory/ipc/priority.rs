@@ -0,0 +1,126 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use parking_lot::{Condvar, Mutex};
+
+use super::Disconnected;
+
+
+/// A message along with when it was sent, this is
+/// ordered so the heap pops the most important first.
+struct Entry<T> {
+    priority: i32,
+    sequence: u64,
+    data: T
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // The heap pops the largest so flip it, lower priorities
+        // come first and then whatever was sent first.
+        (other.priority, other.sequence).cmp(&(self.priority, self.sequence))
+    }
+}
+
+struct Queue<T> {
+    heap: BinaryHeap<Entry<T>>,
+    sequence: u64,
+    closed: bool
+}
+
+/// An IPC channel where messages are received in order of priority
+/// instead of the order they were sent in. Like the scheduler, a lower
+/// number is a higher priority. Messages with the same priority come
+/// out in the order they were sent.
+pub struct PriorityIpcChannel<T> {
+    signal: Condvar,
+    queue: Mutex<Queue<T>>
+}
+
+impl<T> PriorityIpcChannel<T> {
+    pub fn new() -> Self {
+        Self {
+            signal: Condvar::new(),
+            queue: Mutex::new(Queue {
+                heap: BinaryHeap::new(),
+                sequence: 0,
+                closed: false
+            })
+        }
+    }
+    /// Sends a value with a priority, this fails
+    /// if the channel has been closed.
+    pub fn send(&self, data: T, priority: i32) -> Result<(), Disconnected> {
+        let mut queue = self.queue.lock();
+        if queue.closed {
+            return Err(Disconnected);
+        }
+        let sequence = queue.sequence;
+        queue.sequence += 1;
+        queue.heap.push(Entry { priority, sequence, data });
+        self.signal.notify_one();
+        Ok(())
+    }
+    /// Closes the channel and wakes everyone waiting on it.
+    pub fn close(&self) {
+        self.queue.lock().closed = true;
+        self.signal.notify_all();
+    }
+    /// Tries to receive the most important value.
+    pub fn try_recv(&self) -> Option<T> {
+        self.queue.lock().heap.pop().map(|f| f.data)
+    }
+    /// Waits for the most important value, this fails once
+    /// the channel is closed and there is nothing left in it.
+    pub fn recv(&self) -> Result<T, Disconnected> {
+        let mut queue = self.queue.lock();
+        loop {
+            if let Some(entry) = queue.heap.pop() {
+                return Ok(entry.data);
+            }
+            if queue.closed {
+                return Err(Disconnected);
+            }
+            self.signal.wait(&mut queue);
+        }
+    }
+}
+
+impl<T> Default for PriorityIpcChannel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::PriorityIpcChannel;
+
+    #[test]
+    pub fn test_priority_ipc() {
+        let channel = PriorityIpcChannel::new();
+        channel.send("a", 5).unwrap();
+        channel.send("b", 1).unwrap();
+        channel.send("c", 5).unwrap();
+        channel.send("d", 0).unwrap();
+
+        // Equal priorities keep the order they were sent in.
+        let received: Vec<_> = (0..4).map(|_| channel.recv().unwrap()).collect();
+        assert_eq!(received, ["d", "b", "a", "c"]);
+        assert_eq!(channel.try_recv(), None);
+    }
+}
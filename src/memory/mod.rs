@@ -1,24 +1,47 @@
-use std::{cell::UnsafeCell, sync::Arc};
+use std::{cell::UnsafeCell, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
 
 pub mod pool;
 pub mod numa;
 pub mod ipc;
 pub mod paging;
+pub mod sync;
+pub mod segmentation;
+pub mod contiguous;
 
 
 
 /// For unsafe unguarded memory sharing between threads.
-pub struct SharedMemory<T>(Arc<UnsafeCell<T>>);
+pub struct SharedMemory<T>(Arc<UnsafeCell<T>>, Option<Arc<Instruments>>);
+
+/// Seqlock style counters for catching races on [SharedMemory].
+#[derive(Default)]
+struct Instruments {
+    /// Bumped before and after every write.
+    version: AtomicUsize,
+    /// How many writes are happening right now.
+    writers: AtomicUsize,
+    torn_reads: AtomicUsize,
+    concurrent_writes: AtomicUsize
+}
 
 impl<T> SharedMemory<T> {
     pub fn new(obj: T) -> Self {
-        Self(Arc::new(UnsafeCell::new(obj)))
+        Self(Arc::new(UnsafeCell::new(obj)), None)
+    }
+    /// Creates shared memory that detects races, this only
+    /// covers accesses through [SharedMemory::read] and [SharedMemory::write].
+    pub fn instrumented(obj: T) -> Self {
+        Self(Arc::new(UnsafeCell::new(obj)), Some(Arc::default()))
     }
 }
 
+// Guarding it is up to whoever uses it, like with the locks in `sync`.
+unsafe impl<T: Send> Send for SharedMemory<T> {}
+unsafe impl<T: Send + Sync> Sync for SharedMemory<T> {}
+
 impl<T> Clone for SharedMemory<T> {
     fn clone(&self) -> Self {
-        Self(Arc::clone(&self.0))
+        Self(Arc::clone(&self.0), self.1.clone())
     }
 }
 
@@ -29,6 +52,43 @@ impl<T> SharedMemory<T> {
     pub fn get_mut(&self) -> &mut T {
         unsafe { &mut *self.0.get() }
     }
+    /// Reads the memory, if a write happened at the
+    /// same time this is counted as a torn read.
+    pub fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        let Some(instruments) = &self.1 else {
+            return f(self.get());
+        };
+        let before = instruments.version.load(Ordering::SeqCst);
+        let writing = instruments.writers.load(Ordering::SeqCst) != 0;
+        let result = f(self.get());
+        if writing || instruments.version.load(Ordering::SeqCst) != before {
+            instruments.torn_reads.fetch_add(1, Ordering::SeqCst);
+        }
+        result
+    }
+    /// Writes to the memory, if another write was already
+    /// going on this is counted as a concurrent write.
+    pub fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let Some(instruments) = &self.1 else {
+            return f(self.get_mut());
+        };
+        if instruments.writers.fetch_add(1, Ordering::SeqCst) != 0 {
+            instruments.concurrent_writes.fetch_add(1, Ordering::SeqCst);
+        }
+        instruments.version.fetch_add(1, Ordering::SeqCst);
+        let result = f(self.get_mut());
+        instruments.version.fetch_add(1, Ordering::SeqCst);
+        instruments.writers.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+    /// How many reads overlapped a write.
+    pub fn torn_reads(&self) -> usize {
+        self.1.as_ref().map_or(0, |f| f.torn_reads.load(Ordering::SeqCst))
+    }
+    /// How many writes overlapped another write.
+    pub fn concurrent_writes(&self) -> usize {
+        self.1.as_ref().map_or(0, |f| f.concurrent_writes.load(Ordering::SeqCst))
+    }
 }
 
 // impl<T> Deref for SharedMemory<T> {
@@ -42,4 +102,53 @@ impl<T> SharedMemory<T> {
 //     fn deref_mut(&mut self) -> &mut Self::Target {
 //         unsafe { &mut *self.0.get() }
 //     }
-// }
\ No newline at end of file
+// }
+
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::SharedMemory;
+
+    #[test]
+    pub fn test_shared_memory_races() {
+        let memory = SharedMemory::instrumented([0usize; 16]);
+        let workers: Vec<_> = (0..4)
+            .map(|i| {
+                let memory = memory.clone();
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        // Yielding half way makes sure we get interrupted.
+                        memory.write(|f| {
+                            f[..8].fill(i);
+                            thread::yield_now();
+                            f[8..].fill(i);
+                        });
+                        memory.read(|f| {
+                            let first = f[0];
+                            thread::yield_now();
+                            f[15] == first
+                        });
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert!(memory.torn_reads() > 0);
+        assert!(memory.concurrent_writes() > 0);
+    }
+
+    #[test]
+    pub fn test_shared_memory_single_thread() {
+        let memory = SharedMemory::instrumented(0);
+        for i in 0..100 {
+            memory.write(|f| *f += i);
+            assert_eq!(memory.read(|f| *f), (0..=i).sum());
+        }
+        assert_eq!(memory.torn_reads(), 0);
+        assert_eq!(memory.concurrent_writes(), 0);
+    }
+}
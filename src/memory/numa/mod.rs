@@ -1,33 +1,393 @@
-use std::{fmt::Debug, thread, time::Duration};
+use std::{fmt::Debug, sync::Arc, thread, time::Duration};
 
-use rand::{thread_rng, Rng};
+use parking_lot::Mutex;
 
-use super::pool::{MemoryPtrGuard, SyncMemoryPtr};
+use crate::rng::SimRng;
+
+use super::pool::{MemoryMutex, MemoryPtrGuard, RandomAccessMemory, SyncMemoryPtr};
+
+/// How long it takes each node to reach the memory of every other node.
+struct Latencies {
+    /// The latency from one node (the row) to another (the column).
+    matrix: Vec<Vec<Duration>>,
+    /// The most random delay added on top of each access.
+    jitter: Duration
+}
+
+impl Latencies {
+    fn delay(&self, from: usize, to: usize) {
+        assert!(from < self.matrix.len(), "There is no node {from}.");
+        let mut delay = self.matrix[from][to];
+        if !self.jitter.is_zero() {
+            delay += SimRng::global().gen_range(Duration::ZERO..self.jitter);
+        }
+        thread::sleep(delay);
+    }
+}
+
+/// Decides when memory moves between nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum NumaPolicy {
+    /// Memory stays wherever it was stored.
+    #[default]
+    Fixed,
+    /// Memory moves to whichever node touches it first.
+    FirstTouch,
+    /// Memory moves to a node after that many remote accesses in a row from it.
+    MigrateOnThreshold(usize)
+}
+
+/// How a pointer has been accessed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessStats {
+    /// How many times each node has accessed it.
+    pub accesses: Vec<usize>,
+    /// How many times it has moved.
+    pub migrations: usize
+}
+
+/// A set of nodes that each have their own memory, accessing the
+/// memory of another node takes however long the latency matrix says.
+///
+/// ```
+/// use std::time::Duration;
+/// use osconcepts::memory::numa::NumaTopology;
+///
+/// let local = Duration::ZERO;
+/// let remote = Duration::from_millis(5);
+/// let topology = NumaTopology::new(vec![vec![local, remote], vec![remote, local]]);
+///
+/// let ptr = topology.store_on(1, 3);
+/// assert_eq!(ptr.home_node(), Some(1));
+/// assert_eq!(*ptr.lock_from(1).get(), 3);
+/// ```
+pub struct NumaTopology {
+    latencies: Arc<Latencies>,
+    policy: NumaPolicy,
+    memory: Arc<Vec<RandomAccessMemory<MemoryMutex>>>
+}
+
+impl NumaTopology {
+    /// Creates a node for every row of the matrix, where each entry
+    /// is how long that node takes to reach another.
+    ///
+    /// # Panics
+    /// If the matrix is not square.
+    pub fn new(latencies: Vec<Vec<Duration>>) -> Self {
+        assert!(
+            latencies.iter().all(|f| f.len() == latencies.len()),
+            "The latency matrix must be square."
+        );
+        Self {
+            memory: Arc::new((0..latencies.len()).map(|_| RandomAccessMemory::new()).collect()),
+            policy: NumaPolicy::Fixed,
+            latencies: Arc::new(Latencies {
+                matrix: latencies,
+                jitter: Duration::ZERO
+            })
+        }
+    }
+    /// Adds up to this much random delay to every access, there is none by default.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        // Anything already stored keeps the old jitter.
+        self.latencies = Arc::new(Latencies {
+            matrix: self.latencies.matrix.clone(),
+            jitter
+        });
+        self
+    }
+    /// Sets when the memory stored after this gets migrated.
+    pub fn with_policy(mut self, policy: NumaPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+    /// How many nodes there are.
+    pub fn nodes(&self) -> usize {
+        self.memory.len()
+    }
+    /// How long it takes one node to reach the memory of another.
+    pub fn latency(&self, from: usize, to: usize) -> Duration {
+        self.latencies.matrix[from][to]
+    }
+    /// The memory of a node.
+    pub fn memory(&self, node: usize) -> &RandomAccessMemory<MemoryMutex> {
+        &self.memory[node]
+    }
+    /// Stores an object in the memory of a node.
+    ///
+    /// # Panics
+    /// If the node does not exist.
+    pub fn store_on<T: Send + Sync>(&self, node: usize, obj: T) -> NumaPtr<T> {
+        NumaPtr {
+            ptr: Arc::new(Mutex::new(self.memory[node].store(obj))),
+            home: Some(Home {
+                latencies: Arc::clone(&self.latencies),
+                policy: self.policy,
+                memory: Arc::clone(&self.memory),
+                placement: Arc::new(Mutex::new(Placement {
+                    node,
+                    stored: node,
+                    stats: AccessStats {
+                        accesses: vec![0; self.nodes()],
+                        migrations: 0
+                    },
+                    streak: None
+                }))
+            })
+        }
+    }
+}
+
+/// Where a pointer lives in a [NumaTopology].
+#[derive(Clone)]
+struct Home {
+    latencies: Arc<Latencies>,
+    policy: NumaPolicy,
+    /// The memory of every node, so the object can be moved between them.
+    memory: Arc<Vec<RandomAccessMemory<MemoryMutex>>>,
+    /// This is shared between clones so they all move together.
+    placement: Arc<Mutex<Placement>>
+}
+
+struct Placement {
+    node: usize,
+    /// Which node's memory the object is in, this catches up
+    /// with the node when the pointer is next used.
+    stored: usize,
+    stats: AccessStats,
+    /// The node that has been accessing remotely and how many times in a row.
+    streak: Option<(usize, usize)>
+}
+
+impl Placement {
+    fn migrate(&mut self, node: usize) {
+        if node != self.node {
+            self.node = node;
+            self.stats.migrations += 1;
+        }
+        self.streak = None;
+    }
+}
 
 /// A pointer that provides non-uniform memory access,
 /// there will be slight delays.
 ///
 /// Upon calling lock there is a delay between acquiring a lock and
 /// then a delay after.
-#[derive(Clone)]
-pub struct NumaPtr<T>(SyncMemoryPtr<T>);
+pub struct NumaPtr<T> {
+    /// This is shared between clones so they all follow it when it moves.
+    ptr: Arc<Mutex<SyncMemoryPtr<T>>>,
+    /// This is none if the pointer is not part of a topology.
+    home: Option<Home>
+}
+
+impl<T> Clone for NumaPtr<T> {
+    fn clone(&self) -> Self {
+        Self {
+            ptr: Arc::clone(&self.ptr),
+            home: self.home.clone()
+        }
+    }
+}
 
 impl<T: Debug> Debug for NumaPtr<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        self.ptr.lock().fmt(f)
     }
 }
 
-impl<T> NumaPtr<T> {
+impl Home {
+    /// Records an access from a node and applies the policy, this
+    /// returns where the memory was for this access.
+    fn record(&self, node: usize) -> usize {
+        let mut placement = self.placement.lock();
+        assert!(node < placement.stats.accesses.len(), "There is no node {node}.");
+        let first = placement.stats.accesses.iter().all(|&f| f == 0);
+        placement.stats.accesses[node] += 1;
+
+        let home = placement.node;
+        match self.policy {
+            NumaPolicy::Fixed => home,
+            NumaPolicy::FirstTouch => {
+                if first {
+                    placement.migrate(node);
+                }
+                placement.node
+            }
+            NumaPolicy::MigrateOnThreshold(threshold) => {
+                if node == home {
+                    placement.streak = None;
+                    return home;
+                }
+                let count = match placement.streak {
+                    Some((streak, count)) if streak == node => count + 1,
+                    _ => 1
+                };
+                placement.streak = Some((node, count));
+                // This access was still remote, the next one won't be.
+                if count >= threshold {
+                    placement.migrate(node);
+                }
+                home
+            }
+        }
+    }
+}
+
+impl<T: Send + Sync> NumaPtr<T> {
     pub fn upgrade(obj: SyncMemoryPtr<T>) -> Self {
-        Self(obj)
+        Self {
+            ptr: Arc::new(Mutex::new(obj)),
+            home: None
+        }
+    }
+    /// The node the memory lives on.
+    pub fn home_node(&self) -> Option<usize> {
+        self.home.as_ref().map(|f| f.placement.lock().node)
+    }
+    /// How the pointer has been accessed, if it is part of a topology.
+    pub fn access_stats(&self) -> Option<AccessStats> {
+        self.home.as_ref().map(|f| f.placement.lock().stats.clone())
     }
+    /// Moves the memory to another node.
+    ///
+    /// # Panics
+    /// If the pointer is not part of a topology or the node does not exist.
+    pub fn migrate_to(&self, node: usize) {
+        let home = self.home.as_ref().expect("The pointer is not part of a topology.");
+        assert!(node < home.latencies.matrix.len(), "There is no node {node}.");
+        home.placement.lock().migrate(node);
+        self.settle(home);
+    }
+    /// Moves the object into the memory of the node it now lives on,
+    /// freeing it from the one it was in.
+    fn settle(&self, home: &Home) {
+        let mut placement = home.placement.lock();
+        if placement.stored == placement.node {
+            return;
+        }
+        let mut ptr = self.ptr.lock();
+        *ptr = home.memory[placement.node].take(&ptr).expect("The memory of a node has no limit.");
+        placement.stored = placement.node;
+    }
+    /// Locks the object wherever it is now, the pointer can't move
+    /// until we have the lock.
+    fn lock_current(&self) -> MemoryPtrGuard<T> {
+        self.ptr.lock().lock()
+    }
+    /// Locks without knowing which node we are on.
     pub fn lock(&self) -> MemoryPtrGuard<T> {
-        let delay = thread_rng().gen_range(0..50);
+        let delay = SimRng::global().gen_range(0..50);
         thread::sleep(Duration::from_millis(delay));
-        let guard = self.0.lock();
-        let delay = thread_rng().gen_range(0..50);
+        let guard = self.lock_current();
+        let delay = SimRng::global().gen_range(0..50);
         thread::sleep(Duration::from_millis(delay));
         guard
     }
-}
\ No newline at end of file
+    /// Locks from a node, this takes as long as the topology
+    /// says it takes to reach the home node.
+    ///
+    /// If the pointer is not part of a topology this is just [NumaPtr::lock].
+    ///
+    /// # Panics
+    /// If the node is not part of the topology.
+    pub fn lock_from(&self, node: usize) -> MemoryPtrGuard<T> {
+        let Some(home) = &self.home else {
+            return self.lock();
+        };
+        let target = home.record(node);
+        self.settle(home);
+        home.latencies.delay(node, target);
+        self.lock_current()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{NumaPolicy, NumaTopology};
+
+    #[test]
+    pub fn test_numa_latency_matrix() {
+        let ms = Duration::from_millis;
+        let topology = NumaTopology::new(vec![
+            vec![ms(0), ms(10), ms(40)],
+            vec![ms(10), ms(0), ms(20)],
+            vec![ms(40), ms(20), ms(0)],
+        ]);
+        let ptr = topology.store_on(0, 5u32);
+
+        let timings: Vec<_> = (0..3)
+            .map(|node| {
+                let start = Instant::now();
+                *ptr.lock_from(node).get_mut() += 1;
+                start.elapsed()
+            })
+            .collect();
+
+        // Each access takes at least as long as configured and the
+        // farther away nodes are slower.
+        for (node, timing) in timings.iter().enumerate() {
+            assert!(*timing >= topology.latency(node, 0));
+        }
+        assert!(timings[0] < timings[1]);
+        assert!(timings[1] < timings[2]);
+        assert_eq!(*ptr.lock_from(0).get(), 8);
+    }
+
+    #[test]
+    pub fn test_numa_migrate_on_threshold() {
+        let ms = Duration::from_millis;
+        let topology = NumaTopology::new(vec![vec![ms(0), ms(20)], vec![ms(20), ms(0)]])
+            .with_policy(NumaPolicy::MigrateOnThreshold(3));
+        let ptr = topology.store_on(0, 0u32);
+
+        for i in 1..=3 {
+            assert_eq!(ptr.home_node(), Some(0));
+            let start = Instant::now();
+            *ptr.lock_from(1).get_mut() += 1;
+            assert!(start.elapsed() >= ms(20), "access {i} should have been remote");
+        }
+
+        // It moved after the third and is local from now on.
+        assert_eq!(ptr.home_node(), Some(1));
+        let start = Instant::now();
+        *ptr.lock_from(1).get_mut() += 1;
+        assert!(start.elapsed() < ms(20));
+
+        let stats = ptr.access_stats().unwrap();
+        assert_eq!(stats.accesses, [0, 4]);
+        assert_eq!(stats.migrations, 1);
+
+        ptr.migrate_to(0);
+        assert_eq!(ptr.clone().home_node(), Some(0));
+    }
+
+    #[test]
+    pub fn test_numa_migrate_moves_memory() {
+        let topology = NumaTopology::new(vec![vec![Duration::ZERO; 2]; 2]).with_policy(NumaPolicy::FirstTouch);
+        let ptr = topology.store_on(0, 7u64);
+        let clone = ptr.clone();
+        assert_eq!((topology.memory(0).live_objects(), topology.memory(1).live_objects()), (1, 0));
+
+        // The first touch moves it out of the first node and into the second.
+        *ptr.lock_from(1).get_mut() += 1;
+        assert_eq!((topology.memory(0).live_objects(), topology.memory(1).live_objects()), (0, 1));
+        assert_eq!(topology.memory(1).used(), 8);
+
+        // Every clone follows it there and back.
+        clone.migrate_to(0);
+        assert_eq!((topology.memory(0).live_objects(), topology.memory(1).live_objects()), (1, 0));
+        assert_eq!(*ptr.lock_from(0).get(), 8);
+    }
+
+    #[test]
+    pub fn test_numa_first_touch() {
+        let topology = NumaTopology::new(vec![vec![Duration::ZERO; 3]; 3]).with_policy(NumaPolicy::FirstTouch);
+        let ptr = topology.store_on(0, ());
+        drop(ptr.lock_from(2));
+        drop(ptr.lock_from(1));
+        assert_eq!(ptr.home_node(), Some(2));
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::{BTreeSet, HashMap};
+
+
+/// A run of pages handed out by the [BuddyAllocator].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Block {
+    /// The first page of the block.
+    pub start: usize,
+    /// The block is `2^order` pages long.
+    pub order: u32
+}
+
+impl Block {
+    pub fn pages(&self) -> usize {
+        1 << self.order
+    }
+    /// The block this one was split from alongside.
+    fn buddy(&self) -> usize {
+        self.start ^ self.pages()
+    }
+}
+
+/// The buddy system, this manages `2^max_order` pages and hands out
+/// blocks that are a power of two pages long.
+///
+/// Bigger blocks are split in half until they are the right size and when
+/// a block is freed it is merged back with its buddy if that is free too.
+pub struct BuddyAllocator {
+    /// The start of every free block, by order.
+    free: Vec<BTreeSet<usize>>,
+    /// The order of every block that is handed out, by start.
+    allocated: HashMap<usize, u32>
+}
+
+impl BuddyAllocator {
+    pub fn new(max_order: u32) -> Self {
+        let mut free = vec![BTreeSet::new(); max_order as usize + 1];
+        free[max_order as usize].insert(0);
+        Self {
+            free,
+            allocated: HashMap::new()
+        }
+    }
+    pub fn max_order(&self) -> u32 {
+        (self.free.len() - 1) as u32
+    }
+    /// Allocates `2^order` pages that are next to each other, or none
+    /// if there is no free block that big.
+    pub fn alloc(&mut self, order: u32) -> Option<Block> {
+        // Find the smallest block that is big enough.
+        let from = (order as usize..self.free.len()).find(|f| !self.free[*f].is_empty())?;
+        let start = self.free[from].pop_first().unwrap();
+
+        // Split it until it is the right size, the top halves are free.
+        for split in (order as usize..from).rev() {
+            self.free[split].insert(start + (1 << split));
+        }
+        self.allocated.insert(start, order);
+        Some(Block { start, order })
+    }
+    /// Gives a block back, merging it with its buddy as far as it can.
+    ///
+    /// # Panics
+    /// If the block was not handed out by this allocator or was already freed.
+    pub fn free(&mut self, block: Block) {
+        assert_eq!(
+            self.allocated.remove(&block.start),
+            Some(block.order),
+            "Double free of block {block:?}."
+        );
+        let mut block = block;
+        while block.order < self.max_order() && self.free[block.order as usize].remove(&block.buddy()) {
+            block = Block {
+                start: block.start.min(block.buddy()),
+                order: block.order + 1
+            };
+        }
+        self.free[block.order as usize].insert(block.start);
+    }
+    /// How many free blocks there are of each order.
+    pub fn free_blocks(&self) -> Vec<usize> {
+        self.free.iter().map(|f| f.len()).collect()
+    }
+    /// How many pages are free in total.
+    pub fn free_pages(&self) -> usize {
+        self.free.iter().enumerate().map(|(order, f)| f.len() << order).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BuddyAllocator;
+
+    #[test]
+    pub fn test_buddy_coalescing() {
+        let mut buddy = BuddyAllocator::new(4);
+        let a = buddy.alloc(0).unwrap();
+        assert_eq!(buddy.free_blocks(), [1, 1, 1, 1, 0]);
+
+        let b = buddy.alloc(1).unwrap();
+        let c = buddy.alloc(0).unwrap();
+        let d = buddy.alloc(2).unwrap();
+        assert_eq!((a.start, c.start, b.start, d.start), (0, 1, 2, 4));
+        assert_eq!(buddy.free_blocks(), [0, 0, 0, 1, 0]);
+
+        // Everything merges back into the one block.
+        for block in [c, a, d, b] {
+            buddy.free(block);
+        }
+        assert_eq!(buddy.free_blocks(), [0, 0, 0, 0, 1]);
+        assert_eq!(buddy.free_pages(), 16);
+    }
+
+    #[test]
+    pub fn test_buddy_fragmented() {
+        let mut buddy = BuddyAllocator::new(3);
+        let blocks: Vec<_> = (0..8).map(|_| buddy.alloc(0).unwrap()).collect();
+        // Free every other page, half is free but none of it is together.
+        for block in blocks.iter().step_by(2) {
+            buddy.free(*block);
+        }
+        assert_eq!(buddy.free_pages(), 4);
+        assert_eq!(buddy.free_blocks(), [4, 0, 0, 0]);
+        assert!(buddy.alloc(1).is_none());
+        assert!(buddy.alloc(4).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Double free")]
+    pub fn test_buddy_double_free() {
+        let mut buddy = BuddyAllocator::new(2);
+        let block = buddy.alloc(1).unwrap();
+        buddy.free(block);
+        buddy.free(block);
+    }
+}
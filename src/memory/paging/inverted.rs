@@ -0,0 +1,222 @@
+use std::{collections::HashMap, sync::Arc};
+
+use super::{local::LogicalAddress, pager::{PagePtr, Pager}};
+
+
+/// What is in each frame of an [InvertedPageTable].
+struct Entry {
+    pid: u32,
+    logical_root: u16,
+    /// The next entry with the same hash.
+    next: Option<usize>
+}
+
+/// An inverted page table, there is one entry for every frame saying which
+/// process and logical root is in it rather than one table per process.
+///
+/// Searching every entry would be slow, so the (process, logical root) is
+/// hashed to an anchor which points at a chain of the entries to check.
+///
+/// Only pages in memory have an entry. Like a real system the pages that
+/// are swapped out are kept track of separately, referencing one faults it
+/// back in and it gets the entry of whatever frame it ended up in.
+pub struct InvertedPageTable {
+    /// The frames, the index is the frame number.
+    entries: Vec<Option<Entry>>,
+    /// The first entry of each hash chain.
+    anchors: Vec<Option<usize>>,
+    /// Every page a process has, in memory or not.
+    mapped: HashMap<(u32, u16), PagePtr>,
+
+    /// Pager,
+    pager: Arc<Pager>
+}
+
+impl InvertedPageTable {
+    /// Creates a table with this many hash anchors.
+    ///
+    /// # Panics
+    /// If there are no anchors.
+    pub fn new(pager: Arc<Pager>, anchors: usize) -> Self {
+        assert!(anchors > 0, "There has to be at least one anchor.");
+        Self {
+            entries: (0..pager.frames()).map(|_| None).collect(),
+            anchors: vec![None; anchors],
+            mapped: HashMap::new(),
+            pager
+        }
+    }
+    /// Allocates a page to a process and will return a [LogicalAddress].
+    pub fn alloc(&mut self, pid: u32) -> LogicalAddress {
+        let (real, logical) = LogicalAddress::create(self.pager.alloc());
+        self.map(pid, logical, real);
+        logical
+    }
+    /// Allocates a page to a process at a chosen logical root.
+    pub fn alloc_at(&mut self, pid: u32, root: u16) -> LogicalAddress {
+        let (real, logical) = LogicalAddress::create_at(self.pager.alloc(), root);
+        self.map(pid, logical, real);
+        logical
+    }
+    fn map(&mut self, pid: u32, logical: LogicalAddress, real_root: u16) {
+        let page = logical.translate(real_root, self.pager.as_ref());
+        self.mapped.insert((pid, logical.logical_root()), page);
+        self.load(pid, logical.logical_root());
+    }
+    /// Gives a page that was just brought in the entry for its frame.
+    fn load(&mut self, pid: u32, logical_root: u16) {
+        // Bringing it in may have pushed out pages that still have entries.
+        self.drop_evicted();
+        let frame = self.pager.frame_of(&self.mapped[&(pid, logical_root)]).expect("The page was just brought in.");
+        let anchor = self.anchor(pid, logical_root);
+        // The new frame goes on the front of the chain.
+        self.entries[frame] = Some(Entry {
+            pid,
+            logical_root,
+            next: self.anchors[anchor]
+        });
+        self.anchors[anchor] = Some(frame);
+    }
+    /// Takes out the entries of frames that no longer hold their page.
+    fn drop_evicted(&mut self) {
+        for frame in 0..self.entries.len() {
+            if self.entries[frame].is_some() && !self.holds(frame) {
+                self.unlink(frame);
+            }
+        }
+    }
+    /// If the frame still holds the page its entry says it does.
+    fn holds(&self, frame: usize) -> bool {
+        let Some(entry) = &self.entries[frame] else {
+            return false;
+        };
+        let page = &self.mapped[&(entry.pid, entry.logical_root)];
+        self.pager.frame_of(page) == Some(frame)
+    }
+    /// Removes the entry of a frame from its chain.
+    fn unlink(&mut self, frame: usize) {
+        let entry = self.entries[frame].take().unwrap();
+        let anchor = self.anchor(entry.pid, entry.logical_root);
+        if self.anchors[anchor] == Some(frame) {
+            self.anchors[anchor] = entry.next;
+            return;
+        }
+        let mut current = self.anchors[anchor];
+        while let Some(index) = current {
+            let before = self.entries[index].as_mut().unwrap();
+            if before.next == Some(frame) {
+                before.next = entry.next;
+                return;
+            }
+            current = before.next;
+        }
+    }
+    fn anchor(&self, pid: u32, logical_root: u16) -> usize {
+        (pid as usize * 31 + logical_root as usize) % self.anchors.len()
+    }
+    /// Finds the frame a process has a logical root in, none if
+    /// it is swapped out.
+    pub fn frame(&self, pid: u32, addr: LogicalAddress) -> Option<usize> {
+        let logical_root = addr.logical_root();
+        let mut current = self.anchors[self.anchor(pid, logical_root)];
+        while let Some(index) = current {
+            let entry = self.entries[index].as_ref().unwrap();
+            if entry.pid == pid && entry.logical_root == logical_root {
+                return self.holds(index).then_some(index);
+            }
+            current = entry.next;
+        }
+        None
+    }
+    /// Performs a page reference for a process, if the page is not in a
+    /// frame it is faulted back in.
+    ///
+    /// # Panics
+    /// If the process does not have the address mapped.
+    pub fn reference(&mut self, pid: u32, addr: LogicalAddress) -> PagePtr {
+        let page = self
+            .mapped
+            .get(&(pid, addr.logical_root()))
+            .unwrap_or_else(|| panic!("Process {pid} does not have the address mapped."))
+            .clone();
+        if self.frame(pid, addr).is_none() {
+            // Page fault, touching it brings it back in.
+            page.read(0..0);
+            self.load(pid, addr.logical_root());
+        }
+        page
+    }
+    /// How long the chain from each anchor is.
+    pub fn chain_lengths(&self) -> Vec<usize> {
+        self.anchors
+            .iter()
+            .map(|anchor| {
+                let mut length = 0;
+                let mut current = *anchor;
+                while let Some(index) = current {
+                    length += 1;
+                    current = self.entries[index].as_ref().unwrap().next;
+                }
+                length
+            })
+            .collect()
+    }
+    /// The longest chain a lookup might have to walk.
+    pub fn longest_chain(&self) -> usize {
+        self.chain_lengths().into_iter().max().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::memory::paging::pager::Pager;
+
+    use super::InvertedPageTable;
+
+    #[test]
+    pub fn test_inverted_page_table() {
+        let pager = Arc::new(Pager::new(2));
+        // One anchor so everything collides.
+        let mut table = InvertedPageTable::new(Arc::clone(&pager), 1);
+        let first = table.alloc_at(1, 0x40);
+        let second = table.alloc_at(2, 0x40);
+        assert_eq!(first.logical_root(), second.logical_root());
+
+        // The same logical root is a different frame for each process.
+        assert_ne!(table.frame(1, first), table.frame(2, second));
+        table.reference(1, first)[0] = 1;
+        table.reference(2, second)[0] = 2;
+        assert!(table.frame(3, first).is_none());
+        assert_eq!(table.chain_lengths(), [2]);
+
+        // Push them both out, there are only two frames so only two entries.
+        let third = table.alloc_at(1, 0x41);
+        table.reference(1, third)[0] = 3;
+        let fourth = table.alloc(2);
+        assert!(pager.dirty_evictions() >= 2);
+        assert!(table.frame(1, first).is_none());
+        assert!(table.frame(2, second).is_none());
+        assert_eq!(table.chain_lengths(), [2]);
+        let frames = [table.frame(1, third), table.frame(2, fourth)];
+        assert!(frames.iter().all(|f| f.is_some_and(|f| f < 2)));
+
+        // Faulting them back in takes the frames over.
+        assert_eq!(table.reference(1, first)[0], 1);
+        assert_eq!(table.reference(2, second)[0], 2);
+        let mut refaulted = [table.frame(1, first), table.frame(2, second)];
+        refaulted.sort();
+        assert_eq!(refaulted, [Some(0), Some(1)]);
+        assert!(table.frame(1, third).is_none());
+        assert_eq!(table.reference(1, third)[0], 3);
+        assert_eq!(table.longest_chain(), 2);
+
+        // More anchors spread them out.
+        let mut spread = InvertedPageTable::new(pager, 8);
+        for root in 0..4 {
+            spread.alloc_at(1, root);
+        }
+        assert_eq!(spread.longest_chain(), 1);
+    }
+}
@@ -1,14 +1,13 @@
-use rand::random;
-
 use super::pager::{PagePtr, Pager};
 
 
-/// The logical address has the first 6 bits set
-/// to the page number and then the last 10 bits
+/// The logical address has the offset into the page in the
+/// last 12 bits, then the logical root in the 16 bits above that
+/// and the rest of the page address above those.
 /// 
 /// This is designed for little endian.
 #[derive(Clone, Copy, PartialEq)]
-pub struct LogicalAddress(usize);
+pub struct LogicalAddress(u128);
 
 impl LogicalAddress {
     /// Creates a new logical address from a page pointer, it will
@@ -16,8 +15,11 @@ impl LogicalAddress {
     /// logical address.
     pub fn create(page: PagePtr) -> (u16, Self) {
         // Generate a local header address.
-        let local_root: u16 = random();
-
+        let local_root: u16 = page.rng().gen();
+        Self::create_at(page, local_root)
+    }
+    /// Creates a logical address with a chosen logical root.
+    pub fn create_at(page: PagePtr, local_root: u16) -> (u16, Self) {
         let page_number = page.addr() as usize;
 
         // Extract the real root.
@@ -28,17 +30,28 @@ impl LogicalAddress {
         let logical = (page_number & clear_mask) | (local_root as usize);
 
 
-        (real_root, Self(logical))
+        (real_root, Self((logical as u128) << 12))
+    }
+    /// The same address at a different byte of the page.
+    ///
+    /// # Panics
+    /// If the offset is past the end of the page.
+    pub fn with_offset(page: LogicalAddress, offset: u16) -> Self {
+        assert!(offset < 4096, "The offset {offset} is past the end of the page.");
+        Self((page.0 & !0xFFF) | offset as u128)
+    }
+    /// Extracts the offset into the page.
+    pub fn offset(&self) -> u16 {
+        (self.0 & 0xFFF) as u16
     }
     /// Extracts the logical root.
     pub fn logical_root(&self) -> u16 {
-        let clear_mask = (!0 as usize) << 16;
-        (self.0 & !clear_mask) as u16
+        (self.0 >> 12) as u16
     }
     /// Translate the local address ino an actual address with the real rooot.
     pub fn translate(&self, real_root: u16, pager: &Pager) -> PagePtr {
         // Recreate the actual pointer address.
-        let real = (self.0 & ((!0 as usize) << 16)) | (real_root as usize);
+        let real = ((self.0 >> 12) as usize & ((!0 as usize) << 16)) | (real_root as usize);
         unsafe { PagePtr::from_raw(real, pager) }
     }
 }
@@ -59,4 +72,24 @@ mod tests {
         
         assert_eq!(page_ptr, log.translate(root, &allocator));
     }
+
+    #[test]
+    pub fn test_logical_address_offset() {
+        let allocator = Pager::new(1);
+        let (root, page) = LogicalAddress::create(allocator.alloc());
+        let addr = LogicalAddress::with_offset(page, 4095);
+        assert_eq!(addr.offset(), 4095);
+        assert_eq!(page.offset(), 0);
+        assert_eq!(addr.logical_root(), page.logical_root());
+        assert_eq!(addr.translate(root, &allocator), page.translate(root, &allocator));
+        assert_eq!(LogicalAddress::with_offset(addr, 3).offset(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "past the end of the page")]
+    pub fn test_logical_address_offset_range() {
+        let allocator = Pager::new(1);
+        let (_, page) = LogicalAddress::create(allocator.alloc());
+        LogicalAddress::with_offset(page, 4096);
+    }
 }
\ No newline at end of file
@@ -1,10 +1,17 @@
 use std::{ops::{Index, IndexMut}, slice::SliceIndex};
 
-use rand::random;
+use crate::rng::SimRng;
 
 pub mod local;
 pub mod table;
+pub mod multilevel;
+pub mod inverted;
+pub mod buddy;
+pub mod slab;
 pub mod pager;
+pub mod replacement;
+mod swap;
+pub mod tlb;
 
 pub struct Page {
     /// This is the page number, we only use the first six bits of this.
@@ -14,9 +21,9 @@ pub struct Page {
 }
 
 impl Page {
-    pub fn alloc() -> *const Self {
+    pub fn alloc(rng: &SimRng) -> *const Self {
         Box::leak(Box::new(Self {
-            page_number: random(),
+            page_number: rng.gen(),
             data: [0u8; 4096]
         })) as *const Self
     }
@@ -58,10 +65,15 @@ impl PageAllocator {
     /// [Page]. This is very unsafe but again this is for demonstration
     /// purposes.
     pub fn new(pages: usize) -> Self {
+        Self::with_rng(pages, &SimRng::global())
+    }
+    /// Creates a new [PageAllocator] drawing the page numbers from
+    /// the given generator.
+    pub fn with_rng(pages: usize, rng: &SimRng) -> Self {
         let mut page_list = vec![];
         let mut free_pages = vec![];
         for _ in 0..pages {
-            let ptr = Page::alloc();
+            let ptr = Page::alloc(rng);
             page_list.push(ptr);
             free_pages.push(ptr);
         }
@@ -70,12 +82,34 @@ impl PageAllocator {
             free_pages
         }
     }
+    /// The same as [PageAllocator::available].
     pub fn pages(&self) -> usize {
+        self.available()
+    }
+    /// How many pages are free.
+    pub fn available(&self) -> usize {
         self.free_pages.len()
     }
-    
-    pub fn acquire(&mut self) -> *const Page {
-        self.free_pages.pop().unwrap()
+    /// How many pages there are in total, free or not.
+    pub fn total(&self) -> usize {
+        self.page_list.len()
+    }
+    /// Which frame a page is, counting from zero.
+    pub fn frame_number(&self, page: *const Page) -> Option<usize> {
+        self.page_list.iter().position(|f| *f == page)
+    }
+    /// Takes a free page, or none if they are all in use.
+    pub fn acquire(&mut self) -> Option<*const Page> {
+        self.free_pages.pop()
+    }
+    /// Takes n free pages at once, if there are not that
+    /// many free then none are taken.
+    pub fn acquire_n(&mut self, n: usize) -> Option<Vec<*const Page>> {
+        if n > self.free_pages.len() {
+            return None;
+        }
+        let start = self.free_pages.len() - n;
+        Some(self.free_pages.split_off(start))
     }
     pub fn release(&mut self, page: *const Page) {
         // Zero the page.
@@ -104,7 +138,7 @@ mod tests {
     pub fn test_page_alloc() {
         // initialize a page allocator
         let mut alloc = PageAllocator::new(1);
-        let acquire = alloc.acquire();
+        let acquire = alloc.acquire().unwrap();
         let array = unsafe { &mut (*acquire.cast_mut()) };
 
         // Now we have an array we can mess with.
@@ -115,9 +149,37 @@ mod tests {
         alloc.release(acquire);
 
         // acquire the page again, should be zeroed
-        alloc.acquire();
+        alloc.acquire().unwrap();
         // this is still a valid reference (again this is why this is unsafe lol)
         assert_eq!(array[0], 0);
 
     }
+
+    #[test]
+    pub fn test_page_alloc_exhausted() {
+        let mut alloc = PageAllocator::new(3);
+        assert_eq!(alloc.total(), 3);
+
+        let pages: Vec<_> = (0..3).map(|_| alloc.acquire().unwrap()).collect();
+        assert_eq!(alloc.available(), 0);
+        assert!(alloc.acquire().is_none());
+
+        // Once one is back it can be handed out again.
+        alloc.release(pages[1]);
+        assert_eq!(alloc.acquire(), Some(pages[1]));
+        assert_eq!(alloc.total(), 3);
+    }
+
+    #[test]
+    pub fn test_page_alloc_n() {
+        let mut alloc = PageAllocator::new(4);
+        let first = alloc.acquire_n(3).unwrap();
+        assert_eq!(first.len(), 3);
+
+        // It is all or nothing.
+        assert!(alloc.acquire_n(2).is_none());
+        assert_eq!(alloc.available(), 1);
+        assert_eq!(alloc.acquire_n(1).unwrap().len(), 1);
+        assert_eq!(alloc.acquire_n(0), Some(vec![]));
+    }
 }
\ No newline at end of file
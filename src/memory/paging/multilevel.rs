@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use super::{local::LogicalAddress, pager::{PagePtr, Pager}};
+
+
+/// Each inner table covers this many logical roots.
+const INNER_ENTRIES: usize = 256;
+
+/// An inner table, an entry is the real root of a page.
+type InnerTable = [Option<u16>; INNER_ENTRIES];
+
+/// A two level [PageTable](super::table::PageTable), the top byte of the
+/// logical root picks an inner table and the bottom byte the entry in it.
+///
+/// Inner tables are only made when something is mapped into them, so a
+/// sparse address space only pays for the parts it uses.
+pub struct MultiLevelPageTable {
+    outer: Vec<Option<Box<InnerTable>>>,
+
+    /// Pager,
+    pager: Arc<Pager>
+}
+
+impl MultiLevelPageTable {
+    pub fn new(pager: Arc<Pager>) -> Self {
+        Self {
+            outer: vec![None; INNER_ENTRIES],
+            pager
+        }
+    }
+    /// Allocates a page to the local process and will return a [LogicalAddress]
+    /// in a real machine this would be a system call.
+    pub fn alloc(&mut self) -> LogicalAddress {
+        let (real, logical) = LogicalAddress::create(self.pager.alloc());
+        self.map(logical, real);
+        logical
+    }
+    /// Allocates a page at a chosen logical root.
+    pub fn alloc_at(&mut self, root: u16) -> LogicalAddress {
+        let (real, logical) = LogicalAddress::create_at(self.pager.alloc(), root);
+        self.map(logical, real);
+        logical
+    }
+    fn map(&mut self, logical: LogicalAddress, real: u16) {
+        let (outer, inner) = split(logical.logical_root());
+        let table = self.outer[outer].get_or_insert_with(|| Box::new([None; INNER_ENTRIES]));
+        table[inner] = Some(real);
+    }
+    /// Performs a page reference. Needless to say this is incredibly unsafe.
+    ///
+    /// # Panics
+    /// If the address is not mapped.
+    pub fn reference(&self, ptr: LogicalAddress) -> PagePtr {
+        let (outer, inner) = split(ptr.logical_root());
+        let real = self.outer[outer]
+            .as_ref()
+            .and_then(|f| f[inner])
+            .expect("The address is not mapped.");
+        ptr.translate(real, self.pager.as_ref())
+    }
+    /// Reads the byte the address points to.
+    pub fn read_byte(&self, addr: LogicalAddress) -> u8 {
+        self.reference(addr).read(addr.offset() as usize..addr.offset() as usize + 1)[0]
+    }
+    /// Writes the byte the address points to.
+    pub fn write_byte(&self, addr: LogicalAddress, value: u8) {
+        self.reference(addr).write(addr.offset() as usize, &[value]);
+    }
+    /// How many inner tables have been made.
+    pub fn allocated_inner_tables(&self) -> usize {
+        self.outer.iter().filter(|f| f.is_some()).count()
+    }
+}
+
+/// Splits a logical root into the outer and inner index.
+fn split(root: u16) -> (usize, usize) {
+    ((root >> 8) as usize, (root & 0xFF) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::memory::paging::{local::LogicalAddress, pager::Pager};
+
+    use super::MultiLevelPageTable;
+
+    #[test]
+    pub fn test_multilevel_page_table() {
+        let mut table = MultiLevelPageTable::new(Arc::new(Pager::new(4)));
+        assert_eq!(table.allocated_inner_tables(), 0);
+
+        // Next to each other only needs the one.
+        let first = table.alloc_at(0x1200);
+        let second = table.alloc_at(0x1201);
+        assert_eq!(table.allocated_inner_tables(), 1);
+
+        // Far apart needs another.
+        let far = table.alloc_at(0xF000);
+        assert_eq!(table.allocated_inner_tables(), 2);
+
+        table.write_byte(LogicalAddress::with_offset(first, 5), 1);
+        table.reference(second)[5] = 2;
+        table.write_byte(LogicalAddress::with_offset(far, 5), 3);
+        assert_eq!(table.reference(first)[5], 1);
+        assert_eq!(table.read_byte(LogicalAddress::with_offset(second, 5)), 2);
+        assert_eq!(table.read_byte(LogicalAddress::with_offset(far, 5)), 3);
+    }
+}
@@ -1,9 +1,11 @@
-use std::{collections::HashMap, fmt::Debug, hash::Hash, ops::{Index, IndexMut}, slice::SliceIndex, sync::{Arc, Weak}};
+use std::{collections::{HashMap, HashSet}, fmt::Debug, hash::Hash, ops::{Index, IndexMut}, slice::SliceIndex, sync::{Arc, Weak}};
 
 use parking_lot::Mutex;
-use rand::random;
+use crate::rng::SimRng;
 
-use super::{Page, PageAllocator};
+use crate::disks::StorageDevice;
+
+use super::{replacement::{Replacement, ReplacementPolicy}, swap::Swap, Page, PageAllocator};
 
 
 
@@ -17,90 +19,215 @@ struct PagerInternal {
     valid: Vec<(RawPagePtr, bool)>,
 
 
-    /// Pager clock, this is what perfoms LRU
+    /// Pager clock, this ticks on every reference.
     pager_clock: u128,
-    lru_map: HashMap<RawPagePtr, u128>,
+    /// Decides what gets swapped out.
+    replacement: Replacement,
+    /// How many times a page was not in memory when it was referenced.
+    faults: usize,
+    /// When each page was last referenced on the pager clock.
+    last_referenced: HashMap<RawPagePtr, u128>,
+    /// How many ticks back the working set looks.
+    window: u128,
 
 
     /// This translates pointers into actual page pointers.
     translation: HashMap<RawPagePtr, *const Page>,
 
 
-    /// To keep things simple, we will just keep the swap space in here.
-    swap: HashMap<RawPagePtr, [u8; 4096]>
+    /// Where pages go when they are swapped out.
+    swap: Swap,
+    /// The pages in memory that were written to since they were swapped in,
+    /// only these need to be copied to swap when they get swapped out.
+    dirty: HashSet<RawPagePtr>,
+    dirty_evictions: usize,
+    clean_evictions: usize,
+
+    /// The page tables sharing each page, the page is write
+    /// protected for all of them until they copy it.
+    shared: HashMap<RawPagePtr, HashSet<usize>>,
+    /// Where a page table's view of a shared page went after it wrote to it.
+    moved: HashMap<(usize, RawPagePtr), RawPagePtr>,
+    /// How many page tables have been made for this pager.
+    tables: usize,
+    cow_breaks: usize,
+
+    /// Which page table each page belongs to.
+    owners: HashMap<RawPagePtr, usize>,
+    /// The most frames each page table may have.
+    rss_limits: HashMap<usize, usize>,
+    /// How many times each page table had to swap out its own page.
+    local_evictions: HashMap<usize, usize>,
+
+    /// Where the page pointers are drawn from.
+    rng: SimRng
 }
 
+// The frames behind the raw pointers belong to the allocator in here and
+// are only ever touched with the pager locked, so it can move between threads.
+unsafe impl Send for PagerInternal {}
+
 impl PagerInternal {
     pub fn new(pages: usize) -> Self {
+        Self::with_rng(pages, SimRng::global())
+    }
+    pub fn with_rng(pages: usize, rng: SimRng) -> Self {
         let pager = Self {
-            allocator: PageAllocator::new(pages),
+            allocator: PageAllocator::with_rng(pages, &rng),
             valid: Vec::new(),
             pager_clock: 0,
-            lru_map: HashMap::new(),
+            replacement: Replacement::new(ReplacementPolicy::default()),
+            faults: 0,
+            last_referenced: HashMap::new(),
+            window: 10,
             translation: HashMap::new(),
-            swap: HashMap::new()
+            swap: Swap::Memory(HashMap::new()),
+            dirty: HashSet::new(),
+            dirty_evictions: 0,
+            clean_evictions: 0,
+            shared: HashMap::new(),
+            moved: HashMap::new(),
+            tables: 0,
+            cow_breaks: 0,
+            owners: HashMap::new(),
+            rss_limits: HashMap::new(),
+            local_evictions: HashMap::new(),
+            rng
         };
         pager
     }
 
     /// Selects a page for swapping.
     fn select_for_swap(&mut self) -> RawPagePtr {
-        // Find the least recently used page.
-        let page = *self.lru_map.iter_mut().min_by_key(|(_, v)| **v).unwrap().0;
-        self.lru_map.remove(&page);
-
-        // Change the validity bit.
-        let (_, valid) = self.valid.iter_mut().find(|(p, _)| *p == page).unwrap();
-        *valid = false;
-        
-    
-        page
+        // Ask the policy which of the pages in memory should go.
+        let resident: Vec<_> = self.valid.iter().filter(|(_, v)| *v).map(|(p, _)| *p).collect();
+        self.replacement.select(&resident, &self.dirty)
     }
-    /// Will swap a page out of memory.
-    pub fn swap_out(&mut self) -> *const Page {
+    /// Will swap a page out of memory to make room for the incoming one.
+    pub fn swap_out(&mut self, incoming: RawPagePtr) -> *const Page {
         // old page
         let old = self.select_for_swap();
+        self.evict(old, incoming)
+    }
+    /// Takes a page out of memory and returns the frame it was in.
+    fn evict(&mut self, old: RawPagePtr, incoming: RawPagePtr) -> *const Page {
+        self.set_valid(old, false);
         // get the actual pointer
         let actual = self.translation.remove(&old).unwrap();
-        // store this in the swap.
-        let page_data = unsafe { (*actual).data.clone() };
-        self.swap.insert(old, page_data);
+        // Only store this in the swap if it changed, otherwise the swap
+        // already has a copy or it was never written and is all zeros.
+        if self.dirty.remove(&old) {
+            let page_data = unsafe { (*actual).data.clone() };
+            // If the swap is full a page in memory gives up its copy, so it
+            // has to be written out again next time.
+            let valid = &self.valid;
+            let reclaimed = self
+                .swap
+                .put(old, &page_data, |page| reclaimable(valid, incoming, page))
+                .expect("There is always a slot, new pages are refused before the swap can fill up.");
+            if let Some(page) = reclaimed {
+                self.dirty.insert(page);
+            }
+            self.dirty_evictions += 1;
+        } else {
+            self.clean_evictions += 1;
+        }
         // zero the old page.
         unsafe { (*actual.cast_mut()).data.fill(0); }
         actual
     }
-    pub fn new_page(&mut self) -> RawPagePtr {
-        let ptr = RawPagePtr(random());
-
-        // Fill the LRU map.
-        self.lru_map.insert(ptr, self.pager_clock);
+    /// Gets a frame to put a page in, swapping one out if they are all used.
+    fn frame(&mut self, incoming: RawPagePtr) -> *const Page {
+        if let Some(victim) = self.local_victim(incoming) {
+            self.replacement.removed(victim);
+            return self.evict(victim, incoming);
+        }
+        match self.allocator.acquire() {
+            Some(page) => page,
+            None => self.swap_out(incoming)
+        }
+    }
+    /// If the page table the incoming page belongs to is at its limit
+    /// it has to give up the page it used the longest ago.
+    fn local_victim(&mut self, incoming: RawPagePtr) -> Option<RawPagePtr> {
+        let owner = *self.owners.get(&incoming)?;
+        let limit = *self.rss_limits.get(&owner)?;
+        let resident = self.resident_pages(owner);
+        if resident.len() < limit {
+            return None;
+        }
+        let victim = resident.into_iter().min_by_key(|f| self.last_referenced[f])?;
+        // With frames still free more pages can be out of memory than the swap has
+        // slots for, if it can't take the victim a free frame is used instead.
+        let fits = !self.dirty.contains(&victim) || self.swap.has_slot(victim, |page| reclaimable(&self.valid, incoming, page));
+        if !fits {
+            return None;
+        }
+        *self.local_evictions.entry(owner).or_default() += 1;
+        Some(victim)
+    }
+    /// The pages of a page table that are in memory.
+    fn resident_pages(&self, table: usize) -> Vec<RawPagePtr> {
+        self.valid
+            .iter()
+            .filter(|(p, v)| *v && self.owners.get(p) == Some(&table))
+            .map(|(p, _)| *p)
+            .collect()
+    }
+    /// Ticks the clock and tells the policy about a reference.
+    fn tick(&mut self, ptr: RawPagePtr) {
+        self.replacement.referenced(ptr, self.pager_clock);
+        self.last_referenced.insert(ptr, self.pager_clock);
         self.pager_clock += 1;
-
-        if self.allocator.pages() != 0 {
-            // We have an actual page that is ready to be
-            // directly allocated.
-            self.valid.push((ptr, true));
-            self.translation.insert(ptr, self.allocator.acquire());
-        } else {
-            // We need to swap out a current frame.
-            let page = self.swap_out();
-            self.translation.insert(ptr, page);
-            self.valid.push((ptr, true));
+    }
+    /// The pages referenced within the window.
+    fn working_set(&self) -> impl Iterator<Item = RawPagePtr> + '_ {
+        let since = self.pager_clock.saturating_sub(self.window);
+        self.last_referenced.iter().filter(move |(_, t)| **t >= since).map(|(p, _)| *p)
+    }
+    pub fn new_page(&mut self, owner: Option<usize>) -> Result<RawPagePtr, OutOfPages> {
+        self.has_room()?;
+        let ptr = RawPagePtr(self.rng.gen());
+        if let Some(owner) = owner {
+            self.owners.insert(ptr, owner);
+        }
+        self.insert_page(ptr);
+        Ok(ptr)
+    }
+    /// Checks there is somewhere for another page to go.
+    fn has_room(&self) -> Result<(), OutOfPages> {
+        let Some(swap_slots) = self.swap.slots() else {
+            return Ok(());
+        };
+        let frames = self.allocator.total();
+        if self.valid.len() >= frames + swap_slots {
+            return Err(OutOfPages { frames, swap_slots });
         }
-        ptr
+        Ok(())
+    }
+    fn insert_page(&mut self, ptr: RawPagePtr) {
+        // A new page is never in memory yet.
+        self.replacement.allocated(ptr);
+        self.tick(ptr);
+        self.faults += 1;
+
+        let page = self.frame(ptr);
+        self.translation.insert(ptr, page);
+        self.valid.push((ptr, true));
+        self.replacement.loaded(ptr);
     }
     fn is_valid(&self, ptr: RawPagePtr) -> bool {
-        self.valid.iter().find(|(a, _)| *a == ptr).unwrap().1
+        match self.valid.iter().find(|(a, _)| *a == ptr) {
+            Some((_, valid)) => *valid,
+            None => panic!("Use after free of page {:?}.", ptr)
+        }
     }
     pub fn set_valid(&mut self, ptr: RawPagePtr, is_valid: bool) {
         let (_, valid) = self.valid.iter_mut().find(|(a, _)| *a == ptr).unwrap();
         *valid = is_valid;
     }
     pub fn refer<'b>(&mut self, ptr: RawPagePtr) -> *mut [u8; 4096] {
-        
-        // Update the LRU cache.
-        self.lru_map.insert(ptr, self.pager_clock);
-        self.pager_clock += 1;
+        self.tick(ptr);
 
         if self.is_valid(ptr) {
             // The reference is in memory.
@@ -108,22 +235,97 @@ impl PagerInternal {
             unsafe { &mut (*translated.cast_mut()).data }
         } else {
             // The reference is not in memory.
-            let page = self.swap_out();
-         
-            // Get the swap of the old page.
-            let swap = self.swap.remove(&ptr).unwrap();
+            self.faults += 1;
+
+            // Get the swap of the old page, we keep it around in
+            // case this page gets swapped out again without changing.
+            let swap = self.swap.get(ptr).unwrap_or([0; 4096]);
+            let page = self.frame(ptr);
 
             // Restore the old page contents.
             unsafe { &mut (*page.cast_mut()).data }.copy_from_slice(&swap);
 
             self.set_valid(ptr, true);
             self.translation.insert(ptr, page);
+            self.replacement.loaded(ptr);
             unsafe { &mut (*page.cast_mut()).data }
         }
 
     }
+    /// References a page that is about to be written to.
+    pub fn refer_mut(&mut self, ptr: RawPagePtr) -> *mut [u8; 4096] {
+        let page = self.refer(ptr);
+        self.dirty.insert(ptr);
+        page
+    }
+    /// Finds where a page table's view of a page is now.
+    fn resolve(&self, ptr: RawPagePtr, table: Option<usize>) -> RawPagePtr {
+        table.and_then(|t| self.moved.get(&(t, ptr))).copied().unwrap_or(ptr)
+    }
+    /// Gets a page ready for a page table to write to it, if the page is
+    /// shared this is a protection fault and the table gets its own copy.
+    fn write(&mut self, ptr: RawPagePtr, table: Option<usize>) -> RawPagePtr {
+        let ptr = self.resolve(ptr, table);
+        let (Some(table), Some(sharers)) = (table, self.shared.get_mut(&ptr)) else {
+            return ptr;
+        };
+        if !sharers.remove(&table) {
+            return ptr;
+        }
+        if sharers.len() < 2 {
+            // Whoever is left has it all to themselves.
+            self.shared.remove(&ptr);
+        }
+
+        self.has_room().expect("Out of memory.");
+        let data = unsafe { *self.refer(ptr) };
+        // The copy has to keep the top of the address so the
+        // logical addresses still translate to it.
+        let copy = loop {
+            let copy = RawPagePtr((ptr.0 & !0xFFFF) | self.rng.gen::<u16>() as usize);
+            if !self.valid.iter().any(|(a, _)| *a == copy) {
+                break copy;
+            }
+        };
+        self.owners.insert(copy, table);
+        self.insert_page(copy);
+        unsafe { (*self.translation[&copy].cast_mut()).data = data };
+        self.dirty.insert(copy);
+        self.moved.insert((table, ptr), copy);
+        self.cow_breaks += 1;
+        copy
+    }
+    /// Forgets about a page and gives its frame back to the allocator.
+    pub fn free(&mut self, ptr: RawPagePtr) {
+        let Some(position) = self.valid.iter().position(|(a, _)| *a == ptr) else {
+            panic!("Double free of page {:?}.", ptr);
+        };
+        self.valid.remove(position);
+        if let Some(page) = self.translation.remove(&ptr) {
+            self.allocator.release(page);
+        }
+        self.swap.remove(ptr);
+        self.dirty.remove(&ptr);
+        self.last_referenced.remove(&ptr);
+        self.shared.remove(&ptr);
+        self.owners.remove(&ptr);
+        self.moved.retain(|(_, from), to| *from != ptr && *to != ptr);
+        self.replacement.removed(ptr);
+    }
+}
+
+/// Whether a page can give up its copy in the swap, this is any page in
+/// memory and the one coming in, which is about to get a frame.
+fn reclaimable(valid: &[(RawPagePtr, bool)], incoming: RawPagePtr, page: RawPagePtr) -> bool {
+    page == incoming || valid.iter().any(|(p, v)| *p == page && *v)
 }
 
+/// There are no free frames or swap slots left.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutOfPages {
+    pub frames: usize,
+    pub swap_slots: usize
+}
 
 /// This is the public API for the pager, it wraps
 /// it around an [Arc] for better ergonomics, it is
@@ -134,27 +336,192 @@ pub struct Pager {
 
 impl Pager {
     pub fn new(pages: usize) -> Self {
+        Self::from_internal(PagerInternal::new(pages))
+    }
+    /// Creates a pager that draws all of its page numbers and
+    /// addresses from the given generator.
+    pub fn with_rng(pages: usize, rng: SimRng) -> Self {
+        Self::from_internal(PagerInternal::with_rng(pages, rng))
+    }
+    /// Creates a pager that swaps pages out with the given policy.
+    pub fn new_with_policy(pages: usize, policy: ReplacementPolicy) -> Self {
+        let mut internal = PagerInternal::new(pages);
+        internal.replacement = Replacement::new(policy);
+        Self::from_internal(internal)
+    }
+    /// Creates a pager that swaps out to a device, which is split into
+    /// the given number of page sized slots.
+    ///
+    /// The device needs to be at least `slots * 4096` bytes.
+    pub fn with_swap(pages: usize, swap: impl StorageDevice + Send + 'static, slots: usize) -> Self {
+        let mut internal = PagerInternal::new(pages);
+        internal.swap = Swap::device(swap, slots);
+        Self::from_internal(internal)
+    }
+    fn from_internal(internal: PagerInternal) -> Self {
         Self {
-            internal: Arc::new(Mutex::new(PagerInternal::new(pages)))
+            internal: Arc::new(Mutex::new(internal))
         }
     }
+    /// How many page faults there have been, this includes
+    /// the first reference to every page.
+    pub fn faults(&self) -> usize {
+        self.internal.lock().faults
+    }
+    /// How many pages had to be written to swap when they were swapped out.
+    pub fn dirty_evictions(&self) -> usize {
+        self.internal.lock().dirty_evictions
+    }
+    /// How many pages were swapped out without writing them to swap.
+    pub fn clean_evictions(&self) -> usize {
+        self.internal.lock().clean_evictions
+    }
+    /// Sets how many ticks of the pager clock the working set looks
+    /// back over, by default this is 10.
+    pub fn with_window(self, window: u128) -> Self {
+        self.internal.lock().window = window;
+        self
+    }
+    /// The pages that were referenced within the window.
+    pub fn working_set(&self) -> Vec<PagePtr> {
+        self.internal
+            .lock()
+            .working_set()
+            .map(|f| PagePtr(f, Arc::downgrade(&self.internal), None))
+            .collect()
+    }
+    /// If the working set does not fit in memory, in which case
+    /// most of the time is spent swapping.
+    pub fn is_thrashing(&self) -> bool {
+        let internal = self.internal.lock();
+        internal.working_set().count() > internal.allocator.total()
+    }
+    /// The faults per tick of the pager clock.
+    pub fn fault_rate(&self) -> f64 {
+        let internal = self.internal.lock();
+        if internal.pager_clock == 0 {
+            return 0.0;
+        }
+        internal.faults as f64 / internal.pager_clock as f64
+    }
+    /// The (referenced, dirty) class of the last page swapped out, this is
+    /// only known with [ReplacementPolicy::EnhancedClock].
+    pub fn last_eviction_class(&self) -> Option<(bool, bool)> {
+        self.internal.lock().replacement.last_class()
+    }
+    /// How many times a page table wrote to a shared page and had to copy it.
+    pub fn cow_breaks(&self) -> usize {
+        self.internal.lock().cow_breaks
+    }
+    /// Allocates a page.
+    ///
+    /// # Panics
+    /// If there are no frames or swap slots left.
     pub fn alloc(&self) -> PagePtr {
-        let raw = self.internal.lock().new_page();
-        PagePtr(raw, Arc::downgrade(&self.internal))
+        self.try_alloc().expect("Out of memory.")
+    }
+    /// Allocates a page if there is anywhere to put it.
+    pub fn try_alloc(&self) -> Result<PagePtr, OutOfPages> {
+        let raw = self.internal.lock().new_page(None)?;
+        Ok(PagePtr(raw, Arc::downgrade(&self.internal), None))
+    }
+    /// Allocates a page that belongs to a page table.
+    pub(super) fn alloc_for_table(&self, table: usize) -> PagePtr {
+        let raw = self.internal.lock().new_page(Some(table)).expect("Out of memory.");
+        PagePtr(raw, Arc::downgrade(&self.internal), Some(table))
+    }
+    /// Caps how many frames the pages of a page table can use.
+    pub(super) fn set_rss_limit(&self, table: usize, frames: usize) {
+        self.internal.lock().rss_limits.insert(table, frames);
+    }
+    /// How many of the pages of a page table are in memory.
+    pub(super) fn resident_pages(&self, table: usize) -> usize {
+        self.internal.lock().resident_pages(table).len()
+    }
+    /// How many times a page table had to swap out its own pages.
+    pub(super) fn local_evictions(&self, table: usize) -> usize {
+        self.internal.lock().local_evictions.get(&table).copied().unwrap_or(0)
+    }
+    /// How many frames there are.
+    pub(super) fn frames(&self) -> usize {
+        self.internal.lock().allocator.total()
+    }
+    /// The frame a page is in, none if it is swapped out.
+    pub(super) fn frame_of(&self, ptr: &PagePtr) -> Option<usize> {
+        let internal = self.internal.lock();
+        let page = *internal.translation.get(&ptr.0)?;
+        internal.allocator.frame_number(page)
+    }
+    /// Hands out an id for a new page table.
+    pub(super) fn register_table(&self) -> usize {
+        let mut internal = self.internal.lock();
+        internal.tables += 1;
+        internal.tables
+    }
+    /// Where the page is for the table that referenced it.
+    pub(super) fn resolve(&self, ptr: PagePtr) -> PagePtr {
+        let raw = self.internal.lock().resolve(ptr.0, ptr.2);
+        PagePtr(raw, ptr.1, ptr.2)
+    }
+    /// Write protects a page for some page tables until they copy it.
+    pub(super) fn share(&self, ptr: &PagePtr, tables: [usize; 2]) {
+        self.internal.lock().shared.entry(ptr.0).or_default().extend(tables);
+    }
+    /// Gives a page back so its frame can be used by something else.
+    ///
+    /// # Panics
+    /// If the page was already freed.
+    pub fn free(&self, ptr: PagePtr) {
+        self.internal.lock().free(ptr.0);
     }
 }
 
 
 
+/// The last field is the page table it was referenced through, if any.
 #[derive(Clone)]
-pub struct PagePtr(RawPagePtr, Weak<Mutex<PagerInternal>>);
+pub struct PagePtr(RawPagePtr, Weak<Mutex<PagerInternal>>, Option<usize>);
 
 impl PagePtr {
     pub fn addr(&self) -> usize {
         self.0.0
     }
     pub unsafe fn from_raw(addr: usize, arc: &Pager) -> Self {
-        Self(RawPagePtr(addr), Arc::downgrade(&arc.internal))
+        Self(RawPagePtr(addr), Arc::downgrade(&arc.internal), None)
+    }
+    /// Copies bytes out of the page, this all happens while
+    /// the pager is locked so the page can't be swapped out
+    /// from under us.
+    ///
+    /// # Panics
+    /// If the range is outside of the page.
+    pub fn read<R: SliceIndex<[u8], Output = [u8]>>(&self, range: R) -> Vec<u8> {
+        let parent = self.1.upgrade().expect("abort!");
+        let mut pager = parent.lock();
+        let ptr = pager.resolve(self.0, self.2);
+        let page = unsafe { &*pager.refer(ptr) };
+        page[range].to_vec()
+    }
+    /// Copies bytes into the page starting at the offset, this all
+    /// happens while the pager is locked.
+    ///
+    /// # Panics
+    /// If the bytes would go past the end of the page.
+    pub fn write(&mut self, offset: usize, data: &[u8]) {
+        let parent = self.1.upgrade().expect("abort!");
+        let mut pager = parent.lock();
+        self.0 = pager.write(self.0, self.2);
+        let page = unsafe { &mut *pager.refer_mut(self.0) };
+        page[offset..offset + data.len()].copy_from_slice(data);
+    }
+    /// Marks the pointer as being referenced through a page table.
+    pub(super) fn for_table(mut self, table: usize) -> Self {
+        self.2 = Some(table);
+        self
+    }
+    /// The generator of the pager this page belongs to.
+    pub(crate) fn rng(&self) -> SimRng {
+        self.1.upgrade().expect("abort!").lock().rng.clone()
     }
 }
 
@@ -170,19 +537,28 @@ impl Debug for PagePtr {
     }
 }
 
+/// **Deprecated**, use [PagePtr::read] instead. The reference outlives
+/// the pager lock so if the page is swapped out while it is held it
+/// points at whatever is in the frame now.
 impl<Idx: SliceIndex<[u8]>> Index<Idx> for PagePtr {
     type Output = <Idx as SliceIndex<[u8]>>::Output;
     fn index(&self, index: Idx) -> &Self::Output {
         let parent = self.1.upgrade().expect("abort!");
-        let pref = parent.lock().refer(self.0);
+        let mut pager = parent.lock();
+        let ptr = pager.resolve(self.0, self.2);
+        let pref = pager.refer(ptr);
         unsafe { &*pref }.index(index)
     }
 }
 
+/// **Deprecated**, use [PagePtr::write] instead, this has the
+/// same problem as indexing.
 impl<Idx: SliceIndex<[u8]>> IndexMut<Idx> for PagePtr {
     fn index_mut(&mut self, index: Idx) -> &mut Self::Output {
         let parent = self.1.upgrade().expect("abort!");
-        let pref = parent.lock().refer(self.0);
+        let mut pager = parent.lock();
+        self.0 = pager.write(self.0, self.2);
+        let pref = pager.refer_mut(self.0);
         unsafe { &mut *pref }.index_mut(index)
     }
 }
@@ -206,7 +582,7 @@ impl<Idx: SliceIndex<[u8]>> IndexMut<Idx> for PagePtr {
 
 
 #[derive(Clone, Copy, Eq, Debug)]
-struct RawPagePtr(usize);
+pub(super) struct RawPagePtr(usize);
 
 impl PartialEq for RawPagePtr {
     fn eq(&self, other: &Self) -> bool {
@@ -223,9 +599,31 @@ impl Hash for RawPagePtr {
 
 #[cfg(test)]
 mod tests {
-    use crate::memory::paging::pager::PagerInternal;
-
-    use super::Pager;
+    use std::collections::HashMap;
+
+    use crate::{memory::paging::{pager::PagerInternal, replacement::ReplacementPolicy}, rng::SimRng};
+
+    use crate::disks::SecondaryStorage;
+
+    use super::{OutOfPages, PagePtr, Pager};
+
+    /// Replays a reference string, allocating each page on first use,
+    /// and returns how many faults there were.
+    fn replay(frames: usize, policy: ReplacementPolicy, references: &[usize]) -> usize {
+        let pager = Pager::new_with_policy(frames, policy);
+        let mut pages: HashMap<usize, PagePtr> = HashMap::new();
+        for &reference in references {
+            match pages.get(&reference) {
+                Some(page) => {
+                    let _ = page[0];
+                }
+                None => {
+                    pages.insert(reference, pager.alloc());
+                }
+            }
+        }
+        pager.faults()
+    }
 
     #[test]
     pub fn test_pager_proper() {
@@ -245,20 +643,29 @@ mod tests {
     }
 
 
+    #[test]
+    pub fn test_pager_seeded() {
+        let addrs = || {
+            let pager = Pager::with_rng(2, SimRng::from_seed(5));
+            (0..3).map(|_| pager.alloc().addr()).collect::<Vec<_>>()
+        };
+        assert_eq!(addrs(), addrs());
+    }
+
     #[test]
     pub fn test_pager() {
         let mut pager = PagerInternal::new(1);
-        let page = pager.new_page();
-        let page2 = pager.new_page();
-        let page3 = pager.new_page();
+        let page = pager.new_page(None).unwrap();
+        let page2 = pager.new_page(None).unwrap();
+        let page3 = pager.new_page(None).unwrap();
         {
-            let derefed = unsafe { &mut *pager.refer(page) };
+            let derefed = unsafe { &mut *pager.refer_mut(page) };
             derefed[0] = 43;
             assert_eq!(derefed[0], 43);
         }
 
         {
-            let derefed = unsafe { &mut *pager.refer(page2) };
+            let derefed = unsafe { &mut *pager.refer_mut(page2) };
             assert_eq!(derefed[0], 0);
             derefed[1] = 22;
         }
@@ -275,7 +682,220 @@ mod tests {
 
         
     }
-}
 
+    #[test]
+    pub fn test_pager_replacement_policies() {
+        let references = [7, 0, 1, 2, 0, 3, 0, 4, 2, 3, 0, 3, 2, 1, 2, 0, 1, 7, 0, 1];
+        assert_eq!(replay(3, ReplacementPolicy::Fifo, &references), 15);
+        assert_eq!(replay(3, ReplacementPolicy::Lru, &references), 12);
+
+        // Optimal numbers the pages in the order they are allocated.
+        let mut order = vec![];
+        for reference in references {
+            if !order.contains(&reference) {
+                order.push(reference);
+            }
+        }
+        let future = references.iter().map(|r| order.iter().position(|f| f == r).unwrap()).collect();
+        assert_eq!(replay(3, ReplacementPolicy::Optimal(future), &references), 9);
+
+        // Second chance lands between FIFO and LRU here.
+        assert_eq!(replay(3, ReplacementPolicy::Clock, &references), 14);
+    }
+
+    #[test]
+    pub fn test_pager_dirty_bits() {
+        // Only reading never writes anything to swap.
+        let pager = Pager::new(1);
+        let a = pager.alloc();
+        let b = pager.alloc();
+        for _ in 0..5 {
+            assert_eq!(a[0], 0);
+            assert_eq!(b[0], 0);
+        }
+        assert_eq!(pager.dirty_evictions(), 0);
+        assert!(pager.clean_evictions() > 0);
+
+        // Writing makes it dirty so it gets copied out.
+        let pager = Pager::new(1);
+        let mut a = pager.alloc();
+        let b = pager.alloc();
+        a[0] = 7;
+        let _ = b[0];
+        assert_eq!(pager.dirty_evictions(), 1);
+
+        // Reading it back in is clean, the swap still has it.
+        assert_eq!(a[0], 7);
+        let _ = b[0];
+        assert_eq!(pager.dirty_evictions(), 1);
+        assert_eq!(a[0], 7);
+
+        // Writing after the swap in makes it dirty again.
+        a[0] = 8;
+        let _ = b[0];
+        assert_eq!(pager.dirty_evictions(), 2);
+        assert_eq!(a[0], 8);
+    }
+
+    #[test]
+    pub fn test_pager_enhanced_clock() {
+        let pager = Pager::new_with_policy(5, ReplacementPolicy::EnhancedClock);
+        let mut pages: Vec<_> = (0..5).map(|_| pager.alloc()).collect();
+        for page in &mut pages[2..] {
+            page[0] = 1;
+        }
+        // Going round once clears every reference bit and then
+        // takes the first clean page.
+        let _ = pager.alloc();
+        assert_eq!(pager.last_eviction_class(), Some((true, false)));
+
+        // Using some of them again leaves a page in each class, the
+        // new page is (1, 0) and the one we skip is (0, 1).
+        pages[3][0] = 2;
+        pages[4][0] = 3;
+        let _ = pager.alloc();
+        assert_eq!(pager.last_eviction_class(), Some((false, false)));
+        let _ = pager.alloc();
+        assert_eq!(pager.last_eviction_class(), Some((false, true)));
+        let _ = pager.alloc();
+        assert_eq!(pager.last_eviction_class(), Some((true, false)));
+        assert_eq!(pager.dirty_evictions(), 1);
+
+        // Only when everything is used and dirty does it come to (1, 1).
+        let pager = Pager::new_with_policy(2, ReplacementPolicy::EnhancedClock);
+        for _ in 0..2 {
+            pager.alloc()[0] = 1;
+        }
+        let _ = pager.alloc();
+        assert_eq!(pager.last_eviction_class(), Some((true, true)));
+    }
+
+    #[test]
+    pub fn test_pager_enhanced_clock_second_sweep() {
+        let pager = Pager::new_with_policy(3, ReplacementPolicy::EnhancedClock);
+        let mut a = pager.alloc();
+        let mut b = pager.alloc();
+        let _c = pager.alloc();
+        a[0] = 1;
+        b[0] = 1;
+        let _d = pager.alloc();
+        assert_eq!(pager.last_eviction_class(), Some((true, false)));
+
+        // The ring is a (1, 1), b (0, 1), d (1, 0). Looking for (0, 1)
+        // takes b and clears the bit on a as it goes past but not on d.
+        let _ = a[0];
+        let _e = pager.alloc();
+        assert_eq!(pager.last_eviction_class(), Some((false, true)));
+
+        // So now a is the (0, 1) page the hand gets to first.
+        let _f = pager.alloc();
+        assert_eq!(pager.last_eviction_class(), Some((false, true)));
+        assert_eq!(pager.dirty_evictions(), 2);
+    }
+
+    #[test]
+    pub fn test_pager_free() {
+        let pager = Pager::new(2);
+        let mut a = pager.alloc();
+        let b = pager.alloc();
+        a[0] = 1;
+        pager.free(a);
+
+        // The freed frame gets used instead of swapping.
+        let c = pager.alloc();
+        assert_eq!(c[0], 0);
+        assert_eq!(b[0], 0);
+        assert_eq!(pager.clean_evictions() + pager.dirty_evictions(), 0);
+        assert_eq!(pager.faults(), 3);
+
+        // Freeing a page that is swapped out makes room too.
+        let d = pager.alloc();
+        pager.free(b);
+        let _ = c[0];
+        let _ = d[0];
+        assert_eq!(pager.clean_evictions(), 1);
+    }
+
+    #[test]
+    pub fn test_pager_thrashing() {
+        let cycle = |frames| {
+            let pager = Pager::new(frames).with_window(4);
+            let pages: Vec<_> = (0..4).map(|_| pager.alloc()).collect();
+            for _ in 0..10 {
+                for page in &pages {
+                    let _ = page[0];
+                }
+            }
+            assert_eq!(pager.working_set().len(), 4);
+            pager
+        };
+
+        // Every reference misses when only two fit.
+        let small = cycle(2);
+        assert!(small.is_thrashing());
+        assert_eq!(small.fault_rate(), 1.0);
+
+        let big = cycle(4);
+        assert!(!big.is_thrashing());
+        assert_eq!(big.fault_rate(), 4.0 / 44.0);
+    }
 
+    #[test]
+    pub fn test_pager_device_swap() {
+        let pager = Pager::with_swap(2, SecondaryStorage::new(2 * 4096), 2);
+        let mut pages: Vec<_> = (0..4).map(|_| pager.try_alloc().unwrap()).collect();
+        for (i, page) in pages.iter_mut().enumerate() {
+            page[0] = i as u8;
+            page[4095] = 10 + i as u8;
+        }
+
+        // Everything has been through the disk at least once.
+        for _ in 0..3 {
+            for (i, page) in pages.iter().enumerate() {
+                assert_eq!(page[0], i as u8);
+                assert_eq!(page[4095], 10 + i as u8);
+            }
+        }
+        pages[1][7] = 7;
+        for page in &pages {
+            let _ = page[0];
+        }
+        assert_eq!(pages[1][7], 7);
+
+        // Two frames and two slots is all there is.
+        assert_eq!(pager.try_alloc(), Err(OutOfPages { frames: 2, swap_slots: 2 }));
+        pager.free(pages.pop().unwrap());
+        assert!(pager.try_alloc().is_ok());
+    }
+
+    #[test]
+    pub fn test_pager_read_write_swapped() {
+        let pager = Pager::new(2);
+        let mut page = pager.alloc();
+        page.write(0, &[1, 2, 3]);
+
+        // Push it out to swap and recycle its frame.
+        let mut others: Vec<_> = (0..2).map(|_| pager.alloc()).collect();
+        for (i, other) in others.iter_mut().enumerate() {
+            other.write(0, &[10 + i as u8; 3]);
+        }
+        assert!(pager.dirty_evictions() >= 1);
+
+        // This has to land in the copy that gets swapped back in.
+        page.write(1, &[9]);
+        assert_eq!(page.read(0..3), [1, 9, 3]);
+        assert_eq!(page.read(4093..), [0; 3]);
+        for (i, other) in others.iter().enumerate() {
+            assert_eq!(other.read(..3), [10 + i as u8; 3]);
+        }
+    }
 
+    #[test]
+    #[should_panic(expected = "Double free")]
+    pub fn test_pager_double_free() {
+        let pager = Pager::new(1);
+        let page = pager.alloc();
+        pager.free(page.clone());
+        pager.free(page);
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::pager::RawPagePtr;
+
+
+/// How the [Pager](super::pager::Pager) picks which page to swap out.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ReplacementPolicy {
+    /// The page that was swapped in the longest ago.
+    Fifo,
+    /// The page that was used the longest ago.
+    #[default]
+    Lru,
+    /// Second chance, pages get a reference bit set whenever they are
+    /// used and the clock hand clears them as it goes around looking
+    /// for a page without one.
+    Clock,
+    /// Enhanced second chance, this looks at the (referenced, dirty) pair
+    /// and evicts from the lowest class it can in the order (0, 0), (0, 1),
+    /// (1, 0) and then (1, 1), so clean pages get thrown out first.
+    EnhancedClock,
+    /// Belady's algorithm, the page that will not be used for the longest.
+    ///
+    /// This needs to know every reference that is going to happen. Pages
+    /// are numbered in the order they are allocated, so `0` is the first
+    /// page allocated, and every allocation counts as a reference.
+    Optimal(Vec<usize>)
+}
+
+/// The state each policy keeps about the resident pages.
+pub(super) enum Replacement {
+    Fifo(VecDeque<RawPagePtr>),
+    Lru(HashMap<RawPagePtr, u128>),
+    /// The front of the queue is where the hand is.
+    Clock(VecDeque<(RawPagePtr, bool)>),
+    EnhancedClock {
+        ring: VecDeque<(RawPagePtr, bool)>,
+        /// The (referenced, dirty) class of the last page evicted.
+        last_class: Option<(bool, bool)>
+    },
+    Optimal {
+        future: Vec<usize>,
+        /// How far through the future we are.
+        position: usize,
+        /// The order each page was allocated in.
+        order: HashMap<RawPagePtr, usize>
+    }
+}
+
+impl Replacement {
+    pub fn new(policy: ReplacementPolicy) -> Self {
+        match policy {
+            ReplacementPolicy::Fifo => Self::Fifo(VecDeque::new()),
+            ReplacementPolicy::Lru => Self::Lru(HashMap::new()),
+            ReplacementPolicy::Clock => Self::Clock(VecDeque::new()),
+            ReplacementPolicy::EnhancedClock => Self::EnhancedClock {
+                ring: VecDeque::new(),
+                last_class: None
+            },
+            ReplacementPolicy::Optimal(future) => Self::Optimal {
+                future,
+                position: 0,
+                order: HashMap::new()
+            }
+        }
+    }
+    /// A brand new page was allocated.
+    pub fn allocated(&mut self, page: RawPagePtr) {
+        if let Self::Optimal { order, .. } = self {
+            let next = order.len();
+            order.insert(page, next);
+        }
+    }
+    /// A page was referenced at this point on the pager clock, this
+    /// happens before it is swapped in if it needs to be.
+    pub fn referenced(&mut self, page: RawPagePtr, clock: u128) {
+        match self {
+            Self::Fifo(_) => {}
+            Self::Lru(last_used) => {
+                last_used.insert(page, clock);
+            }
+            Self::Clock(ring) | Self::EnhancedClock { ring, .. } => {
+                if let Some((_, bit)) = ring.iter_mut().find(|(p, _)| *p == page) {
+                    *bit = true;
+                }
+            }
+            Self::Optimal { position, .. } => *position += 1
+        }
+    }
+    /// A page was brought into a frame.
+    pub fn loaded(&mut self, page: RawPagePtr) {
+        match self {
+            Self::Fifo(queue) => queue.push_back(page),
+            Self::Clock(ring) | Self::EnhancedClock { ring, .. } => ring.push_back((page, true)),
+            Self::Lru(_) | Self::Optimal { .. } => {}
+        }
+    }
+    /// A page left memory without the policy picking it, either
+    /// it was freed or something else decided to swap it out.
+    pub fn removed(&mut self, page: RawPagePtr) {
+        match self {
+            Self::Fifo(queue) => queue.retain(|f| *f != page),
+            Self::Lru(last_used) => {
+                last_used.remove(&page);
+            }
+            Self::Clock(ring) | Self::EnhancedClock { ring, .. } => ring.retain(|(f, _)| *f != page),
+            Self::Optimal { .. } => {}
+        }
+    }
+    /// The (referenced, dirty) class of the last page evicted, if the
+    /// policy cares about classes.
+    pub fn last_class(&self) -> Option<(bool, bool)> {
+        match self {
+            Self::EnhancedClock { last_class, .. } => *last_class,
+            _ => None
+        }
+    }
+    /// Picks one of the resident pages to swap out and forgets about it.
+    pub fn select(&mut self, resident: &[RawPagePtr], dirty: &HashSet<RawPagePtr>) -> RawPagePtr {
+        match self {
+            Self::Fifo(queue) => queue.pop_front().unwrap(),
+            Self::Lru(last_used) => {
+                let page = *resident.iter().min_by_key(|f| last_used[f]).unwrap();
+                last_used.remove(&page);
+                page
+            }
+            Self::Clock(ring) => loop {
+                let (page, bit) = ring.pop_front().unwrap();
+                if !bit {
+                    break page;
+                }
+                // Give it a second chance.
+                ring.push_back((page, false));
+            },
+            Self::EnhancedClock { ring, last_class } => {
+                let classes: HashMap<_, _> = ring.iter().map(|(p, bit)| (*p, (*bit, dirty.contains(p)))).collect();
+                let page = loop {
+                    // First look for (0, 0) without touching anything.
+                    if let Some(position) = ring.iter().position(|(p, bit)| !bit && !dirty.contains(p)) {
+                        ring.rotate_left(position);
+                        break ring.pop_front().unwrap().0;
+                    }
+                    // Then for (0, 1), clearing the reference bits on the way
+                    // round so the next go finds something.
+                    // There is no (0, 0), so anything unreferenced is (0, 1).
+                    let mut found = None;
+                    for _ in 0..ring.len() {
+                        let (page, bit) = ring.pop_front().unwrap();
+                        if !bit {
+                            found = Some(page);
+                            break;
+                        }
+                        ring.push_back((page, false));
+                    }
+                    if let Some(page) = found {
+                        break page;
+                    }
+                };
+                *last_class = Some(classes[&page]);
+                page
+            }
+            Self::Optimal { future, position, order } => {
+                // The one being referenced right now is at position - 1.
+                let next_use = |page: &RawPagePtr| {
+                    future
+                        .iter()
+                        .skip(*position)
+                        .position(|f| *f == order[page])
+                        .unwrap_or(usize::MAX)
+                };
+                *resident.iter().max_by_key(|f| next_use(f)).unwrap()
+            }
+        }
+    }
+}
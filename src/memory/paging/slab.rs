@@ -0,0 +1,241 @@
+use std::{marker::PhantomData, mem, sync::Arc};
+
+use parking_lot::Mutex;
+
+use super::{Page, PageAllocator};
+
+
+/// One page carved up into slots.
+struct Slab {
+    page: *const Page,
+    /// Where the first slot is, this is aligned for the objects.
+    base: *mut u8,
+    /// How many slots fit after aligning.
+    slots: usize,
+    /// The slots that are not being used.
+    free: Vec<usize>
+}
+
+/// How the slabs of a [SlabCache] are being used.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SlabStats {
+    /// Slabs with every slot used.
+    pub full: usize,
+    /// Slabs with some slots used.
+    pub partial: usize,
+    /// Slabs with no slots used.
+    pub empty: usize,
+    /// How many objects are allocated.
+    pub objects: usize,
+    /// How many more objects fit in the slabs we have.
+    pub free_slots: usize,
+    /// The bytes of each page that are not in a slot, from aligning
+    /// the first one and from the end being too small for another.
+    pub wasted_bytes: usize
+}
+
+/// A slab allocator, this takes pages from a [PageAllocator] and splits
+/// them into slots that are exactly the size of a `T`.
+///
+/// Slabs are kept in a full, partial and empty list, objects come from a
+/// partial slab if there is one so the others can empty out. Empty slabs
+/// are kept around until [SlabCache::shrink] gives them back.
+pub struct SlabCache<T> {
+    allocator: Arc<Mutex<PageAllocator>>,
+    full: Vec<Slab>,
+    partial: Vec<Slab>,
+    empty: Vec<Slab>,
+    _marker: PhantomData<T>
+}
+
+impl<T> SlabCache<T> {
+    /// Creates a cache that takes pages from the allocator.
+    ///
+    /// # Panics
+    /// If `T` is zero sized or does not fit in a page.
+    pub fn new(allocator: Arc<Mutex<PageAllocator>>) -> Self {
+        assert!(mem::size_of::<T>() > 0, "Can't make a slab of zero sized objects.");
+        assert!(Self::fit(mem::align_of::<T>() - 1) > 0, "The objects don't fit in a page.");
+        Self {
+            allocator,
+            full: vec![],
+            partial: vec![],
+            empty: vec![],
+            _marker: PhantomData
+        }
+    }
+    /// How many objects fit after skipping some bytes to align them.
+    fn fit(skipped: usize) -> usize {
+        4096usize.saturating_sub(skipped) / mem::size_of::<T>()
+    }
+    /// Allocates a slot for an object, this is not initialised. This
+    /// is none if there are no pages left for a new slab.
+    pub fn alloc(&mut self) -> Option<*mut T> {
+        let mut slab = match self.partial.pop().or_else(|| self.empty.pop()) {
+            Some(slab) => slab,
+            None => self.grow()?
+        };
+        let slot = slab.free.pop().unwrap();
+        let ptr = unsafe { slab.base.add(slot * mem::size_of::<T>()) }.cast();
+        if slab.free.is_empty() {
+            self.full.push(slab);
+        } else {
+            self.partial.push(slab);
+        }
+        Some(ptr)
+    }
+    /// Makes a new slab out of a fresh page.
+    fn grow(&mut self) -> Option<Slab> {
+        let page = self.allocator.lock().acquire()?;
+        let data = unsafe { (*page.cast_mut()).data.as_mut_ptr() };
+        let skipped = data.align_offset(mem::align_of::<T>());
+        let slots = Self::fit(skipped);
+        Some(Slab {
+            page,
+            base: unsafe { data.add(skipped) },
+            slots,
+            // Hand out the first slot first.
+            free: (0..slots).rev().collect()
+        })
+    }
+    /// Gives a slot back, this does not drop the object in it.
+    ///
+    /// # Panics
+    /// If the pointer did not come from this cache or was already freed.
+    pub fn free(&mut self, ptr: *mut T) {
+        let ptr = ptr.cast::<u8>();
+        let size = mem::size_of::<T>();
+        let find = |slabs: &Vec<Slab>| {
+            slabs.iter().position(|f| {
+                let end = unsafe { f.base.add(f.slots * size) };
+                f.base <= ptr && ptr < end
+            })
+        };
+        let mut slab = if let Some(index) = find(&self.full) {
+            self.full.swap_remove(index)
+        } else if let Some(index) = find(&self.partial) {
+            self.partial.swap_remove(index)
+        } else {
+            panic!("Double free of {ptr:?}, or it is not from this cache.");
+        };
+
+        let offset = ptr as usize - slab.base as usize;
+        assert_eq!(offset % size, 0, "The pointer is not the start of a slot.");
+        let slot = offset / size;
+        assert!(!slab.free.contains(&slot), "Double free of {ptr:?}.");
+        slab.free.push(slot);
+
+        if slab.free.len() == slab.slots {
+            self.empty.push(slab);
+        } else {
+            self.partial.push(slab);
+        }
+    }
+    /// Gives the pages of every empty slab back to the allocator,
+    /// returning how many there were.
+    pub fn shrink(&mut self) -> usize {
+        let mut allocator = self.allocator.lock();
+        let count = self.empty.len();
+        for slab in self.empty.drain(..) {
+            allocator.release(slab.page);
+        }
+        count
+    }
+    pub fn stats(&self) -> SlabStats {
+        let mut stats = SlabStats {
+            full: self.full.len(),
+            partial: self.partial.len(),
+            empty: self.empty.len(),
+            ..Default::default()
+        };
+        for slab in self.full.iter().chain(&self.partial).chain(&self.empty) {
+            stats.objects += slab.slots - slab.free.len();
+            stats.free_slots += slab.free.len();
+            stats.wasted_bytes += 4096 - slab.slots * mem::size_of::<T>();
+        }
+        stats
+    }
+}
+
+impl<T> Drop for SlabCache<T> {
+    fn drop(&mut self) {
+        let mut allocator = self.allocator.lock();
+        for slab in self.full.drain(..).chain(self.partial.drain(..)).chain(self.empty.drain(..)) {
+            allocator.release(slab.page);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{mem, sync::Arc};
+
+    use parking_lot::Mutex;
+
+    use crate::memory::paging::PageAllocator;
+
+    use super::SlabCache;
+
+    #[repr(align(64))]
+    struct Aligned([u8; 100]);
+
+    #[test]
+    pub fn test_slab_cache() {
+        let allocator = Arc::new(Mutex::new(PageAllocator::new(4)));
+        let mut cache = SlabCache::<[u8; 512]>::new(Arc::clone(&allocator));
+
+        // Eight of these fit in a page so the ninth needs another slab.
+        let objects: Vec<_> = (0..9).map(|_| cache.alloc().unwrap()).collect();
+        for (i, object) in objects.iter().enumerate() {
+            unsafe { object.write([i as u8; 512]) };
+        }
+        let stats = cache.stats();
+        assert_eq!((stats.full, stats.partial, stats.empty), (1, 1, 0));
+        assert_eq!((stats.objects, stats.free_slots), (9, 7));
+        assert_eq!(allocator.lock().available(), 2);
+        for (i, object) in objects.iter().enumerate() {
+            assert_eq!(unsafe { (**object)[511] }, i as u8);
+        }
+
+        // Emptying the first slab lets its page go back.
+        for object in &objects[..8] {
+            cache.free(*object);
+        }
+        let stats = cache.stats();
+        assert_eq!((stats.full, stats.partial, stats.empty), (0, 1, 1));
+        assert_eq!(cache.shrink(), 1);
+        assert_eq!(allocator.lock().available(), 3);
+
+        drop(cache);
+        assert_eq!(allocator.lock().available(), 4);
+    }
+
+    #[test]
+    pub fn test_slab_alignment() {
+        let allocator = Arc::new(Mutex::new(PageAllocator::new(2)));
+        let mut cache = SlabCache::<Aligned>::new(allocator);
+        for _ in 0..50 {
+            let ptr = cache.alloc().unwrap();
+            assert_eq!(ptr as usize % mem::align_of::<Aligned>(), 0);
+            unsafe {
+                ptr.write(Aligned([1; 100]));
+                assert_eq!((*ptr).0[99], 1);
+            }
+        }
+        // Each object takes 128 bytes, whatever is left of the two pages
+        // after aligning and filling them is wasted.
+        let stats = cache.stats();
+        assert_eq!(stats.objects, 50);
+        assert_eq!((stats.objects + stats.free_slots) * 128 + stats.wasted_bytes, 2 * 4096);
+    }
+
+    #[test]
+    #[should_panic(expected = "Double free")]
+    pub fn test_slab_double_free() {
+        let mut cache = SlabCache::<u32>::new(Arc::new(Mutex::new(PageAllocator::new(1))));
+        let ptr = cache.alloc().unwrap();
+        cache.alloc().unwrap();
+        cache.free(ptr);
+        cache.free(ptr);
+    }
+}
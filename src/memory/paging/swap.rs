@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use crate::disks::{RawStoragePtr, StorageDevice};
+
+use super::pager::RawPagePtr;
+
+
+/// Every slot of the swap is taken by a page that isn't in memory.
+#[derive(Debug)]
+pub(super) struct SwapFull;
+
+/// Where the [Pager](super::pager::Pager) keeps pages that are not in memory.
+pub(super) enum Swap {
+    /// To keep things simple, the swap is just kept in memory.
+    Memory(HashMap<RawPagePtr, [u8; 4096]>),
+    /// The swap is a fixed number of page sized slots on a device.
+    Device {
+        device: Box<dyn StorageDevice + Send>,
+        /// Which slot each page is stored in.
+        slots: HashMap<RawPagePtr, usize>,
+        free: Vec<usize>
+    }
+}
+
+impl Swap {
+    pub fn device(device: impl StorageDevice + Send + 'static, slots: usize) -> Self {
+        Self::Device {
+            device: Box::new(device),
+            slots: HashMap::new(),
+            // Hand out the first slots first.
+            free: (0..slots).rev().collect()
+        }
+    }
+    /// How many pages can be swapped out at once, if there is a limit.
+    pub fn slots(&self) -> Option<usize> {
+        match self {
+            Self::Memory(_) => None,
+            Self::Device { slots, free, .. } => Some(slots.len() + free.len())
+        }
+    }
+    pub fn get(&self, page: RawPagePtr) -> Option<[u8; 4096]> {
+        match self {
+            Self::Memory(pages) => pages.get(&page).copied(),
+            Self::Device { device, slots, .. } => {
+                let slot = *slots.get(&page)?;
+                let data = device.read(RawStoragePtr::byte_ptr(slot * 4096), 4096);
+                Some(data.try_into().unwrap())
+            }
+        }
+    }
+    /// Whether [Swap::put] would find somewhere for the page.
+    pub fn has_slot(&self, page: RawPagePtr, reclaimable: impl Fn(RawPagePtr) -> bool) -> bool {
+        match self {
+            Self::Memory(_) => true,
+            Self::Device { slots, free, .. } => {
+                !free.is_empty() || slots.contains_key(&page) || slots.keys().any(|f| reclaimable(*f))
+            }
+        }
+    }
+    /// Stores a page, if there are no free slots then one is taken
+    /// from one of the pages that can be reclaimed, which is returned.
+    pub fn put(&mut self, page: RawPagePtr, data: &[u8; 4096], reclaimable: impl Fn(RawPagePtr) -> bool) -> Result<Option<RawPagePtr>, SwapFull> {
+        match self {
+            Self::Memory(pages) => {
+                pages.insert(page, *data);
+                Ok(None)
+            }
+            Self::Device { device, slots, free } => {
+                let mut reclaimed = None;
+                let slot = match slots.get(&page) {
+                    Some(slot) => *slot,
+                    None => {
+                        let slot = match free.pop() {
+                            Some(slot) => slot,
+                            None => {
+                                let victim = *slots.keys().find(|f| reclaimable(**f)).ok_or(SwapFull)?;
+                                reclaimed = Some(victim);
+                                slots.remove(&victim).unwrap()
+                            }
+                        };
+                        slots.insert(page, slot);
+                        slot
+                    }
+                };
+                device.write(RawStoragePtr::byte_ptr(slot * 4096), data);
+                Ok(reclaimed)
+            }
+        }
+    }
+    pub fn remove(&mut self, page: RawPagePtr) {
+        match self {
+            Self::Memory(pages) => {
+                pages.remove(&page);
+            }
+            Self::Device { slots, free, .. } => {
+                if let Some(slot) = slots.remove(&page) {
+                    free.push(slot);
+                }
+            }
+        }
+    }
+}
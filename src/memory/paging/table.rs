@@ -1,6 +1,8 @@
 use std::{collections::HashMap, sync::Arc};
 
-use super::{local::LogicalAddress, pager::{PagePtr, Pager}};
+use parking_lot::Mutex;
+
+use super::{local::LogicalAddress, pager::{PagePtr, Pager}, tlb::{Tlb, TlbPolicy, TlbStats}};
 
 
 /// This [PageTable] will translate local addresses into
@@ -10,27 +12,103 @@ pub struct PageTable {
     mapping: HashMap<u16, u16>,
 
     /// Pager,
-    pager: Arc<Pager>
+    pager: Arc<Pager>,
+
+    /// Checked before the mapping.
+    tlb: Mutex<Tlb>,
+
+    /// How the pager knows this table.
+    id: usize
 }
 
 impl PageTable {
     pub fn new(pager: Arc<Pager>) -> Self {
         Self {
             mapping: HashMap::default(),
-            pager
+            id: pager.register_table(),
+            pager,
+            tlb: Mutex::new(Tlb::new(8, TlbPolicy::default()))
         }
     }
+    /// Sets how many entries the TLB has and how it evicts them, by
+    /// default it has 8 and evicts the least recently used.
+    pub fn with_tlb(mut self, capacity: usize, policy: TlbPolicy) -> Self {
+        self.tlb = Mutex::new(Tlb::new(capacity, policy));
+        self
+    }
+    /// Caps how many frames this table can use, once it is at the limit it swaps
+    /// out its own pages even if the pager has frames free.
+    ///
+    /// # Panics
+    /// If the limit is zero.
+    pub fn with_rss_limit(self, frames: usize) -> Self {
+        assert!(frames > 0, "The page table needs at least one frame.");
+        self.pager.set_rss_limit(self.id, frames);
+        self
+    }
     /// Allocates a page to the local process and will return a [LogicalAddress]
     /// in a real machine this would be a system call.
     pub fn alloc(&mut self) -> LogicalAddress {
-        let (real, logical) = LogicalAddress::create(self.pager.alloc());
+        let (real, logical) = LogicalAddress::create(self.pager.alloc_for_table(self.id));
         self.mapping.insert(logical.logical_root(), real);
         logical
     }
     /// Performs a page reference. Needless to say this is incredibly unsafe.
     pub fn reference(&self, ptr: LogicalAddress) -> PagePtr {
-        let real = *self.mapping.get(&ptr.logical_root()).unwrap();
-        ptr.translate(real, self.pager.as_ref())
+        let logical = ptr.logical_root();
+        let mut tlb = self.tlb.lock();
+        let real = match tlb.lookup(logical) {
+            Some(real) => real,
+            None => {
+                // Walk the table and remember it for next time.
+                let real = *self.mapping.get(&logical).unwrap();
+                tlb.insert(logical, real);
+                real
+            }
+        };
+        ptr.translate(real, self.pager.as_ref()).for_table(self.id)
+    }
+    /// Reads the byte the address points to.
+    pub fn read_byte(&self, addr: LogicalAddress) -> u8 {
+        self.reference(addr).read(addr.offset() as usize..addr.offset() as usize + 1)[0]
+    }
+    /// Writes the byte the address points to.
+    pub fn write_byte(&self, addr: LogicalAddress, value: u8) {
+        self.reference(addr).write(addr.offset() as usize, &[value]);
+    }
+    /// Maps a page into another table at the same address, like a fork. Both
+    /// can read it but the first one to write gets its own copy.
+    ///
+    /// # Panics
+    /// If the tables use different pagers or the other one already maps the address.
+    pub fn share(&mut self, other: &mut PageTable, addr: LogicalAddress) {
+        assert!(Arc::ptr_eq(&self.pager, &other.pager), "The page tables must use the same pager.");
+        let logical = addr.logical_root();
+        assert!(!other.mapping.contains_key(&logical), "The address is already mapped.");
+
+        // We might have already copied it.
+        let page = self.pager.resolve(self.reference(addr));
+        let real = page.addr() as u16;
+        self.mapping.insert(logical, real);
+        self.tlb.lock().invalidate(logical);
+        other.mapping.insert(logical, real);
+        self.pager.share(&page, [self.id, other.id]);
+    }
+    /// How many of the pages of this table are in memory.
+    pub fn resident_pages(&self) -> usize {
+        self.pager.resident_pages(self.id)
+    }
+    /// How many times this table had to swap out one of its own pages.
+    pub fn local_evictions(&self) -> usize {
+        self.pager.local_evictions(self.id)
+    }
+    /// How many references were found in the TLB.
+    pub fn tlb_stats(&self) -> TlbStats {
+        self.tlb.lock().stats()
+    }
+    /// Clears the TLB, like on a context switch.
+    pub fn flush_tlb(&self) {
+        self.tlb.lock().flush();
     }
 }
 
@@ -38,9 +116,9 @@ impl PageTable {
 mod tests {
     use std::sync::Arc;
 
-    use crate::memory::paging::pager::Pager;
+    use crate::{disks::SecondaryStorage, memory::paging::{pager::Pager, tlb::{TlbPolicy, TlbStats}}, rng::SimRng};
 
-    use super::PageTable;
+    use super::{LogicalAddress, PageTable};
 
 
 
@@ -57,4 +135,123 @@ mod tests {
         assert_eq!(page_frame[3], 4);
     }
 
+    #[test]
+    pub fn test_page_table_tlb() {
+        let pager = Arc::new(Pager::with_rng(18, SimRng::from_seed(3)));
+        let mut page_table = PageTable::new(pager).with_tlb(2, TlbPolicy::Lru);
+        let local = page_table.alloc();
+
+        // One miss and then it is cached.
+        for _ in 0..4 {
+            page_table.reference(local);
+        }
+        assert_eq!(page_table.tlb_stats(), TlbStats { hits: 3, misses: 1, evictions: 0 });
+
+        // Three pages don't fit in two entries.
+        let others = [page_table.alloc(), page_table.alloc()];
+        page_table.reference(others[0]);
+        page_table.reference(local);
+        page_table.reference(others[1]);
+        assert_eq!(page_table.tlb_stats(), TlbStats { hits: 4, misses: 3, evictions: 1 });
+        // The first other page was used the longest ago so it is gone.
+        page_table.reference(others[0]);
+        assert_eq!(page_table.tlb_stats().misses, 4);
+
+        // Everything misses after a flush.
+        page_table.flush_tlb();
+        page_table.reference(local);
+        page_table.reference(others[1]);
+        assert_eq!(page_table.tlb_stats(), TlbStats { hits: 4, misses: 6, evictions: 2 });
+    }
+
+    #[test]
+    pub fn test_page_table_rss_limit() {
+        let pager = Arc::new(Pager::new(8));
+        let mut capped = PageTable::new(Arc::clone(&pager)).with_rss_limit(2);
+        let mut other = PageTable::new(Arc::clone(&pager));
+
+        let theirs = [other.alloc(), other.alloc()];
+        let ours: Vec<_> = (0..4).map(|_| capped.alloc()).collect();
+        for (i, addr) in ours.iter().enumerate() {
+            capped.reference(*addr)[0] = i as u8;
+        }
+        for (i, addr) in ours.iter().enumerate() {
+            assert_eq!(capped.reference(*addr)[0], i as u8);
+        }
+
+        // There were free frames but the capped table only swapped its own pages.
+        assert_eq!(capped.resident_pages(), 2);
+        assert_eq!(other.resident_pages(), 2);
+        assert!(capped.local_evictions() > 0);
+        assert_eq!(other.local_evictions(), 0);
+        assert_eq!(pager.clean_evictions() + pager.dirty_evictions(), capped.local_evictions());
+        let faults = pager.faults();
+        for addr in theirs {
+            let _ = other.reference(addr)[0];
+        }
+        assert_eq!(pager.faults(), faults);
+    }
+
+    #[test]
+    pub fn test_page_table_rss_limit_swap_full() {
+        let pager = Arc::new(Pager::with_swap(8, SecondaryStorage::new(2 * 4096), 2));
+        let mut capped = PageTable::new(Arc::clone(&pager)).with_rss_limit(1);
+        let pages: Vec<_> = (0..4).map(|_| capped.alloc()).collect();
+        for (i, addr) in pages.iter().enumerate() {
+            capped.reference(*addr)[0] = i as u8;
+        }
+
+        // The last write found both slots taken so it went in a free frame instead.
+        assert_eq!(capped.resident_pages(), 2);
+        for (i, addr) in pages.iter().enumerate() {
+            assert_eq!(capped.reference(*addr)[0], i as u8);
+        }
+    }
+
+    #[test]
+    pub fn test_page_table_bytes() {
+        let pager = Arc::new(Pager::new(4));
+        let mut first = PageTable::new(Arc::clone(&pager));
+        let mut second = PageTable::new(pager);
+        let page = first.alloc();
+
+        let addr = LogicalAddress::with_offset(page, 1234);
+        first.write_byte(addr, 9);
+        first.write_byte(LogicalAddress::with_offset(page, 4095), 10);
+        assert_eq!(first.reference(page)[1234], 9);
+
+        // The other table sees the same bytes through its own mapping.
+        first.share(&mut second, page);
+        assert_eq!(second.read_byte(addr), 9);
+        assert_eq!(second.read_byte(LogicalAddress::with_offset(page, 4095)), 10);
+        assert_eq!(second.read_byte(LogicalAddress::with_offset(page, 1233)), 0);
+    }
+
+    #[test]
+    pub fn test_page_table_cow() {
+        let pager = Arc::new(Pager::new(4));
+        let mut parent = PageTable::new(Arc::clone(&pager));
+        let mut child = PageTable::new(Arc::clone(&pager));
+        let addr = parent.alloc();
+        parent.reference(addr)[0] = 1;
+
+        parent.share(&mut child, addr);
+        assert_eq!(child.reference(addr)[0], 1);
+        assert_eq!(pager.cow_breaks(), 0);
+
+        // The parent writing only changes what the parent sees.
+        let mut page = parent.reference(addr);
+        page[0] = 2;
+        page[1] = 3;
+        assert_eq!(parent.reference(addr)[0], 2);
+        assert_eq!(child.reference(addr)[0], 1);
+        assert_eq!(pager.cow_breaks(), 1);
+
+        // The child has it to itself now so there is nothing to copy.
+        child.reference(addr)[0] = 4;
+        assert_eq!(child.reference(addr)[0], 4);
+        assert_eq!(parent.reference(addr)[..2], [2, 3]);
+        assert_eq!(pager.cow_breaks(), 1);
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+
+
+/// Which entry the [Tlb] throws out when it is full.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TlbPolicy {
+    /// The entry that was used the longest ago.
+    #[default]
+    Lru,
+    /// The entry that was added the longest ago.
+    Fifo
+}
+
+/// How well the [Tlb] has been doing.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TlbStats {
+    pub hits: usize,
+    pub misses: usize,
+    /// How many entries were thrown out to make room.
+    pub evictions: usize
+}
+
+/// A translation lookaside buffer, a small cache of recent
+/// translations from logical roots to real roots.
+pub struct Tlb {
+    capacity: usize,
+    policy: TlbPolicy,
+    /// The front is the next to be evicted.
+    entries: VecDeque<(u16, u16)>,
+    stats: TlbStats
+}
+
+impl Tlb {
+    pub fn new(capacity: usize, policy: TlbPolicy) -> Self {
+        Self {
+            capacity,
+            policy,
+            entries: VecDeque::with_capacity(capacity),
+            stats: TlbStats::default()
+        }
+    }
+    /// Looks up the real root, counting a hit or a miss.
+    pub fn lookup(&mut self, logical: u16) -> Option<u16> {
+        let Some(position) = self.entries.iter().position(|(l, _)| *l == logical) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        let (_, real) = self.entries[position];
+        if self.policy == TlbPolicy::Lru {
+            // Move it to the back since it was just used.
+            self.entries.remove(position);
+            self.entries.push_back((logical, real));
+        }
+        Some(real)
+    }
+    /// Adds a translation after a miss.
+    pub fn insert(&mut self, logical: u16, real: u16) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+            self.stats.evictions += 1;
+        }
+        self.entries.push_back((logical, real));
+    }
+    /// Forgets the translation of one logical root.
+    pub fn invalidate(&mut self, logical: u16) {
+        self.entries.retain(|(l, _)| *l != logical);
+    }
+    /// Forgets every translation, the stats are kept.
+    pub fn flush(&mut self) {
+        self.entries.clear();
+    }
+    pub fn stats(&self) -> TlbStats {
+        self.stats
+    }
+}
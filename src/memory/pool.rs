@@ -1,71 +1,297 @@
-use std::{collections::HashMap, fmt::Debug, marker::PhantomData, mem, ops::{Deref, DerefMut}, sync::{Arc, Weak}};
+use std::{alloc::{self, Layout}, collections::HashMap, fmt::Debug, marker::PhantomData, ops::{Deref, DerefMut}, ptr::{self, NonNull}, sync::{Arc, Weak}, thread::{self, ThreadId}, time::Duration};
 
-use parking_lot::{ArcMutexGuard, Mutex, RawMutex};
+use parking_lot::{ArcMutexGuard, ArcRwLockReadGuard, ArcRwLockWriteGuard, Mutex, RawMutex, RawRwLock, RwLock};
 
-use super::SharedMemory;
+use crate::rng::SimRng;
 
+use super::sync::LockRegistry;
 
-type RamMap = SharedMemory<HashMap<u32, Box<[u8]>>>;
+
+/// Every object by its address, this has its own lock as objects
+/// with their own locks can be stored and freed at the same time.
+type RamMap = Mutex<HashMap<u32, RawObject>>;
+
+/// The bytes of a stored object, allocated with the
+/// alignment of its type so we can point straight at it.
+struct RawObject {
+    ptr: NonNull<u8>,
+    layout: Layout,
+    /// Runs the destructor of the type that was stored.
+    drop: unsafe fn(*mut u8)
+}
+
+unsafe fn drop_raw<T>(ptr: *mut u8) {
+    unsafe { ptr::drop_in_place(ptr as *mut T) };
+}
+
+// Only objects that are Send get stored, see [RandomAccessMemory::store_inner],
+// so the destructor can run on whichever thread frees them.
+unsafe impl Send for RawObject {}
+
+impl RawObject {
+    fn new<T>(obj: T) -> Self {
+        let layout = Layout::new::<T>();
+        let ptr = if layout.size() == 0 {
+            // Nothing to allocate, it just has to be aligned.
+            NonNull::<T>::dangling().cast()
+        } else {
+            NonNull::new(unsafe { alloc::alloc(layout) }).unwrap_or_else(|| alloc::handle_alloc_error(layout))
+        };
+        unsafe { ptr.cast::<T>().as_ptr().write(obj) };
+        Self {
+            ptr,
+            layout,
+            drop: drop_raw::<T>
+        }
+    }
+}
+
+impl Drop for RawObject {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.ptr.as_ptr()) };
+        if self.layout.size() != 0 {
+            unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) };
+        }
+    }
+}
+
+/// How many bytes the objects in a map take up.
+fn used(map: &HashMap<u32, RawObject>) -> usize {
+    map.values().map(|f| f.layout.size()).sum()
+}
 
 pub struct MemoryRaw;
 pub struct MemoryMutex;
+pub struct MemoryRwLock;
 
 pub enum MemoryProtection {
     /// No memory protections
     Raw,
     /// Mutex
-    Mutex
+    Mutex,
+    /// A readers-writer lock per object
+    RwLock
+}
+
+/// There was not enough room left in memory to store something.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutOfMemory {
+    /// How many bytes were asked for.
+    pub requested: usize,
+    /// How many bytes were free.
+    pub available: usize
+}
+
+/// Something tried to write through a [ReadOnlyPtr].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtectionFault {
+    /// The address that was written to.
+    pub address: u32,
+    /// The thread that tried to write.
+    pub thread: ThreadId
 }
 
 /// the most unsafe thing ever implemented.
 pub struct RandomAccessMemory<P> {
     lookup: Arc<RamMap>,
     guard: Arc<Mutex<()>>,
+    /// Every protection fault in the order they happened.
+    faults: Arc<Mutex<Vec<ProtectionFault>>>,
+    /// Where the addresses are drawn from.
+    rng: SimRng,
+    /// Who gets told about locking, this is off unless asked for.
+    registry: Option<Arc<LockRegistry>>,
+    /// How many bytes we can hold, there is no limit if this is none.
+    capacity: Option<usize>,
     _mode: PhantomData<P>
 }
 
 impl<P> RandomAccessMemory<P> {
     pub fn new() -> Self {
         Self {
-            lookup: Arc::new(SharedMemory::new(HashMap::new())),
+            lookup: Arc::new(Mutex::new(HashMap::new())),
             guard: Arc::new(Mutex::new(())),
+            faults: Arc::new(Mutex::new(vec![])),
+            rng: SimRng::global(),
+            registry: None,
+            capacity: None,
             _mode: PhantomData
         }
     }
-    fn store_inner<T>(&self, obj: T) -> MemoryPtr<T> {
-        let rand = rand::random::<u32>();
-
-        // Store this object as raw bytes on the heap.
-        let structdat = unsafe {core::slice::from_raw_parts(
-            (&obj as *const T) as *const u8,
-            ::core::mem::size_of::<T>(),
-        ) }.to_vec().into_boxed_slice();
-
-        // don't let the destructor run
-        mem::forget(obj);
+    /// Creates memory that can only hold so many bytes, stores
+    /// past that fail with [OutOfMemory].
+    pub fn with_capacity(bytes: usize) -> Self {
+        let mut memory = Self::new();
+        memory.capacity = Some(bytes);
+        memory
+    }
+    /// Draws the addresses from the given generator.
+    pub fn with_rng(mut self, rng: SimRng) -> Self {
+        self.rng = rng;
+        self
+    }
+    /// How many objects are stored and have not been freed.
+    pub fn live_objects(&self) -> usize {
+        self.lookup.lock().len()
+    }
+    /// The protection faults so far.
+    pub fn faults(&self) -> Vec<ProtectionFault> {
+        self.faults.lock().clone()
+    }
+    /// How many bytes the stored objects take up.
+    pub fn used(&self) -> usize {
+        used(&self.lookup.lock())
+    }
+    /// How many bytes we can hold, if there is a limit.
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+    /// Checks there is room for this many more bytes, the map has to
+    /// stay locked until they are placed so nobody else takes the room.
+    fn has_room(&self, map: &HashMap<u32, RawObject>, requested: usize) -> Result<(), OutOfMemory> {
+        if let Some(capacity) = self.capacity {
+            let available = capacity - used(map);
+            if requested > available {
+                return Err(OutOfMemory {
+                    requested,
+                    available
+                });
+            }
+        }
+        Ok(())
+    }
+    fn store_inner<T: Send>(&self, obj: T) -> Result<MemoryPtr<T>, OutOfMemory> {
+        let mut map = self.lookup.lock();
+        self.has_room(&map, Layout::new::<T>().size())?;
 
+        // Move this object onto the heap as raw bytes.
+        Ok(self.place(&mut map, RawObject::new(obj)))
+    }
+    /// Puts the bytes of an object at a new address.
+    fn place<T>(&self, map: &mut HashMap<u32, RawObject>, structdat: RawObject) -> MemoryPtr<T> {
+        // Taking a live address would drop the object already there.
+        let rand = loop {
+            let rand = self.rng.gen::<u32>();
+            if !map.contains_key(&rand) {
+                break rand;
+            }
+        };
 
         // insert this in the map.
-        self.lookup.get_mut().insert(rand, structdat);
+        map.insert(rand, structdat);
 
-   
         MemoryPtr {
             address: rand,
             ram: Arc::downgrade(&self.lookup),
             shr_guard_mutex: Arc::downgrade(&self.guard),
+            faults: Arc::downgrade(&self.faults),
             _type: PhantomData
         }
     }
 }
 
 impl RandomAccessMemory<MemoryMutex> {
-    pub fn store<T>(&self, object: T) -> SyncMemoryPtr<T> {
-        SyncMemoryPtr(self.store_inner(object))
+    /// Reports all the locking to a registry so deadlocks can be detected.
+    ///
+    /// With one lock for the whole memory you can only ever deadlock on
+    /// yourself, so objects stored after this each get their own lock.
+    pub fn with_registry(mut self, registry: Arc<LockRegistry>) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+    /// Stores the object, the pointers can go to any thread so
+    /// it has to be safe to send and share.
+    ///
+    /// ```compile_fail
+    /// use std::rc::Rc;
+    /// use osconcepts::memory::pool::{MemoryMutex, RandomAccessMemory};
+    ///
+    /// RandomAccessMemory::<MemoryMutex>::new().store(Rc::new(1));
+    /// ```
+    ///
+    /// # Panics
+    /// If the memory is full.
+    pub fn store<T: Send + Sync>(&self, object: T) -> SyncMemoryPtr<T> {
+        self.try_store(object).expect("Out of memory.")
+    }
+    /// Stores the object if there is room for it.
+    pub fn try_store<T: Send + Sync>(&self, object: T) -> Result<SyncMemoryPtr<T>, OutOfMemory> {
+        Ok(SyncMemoryPtr {
+            ptr: self.store_inner(object)?,
+            tracking: self.registry.as_ref().map(|registry| Tracking {
+                registry: Arc::clone(registry),
+                lock: Arc::new(Mutex::new(()))
+            })
+        })
+    }
+    /// Moves an object out of the memory it is in and into this one at a
+    /// new address, the old pointers to it are left dangling like it was freed.
+    /// If it is already in this memory it stays where it is.
+    ///
+    /// # Panics
+    /// If the object was already freed.
+    pub fn take<T: Send + Sync>(&self, ptr: &SyncMemoryPtr<T>) -> Result<SyncMemoryPtr<T>, OutOfMemory> {
+        let Some(source) = ptr.ptr.ram.upgrade() else {
+            panic!("Deref to null ram!");
+        };
+        if Arc::ptr_eq(&source, &self.lookup) {
+            return Ok(ptr.clone());
+        }
+        // Nobody can be using it while it moves.
+        let guard = ptr.lock();
+        // Always lock the two maps in the same order so two moves can't deadlock.
+        let (mut from, mut to) = if Arc::as_ptr(&source) < Arc::as_ptr(&self.lookup) {
+            let from = source.lock();
+            (from, self.lookup.lock())
+        } else {
+            let to = self.lookup.lock();
+            (source.lock(), to)
+        };
+        let address = ptr.ptr.address;
+        let size = from
+            .get(&address)
+            .unwrap_or_else(|| panic!("Use after free of address {address}."))
+            .layout
+            .size();
+        self.has_room(&to, size)?;
+        let raw = from.remove(&address).unwrap();
+        let moved = self.place(&mut to, raw);
+        drop((from, to, guard));
+        Ok(SyncMemoryPtr {
+            ptr: moved,
+            tracking: ptr.tracking.clone()
+        })
+    }
+}
+
+impl RandomAccessMemory<MemoryRwLock> {
+    /// Stores the object behind its own readers-writer lock, unlike
+    /// the mutex mode this does not share a lock with everything else.
+    ///
+    /// # Panics
+    /// If the memory is full.
+    pub fn store<T: Send + Sync>(&self, object: T) -> RwMemoryPtr<T> {
+        self.try_store(object).expect("Out of memory.")
+    }
+    /// Stores the object if there is room for it.
+    pub fn try_store<T: Send + Sync>(&self, object: T) -> Result<RwMemoryPtr<T>, OutOfMemory> {
+        Ok(RwMemoryPtr {
+            ptr: self.store_inner(object)?,
+            lock: Arc::new(RwLock::new(()))
+        })
     }
 }
 
 impl RandomAccessMemory<MemoryRaw> {
-    pub fn store<T>(&self, object: T) -> MemoryPtr<T> {
+    /// Stores the object.
+    ///
+    /// # Panics
+    /// If the memory is full.
+    pub fn store<T: Send>(&self, object: T) -> MemoryPtr<T> {
+        self.try_store(object).expect("Out of memory.")
+    }
+    /// Stores the object if there is room for it.
+    pub fn try_store<T: Send>(&self, object: T) -> Result<MemoryPtr<T>, OutOfMemory> {
         self.store_inner(object)
     }
 }
@@ -74,52 +300,249 @@ pub struct MemoryPtr<T> {
     address: u32,
     ram: Weak<RamMap>,
     shr_guard_mutex: Weak<Mutex<()>>,
+    faults: Weak<Mutex<Vec<ProtectionFault>>>,
     _type: PhantomData<T>
 }
 
 
-pub struct SyncMemoryPtr<T>(MemoryPtr<T>);
+/// The lock of an object and where to report it.
+#[derive(Clone)]
+struct Tracking {
+    registry: Arc<LockRegistry>,
+    lock: Arc<Mutex<()>>
+}
+
+pub struct SyncMemoryPtr<T> {
+    ptr: MemoryPtr<T>,
+    tracking: Option<Tracking>
+}
 
 impl<T> Clone for SyncMemoryPtr<T> {
     fn clone(&self) -> Self {
-        Self(MemoryPtr::clone(&self.0))
+        Self {
+            ptr: MemoryPtr::clone(&self.ptr),
+            tracking: self.tracking.clone()
+        }
     }
 }
 
 impl<T> SyncMemoryPtr<T> {
     pub fn lock<'a>(&self) -> MemoryPtrGuard<T> {
-        let mutex_arc = self.0.shr_guard_mutex.upgrade().unwrap().clone();
-        let guard= mutex_arc.lock_arc();
+        self.lock_inner(|mutex| Some(mutex.lock_arc())).unwrap()
+    } 
+    /// Locks the pointer just for the closure.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.lock())
+    }
+    /// Tries to lock the pointer, giving up after the timeout.
+    pub fn try_lock_for(&self, timeout: Duration) -> Option<MemoryPtrGuard<T>> {
+        self.lock_inner(|mutex| mutex.try_lock_arc_for(timeout))
+    }
+    /// Frees the object once nobody else has it locked.
+    ///
+    /// # Panics
+    /// If the object was already freed.
+    pub fn free(self) {
+        let guard = self.lock();
+        self.ptr.free();
+        drop(guard);
+    }
+    /// Makes a pointer to the same object that can only be read.
+    pub fn downgrade_readonly(&self) -> ReadOnlyPtr<T> {
+        ReadOnlyPtr(self.clone())
+    }
+    fn lock_inner(&self, lock: impl FnOnce(&Arc<Mutex<()>>) -> Option<ArcMutexGuard<RawMutex, ()>>) -> Option<MemoryPtrGuard<T>> {
+        let Some(tracking) = &self.tracking else {
+            let mutex_arc = self.ptr.shr_guard_mutex.upgrade().unwrap().clone();
+            return lock(&mutex_arc).map(|guard| MemoryPtrGuard {
+                ptr: self.ptr.clone(),
+                registry: None,
+                _guard: guard
+            });
+        };
+
+        let thread = thread::current().id();
+        tracking.registry.waiting(thread, self.ptr.address);
+        let Some(guard) = lock(&tracking.lock) else {
+            tracking.registry.gave_up(thread);
+            return None;
+        };
+        tracking.registry.acquired(thread, self.ptr.address);
 
-        MemoryPtrGuard {
-            ptr: self.0.clone(),
+        Some(MemoryPtrGuard {
+            ptr: self.ptr.clone(),
+            registry: Some(Arc::clone(&tracking.registry)),
             _guard: guard
+        })
+    }
+}
+
+/// A pointer that can only be read through, trying to write
+/// gets logged as a fault on the memory it came from.
+pub struct ReadOnlyPtr<T>(SyncMemoryPtr<T>);
+
+impl<T> Clone for ReadOnlyPtr<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> ReadOnlyPtr<T> {
+    pub fn get(&self) -> ReadOnlyGuard<T> {
+        ReadOnlyGuard(self.0.lock())
+    }
+    /// This always faults.
+    pub fn get_mut(&self) -> Result<MemoryPtrGuard<T>, ProtectionFault> {
+        let fault = ProtectionFault {
+            address: self.0.ptr.address,
+            thread: thread::current().id()
+        };
+        if let Some(faults) = self.0.ptr.faults.upgrade() {
+            faults.lock().push(fault.clone());
         }
-    } 
+        Err(fault)
+    }
+}
+
+pub struct ReadOnlyGuard<T>(MemoryPtrGuard<T>);
+
+impl<T> Deref for ReadOnlyGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.0.get()
+    }
+}
+
+/// A pointer where any number of readers can access the object
+/// at once but a writer gets it all to itself.
+pub struct RwMemoryPtr<T> {
+    ptr: MemoryPtr<T>,
+    lock: Arc<RwLock<()>>
+}
+
+impl<T> Clone for RwMemoryPtr<T> {
+    fn clone(&self) -> Self {
+        Self {
+            ptr: self.ptr.clone(),
+            lock: Arc::clone(&self.lock)
+        }
+    }
+}
+
+impl<T> RwMemoryPtr<T> {
+    /// Waits until there are no writers.
+    pub fn read(&self) -> RwMemoryReadGuard<T> {
+        RwMemoryReadGuard {
+            ptr: self.ptr.clone(),
+            _guard: self.lock.read_arc()
+        }
+    }
+    /// Waits until there are no readers or writers.
+    pub fn write(&self) -> RwMemoryWriteGuard<T> {
+        RwMemoryWriteGuard {
+            ptr: self.ptr.clone(),
+            _guard: self.lock.write_arc()
+        }
+    }
+    /// Frees the object once there are no readers or writers.
+    ///
+    /// # Panics
+    /// If the object was already freed.
+    pub fn free(self) {
+        let _guard = self.lock.write();
+        self.ptr.free();
+    }
+}
+
+pub struct RwMemoryReadGuard<T> {
+    ptr: MemoryPtr<T>,
+    _guard: ArcRwLockReadGuard<RawRwLock, ()>
+}
+
+impl<T> RwMemoryReadGuard<T> {
+    pub fn get(&self) -> &T {
+        self.ptr.get()
+    }
+}
+
+impl<T> Deref for RwMemoryReadGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.get()
+    }
+}
+
+pub struct RwMemoryWriteGuard<T> {
+    ptr: MemoryPtr<T>,
+    _guard: ArcRwLockWriteGuard<RawRwLock, ()>
+}
+
+impl<T> RwMemoryWriteGuard<T> {
+    pub fn get(&self) -> &T {
+        self.ptr.get()
+    }
+    pub fn get_mut(&mut self) -> &mut T {
+        self.ptr.get_mut()
+    }
+}
+
+impl<T> Deref for RwMemoryWriteGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.get()
+    }
+}
+
+impl<T> DerefMut for RwMemoryWriteGuard<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.get_mut()
+    }
 }
 
 impl<T: Debug>  Debug for SyncMemoryPtr<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        self.ptr.fmt(f)
     }
 }
 
 pub struct MemoryPtrGuard<T> {
    ptr: MemoryPtr<T>,
+   registry: Option<Arc<LockRegistry>>,
    _guard: ArcMutexGuard<RawMutex, ()> 
 }  
 
+impl<T> Drop for MemoryPtrGuard<T> {
+    fn drop(&mut self) {
+        if let Some(registry) = &self.registry {
+            registry.released(self.ptr.address);
+        }
+    }
+}
+
 
 
 impl<T> MemoryPtrGuard<T> {
     pub fn get(&self) -> &T {
         self.ptr.get()
     }
-    pub fn get_mut(&self) -> &mut T {
+    pub fn get_mut(&mut self) -> &mut T {
         self.ptr.get_mut()
     }
 }
 
+impl<T> Deref for MemoryPtrGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.get()
+    }
+}
+
+impl<T> DerefMut for MemoryPtrGuard<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.get_mut()
+    }
+}
+
 
 impl<T: Debug> Debug for MemoryPtr<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -136,6 +559,7 @@ impl<T> Clone for MemoryPtr<T> {
             address: self.address,
             ram: Weak::clone(&self.ram),
             shr_guard_mutex: Weak::clone(&self.shr_guard_mutex),
+            faults: Weak::clone(&self.faults),
             _type: PhantomData
         }
     }
@@ -145,23 +569,41 @@ impl<T> Clone for MemoryPtr<T> {
 
 
 impl<T> MemoryPtr<T> {
-    fn get_raw(&self) -> *const [u8] {
+    fn get_raw(&self) -> *mut T {
         if let Some(map) = self.ram.upgrade() {
-            let actual_ptr = map.get().get(&self.address).unwrap().as_ref() as *const [u8];
+            let actual_ptr = map
+                .lock()
+                .get(&self.address)
+                .unwrap_or_else(|| panic!("Use after free of address {}.", self.address))
+                .ptr
+                .cast::<T>()
+                .as_ptr();
             actual_ptr
         } else {
             panic!("Deref to null ram!");
         }
     }
+    /// Removes the object from memory and runs its destructor, any
+    /// other pointers to it will panic if they are used.
+    ///
+    /// # Panics
+    /// If the object was already freed.
+    pub fn free(self) {
+        let Some(map) = self.ram.upgrade() else {
+            panic!("Deref to null ram!");
+        };
+        let raw = map
+            .lock()
+            .remove(&self.address)
+            .unwrap_or_else(|| panic!("Double free of address {}.", self.address));
+        // The destructor runs without the map locked.
+        drop(raw);
+    }
     pub fn get(&self) -> &T {
-        let ptr = unsafe { &*self.get_raw() };
-        let (_, body, _) = unsafe { ptr.align_to::<T>() };
-        &body[0]
+        unsafe { &*self.get_raw() }
     }
     pub fn get_mut(&self) -> &mut T {
-        let ptr = unsafe { &mut *(self.get_raw() as *mut [u8]) };
-        let (_, body, _) = unsafe { ptr.align_to_mut::<T>() };
-        &mut body[0]
+        unsafe { &mut *self.get_raw() }
     }
 }
 
@@ -181,7 +623,9 @@ impl<T> DerefMut for MemoryPtr<T> {
 
 #[cfg(test)]
 mod tests {
-    use crate::memory::pool::MemoryRaw;
+    use std::{sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Barrier}, thread, time::Duration};
+
+    use crate::memory::{pool::{MemoryMutex, MemoryRaw, MemoryRwLock, OutOfMemory}, sync::LockRegistry};
 
     use super::RandomAccessMemory;
 
@@ -190,6 +634,15 @@ mod tests {
         a: u16
     }
 
+    /// Counts how many times it has been dropped.
+    pub struct DropCounter(Arc<AtomicUsize>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
     #[test]
     pub fn test_random_anonymous_map() {
         let ram = RandomAccessMemory::<MemoryRaw>::new();
@@ -211,4 +664,245 @@ mod tests {
 
         
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn test_rw_memory_readers() {
+        let ram = RandomAccessMemory::<MemoryRwLock>::new();
+        let ptr = ram.store(TestStub { a: 3 });
+
+        // Both readers hold their guard at the same time.
+        let held = Arc::new(Barrier::new(3));
+        let release = Arc::new(Barrier::new(3));
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let (ptr, held, release) = (ptr.clone(), held.clone(), release.clone());
+                thread::spawn(move || {
+                    let guard = ptr.read();
+                    held.wait();
+                    assert_eq!(guard.get().a, 3);
+                    release.wait();
+                })
+            })
+            .collect();
+        held.wait();
+
+        let written = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (ptr, written) = (ptr.clone(), written.clone());
+            thread::spawn(move || {
+                // The guard derefs to the object like the mutex one does.
+                ptr.write().a = 24;
+                written.store(true, Ordering::SeqCst);
+            })
+        };
+
+        // The writer has to wait for the readers.
+        thread::sleep(Duration::from_millis(50));
+        assert!(!written.load(Ordering::SeqCst));
+
+        release.wait();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        writer.join().unwrap();
+        assert!(written.load(Ordering::SeqCst));
+        assert_eq!(ptr.read().a, 24);
+    }
+
+    #[test]
+    pub fn test_lock_registry_deadlock() {
+        let registry = Arc::new(LockRegistry::new());
+        let ram = RandomAccessMemory::<MemoryMutex>::new().with_registry(registry.clone());
+        let a = ram.store(TestStub { a: 1 });
+        let b = ram.store(TestStub { a: 2 });
+
+        // One thread goes A then B, the other B then A.
+        let both_held = Arc::new(Barrier::new(2));
+        let workers: Vec<_> = [(a.clone(), b.clone()), (b, a)]
+            .into_iter()
+            .map(|(first, second)| {
+                let both_held = both_held.clone();
+                thread::spawn(move || {
+                    let _first = first.lock();
+                    both_held.wait();
+                    second.try_lock_for(Duration::from_millis(500)).is_some()
+                })
+            })
+            .collect();
+        let threads: Vec<_> = workers.iter().map(|f| f.thread().id()).collect();
+
+        let mut cycle = None;
+        while cycle.is_none() && !workers.iter().all(|f| f.is_finished()) {
+            cycle = registry.detect_cycle();
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        // Whoever times out first lets the other one through.
+        let acquired: Vec<_> = workers.into_iter().map(|f| f.join().unwrap()).collect();
+        assert!(acquired.contains(&false));
+        let cycle = cycle.expect("The deadlock was not detected.");
+        assert_eq!(cycle.len(), 2);
+        assert!(threads.iter().all(|f| cycle.contains(f)));
+        assert!(registry.detect_cycle().is_none());
+    }
+
+    #[test]
+    pub fn test_memory_free() {
+        let ram = RandomAccessMemory::<MemoryRaw>::new();
+        let drops = Arc::new(AtomicUsize::new(0));
+        let ptr = ram.store(DropCounter(drops.clone()));
+        let _other = ram.store(TestStub { a: 1 });
+        assert_eq!(ram.live_objects(), 2);
+        assert_eq!(drops.load(Ordering::SeqCst), 0);
+
+        ptr.free();
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+        assert_eq!(ram.live_objects(), 1);
+
+        let ram = RandomAccessMemory::<MemoryMutex>::new();
+        ram.store(DropCounter(drops.clone())).free();
+        assert_eq!(drops.load(Ordering::SeqCst), 2);
+        assert_eq!(ram.live_objects(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Use after free")]
+    pub fn test_memory_use_after_free() {
+        let ram = RandomAccessMemory::<MemoryRaw>::new();
+        let ptr = ram.store(TestStub { a: 3 });
+        let stale = ptr.clone();
+        ptr.free();
+        let _ = stale.a;
+    }
+
+    #[test]
+    #[should_panic(expected = "Double free")]
+    pub fn test_memory_double_free() {
+        let ram = RandomAccessMemory::<MemoryRaw>::new();
+        let ptr = ram.store(TestStub { a: 3 });
+        ptr.clone().free();
+        ptr.free();
+    }
+
+    #[test]
+    pub fn test_memory_capacity() {
+        let ram = RandomAccessMemory::<MemoryMutex>::with_capacity(6);
+        let ptrs: Vec<_> = (0..3).map(|a| ram.try_store(TestStub { a }).unwrap()).collect();
+        assert_eq!(ram.used(), 6);
+        assert_eq!(ram.capacity(), Some(6));
+        assert_eq!(
+            ram.try_store(TestStub { a: 3 }).unwrap_err(),
+            OutOfMemory { requested: 2, available: 0 }
+        );
+
+        // Freeing something gives the room back.
+        ptrs[0].clone().free();
+        assert_eq!(ram.used(), 4);
+        assert!(ram.try_store(TestStub { a: 3 }).is_ok());
+        assert!(ram.try_store(7u8).is_err());
+    }
+
+    #[test]
+    pub fn test_memory_concurrent_store_free() {
+        // Objects with their own locks are stored and freed from every thread at once.
+        let ram = RandomAccessMemory::<MemoryRwLock>::with_capacity(32);
+        thread::scope(|scope| {
+            for thread in 0..8u64 {
+                let ram = &ram;
+                scope.spawn(move || {
+                    for i in 0..500 {
+                        let Ok(ptr) = ram.try_store(thread * 1000 + i) else {
+                            continue;
+                        };
+                        assert!(ram.used() <= 32);
+                        assert_eq!(*ptr.read().get(), thread * 1000 + i);
+                        ptr.free();
+                    }
+                });
+            }
+        });
+        assert_eq!((ram.used(), ram.live_objects()), (0, 0));
+    }
+
+    #[derive(Debug, PartialEq)]
+    #[repr(align(64))]
+    pub struct CacheLine {
+        a: u8,
+        b: u64
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct Empty;
+
+    #[test]
+    pub fn test_memory_alignment() {
+        let ram = RandomAccessMemory::<MemoryRaw>::new();
+        let padding: Vec<_> = (0..8).map(|f| ram.store(f as u8)).collect();
+
+        let word = ram.store(u64::MAX - 1);
+        assert_eq!(*word, u64::MAX - 1);
+        assert_eq!(word.get() as *const u64 as usize % 8, 0);
+
+        let line = ram.store(CacheLine { a: 1, b: 2 });
+        line.get_mut().b = 3;
+        assert_eq!(*line, CacheLine { a: 1, b: 3 });
+        assert_eq!(line.get() as *const CacheLine as usize % 64, 0);
+
+        let empty = ram.store(Empty);
+        assert_eq!(*empty, Empty);
+        assert_eq!(ram.used(), 8 + 8 + 64);
+        assert_eq!(*padding[5], 5);
+    }
+
+    #[test]
+    pub fn test_memory_protection_fault() {
+        let ram = RandomAccessMemory::<MemoryMutex>::new();
+        let writable = ram.store(TestStub { a: 1 });
+        let readonly = writable.clone().downgrade_readonly();
+
+        writable.lock().get_mut().a = 2;
+        assert_eq!(readonly.get().a, 2);
+        assert!(ram.faults().is_empty());
+
+        let fault = readonly.get_mut().err().unwrap();
+        assert_eq!(ram.faults(), [fault]);
+
+        // The writable one is unaffected.
+        writable.lock().get_mut().a = 3;
+        assert_eq!(readonly.get().a, 3);
+        assert_eq!(ram.faults().len(), 1);
+    }
+
+    #[test]
+    pub fn test_memory_with() {
+        let ram = RandomAccessMemory::<MemoryMutex>::new();
+        let ptr = ram.store(vec![1]);
+        let other = ptr.clone();
+
+        ptr.with(|f| f.push(2));
+        assert_eq!(other.with(|f| f.len()), 2);
+
+        // The guard derefs to the object too.
+        let mut guard = other.lock();
+        guard.push(3);
+        drop(guard);
+        assert_eq!(*ptr.lock(), [1, 2, 3]);
+    }
+
+    #[test]
+    pub fn test_memory_drops() {
+        let ram = RandomAccessMemory::<MemoryMutex>::new();
+        let names = ram.store(vec!["a".to_string()]);
+        names.lock().push("b".to_string());
+        names.lock().get_mut()[0].push('c');
+        assert_eq!(*names.lock(), ["ac", "b"]);
+
+        // Freeing runs the destructor once and so does dropping the memory.
+        let drops = Arc::new(AtomicUsize::new(0));
+        ram.store(DropCounter(drops.clone())).free();
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+        let _kept = ram.store(DropCounter(drops.clone()));
+        drop(ram);
+        assert_eq!(drops.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,188 @@
+//! Segmentation, memory is split into segments that each have a base
+//! and a limit and addresses are a selector and an offset into one.
+
+use crate::disks::{RawStoragePtr, StorageDevice};
+
+
+/// Why an access to a segment was not allowed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SegFault {
+    /// There is no segment with that selector.
+    BadSelector(u16),
+    /// The access goes past the end of the segment.
+    LimitExceeded {
+        selector: u16,
+        offset: usize,
+        limit: usize
+    },
+    /// The segment can only be read.
+    WriteToReadOnly(u16),
+    /// The segment would overlap another one.
+    Overlap,
+    /// Every selector is in use.
+    TooManySegments
+}
+
+/// What an access wants to do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write
+}
+
+/// An entry in the [SegmentTable].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    /// Where the segment starts in physical memory.
+    pub base: usize,
+    /// How many bytes long the segment is.
+    pub limit: usize,
+    pub writable: bool
+}
+
+impl Segment {
+    fn end(&self) -> usize {
+        self.base + self.limit
+    }
+}
+
+/// Maps segment selectors to where they are in a flat memory.
+///
+/// ```
+/// use osconcepts::{disks::SecondaryStorage, memory::segmentation::{SegFault, SegmentTable}};
+///
+/// let mut table = SegmentTable::new(SecondaryStorage::new(1024));
+/// let code = table.add(0, 16, false).unwrap();
+/// assert_eq!(table.read(code, 15, 1).unwrap(), [0]);
+/// assert_eq!(table.write(code, 0, &[1]), Err(SegFault::WriteToReadOnly(code)));
+/// ```
+pub struct SegmentTable<S> {
+    /// The index is the selector.
+    segments: Vec<Segment>,
+    memory: S
+}
+
+impl<S: StorageDevice> SegmentTable<S> {
+    pub fn new(memory: S) -> Self {
+        Self {
+            segments: vec![],
+            memory
+        }
+    }
+    /// Adds a segment and returns its selector, this fails if it
+    /// overlaps another segment or there are no selectors left.
+    pub fn add(&mut self, base: usize, limit: usize, writable: bool) -> Result<u16, SegFault> {
+        let segment = Segment { base, limit, writable };
+        if !self.segments.iter().all(|f| segment.end() <= f.base || f.end() <= base) {
+            return Err(SegFault::Overlap);
+        }
+        let selector = u16::try_from(self.segments.len()).map_err(|_| SegFault::TooManySegments)?;
+        self.segments.push(segment);
+        Ok(selector)
+    }
+    /// Looks up a segment.
+    pub fn segment(&self, selector: u16) -> Result<Segment, SegFault> {
+        self.segments
+            .get(selector as usize)
+            .copied()
+            .ok_or(SegFault::BadSelector(selector))
+    }
+    /// Translates an offset into a segment into a physical address.
+    pub fn translate(&self, selector: u16, offset: usize, access: Access) -> Result<usize, SegFault> {
+        let segment = self.segment(selector)?;
+        if offset >= segment.limit {
+            return Err(SegFault::LimitExceeded {
+                selector,
+                offset,
+                limit: segment.limit
+            });
+        }
+        if access == Access::Write && !segment.writable {
+            return Err(SegFault::WriteToReadOnly(selector));
+        }
+        Ok(segment.base + offset)
+    }
+    /// Reads bytes from a segment, all of them have to be inside it.
+    pub fn read(&self, selector: u16, offset: usize, length: usize) -> Result<Vec<u8>, SegFault> {
+        let physical = self.translate(selector, offset, Access::Read)?;
+        if length > 0 {
+            self.translate(selector, offset + length - 1, Access::Read)?;
+        }
+        Ok(self.memory.read(RawStoragePtr::byte_ptr(physical), length))
+    }
+    /// Writes bytes to a segment, all of them have to be inside it.
+    pub fn write(&mut self, selector: u16, offset: usize, data: &[u8]) -> Result<(), SegFault> {
+        let physical = self.translate(selector, offset, Access::Write)?;
+        if !data.is_empty() {
+            self.translate(selector, offset + data.len() - 1, Access::Write)?;
+        }
+        self.memory.write(RawStoragePtr::byte_ptr(physical), data);
+        Ok(())
+    }
+    /// Makes a segment longer, this fails if it would grow into another segment.
+    pub fn grow(&mut self, selector: u16, by: usize) -> Result<(), SegFault> {
+        let segment = self.segment(selector)?;
+        let end = segment.end() + by;
+        if !self.segments.iter().all(|f| f.base < segment.base || f.base >= end || *f == segment) {
+            return Err(SegFault::Overlap);
+        }
+        self.segments[selector as usize].limit += by;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disks::SecondaryStorage;
+
+    use super::{Access, SegFault, Segment, SegmentTable};
+
+    #[test]
+    pub fn test_segment_table() {
+        let mut table = SegmentTable::new(SecondaryStorage::new(1024));
+        let code = table.add(0, 100, false).unwrap();
+        let data = table.add(100, 50, true).unwrap();
+
+        assert_eq!(table.translate(data, 10, Access::Write), Ok(110));
+        table.write(data, 10, &[1, 2, 3]).unwrap();
+        assert_eq!(table.read(data, 10, 3).unwrap(), [1, 2, 3]);
+        assert_eq!(table.translate(9, 0, Access::Read), Err(SegFault::BadSelector(9)));
+
+        // The last byte is fine but one past it is not.
+        assert!(table.translate(data, 49, Access::Read).is_ok());
+        let fault = SegFault::LimitExceeded { selector: data, offset: 50, limit: 50 };
+        assert_eq!(table.translate(data, 50, Access::Read), Err(fault));
+        assert_eq!(table.write(data, 48, &[0; 3]), Err(fault));
+
+        // Code can be read but not written.
+        assert!(table.read(code, 0, 100).is_ok());
+        assert_eq!(table.write(code, 0, &[0]), Err(SegFault::WriteToReadOnly(code)));
+
+        // Growing the data segment makes room.
+        table.grow(data, 10).unwrap();
+        table.write(data, 48, &[7; 3]).unwrap();
+        assert_eq!(table.read(data, 50, 1).unwrap(), [7]);
+        assert_eq!(table.segment(data).unwrap().limit, 60);
+    }
+
+    #[test]
+    pub fn test_segment_overlap() {
+        let mut table = SegmentTable::new(SecondaryStorage::new(1024));
+        let first = table.add(0, 10, true).unwrap();
+        assert_eq!(table.add(5, 10, true), Err(SegFault::Overlap));
+        table.add(10, 10, true).unwrap();
+
+        // Growing into the next one fails and leaves the segment as it was.
+        assert_eq!(table.grow(first, 1), Err(SegFault::Overlap));
+        assert_eq!(table.segment(first).unwrap().limit, 10);
+    }
+
+    #[test]
+    pub fn test_segment_selectors_run_out() {
+        let mut table = SegmentTable::new(SecondaryStorage::new(0));
+        // Fill it up without checking each one against all the others.
+        table.segments = (0..u16::MAX as usize).map(|base| Segment { base, limit: 0, writable: false }).collect();
+        assert_eq!(table.add(u16::MAX as usize, 0, false), Ok(u16::MAX));
+        assert_eq!(table.add(u16::MAX as usize + 1, 0, false), Err(SegFault::TooManySegments));
+    }
+}
@@ -0,0 +1,104 @@
+use parking_lot::{Condvar, Mutex};
+
+
+struct State {
+    /// How many threads are waiting in the current generation.
+    arrived: usize,
+    /// How many times the barrier has been released.
+    generation: usize
+}
+
+/// A reusable barrier, everyone waits until the last
+/// thread arrives and then they are all let through together.
+///
+/// ```
+/// use osconcepts::memory::sync::Barrier;
+///
+/// let barrier = Barrier::new(1);
+/// assert_eq!(barrier.wait(), 0);
+/// assert_eq!(barrier.wait(), 1);
+/// assert_eq!(barrier.generation(), 2);
+/// ```
+pub struct Barrier {
+    threads: usize,
+    state: Mutex<State>,
+    signal: Condvar
+}
+
+impl Barrier {
+    /// Creates a barrier that releases once `threads` threads are waiting.
+    ///
+    /// # Panics
+    /// If `threads` is zero.
+    pub fn new(threads: usize) -> Self {
+        assert!(threads > 0, "A barrier needs at least one thread.");
+        Self {
+            threads,
+            state: Mutex::new(State {
+                arrived: 0,
+                generation: 0
+            }),
+            signal: Condvar::new()
+        }
+    }
+    /// Waits for everyone else to arrive, this returns
+    /// the generation that was just released.
+    pub fn wait(&self) -> usize {
+        let mut state = self.state.lock();
+        let generation = state.generation;
+        state.arrived += 1;
+        if state.arrived == self.threads {
+            // Last one in resets it for the next round.
+            state.arrived = 0;
+            state.generation += 1;
+            self.signal.notify_all();
+        } else {
+            while state.generation == generation {
+                self.signal.wait(&mut state);
+            }
+        }
+        generation
+    }
+    /// How many times the barrier has been released.
+    pub fn generation(&self) -> usize {
+        self.state.lock().generation
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc}, thread};
+
+    use super::Barrier;
+
+    #[test]
+    pub fn test_barrier_phases() {
+        const THREADS: usize = 4;
+        const PHASES: usize = 100;
+
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let finished = Arc::new(AtomicUsize::new(0));
+        let workers: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let (barrier, finished) = (barrier.clone(), finished.clone());
+                thread::spawn(move || {
+                    for phase in 0..PHASES {
+                        // Nobody can be more than a phase ahead of us.
+                        let done = finished.fetch_add(1, Ordering::SeqCst);
+                        assert!(done < THREADS * (phase + 1));
+                        assert!(done >= THREADS * phase);
+
+                        assert_eq!(barrier.wait(), phase);
+                        assert!(finished.load(Ordering::SeqCst) >= THREADS * (phase + 1));
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(barrier.generation(), PHASES);
+        assert_eq!(finished.load(Ordering::SeqCst), THREADS * PHASES);
+    }
+}
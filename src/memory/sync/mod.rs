@@ -0,0 +1,27 @@
+//! Synchronization primitives.
+
+mod barrier;
+mod peterson;
+mod registry;
+mod semaphore;
+mod spin;
+
+pub use barrier::Barrier;
+pub use peterson::{Peterson, PetersonGuard};
+pub use registry::LockRegistry;
+pub use semaphore::{Semaphore, SemaphoreGuard};
+pub use spin::{SpinLock, SpinLockGuard};
+
+use std::{hint, thread};
+
+/// Spins for a bit and then starts giving the rest of our time
+/// slice away, otherwise on a single core we would spin until
+/// the holder gets scheduled again.
+fn backoff(spins: &mut u32) {
+    if *spins < 64 {
+        *spins += 1;
+        hint::spin_loop();
+    } else {
+        thread::yield_now();
+    }
+}
@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use super::backoff;
+
+
+/// Peterson's algorithm, mutual exclusion for two threads
+/// using nothing but shared variables.
+///
+/// Each thread has an id of either zero or one, it raises its flag and
+/// then gives the turn away. A thread only waits while the other one wants
+/// in and it is the other one's turn.
+///
+/// ```
+/// use osconcepts::memory::sync::Peterson;
+///
+/// let peterson = Peterson::new();
+/// let guard = peterson.lock(0);
+/// drop(guard);
+/// ```
+#[derive(Default)]
+pub struct Peterson {
+    flags: [AtomicBool; 2],
+    turn: AtomicUsize
+}
+
+impl Peterson {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Enters the critical section as thread `me`.
+    ///
+    /// # Panics
+    /// If `me` is not zero or one.
+    pub fn lock(&self, me: usize) -> PetersonGuard<'_> {
+        assert!(me < 2, "Peterson's algorithm only works for two threads.");
+        let other = 1 - me;
+        // This has to be SeqCst, with acquire/release our store to the
+        // flag could be reordered after reading theirs and both get in.
+        self.flags[me].store(true, Ordering::SeqCst);
+        self.turn.store(other, Ordering::SeqCst);
+        let mut spins = 0;
+        while self.flags[other].load(Ordering::SeqCst) && self.turn.load(Ordering::SeqCst) == other {
+            backoff(&mut spins);
+        }
+        PetersonGuard { lock: self, me }
+    }
+}
+
+/// Leaves the critical section when dropped.
+pub struct PetersonGuard<'a> {
+    lock: &'a Peterson,
+    me: usize
+}
+
+impl Drop for PetersonGuard<'_> {
+    fn drop(&mut self) {
+        self.lock.flags[self.me].store(false, Ordering::SeqCst);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use crate::memory::SharedMemory;
+
+    use super::Peterson;
+
+    #[test]
+    pub fn test_peterson_counter() {
+        let peterson = Arc::new(Peterson::new());
+        let counter = SharedMemory::new(0usize);
+        let workers: Vec<_> = (0..2)
+            .map(|me| {
+                let (peterson, counter) = (peterson.clone(), counter.clone());
+                thread::spawn(move || {
+                    for _ in 0..100_000 {
+                        let _guard = peterson.lock(me);
+                        *counter.get_mut() += 1;
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(*counter.get(), 200_000);
+    }
+}
@@ -0,0 +1,66 @@
+use std::{collections::HashMap, thread::ThreadId};
+
+use parking_lot::Mutex;
+
+
+#[derive(Default)]
+struct WaitGraph {
+    /// Which thread holds each lock, keyed by the memory address.
+    holders: HashMap<u32, ThreadId>,
+    /// Which lock each blocked thread is waiting on.
+    waiting: HashMap<ThreadId, u32>
+}
+
+/// Keeps track of who holds and who is waiting on the locks of a
+/// [RandomAccessMemory](crate::memory::pool::RandomAccessMemory) so
+/// deadlocks can be found with a wait-for graph.
+///
+/// Memory only reports here when it was made with
+/// [with_registry](crate::memory::pool::RandomAccessMemory::with_registry).
+#[derive(Default)]
+pub struct LockRegistry(Mutex<WaitGraph>);
+
+impl LockRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub(crate) fn waiting(&self, thread: ThreadId, address: u32) {
+        self.0.lock().waiting.insert(thread, address);
+    }
+    pub(crate) fn acquired(&self, thread: ThreadId, address: u32) {
+        let mut graph = self.0.lock();
+        graph.waiting.remove(&thread);
+        graph.holders.insert(address, thread);
+    }
+    /// The thread stopped waiting without getting the lock.
+    pub(crate) fn gave_up(&self, thread: ThreadId) {
+        self.0.lock().waiting.remove(&thread);
+    }
+    pub(crate) fn released(&self, address: u32) {
+        self.0.lock().holders.remove(&address);
+    }
+    /// Looks for a cycle in the wait-for graph, returning the
+    /// threads that are deadlocked on each other.
+    pub fn detect_cycle(&self) -> Option<Vec<ThreadId>> {
+        let graph = self.0.lock();
+        for &start in graph.waiting.keys() {
+            // Every thread waits on at most one lock, so we only
+            // have to follow the chain until it ends or loops.
+            let mut path = vec![start];
+            let mut current = start;
+            while let Some(next) = graph
+                .waiting
+                .get(&current)
+                .and_then(|f| graph.holders.get(f))
+                .copied()
+            {
+                if let Some(position) = path.iter().position(|&f| f == next) {
+                    return Some(path.split_off(position));
+                }
+                path.push(next);
+                current = next;
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,132 @@
+use std::mem;
+
+use parking_lot::{Condvar, Mutex};
+
+
+/// A counting semaphore.
+///
+/// ```
+/// use osconcepts::memory::sync::Semaphore;
+///
+/// let semaphore = Semaphore::new(1);
+/// let guard = semaphore.acquire();
+/// assert!(semaphore.try_acquire().is_none());
+/// drop(guard);
+/// assert!(semaphore.try_acquire().is_some());
+/// ```
+pub struct Semaphore {
+    permits: Mutex<usize>,
+    signal: Condvar
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            permits: Mutex::new(permits),
+            signal: Condvar::new()
+        }
+    }
+    /// Waits for a permit, the permit is given back
+    /// when the guard is dropped.
+    pub fn acquire(&self) -> SemaphoreGuard<'_> {
+        let mut permits = self.permits.lock();
+        while *permits == 0 {
+            self.signal.wait(&mut permits);
+        }
+        *permits -= 1;
+        SemaphoreGuard(self)
+    }
+    /// Takes a permit if there is one available.
+    pub fn try_acquire(&self) -> Option<SemaphoreGuard<'_>> {
+        let mut permits = self.permits.lock();
+        if *permits == 0 {
+            return None;
+        }
+        *permits -= 1;
+        Some(SemaphoreGuard(self))
+    }
+    /// Adds a permit, waking up someone waiting on one.
+    pub fn release(&self) {
+        *self.permits.lock() += 1;
+        self.signal.notify_one();
+    }
+    /// The number of permits available right now.
+    pub fn available(&self) -> usize {
+        *self.permits.lock()
+    }
+}
+
+/// Holds a permit of a [Semaphore] until dropped.
+pub struct SemaphoreGuard<'a>(&'a Semaphore);
+
+impl SemaphoreGuard<'_> {
+    /// Keeps the permit taken, this is for when the semaphore is used
+    /// for signalling and someone else is going to release it.
+    pub fn forget(self) {
+        mem::forget(self);
+    }
+}
+
+impl Drop for SemaphoreGuard<'_> {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, sync::Arc, thread};
+
+    use parking_lot::Mutex;
+
+    use super::Semaphore;
+
+    #[test]
+    pub fn test_semaphore_bounded_buffer() {
+        const CAPACITY: usize = 4;
+        const ITEMS: usize = 1000;
+
+        let empty = Arc::new(Semaphore::new(CAPACITY));
+        let full = Arc::new(Semaphore::new(0));
+        let buffer = Arc::new(Mutex::new(VecDeque::new()));
+
+        let producers: Vec<_> = (0..3)
+            .map(|p| {
+                let (empty, full, buffer) = (empty.clone(), full.clone(), buffer.clone());
+                thread::spawn(move || {
+                    for i in 0..ITEMS {
+                        empty.acquire().forget();
+                        buffer.lock().push_back(p * ITEMS + i);
+                        assert!(buffer.lock().len() <= CAPACITY);
+                        full.release();
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..3)
+            .map(|_| {
+                let (empty, full, buffer) = (empty.clone(), full.clone(), buffer.clone());
+                thread::spawn(move || {
+                    let mut taken = vec![];
+                    for _ in 0..ITEMS {
+                        full.acquire().forget();
+                        taken.push(buffer.lock().pop_front().unwrap());
+                        empty.release();
+                    }
+                    taken
+                })
+            })
+            .collect();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        // Nothing was lost or taken twice.
+        let mut taken: Vec<_> = consumers.into_iter().flat_map(|f| f.join().unwrap()).collect();
+        taken.sort();
+        assert_eq!(taken, (0..3 * ITEMS).collect::<Vec<_>>());
+        assert_eq!(empty.available(), CAPACITY);
+    }
+}
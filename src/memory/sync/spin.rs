@@ -0,0 +1,116 @@
+use std::{cell::UnsafeCell, ops::{Deref, DerefMut}, sync::atomic::{AtomicBool, Ordering}};
+
+use super::backoff;
+
+
+/// A lock that busy waits instead of going to sleep.
+///
+/// ```
+/// use osconcepts::memory::sync::SpinLock;
+///
+/// let lock = SpinLock::new(3);
+/// *lock.lock() += 1;
+/// assert_eq!(*lock.lock(), 4);
+/// ```
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>
+}
+
+unsafe impl<T: Send> Send for SpinLock<T> {}
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data)
+        }
+    }
+    /// Spins until we get the lock.
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let mut spins = 0;
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            // Wait for it to look free before trying again, this
+            // keeps us from fighting over the cache line.
+            while self.locked.load(Ordering::Relaxed) {
+                backoff(&mut spins);
+            }
+        }
+        SpinLockGuard(self)
+    }
+    /// Takes the lock if it is free.
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| SpinLockGuard(self))
+    }
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+/// Releases the [SpinLock] when dropped.
+pub struct SpinLockGuard<'a, T>(&'a SpinLock<T>);
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.0.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.0.data.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.0.locked.store(false, Ordering::Release);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use crate::memory::SharedMemory;
+
+    use super::SpinLock;
+
+    #[test]
+    pub fn test_spinlock_counter() {
+        let lock = Arc::new(SpinLock::new(()));
+        let counter = SharedMemory::new(0usize);
+        let workers: Vec<_> = (0..2)
+            .map(|_| {
+                let (lock, counter) = (lock.clone(), counter.clone());
+                thread::spawn(move || {
+                    for _ in 0..100_000 {
+                        let _guard = lock.lock();
+                        *counter.get_mut() += 1;
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(*counter.get(), 200_000);
+    }
+
+    #[test]
+    pub fn test_spinlock_guard_drop() {
+        let lock = SpinLock::new(vec![1]);
+        let mut guard = lock.lock();
+        guard.push(2);
+        assert!(lock.try_lock().is_none());
+        drop(guard);
+        assert!(!lock.is_locked());
+        assert_eq!(*lock.try_lock().unwrap(), [1, 2]);
+    }
+}
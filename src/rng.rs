@@ -0,0 +1,104 @@
+use std::{cell::RefCell, ops::Range, sync::Arc};
+
+use parking_lot::Mutex;
+use rand::{distributions::{uniform::SampleUniform, Distribution, Standard}, rngs::StdRng, Rng, SeedableRng};
+
+thread_local! {
+    /// The generator used when nothing else is passed in.
+    static GLOBAL: RefCell<SimRng> = RefCell::new(SimRng::from_entropy());
+}
+
+/// The random number generator used by the simulations, this is
+/// a handle so cloning it shares the same generator.
+///
+/// Everything that needs randomness draws from the thread's global
+/// generator unless it is given one. The threads the simulations start,
+/// like the disk worker and the cores, are handed the global generator of
+/// the thread that made them. So seeding it before building a simulation
+/// makes the numbers reproducible, though if two threads draw at the same
+/// time which one gets which number is down to the scheduler.
+///
+/// ```
+/// use osconcepts::rng::SimRng;
+///
+/// let a = SimRng::from_seed(7);
+/// let b = SimRng::from_seed(7);
+/// assert_eq!(a.gen::<u32>(), b.gen::<u32>());
+/// ```
+#[derive(Clone)]
+pub struct SimRng(Arc<Mutex<StdRng>>);
+
+impl SimRng {
+    /// Creates a new generator with a fixed seed.
+    pub fn from_seed(seed: u64) -> Self {
+        Self(Arc::new(Mutex::new(StdRng::seed_from_u64(seed))))
+    }
+    /// Creates a new generator seeded from the operating system.
+    pub fn from_entropy() -> Self {
+        Self(Arc::new(Mutex::new(StdRng::from_entropy())))
+    }
+    /// A handle to the global generator of this thread.
+    pub fn global() -> Self {
+        GLOBAL.with(|f| f.borrow().clone())
+    }
+    /// Makes a generator the global one of this thread, this is
+    /// how a new thread shares the generator of the one that started it.
+    pub fn set_global(rng: SimRng) {
+        GLOBAL.with(|f| *f.borrow_mut() = rng);
+    }
+    /// Reseeds the global generator of this thread, every handle
+    /// to it will see the new seed.
+    pub fn seed_global(seed: u64) {
+        GLOBAL.with(|f| f.borrow().reseed(seed));
+    }
+    /// Reseeds the generator in place.
+    pub fn reseed(&self, seed: u64) {
+        *self.0.lock() = StdRng::seed_from_u64(seed);
+    }
+    /// Generates a random value.
+    pub fn gen<T>(&self) -> T where Standard: Distribution<T> {
+        self.0.lock().gen()
+    }
+    /// Generates a random value in the range.
+    pub fn gen_range<T: SampleUniform + PartialOrd>(&self, range: Range<T>) -> T {
+        self.0.lock().gen_range(range)
+    }
+}
+
+impl Default for SimRng {
+    fn default() -> Self {
+        Self::global()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SimRng;
+
+    #[test]
+    pub fn test_rng_global_seed() {
+        let handle = SimRng::global();
+        SimRng::seed_global(3);
+        let first: Vec<u32> = (0..4).map(|_| handle.gen()).collect();
+        SimRng::seed_global(3);
+        let second: Vec<u32> = (0..4).map(|_| SimRng::global().gen()).collect();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    pub fn test_rng_shared_with_thread() {
+        let rng = SimRng::from_seed(9);
+        let expected: Vec<u32> = {
+            let copy = SimRng::from_seed(9);
+            (0..4).map(|_| copy.gen()).collect()
+        };
+        let drawn = std::thread::spawn({
+            let rng = rng.clone();
+            move || {
+                SimRng::set_global(rng);
+                (0..4).map(|_| SimRng::global().gen()).collect::<Vec<u32>>()
+            }
+        });
+        assert_eq!(drawn.join().unwrap(), expected);
+    }
+}